@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::camera::CameraMode;
+use crate::custom_pipeline::config::PipelineConfig;
+use crate::effect::Effect;
+use crate::performance::PerformanceData;
+use crate::scene::{CameraConfig, SceneConfig};
+use crate::window::BackendType;
+
+const REPORT_HEADER: &str = "scene,backend,width,height,tile_size,effect,avg_fps,min_fps,max_fps,fps_5_percent_low,fps_1_percent_low,cpu_usage,memory_mb";
+
+/// A benchmark sweep loaded from a TOML file.
+///
+/// Every combination of scene, backend, resolution, tile size and effect becomes one run.
+/// Tile sizes only apply to the custom pipeline, so WGPU runs are not repeated per tile size.
+#[derive(Debug, Deserialize)]
+pub struct SweepConfig {
+    /// CSV file that results are appended to. Runs already present in it are skipped.
+    #[serde(default = "default_report")]
+    pub report: String,
+    #[serde(default = "default_duration_secs")]
+    pub duration_secs: u64,
+    pub scenes: Vec<SweepScene>,
+    #[serde(default = "default_backends")]
+    pub backends: Vec<String>,
+    #[serde(default = "default_resolutions")]
+    pub resolutions: Vec<[u32; 2]>,
+    #[serde(default = "default_tile_sizes")]
+    pub tile_sizes: Vec<u32>,
+    #[serde(default = "default_effects")]
+    pub effects: Vec<SweepEffect>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SweepScene {
+    pub model_path: String,
+    #[serde(default)]
+    pub camera_position: [f32; 3],
+}
+
+/// Effect parameters mirror the `effect` subcommand, including its defaults.
+#[derive(Debug, Deserialize)]
+pub struct SweepEffect {
+    pub name: String,
+    #[serde(default = "default_param1")]
+    pub param1: f32,
+    #[serde(default = "default_param2")]
+    pub param2: f32,
+    #[serde(default = "default_param3")]
+    pub param3: f32,
+    #[serde(default)]
+    pub param4: u32,
+}
+
+fn default_report() -> String {
+    "sweep_report.csv".to_string()
+}
+
+fn default_duration_secs() -> u64 {
+    30
+}
+
+fn default_backends() -> Vec<String> {
+    vec!["custom".to_string(), "wgpu".to_string()]
+}
+
+fn default_resolutions() -> Vec<[u32; 2]> {
+    vec![[1024, 768]]
+}
+
+fn default_tile_sizes() -> Vec<u32> {
+    vec![PipelineConfig::default().tile_size]
+}
+
+fn default_effects() -> Vec<SweepEffect> {
+    vec![SweepEffect {
+        name: "none".to_string(),
+        param1: default_param1(),
+        param2: default_param2(),
+        param3: default_param3(),
+        param4: 0,
+    }]
+}
+
+fn default_param1() -> f32 {
+    3.0
+}
+
+fn default_param2() -> f32 {
+    0.2
+}
+
+fn default_param3() -> f32 {
+    1.0
+}
+
+impl SweepEffect {
+    /// Name plus parameters, so two runs of the same effect with different settings are told apart.
+    fn label(&self) -> String {
+        if self.name == "none" {
+            return self.name.clone();
+        }
+        format!(
+            "{}({} {} {} {})",
+            self.name, self.param1, self.param2, self.param3, self.param4
+        )
+    }
+}
+
+/// Identifies a single run of a sweep. Used to skip completed runs when a sweep is resumed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RunKey {
+    pub scene: String,
+    pub backend: String,
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: Option<u32>,
+    pub effect: String,
+}
+
+impl RunKey {
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            csv_field(&self.scene),
+            self.backend,
+            self.width,
+            self.height,
+            self.tile_size.map(|t| t.to_string()).unwrap_or_default(),
+            csv_field(&self.effect)
+        )
+    }
+
+    fn from_csv(fields: &[&str]) -> Option<Self> {
+        Some(Self {
+            scene: fields.first()?.to_string(),
+            backend: fields.get(1)?.to_string(),
+            width: fields.get(2)?.parse().ok()?,
+            height: fields.get(3)?.parse().ok()?,
+            tile_size: fields.get(4)?.parse().ok(),
+            effect: fields.get(5)?.to_string(),
+        })
+    }
+}
+
+// The report is a plain comma separated file, so commas are swapped out rather than quoted.
+fn csv_field(value: &str) -> String {
+    value.replace(',', ";")
+}
+
+pub struct BenchmarkRun {
+    pub key: RunKey,
+    pub scene_config: SceneConfig,
+}
+
+impl SweepConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read sweep config '{}': {}", path, e))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid sweep config '{}': {}", path, e))
+    }
+
+    /// Expands the sweep into the cartesian product of its parameters.
+    pub fn runs(&self) -> Result<Vec<BenchmarkRun>, String> {
+        let backends = self
+            .backends
+            .iter()
+            .map(|b| b.parse::<BackendType>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for &tile_size in &self.tile_sizes {
+            PipelineConfig {
+                tile_size,
+                ..Default::default()
+            }
+            .validate()?;
+        }
+
+        let mut runs = Vec::new();
+        for scene in &self.scenes {
+            for &backend_type in &backends {
+                for &[width, height] in &self.resolutions {
+                    let tile_sizes = match backend_type {
+                        BackendType::CustomPipeline => {
+                            self.tile_sizes.iter().map(|&t| Some(t)).collect()
+                        }
+                        BackendType::WgpuPipeline => vec![None],
+                    };
+                    for tile_size in tile_sizes {
+                        for sweep_effect in &self.effects {
+                            let effect = Effect::from_args(
+                                &sweep_effect.name,
+                                sweep_effect.param1,
+                                sweep_effect.param2,
+                                sweep_effect.param3,
+                                sweep_effect.param4,
+                            )?;
+
+                            let key = RunKey {
+                                scene: csv_field(&scene.model_path),
+                                backend: backend_type.to_string(),
+                                width,
+                                height,
+                                tile_size,
+                                effect: sweep_effect.label(),
+                            };
+
+                            let scene_config = SceneConfig {
+                                model_path: scene.model_path.clone(),
+                                camera_config: CameraConfig {
+                                    position: scene.camera_position,
+                                    mode: CameraMode::FirstPerson,
+                                    ..Default::default()
+                                },
+                                effect,
+                                benchmark_duration_secs: self.duration_secs,
+                                backend_type,
+                                pipeline_config: PipelineConfig {
+                                    tile_size: tile_size
+                                        .unwrap_or(PipelineConfig::default().tile_size),
+                                    ..Default::default()
+                                },
+                                resolution: Some([width, height]),
+                                ..Default::default()
+                            };
+
+                            runs.push(BenchmarkRun { key, scene_config });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(runs)
+    }
+}
+
+/// Results of a sweep, persisted as CSV so an interrupted sweep can pick up where it stopped.
+pub struct BenchmarkReport {
+    path: PathBuf,
+    completed: HashSet<RunKey>,
+}
+
+impl BenchmarkReport {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        let mut completed = HashSet::new();
+
+        if path.exists() {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read report '{}': {}", path.display(), e))?;
+            for line in text.lines().skip(1) {
+                let fields: Vec<&str> = line.split(',').collect();
+                if let Some(key) = RunKey::from_csv(&fields) {
+                    completed.insert(key);
+                }
+            }
+        }
+
+        Ok(Self { path, completed })
+    }
+
+    pub fn is_completed(&self, key: &RunKey) -> bool {
+        self.completed.contains(key)
+    }
+
+    pub fn record(&mut self, key: &RunKey, data: &PerformanceData) -> std::io::Result<()> {
+        let write_header = !self.path.exists();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        if write_header {
+            writeln!(file, "{}", REPORT_HEADER)?;
+        }
+
+        writeln!(
+            file,
+            "{},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2},{:.2}",
+            key.to_csv(),
+            data.avg_fps,
+            data.min_fps,
+            data.max_fps,
+            data.fps_5_percent_low,
+            data.fps_1_percent_low,
+            data.cpu_usage,
+            data.memory_usage as f64 / (1024.0 * 1024.0)
+        )?;
+
+        self.completed.insert(key.clone());
+        Ok(())
+    }
+
+    /// Prints every run recorded so far as a single aligned table.
+    pub fn print_table(&self) {
+        let Ok(text) = fs::read_to_string(&self.path) else {
+            println!("No benchmark results recorded in {}", self.path.display());
+            return;
+        };
+
+        let rows: Vec<Vec<&str>> = text.lines().map(|l| l.split(',').collect()).collect();
+        let columns = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|c| {
+                rows.iter()
+                    .map(|r| r.get(c).map_or(0, |f| f.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        println!("Benchmark report: {}", self.path.display());
+        for (i, row) in rows.iter().enumerate() {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(field, width)| format!("{:<width$}", field, width = width))
+                .collect();
+            println!("{}", line.join(" | "));
+            if i == 0 {
+                let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+                println!("{}", rule.join("-+-"));
+            }
+        }
+    }
+}
+
+/// Links the scenes a window cycles through to their sweep runs and the report they are recorded in.
+pub struct SweepState {
+    pub report: BenchmarkReport,
+    pub keys: Vec<RunKey>,
+}
+
+impl SweepState {
+    pub fn record(&mut self, scene_index: usize, data: &PerformanceData) {
+        if let Some(key) = self.keys.get(scene_index) {
+            if let Err(e) = self.report.record(key, data) {
+                eprintln!("Failed to record benchmark result: {}", e);
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashSet;
 
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use winit::keyboard::KeyCode;
 
 #[repr(C)]
@@ -8,12 +8,32 @@ use winit::keyboard::KeyCode;
 pub struct CameraUniform {
     pub view_position: [f32; 4],
     pub view_proj: [[f32; 4]; 4],
+    /// The previous frame's view_proj, without jitter, for the custom pipeline's motion
+    /// vectors. The same as view_proj unless TAA sets it.
+    pub prev_view_proj: [[f32; 4]; 4],
+    /// Sub-pixel offset of view_proj in NDC (xy), applied by TAA. Zero otherwise.
+    pub jitter: [f32; 4],
+    /// Inverse of view_proj, to rebuild world positions from depth.
+    pub inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
     pub fn update_view_proj(&mut self, camera: &Camera) {
         self.view_position = [camera.eye.x, camera.eye.y, camera.eye.z, 1.0];
-        self.view_proj = camera.build_view_projection_matrix().to_cols_array_2d();
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.to_cols_array_2d();
+        self.prev_view_proj = self.view_proj;
+        self.inv_view_proj = view_proj.inverse().to_cols_array_2d();
+    }
+
+    /// Offsets view_proj by `jitter` in NDC, so each frame samples a different point of
+    /// every pixel.
+    pub fn apply_jitter(&mut self, jitter: Vec2) {
+        let offset = Mat4::from_translation(jitter.extend(0.0));
+        let view_proj = offset * Mat4::from_cols_array_2d(&self.view_proj);
+        self.view_proj = view_proj.to_cols_array_2d();
+        self.inv_view_proj = view_proj.inverse().to_cols_array_2d();
+        self.jitter = [jitter.x, jitter.y, 0.0, 0.0];
     }
 }
 
@@ -22,6 +42,9 @@ impl Default for CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            prev_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            jitter: [0.0; 4],
+            inv_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
         }
     }
 }
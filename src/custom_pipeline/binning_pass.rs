@@ -1,20 +1,25 @@
 use wgpu::PipelineCompilationOptions;
 
-use super::{util::create_buffer_bind_group_layout_entry, GpuBuffers};
+use super::{config::PipelineConfig, util::create_buffer_bind_group_layout_entry, GpuBuffers};
 
 pub struct BinningPass {
     pub pipeline_count: wgpu::ComputePipeline,
-    pub pipeline_scan_first: wgpu::ComputePipeline,
-    pub pipeline_scan_second: wgpu::ComputePipeline,
-    pub pipeline_store: wgpu::ComputePipeline,
+    pub pipeline_scan_coarse: wgpu::ComputePipeline,
+    pub pipeline_store_coarse: wgpu::ComputePipeline,
+    pub pipeline_count_fine: wgpu::ComputePipeline,
+    pub pipeline_scan_blocks: wgpu::ComputePipeline,
+    pub pipeline_scan_block_sums: wgpu::ComputePipeline,
+    pub pipeline_add_block_offsets: wgpu::ComputePipeline,
+    pub pipeline_store_fine: wgpu::ComputePipeline,
     pub bind_group_0: wgpu::BindGroup,
     pub bind_group_1: wgpu::BindGroup,
     pub bind_group_2: wgpu::BindGroup,
     pub bind_group_3: wgpu::BindGroup,
+    config: PipelineConfig,
 }
 
 impl BinningPass {
-    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers) -> Self {
+    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers, config: &PipelineConfig) -> Self {
         // 1) Create bind group layouts
         let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Binning Pass: Group0 Layout"),
@@ -52,61 +57,32 @@ impl BinningPass {
         let group2_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("BinningPass::Group2"),
             entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                create_buffer_bind_group_layout_entry(2, false),
-                wgpu::BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
+                create_buffer_bind_group_layout_entry(0, true),
+                create_buffer_bind_group_layout_entry(1, true),
             ],
         });
 
         let group3_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("BinningPass::Group3"),
-            entries: &[create_buffer_bind_group_layout_entry(0, false)],
+            entries: &[
+                create_buffer_bind_group_layout_entry(0, false),
+                create_buffer_bind_group_layout_entry(1, false),
+                create_buffer_bind_group_layout_entry(2, false),
+                create_buffer_bind_group_layout_entry(3, false),
+                create_buffer_bind_group_layout_entry(4, false),
+            ],
         });
 
-        let count_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Count Pipeline layout"),
-                bind_group_layouts: &[&group0_layout, &group1_layout, &group2_layout],
-                push_constant_ranges: &[],
-            });
-
         let scan_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Scan Pipeline layout"),
             bind_group_layouts: &[&group0_layout, &group1_layout],
             push_constant_ranges: &[],
         });
 
-        let store_pipeline_layout =
+        // Every binning stage apart from the fine tile scan touches the tile lists in group 3.
+        let binning_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Store Pipeline layout"),
+                label: Some("Binning Pipeline layout"),
                 bind_group_layouts: &[
                     &group0_layout,
                     &group1_layout,
@@ -116,45 +92,90 @@ impl BinningPass {
                 push_constant_ranges: &[],
             });
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/binning.wgsl"));
+        let shader = config.create_shader_module(
+            device,
+            "Binning Shader",
+            include_str!("shaders/binning.wgsl"),
+        );
 
         let pipeline_count = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Count Triangles"),
-            layout: Some(&count_pipeline_layout),
+            layout: Some(&binning_pipeline_layout),
             module: &shader,
             entry_point: Some("count_triangles"),
             cache: None,
             compilation_options: PipelineCompilationOptions::default(),
         });
 
-        let pipeline_scan_first =
+        let pipeline_scan_coarse =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Scan Coarse Tiles"),
+                layout: Some(&binning_pipeline_layout),
+                module: &shader,
+                entry_point: Some("scan_coarse"),
+                cache: None,
+                compilation_options: PipelineCompilationOptions::default(),
+            });
+
+        let pipeline_store_coarse =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Store Coarse Triangles"),
+                layout: Some(&binning_pipeline_layout),
+                module: &shader,
+                entry_point: Some("store_coarse"),
+                cache: None,
+                compilation_options: PipelineCompilationOptions::default(),
+            });
+
+        let pipeline_count_fine =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Count Fine Triangles"),
+                layout: Some(&binning_pipeline_layout),
+                module: &shader,
+                entry_point: Some("count_fine"),
+                cache: None,
+                compilation_options: PipelineCompilationOptions::default(),
+            });
+
+        let pipeline_scan_blocks =
             device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("Scan First Pass"),
+                label: Some("Scan Tile Blocks"),
                 layout: Some(&scan_pipeline_layout),
                 module: &shader,
-                entry_point: Some("scan_first_pass"),
+                entry_point: Some("scan_tile_blocks"),
                 cache: None,
                 compilation_options: PipelineCompilationOptions::default(),
             });
 
-        let pipeline_scan_second =
+        let pipeline_scan_block_sums =
             device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some("Scan Second Pass"),
+                label: Some("Scan Block Sums"),
                 layout: Some(&scan_pipeline_layout),
                 module: &shader,
-                entry_point: Some("scan_second_pass"),
+                entry_point: Some("scan_block_sums"),
                 cache: None,
                 compilation_options: PipelineCompilationOptions::default(),
             });
 
-        let pipeline_store = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Store Triangles"),
-            layout: Some(&store_pipeline_layout),
-            module: &shader,
-            entry_point: Some("store_triangles"),
-            cache: None,
-            compilation_options: PipelineCompilationOptions::default(),
-        });
+        let pipeline_add_block_offsets =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Add Block Offsets"),
+                layout: Some(&scan_pipeline_layout),
+                module: &shader,
+                entry_point: Some("add_block_offsets"),
+                cache: None,
+                compilation_options: PipelineCompilationOptions::default(),
+            });
+
+        let pipeline_store_fine =
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("Store Fine Triangles"),
+                layout: Some(&binning_pipeline_layout),
+                module: &shader,
+                entry_point: Some("store_fine"),
+                cache: None,
+                compilation_options: PipelineCompilationOptions::default(),
+            });
 
         let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Binning Pass: Group0"),
@@ -198,37 +219,52 @@ impl BinningPass {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: buffers.vertex_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
                     resource: buffers.projected_buffer.as_entire_binding(),
                 },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: buffers.camera_buffer.as_entire_binding(),
-                },
             ],
         });
 
         let bind_group_3 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("BinningPass::BG3"),
             layout: &group3_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffers.triangle_list_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.triangle_list_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.coarse_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.coarse_list_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.big_triangle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.stats_buffer.as_entire_binding(),
+                },
+            ],
         });
 
         Self {
             pipeline_count,
-            pipeline_scan_first,
-            pipeline_scan_second,
-            pipeline_store,
+            pipeline_scan_coarse,
+            pipeline_store_coarse,
+            pipeline_count_fine,
+            pipeline_scan_blocks,
+            pipeline_scan_block_sums,
+            pipeline_add_block_offsets,
+            pipeline_store_fine,
             bind_group_0,
             bind_group_1,
             bind_group_2,
             bind_group_3,
+            config: *config,
         }
     }
 
@@ -237,8 +273,13 @@ impl BinningPass {
         encoder: &mut wgpu::CommandEncoder,
         gx_tris: u32,
         gy_tris: u32,
-        total_tile_dispatch: u32,
+        width: u32,
+        height: u32,
     ) {
+        let (num_tiles_x, num_tiles_y) = self.config.num_tiles(width, height);
+        let total_tile_dispatch = self.config.dispatch_size(num_tiles_x * num_tiles_y);
+        let (num_coarse_x, num_coarse_y) = self.config.num_coarse_tiles(width, height);
+
         let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Binning::count_triangles"),
             timestamp_writes: None,
@@ -248,16 +289,137 @@ impl BinningPass {
         pass.set_bind_group(2, &self.bind_group_2, &[]);
         pass.set_bind_group(3, &self.bind_group_3, &[]);
 
+        // Coarse level: bin every triangle into the coarse tiles it overlaps.
         pass.set_pipeline(&self.pipeline_count);
         pass.dispatch_workgroups(gx_tris, gy_tris, 1);
 
-        pass.set_pipeline(&self.pipeline_scan_first);
+        pass.set_pipeline(&self.pipeline_scan_coarse);
+        pass.dispatch_workgroups(1, 1, 1);
+
+        pass.set_pipeline(&self.pipeline_store_coarse);
+        pass.dispatch_workgroups(gx_tris, gy_tris, 1);
+
+        // Fine level: each coarse tile bins its own triangles into its fine tiles.
+        pass.set_pipeline(&self.pipeline_count_fine);
+        pass.dispatch_workgroups(num_coarse_x, num_coarse_y, 1);
+
+        self.scan_tiles(&mut pass, total_tile_dispatch);
+
+        pass.set_pipeline(&self.pipeline_store_fine);
+        pass.dispatch_workgroups(num_coarse_x, num_coarse_y, 1);
+    }
+
+    /// Turns the per-tile triangle counts into offsets into the triangle list.
+    fn scan_tiles(&self, pass: &mut wgpu::ComputePass, total_tile_dispatch: u32) {
+        pass.set_bind_group(0, &self.bind_group_0, &[]);
+        pass.set_bind_group(1, &self.bind_group_1, &[]);
+
+        pass.set_pipeline(&self.pipeline_scan_blocks);
         pass.dispatch_workgroups(total_tile_dispatch, 1, 1);
 
-        pass.set_pipeline(&self.pipeline_scan_second);
+        pass.set_pipeline(&self.pipeline_scan_block_sums);
+        pass.dispatch_workgroups(1, 1, 1);
+
+        pass.set_pipeline(&self.pipeline_add_block_offsets);
         pass.dispatch_workgroups(total_tile_dispatch, 1, 1);
+    }
+}
 
-        pass.set_pipeline(&self.pipeline_store);
-        pass.dispatch_workgroups(gx_tris, gy_tris, 1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::test_util::{quad_model, read_buffer, test_device};
+    use crate::scene::{LightConfig, Scene};
+    use crate::vertex::CustomVertex;
+
+    /// A square over the whole screen. The scan test writes the tile counts itself, so the
+    /// scene only sizes the buffers.
+    fn screen_quad_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_model(quad_model(1.0, |x, y| CustomVertex {
+            position: [x, y, 0.0],
+            ..Default::default()
+        }));
+        scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+        scene
+    }
+
+    /// Xorshift generator, so the test needs no extra dependencies.
+    struct Rng(u32);
+
+    impl Rng {
+        fn next(&mut self) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0
+        }
+    }
+
+    #[test]
+    fn tile_scan_matches_sequential_scan() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping tile scan test");
+            return;
+        };
+        let scene = screen_quad_scene();
+        let mut rng = Rng(0x9e37_79b9);
+
+        let default_config = PipelineConfig::default();
+        let small_workgroups = PipelineConfig {
+            workgroup_size: 64,
+            ..Default::default()
+        };
+
+        // Tile counts around a block boundary, and screens with more blocks than
+        // a single workgroup scans in one go.
+        let cases = [
+            (default_config, 8, 8),
+            (default_config, 2048, 8),
+            (default_config, 2056, 8),
+            (default_config, 640, 480),
+            (small_workgroups, 640, 480),
+            (small_workgroups, 1024, 768),
+        ];
+
+        for (config, width, height) in cases {
+            let buffers = GpuBuffers::new(&device, width, height, &scene, &config);
+            let binning_pass = BinningPass::new(&device, &buffers, &config);
+
+            let (num_tiles_x, num_tiles_y) = config.num_tiles(width, height);
+            let num_tiles = num_tiles_x * num_tiles_y;
+            let counts: Vec<u32> = (0..num_tiles).map(|_| rng.next() % 64).collect();
+            let tiles: Vec<[u32; 3]> = counts.iter().map(|&count| [count, 0, 0]).collect();
+            queue.write_buffer(&buffers.tile_buffer, 0, bytemuck::cast_slice(&tiles));
+
+            let mut encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+                binning_pass.scan_tiles(&mut pass, config.dispatch_size(num_tiles));
+            }
+            queue.submit(Some(encoder.finish()));
+
+            let offsets: Vec<u32> = read_buffer(&device, &queue, &buffers.tile_buffer)
+                .chunks_exact(3)
+                .take(num_tiles as usize)
+                .map(|tile| tile[1])
+                .collect();
+
+            let expected: Vec<u32> = counts
+                .iter()
+                .scan(0, |sum, &count| {
+                    let offset = *sum;
+                    *sum += count;
+                    Some(offset)
+                })
+                .collect();
+
+            assert_eq!(
+                offsets, expected,
+                "{}x{} with workgroup size {}",
+                width, height, config.workgroup_size
+            );
+        }
     }
 }
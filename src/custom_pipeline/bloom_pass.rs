@@ -0,0 +1,185 @@
+use super::{
+    config::PipelineConfig,
+    post_chain::{create_linear_sampler, dispatch_image},
+    present_pass::HDR_FORMAT,
+    util::{
+        create_storage_texture_bind_group_layout_entry, create_texture_bind_group_layout_entry,
+    },
+};
+
+/// Mips of the bloom chain, each half the size of the one before, the first at half the
+/// resolution of the output.
+const MAX_BLOOM_MIPS: u32 = 6;
+
+/// Adds bloom to the output. Its bright parts are downsampled into a chain of mips, and the
+/// chain is upsampled back with each mip added on the way, so the glow is as wide as the
+/// smallest mip and as sharp as the largest.
+pub struct BloomPass {
+    prefilter_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    upsample_pipeline: wgpu::ComputePipeline,
+    composite_pipeline: wgpu::ComputePipeline,
+    /// One per dispatch, in the order they are recorded, with the size of the mip written.
+    steps: Vec<(wgpu::BindGroup, u32, u32)>,
+}
+
+impl BloomPass {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+    ) -> Self {
+        let mips = MAX_BLOOM_MIPS
+            .min((size.width.min(size.height) / 2).max(1).ilog2() + 1)
+            .max(1);
+        let chain_texture = |label, mip_level_count| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: (size.width / 2).max(1),
+                    height: (size.height / 2).max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let mip_views = |texture: &wgpu::Texture| {
+            (0..texture.mip_level_count())
+                .map(|level| {
+                    texture.create_view(&wgpu::TextureViewDescriptor {
+                        base_mip_level: level,
+                        mip_level_count: Some(1),
+                        ..Default::default()
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let down_texture = chain_texture("Bloom Downsample Texture", mips);
+        let down = mip_views(&down_texture);
+        // The smallest mip is only read, so the upsampled chain is one shorter.
+        let up_texture = chain_texture("Bloom Upsample Texture", (mips - 1).max(1));
+        let up = mip_views(&up_texture);
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Pass: Group0 Layout"),
+            entries: &[
+                create_texture_bind_group_layout_entry(0, true, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                create_storage_texture_bind_group_layout_entry(2, HDR_FORMAT),
+                create_texture_bind_group_layout_entry(3, false, wgpu::TextureViewDimension::D2),
+            ],
+        });
+        let sampler = create_linear_sampler(device);
+        // Steps without a base are given the input, which they don't read.
+        let step = |source: &wgpu::TextureView, target: &wgpu::TextureView, base, level| {
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Bloom Pass: Group0"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(target),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(base),
+                    },
+                ],
+            });
+            match level {
+                Some(level) => {
+                    let mip = down_texture
+                        .size()
+                        .mip_level_size(level, wgpu::TextureDimension::D2);
+                    (bind_group, mip.width, mip.height)
+                }
+                None => (bind_group, size.width, size.height),
+            }
+        };
+
+        let mut steps = vec![step(input, &down[0], input, Some(0))];
+        for level in 1..mips as usize {
+            steps.push(step(
+                &down[level - 1],
+                &down[level],
+                input,
+                Some(level as u32),
+            ));
+        }
+        for level in (0..mips as usize - 1).rev() {
+            let source = if level + 2 == mips as usize {
+                &down[level + 1]
+            } else {
+                &up[level + 1]
+            };
+            steps.push(step(source, &up[level], &down[level], Some(level as u32)));
+        }
+        let composite_source = if mips > 1 { &up[0] } else { &down[0] };
+        steps.push(step(composite_source, output, input, None));
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader =
+            config.create_shader_module(device, "Bloom Shader", include_str!("shaders/bloom.wgsl"));
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            prefilter_pipeline: pipeline("Bloom Prefilter Pipeline", "prefilter_main"),
+            downsample_pipeline: pipeline("Bloom Downsample Pipeline", "downsample_main"),
+            upsample_pipeline: pipeline("Bloom Upsample Pipeline", "upsample_main"),
+            composite_pipeline: pipeline("Bloom Composite Pipeline", "composite_main"),
+            steps,
+        }
+    }
+
+    pub fn record(&self, pass: &mut wgpu::ComputePass) {
+        // Prefiltering and downsampling write each mip, as upsampling and compositing do.
+        let mips = self.steps.len() / 2;
+        for (index, (bind_group, width, height)) in self.steps.iter().enumerate() {
+            let pipeline = if index == 0 {
+                &self.prefilter_pipeline
+            } else if index < mips {
+                &self.downsample_pipeline
+            } else if index + 1 < self.steps.len() {
+                &self.upsample_pipeline
+            } else {
+                &self.composite_pipeline
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            dispatch_image(pass, *width, *height);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Reads the custom pipeline's intermediate buffers back to the CPU after the raster pass
+//! of a frame, decoded into the structs they hold, for debugging and tests.
+
+use std::{fs::File, io::BufWriter, path::Path};
+
+use super::{
+    config::PipelineConfig,
+    util::{Fragment, ProjectedVertex, TileTriangles, TriangleMeta},
+    GpuBuffers,
+};
+
+/// Intermediate buffers of the custom pipeline that can be captured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureBuffer {
+    /// Each tile's slice of the triangle list.
+    Tiles,
+    /// Base index of every binned triangle, in the slices of the tiles. Entries past the
+    /// end of the last slice are left over from earlier frames or zero.
+    TriangleList,
+    /// Screen bounds and tile range of every triangle.
+    TriangleMeta,
+    /// Every vertex after the vertex pass.
+    Projected,
+    /// The fragment of every pixel. Only written without MSAA or a visibility buffer, and a
+    /// single placeholder otherwise.
+    Fragments,
+}
+
+impl CaptureBuffer {
+    pub const ALL: [CaptureBuffer; 5] = [
+        CaptureBuffer::Tiles,
+        CaptureBuffer::TriangleList,
+        CaptureBuffer::TriangleMeta,
+        CaptureBuffer::Projected,
+        CaptureBuffer::Fragments,
+    ];
+
+    /// Name of the buffer in [`GpuBuffers`], which its dump files are named after.
+    pub fn name(self) -> &'static str {
+        match self {
+            CaptureBuffer::Tiles => "tile_buffer",
+            CaptureBuffer::TriangleList => "triangle_list_buffer",
+            CaptureBuffer::TriangleMeta => "triangle_meta_buffer",
+            CaptureBuffer::Projected => "projected_buffer",
+            CaptureBuffer::Fragments => "fragment_buffer",
+        }
+    }
+
+    fn source(self, buffers: &GpuBuffers) -> &wgpu::Buffer {
+        match self {
+            CaptureBuffer::Tiles => &buffers.tile_buffer,
+            CaptureBuffer::TriangleList => &buffers.triangle_list_buffer,
+            CaptureBuffer::TriangleMeta => &buffers.triangle_meta_buffer,
+            CaptureBuffer::Projected => &buffers.projected_buffer,
+            CaptureBuffer::Fragments => &buffers.fragment_buffer,
+        }
+    }
+}
+
+/// Staging copies of the captured buffers, recorded into a frame's commands and read back
+/// once they have been submitted.
+pub struct PendingCapture {
+    width: u32,
+    height: u32,
+    config: PipelineConfig,
+    staging: Vec<(CaptureBuffer, wgpu::Buffer)>,
+}
+
+impl PendingCapture {
+    /// Copies `captured` into staging buffers. Recorded after the raster pass, as the
+    /// fragment pass clears the fragments once it has shaded them.
+    pub fn record(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        buffers: &GpuBuffers,
+        config: &PipelineConfig,
+        width: u32,
+        height: u32,
+        captured: &[CaptureBuffer],
+    ) -> Self {
+        let staging = captured
+            .iter()
+            .map(|&buffer| {
+                let source = buffer.source(buffers);
+                let staging = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("Capture Staging Buffer"),
+                    size: source.size(),
+                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                encoder.copy_buffer_to_buffer(source, 0, &staging, 0, source.size());
+                (buffer, staging)
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            config: *config,
+            staging,
+        }
+    }
+
+    /// Maps the staging buffers and decodes them. Blocks until the GPU is idle.
+    pub fn read(self, device: &wgpu::Device) -> FrameCapture {
+        for (_, staging) in &self.staging {
+            staging.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        }
+        device.poll(wgpu::Maintain::Wait);
+
+        let (num_tiles_x, num_tiles_y) = self.config.num_tiles(self.width, self.height);
+        let mut capture = FrameCapture {
+            width: self.width,
+            height: self.height,
+            tile_size: self.config.tile_size,
+            num_tiles_x,
+            num_tiles_y,
+            ..Default::default()
+        };
+        for (buffer, staging) in &self.staging {
+            let bytes = staging.slice(..).get_mapped_range();
+            match buffer {
+                // The tile buffer has room for a fourth word per tile.
+                CaptureBuffer::Tiles => {
+                    let len = (num_tiles_x * num_tiles_y) as usize;
+                    capture.tiles = Some(decode(&bytes[..len * size_of::<TileTriangles>()]));
+                }
+                CaptureBuffer::TriangleList => capture.triangle_list = Some(decode(&bytes)),
+                CaptureBuffer::TriangleMeta => capture.triangle_meta = Some(decode(&bytes)),
+                CaptureBuffer::Projected => capture.projected = Some(decode(&bytes)),
+                CaptureBuffer::Fragments => capture.fragments = Some(decode(&bytes)),
+            }
+            drop(bytes);
+            staging.unmap();
+        }
+        capture
+    }
+}
+
+/// The whole structs in `bytes`, which needn't be aligned for them.
+fn decode<T: bytemuck::Pod>(bytes: &[u8]) -> Vec<T> {
+    let mut decoded = vec![T::zeroed(); bytes.len() / size_of::<T>()];
+    let len = decoded.len() * size_of::<T>();
+    bytemuck::cast_slice_mut(&mut decoded).copy_from_slice(&bytes[..len]);
+    decoded
+}
+
+/// The intermediate buffers of a frame, with those that weren't captured left out.
+#[derive(Debug, Default)]
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    pub num_tiles_x: u32,
+    pub num_tiles_y: u32,
+    pub tiles: Option<Vec<TileTriangles>>,
+    pub triangle_list: Option<Vec<u32>>,
+    pub triangle_meta: Option<Vec<TriangleMeta>>,
+    pub projected: Option<Vec<ProjectedVertex>>,
+    pub fragments: Option<Vec<Fragment>>,
+}
+
+/// What `frame.json` records of a dumped frame.
+#[derive(serde::Serialize)]
+struct FrameInfo<'a> {
+    width: u32,
+    height: u32,
+    tile_size: u32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
+    buffers: Vec<&'a str>,
+}
+
+impl FrameCapture {
+    /// Writes each captured buffer to `dir`, creating it if needed, as `<name>.bin` with its
+    /// structs laid out as on the GPU, and as `<name>.json` decoded. `frame.json` records
+    /// the frame's size and tiling and the buffers captured.
+    pub fn write_to(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        let mut buffers = Vec::new();
+        if let Some(tiles) = &self.tiles {
+            write_buffer(dir, CaptureBuffer::Tiles, tiles)?;
+            buffers.push(CaptureBuffer::Tiles.name());
+        }
+        if let Some(triangle_list) = &self.triangle_list {
+            write_buffer(dir, CaptureBuffer::TriangleList, triangle_list)?;
+            buffers.push(CaptureBuffer::TriangleList.name());
+        }
+        if let Some(triangle_meta) = &self.triangle_meta {
+            write_buffer(dir, CaptureBuffer::TriangleMeta, triangle_meta)?;
+            buffers.push(CaptureBuffer::TriangleMeta.name());
+        }
+        if let Some(projected) = &self.projected {
+            write_buffer(dir, CaptureBuffer::Projected, projected)?;
+            buffers.push(CaptureBuffer::Projected.name());
+        }
+        if let Some(fragments) = &self.fragments {
+            write_buffer(dir, CaptureBuffer::Fragments, fragments)?;
+            buffers.push(CaptureBuffer::Fragments.name());
+        }
+
+        let info = FrameInfo {
+            width: self.width,
+            height: self.height,
+            tile_size: self.tile_size,
+            num_tiles_x: self.num_tiles_x,
+            num_tiles_y: self.num_tiles_y,
+            buffers,
+        };
+        let file = BufWriter::new(File::create(dir.join("frame.json"))?);
+        serde_json::to_writer_pretty(file, &info)?;
+        Ok(())
+    }
+}
+
+fn write_buffer<T: bytemuck::Pod + serde::Serialize>(
+    dir: &Path,
+    buffer: CaptureBuffer,
+    data: &[T],
+) -> std::io::Result<()> {
+    std::fs::write(
+        dir.join(format!("{}.bin", buffer.name())),
+        bytemuck::cast_slice(data),
+    )?;
+    let file = BufWriter::new(File::create(dir.join(format!("{}.json", buffer.name())))?);
+    serde_json::to_writer(file, data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::binning_pass::BinningPass;
+    use crate::custom_pipeline::test_util::{split_quad_scene, test_device};
+    use crate::custom_pipeline::{RasterPass, VertexPass};
+
+    const SIZE: u32 = 32;
+
+    #[test]
+    fn captures_decode_the_raster_inputs_and_output() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping capture test");
+            return;
+        };
+        let scene = split_quad_scene(SIZE);
+        let config = PipelineConfig::default();
+        let buffers = GpuBuffers::new(&device, SIZE, SIZE, &scene, &config);
+        let vertex_pass = VertexPass::new(&device, &buffers, &config);
+        let binning_pass = BinningPass::new(&device, &buffers, &config);
+        let raster_pass = RasterPass::new(&device, &buffers, &config);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        vertex_pass.execute(&mut encoder, buffers.vertex_count);
+        binning_pass.execute(&mut encoder, scene.gx_tris, scene.gy_tris, SIZE, SIZE);
+        raster_pass.execute(&mut encoder, SIZE, SIZE);
+        let pending = PendingCapture::record(
+            &device,
+            &mut encoder,
+            &buffers,
+            &config,
+            SIZE,
+            SIZE,
+            &CaptureBuffer::ALL,
+        );
+        queue.submit(Some(encoder.finish()));
+        let capture = pending.read(&device);
+
+        // The corners land on pixel centres, counter-clockwise from the bottom left.
+        let projected = capture.projected.unwrap();
+        let corners: Vec<[f32; 2]> = projected
+            .iter()
+            .map(|vertex| [vertex.screen_pos[0], vertex.screen_pos[1]])
+            .collect();
+        assert_eq!(
+            corners,
+            [[2.5, 29.5], [29.5, 29.5], [29.5, 2.5], [2.5, 2.5]]
+        );
+
+        // Both triangles' bounds overlap every tile, and each tile lists only them.
+        let meta = capture.triangle_meta.unwrap();
+        assert_eq!(meta.len(), 2);
+        for triangle in &meta {
+            assert_eq!(triangle.start_tile, [0, 0]);
+            assert_eq!(triangle.tile_range, [4, 4]);
+        }
+        let tiles = capture.tiles.unwrap();
+        let triangle_list = capture.triangle_list.unwrap();
+        assert_eq!(tiles.len(), 16);
+        for tile in &tiles {
+            let start = tile.offset as usize;
+            let mut listed = triangle_list[start..start + tile.count as usize].to_vec();
+            listed.sort();
+            assert!(listed == [0] || listed == [3] || listed == [0, 3]);
+        }
+
+        let fragments = capture.fragments.unwrap();
+        let covered = fragments
+            .iter()
+            .filter(|fragment| fragment.flag != 0)
+            .count();
+        assert_eq!(covered, 27 * 27);
+    }
+}
@@ -0,0 +1,624 @@
+use crate::scene::Scene;
+
+/// Runtime parameters of the custom pipeline.
+///
+/// These values are baked into every custom pipeline shader as WGSL constants
+/// (see [`PipelineConfig::shader_header`]), so changing them requires the passes
+/// to be recreated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PipelineConfig {
+    /// Width and height of a screen tile in pixels.
+    pub tile_size: u32,
+    /// Threads per workgroup for the one dimensional passes (prefix scan, fragment shading).
+    /// Must be a power of two because the scan is a Blelloch tree.
+    pub workgroup_size: u32,
+    /// Threads cooperating on a single triangle while binning it into tiles.
+    pub binning_threads: u32,
+    /// Rasterise into a visibility buffer of packed depth and triangle ID, and rebuild
+    /// attributes in the fragment pass, instead of writing full fragments while rasterising.
+    pub visibility_buffer: bool,
+    /// Coverage and depth samples per pixel: 1 (no anti-aliasing), 2, 4 or 8.
+    pub msaa_samples: u32,
+    /// Write a G-buffer of each pixel's opaque surface in the fragment pass and light it in a
+    /// separate pass, with only the lights a light culling pass found reaching each tile,
+    /// instead of shading every fragment with every light.
+    pub deferred: bool,
+    /// List the lights reaching each screen tile in a compute pass before shading, from the
+    /// lights' ranges and the depth bounds of the tile's opaque surfaces, and light opaque
+    /// surfaces with only those, in both backends. Deferred mode always culls lights.
+    pub light_culling: bool,
+    /// Render shadow maps for each of the first [`Self::MAX_SHADOWED_LIGHTS`] lights every
+    /// frame, and shade with them.
+    pub shadows: bool,
+    /// Width and height of each shadow map (a cube face, or a directional light's single
+    /// map) in texels.
+    pub shadow_map_size: u32,
+    /// Only resolve the nearest opaque depth, leaving transparent triangles out entirely.
+    /// Set for the passes that render shadow maps rather than an image.
+    pub depth_only: bool,
+    /// How the fragment pass lights surfaces.
+    pub shading: ShadingModel,
+    /// Operator mapping the HDR image into display range when presenting it.
+    pub tone_mapping: ToneMapping,
+    /// Exposure in stops: the whole exposure when set manually, and a compensation on top
+    /// of the metered one with auto exposure.
+    pub exposure: f32,
+    /// Meter the exposure every frame from a luminance histogram of the image, easing
+    /// towards it over time.
+    pub auto_exposure: bool,
+    /// Compute post-processing passes run on the custom pipeline's output when it starts.
+    /// Each can be toggled while it runs.
+    pub post_effects: PostEffects,
+    /// What the custom pipeline's fragment pass shows: the shaded image, or a view of the
+    /// pipeline's internals.
+    pub debug_view: DebugView,
+}
+
+/// Lighting model of the fragment stages of both backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingModel {
+    /// Lambertian diffuse from white light with a Phong highlight of the material's `Ks`
+    /// and `Ns`.
+    #[default]
+    Phong,
+    /// Metallic-roughness microfacet shading: a GGX distribution with the Smith geometry
+    /// term and Schlick's Fresnel approximation.
+    Pbr,
+}
+
+/// Tone mapping operator of the present passes of both backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+    /// Clamps each channel to one, as before the image was HDR.
+    None = 0,
+    /// Maps each channel x to x / (1 + x).
+    Reinhard = 1,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms.
+    #[default]
+    Aces = 2,
+    /// Troy Sobotka's AgX, with the polynomial fit of its default contrast curve.
+    Agx = 3,
+}
+
+impl std::str::FromStr for ToneMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(ToneMapping::None),
+            "reinhard" => Ok(ToneMapping::Reinhard),
+            "aces" => Ok(ToneMapping::Aces),
+            "agx" => Ok(ToneMapping::Agx),
+            other => Err(format!(
+                "Invalid tone mapping '{}'. Use 'none', 'reinhard', 'aces' or 'agx'.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToneMapping::None => write!(f, "no tone mapping"),
+            ToneMapping::Reinhard => write!(f, "Reinhard"),
+            ToneMapping::Aces => write!(f, "ACES"),
+            ToneMapping::Agx => write!(f, "AgX"),
+        }
+    }
+}
+
+/// Views of the custom pipeline's internals, each shown by an alternate fragment pass entry
+/// point in place of shading. The present pass shows their colours as they are, without
+/// exposure or tone mapping, and light culling and post-processing are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DebugView {
+    /// The shaded image.
+    #[default]
+    None = 0,
+    /// A heatmap of the triangles binned into each tile, from the tile's count.
+    TileTriangles = 1,
+    /// A heatmap of the depth tests in each pixel, opaque and transparent, per sample.
+    Overdraw = 2,
+    /// Distance of the opaque surface from the camera, linearly from white to black.
+    Depth = 3,
+    /// World space normal of the opaque surface.
+    Normals = 4,
+    /// Texture coordinates of the opaque surface, wrapped to the unit square.
+    Uvs = 5,
+    /// A colour hashed from the ID of the opaque surface's triangle.
+    TriangleIds = 6,
+    /// The shaded opaque surface with the edges of its triangle drawn over it.
+    Wireframe = 7,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 8] = [
+        DebugView::None,
+        DebugView::TileTriangles,
+        DebugView::Overdraw,
+        DebugView::Depth,
+        DebugView::Normals,
+        DebugView::Uvs,
+        DebugView::TriangleIds,
+        DebugView::Wireframe,
+    ];
+
+    /// The view after this one, back to the shaded image after the last.
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+}
+
+impl std::str::FromStr for DebugView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DebugView::None),
+            "tiles" => Ok(DebugView::TileTriangles),
+            "overdraw" => Ok(DebugView::Overdraw),
+            "depth" => Ok(DebugView::Depth),
+            "normals" => Ok(DebugView::Normals),
+            "uvs" => Ok(DebugView::Uvs),
+            "triangles" => Ok(DebugView::TriangleIds),
+            "wireframe" => Ok(DebugView::Wireframe),
+            other => Err(format!(
+                "Invalid debug view '{}'. Use 'none', 'tiles', 'overdraw', 'depth', 'normals', 'uvs', 'triangles' or 'wireframe'.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for DebugView {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DebugView::None => write!(f, "shaded"),
+            DebugView::TileTriangles => write!(f, "tile triangle count"),
+            DebugView::Overdraw => write!(f, "overdraw"),
+            DebugView::Depth => write!(f, "linear depth"),
+            DebugView::Normals => write!(f, "normals"),
+            DebugView::Uvs => write!(f, "UVs"),
+            DebugView::TriangleIds => write!(f, "triangle IDs"),
+            DebugView::Wireframe => write!(f, "wireframe"),
+        }
+    }
+}
+
+/// Compute post-processing passes of the custom pipeline, run in this order on its HDR
+/// output before it is metered and presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffect {
+    /// Screen space ambient occlusion from the depth and normal of each pixel.
+    Ssao,
+    /// Temporal anti-aliasing, jittering the camera and accumulating frames.
+    Taa,
+    /// Bright areas bleeding into their surroundings, from a chain of downsampled mips.
+    Bloom,
+    /// Fast approximate anti-aliasing of luma edges.
+    Fxaa,
+    /// Colour grading through a 3D lookup table.
+    ColorGrading,
+    /// Darkening towards the corners of the image.
+    Vignette,
+}
+
+impl PostEffect {
+    pub const ALL: [PostEffect; 6] = [
+        PostEffect::Ssao,
+        PostEffect::Taa,
+        PostEffect::Bloom,
+        PostEffect::Fxaa,
+        PostEffect::ColorGrading,
+        PostEffect::Vignette,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl std::str::FromStr for PostEffect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssao" => Ok(PostEffect::Ssao),
+            "taa" => Ok(PostEffect::Taa),
+            "bloom" => Ok(PostEffect::Bloom),
+            "fxaa" => Ok(PostEffect::Fxaa),
+            "lut" => Ok(PostEffect::ColorGrading),
+            "vignette" => Ok(PostEffect::Vignette),
+            other => Err(format!(
+                "Invalid post effect '{}'. Use 'ssao', 'taa', 'bloom', 'fxaa', 'lut' or 'vignette'.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for PostEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostEffect::Ssao => write!(f, "SSAO"),
+            PostEffect::Taa => write!(f, "TAA"),
+            PostEffect::Bloom => write!(f, "bloom"),
+            PostEffect::Fxaa => write!(f, "FXAA"),
+            PostEffect::ColorGrading => write!(f, "colour grading"),
+            PostEffect::Vignette => write!(f, "vignette"),
+        }
+    }
+}
+
+/// A set of post effects, parsed from a comma separated list of their names or "none".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PostEffects(u32);
+
+impl PostEffects {
+    pub fn contains(&self, effect: PostEffect) -> bool {
+        self.0 & effect.bit() != 0
+    }
+
+    pub fn insert(&mut self, effect: PostEffect) {
+        self.0 |= effect.bit();
+    }
+
+    /// Turns `effect` on or off, returning whether it is now on.
+    pub fn toggle(&mut self, effect: PostEffect) -> bool {
+        self.0 ^= effect.bit();
+        self.contains(effect)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The effects in the set, in the order they run.
+    pub fn iter(&self) -> impl Iterator<Item = PostEffect> + '_ {
+        PostEffect::ALL
+            .into_iter()
+            .filter(|&effect| self.contains(effect))
+    }
+}
+
+impl std::str::FromStr for PostEffects {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut effects = PostEffects::default();
+        if s == "none" {
+            return Ok(effects);
+        }
+        for name in s.split(',') {
+            effects.insert(name.trim().parse()?);
+        }
+        Ok(effects)
+    }
+}
+
+impl std::fmt::Display for PostEffects {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<String> = self.iter().map(|effect| effect.to_string()).collect();
+        write!(f, "{}", names.join(" + "))
+    }
+}
+
+impl std::str::FromStr for ShadingModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "phong" => Ok(ShadingModel::Phong),
+            "pbr" => Ok(ShadingModel::Pbr),
+            other => Err(format!(
+                "Invalid shading model '{}'. Use 'phong' or 'pbr'.",
+                other
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ShadingModel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShadingModel::Phong => write!(f, "Phong"),
+            ShadingModel::Pbr => write!(f, "PBR"),
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub const MAX_TILE_SIZE: u32 = 32;
+    /// Largest workgroup WebGPU guarantees, used to cap the rasteriser's threads per tile.
+    pub const MAX_WORKGROUP_SIZE: u32 = 256;
+    const COARSE_TILE_PIXELS: u32 = 64;
+    /// Triangles overlapping more coarse tiles than this skip binning and go into the big triangle list.
+    pub const BIG_TRIANGLE_COARSE_TILES: u32 = 16;
+    /// Samples in a tile the rasteriser can resolve: two u32 per sample must fit in the
+    /// 16 KiB of workgroup memory WebGPU guarantees.
+    pub const MAX_TILE_SAMPLES: u32 = 2048;
+    /// Transparent fragments stored for the whole screen, as an average per pixel. Fragments
+    /// appended once the storage is full are dropped.
+    pub const TRANSPARENT_NODES_PER_PIXEL: u64 = 4;
+    /// Nearest transparent fragments the fragment pass blends in each pixel. Any further
+    /// back are left out.
+    pub const MAX_TRANSPARENT_LAYERS: u32 = 8;
+    /// Lights the light culling pass lists for a tile. Any further lights reaching it are
+    /// left out.
+    pub const MAX_TILE_LIGHTS: u32 = 255;
+    /// Lights after the first few don't cast shadows, as every shadowed light costs up to
+    /// six extra depth passes a frame.
+    pub const MAX_SHADOWED_LIGHTS: usize = 4;
+    pub const MAX_SHADOW_MAP_SIZE: u32 = 4096;
+    /// Largest exposure, in stops either way.
+    pub const MAX_EXPOSURE: f32 = 20.0;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tile_size == 0 || self.tile_size > Self::MAX_TILE_SIZE {
+            return Err(format!(
+                "Invalid tile size {}. Use a value between 1 and {}.",
+                self.tile_size,
+                Self::MAX_TILE_SIZE
+            ));
+        }
+        if !self.workgroup_size.is_power_of_two() || self.workgroup_size > Self::MAX_WORKGROUP_SIZE
+        {
+            return Err(format!(
+                "Invalid workgroup size {}. Use a power of two no larger than {}.",
+                self.workgroup_size,
+                Self::MAX_WORKGROUP_SIZE
+            ));
+        }
+        if self.binning_threads == 0 || self.binning_threads > Self::MAX_WORKGROUP_SIZE {
+            return Err(format!(
+                "Invalid binning thread count {}. Use a value between 1 and {}.",
+                self.binning_threads,
+                Self::MAX_WORKGROUP_SIZE
+            ));
+        }
+        if !matches!(self.msaa_samples, 1 | 2 | 4 | 8) {
+            return Err(format!(
+                "Invalid MSAA sample count {}. Use 1, 2, 4 or 8.",
+                self.msaa_samples
+            ));
+        }
+        if self.tile_size * self.tile_size * self.msaa_samples > Self::MAX_TILE_SAMPLES {
+            return Err(format!(
+                "Tile size {} is too large for {}x MSAA. Use a tile size of at most {}.",
+                self.tile_size,
+                self.msaa_samples,
+                (Self::MAX_TILE_SAMPLES / self.msaa_samples).isqrt()
+            ));
+        }
+        if self.deferred && self.msaa_samples > 1 {
+            return Err(
+                "Deferred shading keeps a single surface per pixel, so it can't be combined with MSAA."
+                    .to_string(),
+            );
+        }
+        if self.shadow_map_size == 0 || self.shadow_map_size > Self::MAX_SHADOW_MAP_SIZE {
+            return Err(format!(
+                "Invalid shadow map size {}. Use a value between 1 and {}.",
+                self.shadow_map_size,
+                Self::MAX_SHADOW_MAP_SIZE
+            ));
+        }
+        if !self.exposure.is_finite() || self.exposure.abs() > Self::MAX_EXPOSURE {
+            return Err(format!(
+                "Invalid exposure {}. Use a value between -{max} and {max} stops.",
+                self.exposure,
+                max = Self::MAX_EXPOSURE
+            ));
+        }
+        Ok(())
+    }
+
+    /// Shadow maps rendered each frame for `scene`.
+    pub fn shadow_views(&self, scene: &Scene) -> usize {
+        if self.shadows && !self.depth_only {
+            scene.shadow_view_count()
+        } else {
+            0
+        }
+    }
+
+    /// Checks that `shadow_views` shadow maps fit in a single storage buffer binding of
+    /// `max_binding_size` bytes, as the custom pipeline keeps them all in one buffer of two
+    /// words per texel.
+    pub fn check_shadow_maps(
+        &self,
+        shadow_views: usize,
+        max_binding_size: u64,
+    ) -> Result<(), String> {
+        let texels = (self.shadow_map_size as u64).pow(2);
+        let size = shadow_views as u64 * texels * std::mem::size_of::<u64>() as u64;
+        if size > max_binding_size {
+            return Err(format!(
+                "{} shadow maps of {}x{} texels need {} MB, more than the {} MB the GPU can bind. Use a smaller --shadow-map-size.",
+                shadow_views,
+                self.shadow_map_size,
+                self.shadow_map_size,
+                size >> 20,
+                max_binding_size >> 20
+            ));
+        }
+        Ok(())
+    }
+
+    /// Configuration of the passes that render shadow maps. They keep the tiling of the main
+    /// passes but store a single depth per texel in a visibility buffer.
+    pub fn shadow_map_config(&self) -> Self {
+        Self {
+            visibility_buffer: true,
+            msaa_samples: 1,
+            deferred: false,
+            light_culling: false,
+            shadows: false,
+            depth_only: true,
+            debug_view: DebugView::None,
+            ..*self
+        }
+    }
+
+    /// Whether the raster pass stores the nearest triangle of every sample rather than full
+    /// fragments. Besides visibility buffer mode, MSAA needs this so the fragment pass can shade
+    /// each triangle in a pixel once and resolve the samples.
+    pub fn stores_visibility(&self) -> bool {
+        self.visibility_buffer || self.msaa_samples > 1
+    }
+
+    /// Whether a light culling pass lists the lights of each tile for the opaque surfaces
+    /// to be lit with.
+    pub fn culls_lights(&self) -> bool {
+        self.light_culling || self.deferred
+    }
+
+    /// Whether the fragment pass shows a debug view instead of the shaded image.
+    pub fn shows_debug_view(&self) -> bool {
+        self.debug_view != DebugView::None
+    }
+
+    /// Length in u32 of the per-tile light lists of a `width` by `height` image: a count and
+    /// [`Self::MAX_TILE_LIGHTS`] indices per tile.
+    pub fn tile_light_len(&self, width: u32, height: u32) -> u64 {
+        let (num_tiles_x, num_tiles_y) = self.num_tiles(width, height);
+        (num_tiles_x * num_tiles_y) as u64 * (Self::MAX_TILE_LIGHTS as u64 + 1)
+    }
+
+    /// Sample positions within a pixel, in sixteenths of a pixel from its centre. These are
+    /// the standard Direct3D/Vulkan patterns, so they match the hardware backend's MSAA.
+    pub fn sample_offsets(&self) -> &'static [[i32; 2]] {
+        match self.msaa_samples {
+            2 => &[[4, 4], [-4, -4]],
+            4 => &[[-2, -6], [6, -2], [-6, 2], [2, 6]],
+            8 => &[
+                [1, -3],
+                [-1, 3],
+                [5, 1],
+                [-3, -5],
+                [-5, 5],
+                [-7, -1],
+                [3, 7],
+                [7, -7],
+            ],
+            _ => &[[0, 0]],
+        }
+    }
+
+    /// Threads used by the rasteriser for each tile: one per pixel, capped at the portable workgroup limit.
+    pub fn raster_threads(&self) -> u32 {
+        (self.tile_size * self.tile_size).min(Self::MAX_WORKGROUP_SIZE)
+    }
+
+    pub fn num_tiles(&self, width: u32, height: u32) -> (u32, u32) {
+        (
+            width.div_ceil(self.tile_size),
+            height.div_ceil(self.tile_size),
+        )
+    }
+
+    /// Fine tiles along each side of a coarse binning tile, keeping coarse tiles around 64 pixels wide.
+    pub fn coarse_factor(&self) -> u32 {
+        (Self::COARSE_TILE_PIXELS / self.tile_size).max(1)
+    }
+
+    pub fn num_coarse_tiles(&self, width: u32, height: u32) -> (u32, u32) {
+        let (num_tiles_x, num_tiles_y) = self.num_tiles(width, height);
+        (
+            num_tiles_x.div_ceil(self.coarse_factor()),
+            num_tiles_y.div_ceil(self.coarse_factor()),
+        )
+    }
+
+    /// Number of workgroups needed to cover `len` items in a one dimensional pass.
+    pub fn dispatch_size(&self, len: u32) -> u32 {
+        len.div_ceil(self.workgroup_size)
+    }
+
+    /// WGSL constant declarations shared by the custom pipeline shaders.
+    pub fn shader_header(&self) -> String {
+        let sample_offsets = self
+            .sample_offsets()
+            .iter()
+            .map(|[x, y]| format!("vec2<i32>({}, {})", x, y))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "const TILE_SIZE: u32 = {}u;\n\
+             const WORKGROUP_SIZE: u32 = {}u;\n\
+             const BINNING_THREADS: u32 = {}u;\n\
+             const RASTER_THREADS: u32 = {}u;\n\
+             const COARSE_FACTOR: u32 = {}u;\n\
+             const BIG_TRIANGLE_COARSE_TILES: u32 = {}u;\n\
+             const MSAA_SAMPLES: u32 = {}u;\n\
+             const MAX_TRANSPARENT_LAYERS: u32 = {}u;\n\
+             const MAX_TILE_LIGHTS: u32 = {}u;\n\
+             const LIGHT_CULLING: bool = {};\n\
+             const DEPTH_ONLY: bool = {};\n\
+             const SHADOW_MAP_SIZE: u32 = {}u;\n\
+             const PBR: bool = {};\n\
+             const TONE_MAPPING: u32 = {}u;\n\
+             const EXPOSURE: f32 = {:?};\n\
+             const AUTO_EXPOSURE: bool = {};\n\
+             const DEBUG_VIEW: u32 = {}u;\n\
+             const SAMPLE_OFFSETS = array<vec2<i32>, {}>({});\n",
+            self.tile_size,
+            self.workgroup_size,
+            self.binning_threads,
+            self.raster_threads(),
+            self.coarse_factor(),
+            Self::BIG_TRIANGLE_COARSE_TILES,
+            self.msaa_samples,
+            Self::MAX_TRANSPARENT_LAYERS,
+            Self::MAX_TILE_LIGHTS,
+            self.culls_lights(),
+            self.depth_only,
+            self.shadow_map_size,
+            self.shading == ShadingModel::Pbr,
+            self.tone_mapping as u32,
+            self.exposure,
+            self.auto_exposure,
+            self.debug_view as u32,
+            self.msaa_samples,
+            sample_offsets,
+        )
+    }
+
+    /// Creates a shader module from a custom pipeline shader with the configured constants injected.
+    pub fn create_shader_module(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+    ) -> wgpu::ShaderModule {
+        let source = format!("{}\n{}", self.shader_header(), source);
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            tile_size: 8,
+            workgroup_size: 256,
+            binning_threads: 3,
+            visibility_buffer: false,
+            msaa_samples: 1,
+            deferred: false,
+            light_culling: false,
+            shadows: false,
+            shadow_map_size: 512,
+            depth_only: false,
+            shading: ShadingModel::Phong,
+            tone_mapping: ToneMapping::Aces,
+            exposure: 0.0,
+            auto_exposure: false,
+            post_effects: PostEffects::default(),
+            debug_view: DebugView::None,
+        }
+    }
+}
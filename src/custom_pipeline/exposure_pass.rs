@@ -0,0 +1,245 @@
+use std::time::Instant;
+
+use wgpu::util::DeviceExt;
+
+use super::{config::PipelineConfig, util::create_buffer_bind_group_layout_entry};
+
+/// Bins of the luminance histogram, one per thread of its workgroups.
+const HISTOGRAM_BINS: u64 = 256;
+
+/// Meters the exposure of the HDR image both backends render before presenting it. With
+/// auto exposure, a histogram of the log luminance of its pixels is averaged every frame,
+/// and the exposure eases towards the average over time. Otherwise the exposure buffer
+/// keeps the manual exposure it was created with.
+pub struct ExposurePass {
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    /// The metered luminance and the exposure the present pass multiplies the image by.
+    pub exposure_buffer: wgpu::Buffer,
+    frame_buffer: wgpu::Buffer,
+    auto_exposure: bool,
+    last_frame: Option<Instant>,
+}
+
+impl ExposurePass {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        config: &PipelineConfig,
+    ) -> Self {
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            size: HISTOGRAM_BINS * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        // No luminance has been metered yet, so the first frame's is taken as it is.
+        let exposure_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Exposure Buffer"),
+            contents: bytemuck::cast_slice(&[0.0f32, config.exposure.exp2()]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Frame Buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Exposure Pass: Group0 Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                create_buffer_bind_group_layout_entry(1, false),
+                create_buffer_bind_group_layout_entry(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Pass: Group0"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: frame_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Exposure Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = config.create_shader_module(
+            device,
+            "Exposure Shader",
+            include_str!("shaders/exposure.wgsl"),
+        );
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            histogram_pipeline: pipeline("Luminance Histogram Pipeline", "histogram_main"),
+            average_pipeline: pipeline("Luminance Average Pipeline", "average_main"),
+            bind_group,
+            exposure_buffer,
+            frame_buffer,
+            auto_exposure: config.auto_exposure,
+            last_frame: None,
+        }
+    }
+
+    /// Meters the image of a `width` by `height` frame, adapting by the time since the last
+    /// call. Does nothing without auto exposure.
+    pub fn execute(
+        &mut self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        width: u32,
+        height: u32,
+    ) {
+        if !self.auto_exposure {
+            return;
+        }
+
+        let now = Instant::now();
+        let delta_time = self
+            .last_frame
+            .map_or(0.0, |last_frame| (now - last_frame).as_secs_f32());
+        self.last_frame = Some(now);
+        queue.write_buffer(
+            &self.frame_buffer,
+            0,
+            bytemuck::cast_slice(&[delta_time, 0.0, 0.0, 0.0]),
+        );
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Exposure Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(&self.histogram_pipeline);
+        pass.dispatch_workgroups(width.div_ceil(16), height.div_ceil(16), 1);
+        pass.set_pipeline(&self.average_pipeline);
+        pass.dispatch_workgroups(1, 1, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::present_pass::HDR_FORMAT;
+    use crate::custom_pipeline::test_util::{read_buffer, test_device};
+
+    /// Half floats of 4.0 and 0.0.
+    const FOUR: u16 = 0x4400;
+    const ZERO: u16 = 0x0000;
+
+    #[test]
+    fn auto_exposure_maps_average_luminance_to_middle_grey() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping exposure test");
+            return;
+        };
+        let (width, height) = (40, 24);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Test HDR Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        // Grey pixels of luminance 4, with a black border that isn't metered.
+        let texels: Vec<[u16; 4]> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                if x < 4 || y < 4 {
+                    [ZERO, ZERO, ZERO, ZERO]
+                } else {
+                    [FOUR, FOUR, FOUR, FOUR]
+                }
+            })
+            .collect();
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 8),
+                rows_per_image: None,
+            },
+            texture.size(),
+        );
+
+        let config = PipelineConfig {
+            auto_exposure: true,
+            exposure: 1.0,
+            ..Default::default()
+        };
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut pass = ExposurePass::new(&device, &view, &config);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        pass.execute(&queue, &mut encoder, width, height);
+        queue.submit(Some(encoder.finish()));
+
+        // The first frame takes the metered luminance as it is, to within a bin's width.
+        let exposure = read_buffer(&device, &queue, &pass.exposure_buffer);
+        let (luminance, exposure) = (f32::from_bits(exposure[0]), f32::from_bits(exposure[1]));
+        let bin_width = (16.0f32 / 254.0).exp2();
+        assert!(
+            (4.0 / bin_width..4.0 * bin_width).contains(&luminance),
+            "metered luminance {} instead of 4",
+            luminance
+        );
+        // One stop of compensation over middle grey.
+        assert!((exposure - 2.0 * 0.18 / luminance).abs() < 1e-6);
+    }
+}
@@ -1,4 +1,14 @@
-use super::{util::create_buffer_bind_group_layout_entry, GpuBuffers};
+use super::{
+    config::PipelineConfig,
+    gpu_buffers::{ALBEDO_FORMAT, DEPTH_NORMAL_FORMAT, MATERIAL_ID_FORMAT, MOTION_FORMAT},
+    picking::OBJECT_ID_FORMAT,
+    present_pass::HDR_FORMAT,
+    util::{
+        create_buffer_bind_group_layout_entry, create_storage_texture_bind_group_layout_entry,
+        create_texture_bind_group_layout_entry,
+    },
+    GpuBuffers,
+};
 
 pub struct FragmentPass {
     pub pipeline: wgpu::ComputePipeline,
@@ -8,22 +18,51 @@ pub struct FragmentPass {
     pub bind_group_3: wgpu::BindGroup,
     pub bind_group_4: wgpu::BindGroup,
     pub bind_group_5: wgpu::BindGroup,
+    /// In deferred mode, the pipeline lighting the G-buffer the main one writes, and its
+    /// group 0 reading the G-buffer. The other groups are shared. Debug views don't write
+    /// the G-buffer, so they have none.
+    pub lighting: Option<(wgpu::ComputePipeline, wgpu::BindGroup)>,
 }
 
 impl FragmentPass {
-    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers) -> Self {
+    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers, config: &PipelineConfig) -> Self {
         let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Fragment Pass: Group0 Layout (Output)"),
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    access: wgpu::StorageTextureAccess::WriteOnly,
-                    format: wgpu::TextureFormat::Bgra8Unorm,
-                    view_dimension: wgpu::TextureViewDimension::D2,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: HDR_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: DEPTH_NORMAL_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: MOTION_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                create_storage_texture_bind_group_layout_entry(3, ALBEDO_FORMAT),
+                create_storage_texture_bind_group_layout_entry(4, MATERIAL_ID_FORMAT),
+                create_storage_texture_bind_group_layout_entry(8, OBJECT_ID_FORMAT),
+            ],
         });
 
         let group1_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -55,8 +94,13 @@ impl FragmentPass {
         });
 
         let group3_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Fragment Pass: Group3 Layout (Lights)"),
-            entries: &[create_buffer_bind_group_layout_entry(0, true)],
+            label: Some("Fragment Pass: Group3 Layout (Lights, Shadow Maps)"),
+            entries: &[
+                create_buffer_bind_group_layout_entry(0, true),
+                create_buffer_bind_group_layout_entry(1, true),
+                create_buffer_bind_group_layout_entry(2, true),
+                create_buffer_bind_group_layout_entry(3, true),
+            ],
         });
 
         let group4_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -73,9 +117,22 @@ impl FragmentPass {
             }],
         });
 
+        // The raster output is either full fragments or the visibility buffer. Transparent
+        // fragments are rebuilt from their triangles in both cases.
+        let output_binding = if config.stores_visibility() { 1 } else { 0 };
         let group5_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Fragment Pass: Group5 Layout (Fragments)"),
-            entries: &[create_buffer_bind_group_layout_entry(0, false)],
+            label: Some("Fragment Pass: Group5 Layout (Raster Output)"),
+            entries: &[
+                create_buffer_bind_group_layout_entry(output_binding, config.stores_visibility()),
+                create_buffer_bind_group_layout_entry(2, true),
+                create_buffer_bind_group_layout_entry(3, true),
+                create_buffer_bind_group_layout_entry(4, true),
+                create_buffer_bind_group_layout_entry(5, true),
+                create_buffer_bind_group_layout_entry(6, true),
+                create_buffer_bind_group_layout_entry(7, true),
+                create_buffer_bind_group_layout_entry(8, true),
+                create_buffer_bind_group_layout_entry(9, true),
+            ],
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -91,13 +148,35 @@ impl FragmentPass {
             push_constant_ranges: &[],
         });
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/fragment.wgsl"));
+        // The visibility buffer samples are rebuilt with the rasteriser's own triangle setup.
+        let shader = config.create_shader_module(
+            device,
+            "Fragment Shader",
+            &format!(
+                "{}\n{}",
+                include_str!("shaders/raster_common.wgsl"),
+                include_str!("shaders/fragment.wgsl")
+            ),
+        );
 
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some("Fragment Pass Pipeline"),
             layout: Some(&pipeline_layout),
             module: &shader,
-            entry_point: Some("fragment_main"),
+            entry_point: Some(
+                match (
+                    config.shows_debug_view(),
+                    config.deferred,
+                    config.stores_visibility(),
+                ) {
+                    (true, _, false) => "debug_main",
+                    (true, _, true) => "debug_visibility_main",
+                    (false, false, false) => "fragment_main",
+                    (false, false, true) => "fragment_visibility_main",
+                    (false, true, false) => "gbuffer_main",
+                    (false, true, true) => "gbuffer_visibility_main",
+                },
+            ),
             compilation_options: wgpu::PipelineCompilationOptions::default(),
             cache: None,
         });
@@ -105,10 +184,32 @@ impl FragmentPass {
         let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Fragment Pass: Group0"),
             layout: &group0_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&buffers.output_view),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&buffers.output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&buffers.depth_normal_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&buffers.motion_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&buffers.albedo_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&buffers.material_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&buffers.object_id_view),
+                },
+            ],
         });
 
         let bind_group_1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -132,10 +233,24 @@ impl FragmentPass {
         let bind_group_3 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Fragment Pass: Group3"),
             layout: &group3_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffers.light_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.shadow_view_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.shadow_map_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.tile_light_buffer.as_entire_binding(),
+                },
+            ],
         });
 
         let bind_group_4 = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -147,13 +262,69 @@ impl FragmentPass {
             }],
         });
 
+        let output_buffer = if config.stores_visibility() {
+            &buffers.visibility_buffer
+        } else {
+            &buffers.fragment_buffer
+        };
         let bind_group_5 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Fragment Pass: Group5"),
             layout: &group5_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffers.fragment_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: output_binding,
+                    resource: output_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.projected_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.index_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffers.transparent_head_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: buffers.transparent_node_buffer.as_entire_binding(),
+                },
+                // Only read by the debug views.
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: buffers.tile_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: buffers.overdraw_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: buffers.texture_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let lighting = (config.deferred && !config.shows_debug_view()).then(|| {
+            Self::create_lighting(
+                device,
+                buffers,
+                config,
+                &shader,
+                [
+                    &group1_layout,
+                    &group2_layout,
+                    &group3_layout,
+                    &group4_layout,
+                    &group5_layout,
+                ],
+            )
         });
 
         Self {
@@ -164,9 +335,90 @@ impl FragmentPass {
             bind_group_3,
             bind_group_4,
             bind_group_5,
+            lighting,
         }
     }
 
+    /// The deferred lighting pipeline, which writes the output from the G-buffer, with the
+    /// layouts of groups 1 to 5 of the main pipeline.
+    fn create_lighting(
+        device: &wgpu::Device,
+        buffers: &GpuBuffers,
+        config: &PipelineConfig,
+        shader: &wgpu::ShaderModule,
+        shared_layouts: [&wgpu::BindGroupLayout; 5],
+    ) -> (wgpu::ComputePipeline, wgpu::BindGroup) {
+        let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Fragment Pass: Group0 Layout (G-Buffer)"),
+            entries: &[
+                create_storage_texture_bind_group_layout_entry(0, HDR_FORMAT),
+                create_texture_bind_group_layout_entry(5, false, wgpu::TextureViewDimension::D2),
+                create_texture_bind_group_layout_entry(6, false, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Uint,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fragment Pass: Group0 (G-Buffer)"),
+            layout: &group0_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&buffers.output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&buffers.depth_normal_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&buffers.albedo_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&buffers.material_view),
+                },
+            ],
+        });
+
+        let [group1_layout, group2_layout, group3_layout, group4_layout, group5_layout] =
+            shared_layouts;
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Deferred Lighting Pipeline Layout"),
+            bind_group_layouts: &[
+                &group0_layout,
+                group1_layout,
+                group2_layout,
+                group3_layout,
+                group4_layout,
+                group5_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Deferred Lighting Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: shader,
+            entry_point: Some(if config.stores_visibility() {
+                "deferred_visibility_main"
+            } else {
+                "deferred_main"
+            }),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        (pipeline, bind_group_0)
+    }
+
     pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, total_pixel_dispatch: u32) {
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Fragment Pass"),
@@ -183,4 +435,147 @@ impl FragmentPass {
 
         cpass.dispatch_workgroups(total_pixel_dispatch, 1, 1);
     }
+
+    /// Lights the G-buffer in deferred mode, once the light culling pass has listed the
+    /// lights of every tile.
+    pub fn execute_lighting(&self, encoder: &mut wgpu::CommandEncoder, total_pixel_dispatch: u32) {
+        let Some((pipeline, bind_group_0)) = &self.lighting else {
+            return;
+        };
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Deferred Lighting Pass"),
+            timestamp_writes: None,
+        });
+
+        cpass.set_pipeline(pipeline);
+        cpass.set_bind_group(0, bind_group_0, &[]);
+        cpass.set_bind_group(1, &self.bind_group_1, &[]);
+        cpass.set_bind_group(2, &self.bind_group_2, &[]);
+        cpass.set_bind_group(3, &self.bind_group_3, &[]);
+        cpass.set_bind_group(4, &self.bind_group_4, &[]);
+        cpass.set_bind_group(5, &self.bind_group_5, &[]);
+
+        cpass.dispatch_workgroups(total_pixel_dispatch, 1, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::binning_pass::BinningPass;
+    use crate::custom_pipeline::config::DebugView;
+    use crate::custom_pipeline::test_util::{half_to_f32, quad_model, read_texture, test_device};
+    use crate::custom_pipeline::{RasterPass, VertexPass};
+    use crate::scene::{LightConfig, Scene};
+    use crate::vertex::CustomVertex;
+
+    const SIZE: u32 = 32;
+
+    /// A lit square over the middle half of the screen, split into two triangles along a
+    /// diagonal, with texture coordinates across it.
+    fn quad_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_model(quad_model(0.5, |x, y| CustomVertex {
+            position: [x, y, 0.5],
+            normal: [0.0, 0.0, -1.0],
+            tex_coords: [x + 0.5, y + 0.5],
+            ..Default::default()
+        }));
+        scene.add_light(LightConfig::point([0.0, 0.0, 0.0], [1.0; 3], 1.0));
+        scene
+    }
+
+    /// Renders the scene through the fragment pass and reads back the output, as RGB per pixel.
+    fn render(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        config: &PipelineConfig,
+    ) -> Vec<[f32; 3]> {
+        let buffers = GpuBuffers::new(device, SIZE, SIZE, scene, config);
+        let vertex_pass = VertexPass::new(device, &buffers, config);
+        let binning_pass = BinningPass::new(device, &buffers, config);
+        let raster_pass = RasterPass::new(device, &buffers, config);
+        let fragment_pass = FragmentPass::new(device, &buffers, config);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        vertex_pass.execute(&mut encoder, buffers.vertex_count);
+        binning_pass.execute(&mut encoder, scene.gx_tris, scene.gy_tris, SIZE, SIZE);
+        raster_pass.execute(&mut encoder, SIZE, SIZE);
+        fragment_pass.execute(&mut encoder, config.dispatch_size(SIZE * SIZE));
+        queue.submit(Some(encoder.finish()));
+
+        read_texture(device, queue, &buffers.output_texture)
+            .chunks_exact(8)
+            .map(|texel| {
+                let channel = |i: usize| half_to_f32(u16::from_le_bytes([texel[i], texel[i + 1]]));
+                [channel(0), channel(2), channel(4)]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn debug_views_match_between_raster_modes() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping debug view test");
+            return;
+        };
+        let scene = quad_scene();
+
+        for debug_view in DebugView::ALL.into_iter().skip(1) {
+            let config = PipelineConfig {
+                debug_view,
+                ..Default::default()
+            };
+            let standard = render(&device, &queue, &scene, &config);
+            let visibility = render(
+                &device,
+                &queue,
+                &scene,
+                &PipelineConfig {
+                    visibility_buffer: true,
+                    ..config
+                },
+            );
+
+            for (pixel, (a, b)) in standard.iter().zip(&visibility).enumerate() {
+                assert!(
+                    a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3),
+                    "{} view, pixel {}: {:?} in standard mode, {:?} with a visibility buffer",
+                    debug_view,
+                    pixel,
+                    a,
+                    b
+                );
+            }
+            // Only the square's pixels show anything, apart from the tiles it was binned into.
+            let centre = (SIZE / 2 * SIZE + SIZE / 2) as usize;
+            assert_ne!(standard[centre], [0.0; 3], "{} view", debug_view);
+            if debug_view != DebugView::TileTriangles {
+                assert_eq!(standard[0], [0.0; 3], "{} view", debug_view);
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_id_view_colours_each_triangle() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping triangle ID view test");
+            return;
+        };
+        let config = PipelineConfig {
+            debug_view: DebugView::TriangleIds,
+            ..Default::default()
+        };
+        let output = render(&device, &queue, &quad_scene(), &config);
+
+        // Either side of the diagonal, just inside the square.
+        let pixel = |x: u32, y: u32| output[(y * SIZE + x) as usize];
+        let below = pixel(SIZE - 10, SIZE - 10 - 2);
+        let above = pixel(10, 10 + 2);
+        assert_ne!(below, [0.0; 3]);
+        assert_ne!(above, [0.0; 3]);
+        assert_ne!(below, above);
+        assert_eq!(pixel(SIZE - 10, SIZE - 10 - 4), below);
+    }
 }
@@ -2,12 +2,28 @@ use wgpu::util::DeviceExt;
 
 use crate::{
     camera,
-    custom_pipeline::util::{Fragment, ScreenUniform},
+    custom_pipeline::util::{
+        Fragment, MaterialInfo, PipelineStats, ProjectedVertex, ScreenUniform, TriangleMeta,
+    },
     effect::EffectUniform,
     scene,
 };
 
-use super::raster_pass::TILE_SIZE;
+use super::{
+    config::{DebugView, PipelineConfig},
+    picking::OBJECT_ID_FORMAT,
+    present_pass::HDR_FORMAT,
+};
+
+/// Full 32-bit floats, as depth is near one over most of the view.
+pub const DEPTH_NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+/// Motion vectors in UV (xy), which half floats would round to whole pixels at large sizes.
+/// Two channel formats can't be written from shaders on every device.
+pub const MOTION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+/// Base colour of the G-buffer's surfaces in deferred mode.
+pub const ALBEDO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Material index of the G-buffer's surfaces plus one, so empty pixels hold zero.
+pub const MATERIAL_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
 
 pub struct GpuBuffers {
     pub camera_buffer: wgpu::Buffer,
@@ -16,45 +32,150 @@ pub struct GpuBuffers {
     pub screen_buffer: wgpu::Buffer,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    pub material_buffer: wgpu::Buffer,
+    /// Texels of every model's base colour textures, which the materials point into. A
+    /// single placeholder texel when no model has textures.
+    pub texture_buffer: wgpu::Buffer,
     pub projected_buffer: wgpu::Buffer,
     pub fragment_buffer: wgpu::Buffer,
+    pub visibility_buffer: wgpu::Buffer,
+    pub transparent_head_buffer: wgpu::Buffer,
+    pub transparent_node_buffer: wgpu::Buffer,
+    pub shadow_view_buffer: wgpu::Buffer,
+    pub shadow_map_buffer: wgpu::Buffer,
     pub tile_buffer: wgpu::Buffer,
     pub triangle_list_buffer: wgpu::Buffer,
     pub partial_sums_buffer: wgpu::Buffer,
     pub triangle_meta_buffer: wgpu::Buffer,
+    pub coarse_buffer: wgpu::Buffer,
+    pub coarse_list_buffer: wgpu::Buffer,
+    pub big_triangle_buffer: wgpu::Buffer,
+    pub stats_buffer: wgpu::Buffer,
+    pub stats_readback_buffer: wgpu::Buffer,
+    pub output_texture: wgpu::Texture,
     pub output_view: wgpu::TextureView,
+    /// World space normal and depth of the opaque surface in each pixel, written by the
+    /// fragment pass for the post-processing passes. Empty pixels have a depth of one.
+    pub depth_normal_view: wgpu::TextureView,
+    /// How far the opaque surface in each pixel moved in UV since the previous frame, also
+    /// written by the fragment pass, for TAA.
+    #[allow(dead_code)]
+    pub motion_texture: wgpu::Texture,
+    pub motion_view: wgpu::TextureView,
+    /// The rest of the G-buffer the fragment pass writes in deferred mode, which also uses
+    /// the depth, normal and motion above. Otherwise single texel placeholders.
+    pub albedo_view: wgpu::TextureView,
+    pub material_view: wgpu::TextureView,
+    /// The opaque triangle in each pixel, written by the fragment pass alongside the depth
+    /// and normal, for picking and the selection outline.
+    pub object_id_texture: wgpu::Texture,
+    pub object_id_view: wgpu::TextureView,
+    /// Per tile, the number of lights reaching it followed by the index of each, in slots
+    /// of [`PipelineConfig::MAX_TILE_LIGHTS`] plus one. Written by the light culling pass
+    /// when lights are culled, and a placeholder otherwise.
+    pub tile_light_buffer: wgpu::Buffer,
+    /// Depth tests of each pixel's samples, counted by the raster pass for the overdraw debug
+    /// view and cleared every frame. A placeholder with any other view.
+    pub overdraw_buffer: wgpu::Buffer,
+    pub vertex_count: u32,
 }
 
 impl GpuBuffers {
-    pub fn new(device: &wgpu::Device, width: u32, height: u32, scene: &scene::Scene) -> Self {
-        let screen_uniform_data = ScreenUniform::new(width as f32, height as f32);
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        scene: &scene::Scene,
+        config: &PipelineConfig,
+    ) -> Self {
+        let screen_uniform_data = ScreenUniform::new(width as f32, height as f32, config.tile_size);
 
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
+        let mut materials = Vec::new();
+        let mut textures = Vec::new();
 
         for model in &scene.models {
-            // Add pre-processed vertices and indices
-            vertices.extend_from_slice(&model.processed_vertices_custom);
+            // Add pre-processed vertices and indices, with materials numbered across the scene
+            // and textures put one after another
+            let first_material = materials.len() as u32;
+            let first_texel = textures.len() as u32;
+            vertices.extend(model.processed_vertices_custom.iter().map(|vertex| {
+                crate::vertex::CustomVertex {
+                    material: vertex.material + first_material,
+                    ..*vertex
+                }
+            }));
             indices.extend_from_slice(&model.processed_indices);
+            materials.extend(
+                model
+                    .materials
+                    .iter()
+                    .map(|material| material.with_first_texel(first_texel)),
+            );
+            textures.extend_from_slice(&model.textures);
+        }
+        if materials.is_empty() {
+            materials.push(MaterialInfo::default());
+        }
+        if textures.is_empty() {
+            textures.push(0);
         }
 
         let index_length = indices.len();
 
-        let max_fragments = (width * height) as u64;
+        // Only the buffer the configured raster mode writes is sized for the whole screen.
+        let num_pixels = (width * height) as u64;
+        let (max_fragments, visibility_samples) = if config.stores_visibility() {
+            (1, num_pixels * config.msaa_samples as u64)
+        } else {
+            (num_pixels, 1)
+        };
+
+        // A count of appended nodes followed by the nodes, capped at what can be bound at once.
+        // Depth only passes never list transparent fragments, so they only get a placeholder.
+        let max_binding_size = device.limits().max_storage_buffer_binding_size as u64;
+        let transparent_node_size = std::mem::size_of::<[u32; 4]>() as u64;
+        let (transparent_pixels, max_transparent_nodes) = if config.depth_only {
+            (1, 1)
+        } else {
+            (
+                num_pixels,
+                (num_pixels * PipelineConfig::TRANSPARENT_NODES_PER_PIXEL)
+                    .min(max_binding_size / transparent_node_size - 1),
+            )
+        };
+
+        // Each shadow map view of a shadowed light keeps the visibility buffer its depth pass
+        // rendered, two words per texel. Without shadows these are unused placeholders.
+        // Sizes the GPU can't bind are rejected before the first scene in main, so this only
+        // catches other callers.
+        let shadow_views = config.shadow_views(scene) as u64;
+        let shadow_texels = (config.shadow_map_size as u64).pow(2);
+        let shadow_map_size = shadow_views * shadow_texels * std::mem::size_of::<u64>() as u64;
+        if let Err(e) = config.check_shadow_maps(shadow_views as usize, max_binding_size) {
+            panic!("{}", e);
+        }
 
         let camera_uniform = camera::CameraUniform::default();
 
         let effect_data = EffectUniform::default();
 
-        let num_tiles_x = (width + TILE_SIZE - 1) / TILE_SIZE;
-        let num_tiles_y = (height + TILE_SIZE - 1) / TILE_SIZE;
+        let (num_tiles_x, num_tiles_y) = config.num_tiles(width, height);
         let num_tiles = (num_tiles_x * num_tiles_y) as u64;
 
         let total_triangles = (index_length / 3) as u32;
 
+        let (num_coarse_x, num_coarse_y) = config.num_coarse_tiles(width, height);
+        let num_coarse_tiles = (num_coarse_x * num_coarse_y) as u64;
+
+        // Triangles covering more coarse tiles than this are not binned, so this bounds the coarse lists.
+        let max_coarse_entries =
+            total_triangles.max(1) as u64 * PipelineConfig::BIG_TRIANGLE_COARSE_TILES as u64;
+
         // Calculate max triangles per tile based on screen coverage
         let avg_triangle_area = (width * height) as f32 / total_triangles as f32;
-        let tile_area = (TILE_SIZE * TILE_SIZE) as f32;
+        let tile_area = (config.tile_size * config.tile_size) as f32;
 
         // Base estimate: how many triangles could fit in a tile
         let base_triangles_per_tile = (tile_area / avg_triangle_area * 2.0) as u32;
@@ -62,14 +183,6 @@ impl GpuBuffers {
         // Add safety margin for overlapping triangles and uneven distribution
         let max_triangles_per_tile = std::cmp::max(base_triangles_per_tile, 128) as u64;
 
-        #[repr(C)]
-        #[derive(Copy, Clone)]
-        struct TriangleMeta {
-            min_max: [f32; 4],
-            start_tile: [u32; 2],
-            tile_range: [u32; 2],
-        }
-
         let texture_desc = wgpu::TextureDescriptor {
             label: Some("Output Texture"),
             size: wgpu::Extent3d {
@@ -79,14 +192,71 @@ impl GpuBuffers {
             },
             mip_level_count: 1,
             sample_count: 1,
-            format: wgpu::TextureFormat::Bgra8Unorm,
+            format: HDR_FORMAT,
             usage: wgpu::TextureUsages::STORAGE_BINDING
                 | wgpu::TextureUsages::TEXTURE_BINDING
-                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                | wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
             dimension: wgpu::TextureDimension::D2,
             view_formats: &[],
         };
         let output_texture = device.create_texture(&texture_desc);
+        let depth_normal_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Normal Texture"),
+            format: DEPTH_NORMAL_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            ..texture_desc
+        });
+        let motion_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Motion Texture"),
+            format: MOTION_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            ..texture_desc
+        });
+        let gbuffer_size = if config.deferred {
+            texture_desc.size
+        } else {
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            }
+        };
+        let albedo_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("G-Buffer Albedo Texture"),
+            size: gbuffer_size,
+            format: ALBEDO_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            ..texture_desc
+        });
+        let material_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("G-Buffer Material Texture"),
+            size: gbuffer_size,
+            format: MATERIAL_ID_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            ..texture_desc
+        });
+        let object_id_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Object ID Texture"),
+            format: OBJECT_ID_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            ..texture_desc
+        });
+        let tile_light_slots = if config.culls_lights() {
+            config.tile_light_len(width, height)
+        } else {
+            1
+        };
+        let overdraw_pixels = if config.debug_view == DebugView::Overdraw {
+            num_pixels
+        } else {
+            1
+        };
 
         Self {
             camera_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -102,7 +272,9 @@ impl GpuBuffers {
             effect_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Effect Buffer"),
                 contents: bytemuck::bytes_of(&effect_data),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                usage: wgpu::BufferUsages::UNIFORM
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
             }),
             screen_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Screen Buffer"),
@@ -119,28 +291,85 @@ impl GpuBuffers {
                 contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
             }),
+            material_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Material Buffer"),
+                contents: bytemuck::cast_slice(&materials),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
+            texture_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Texture Buffer"),
+                contents: bytemuck::cast_slice(&textures),
+                usage: wgpu::BufferUsages::STORAGE,
+            }),
             projected_buffer: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Projected Buffer"),
-                size: (vertices.len() * std::mem::size_of::<[u32; 16]>()) as u64,
-                usage: wgpu::BufferUsages::STORAGE,
+                size: (vertices.len() * std::mem::size_of::<ProjectedVertex>()) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             }),
             fragment_buffer: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Fragment Buffer"),
                 size: max_fragments * std::mem::size_of::<Fragment>() as u64,
-                usage: wgpu::BufferUsages::STORAGE,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            // One packed 64-bit depth and triangle key per sample.
+            visibility_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Visibility Buffer"),
+                size: visibility_samples * std::mem::size_of::<u64>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            // Index of each pixel's most recently appended transparent fragment plus one,
+            // so a cleared buffer holds empty lists.
+            transparent_head_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Transparent Head Buffer"),
+                size: transparent_pixels * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            transparent_node_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Transparent Node Buffer"),
+                size: std::mem::size_of::<u32>() as u64
+                    + max_transparent_nodes * transparent_node_size,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            // The camera of every shadow map view, written by Scene::update_buffers.
+            shadow_view_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Shadow View Buffer"),
+                size: shadow_views.max(1) * std::mem::size_of::<camera::CameraUniform>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            shadow_map_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Shadow Map Buffer"),
+                size: shadow_map_size.max(std::mem::size_of::<u64>() as u64),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             }),
             tile_buffer: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Tile Buffer"),
                 size: num_tiles * std::mem::size_of::<[u32; 4]>() as u64,
-                usage: wgpu::BufferUsages::STORAGE,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
                 mapped_at_creation: false,
             }),
             triangle_list_buffer: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Triangle List Buffer"),
                 size: num_tiles * max_triangles_per_tile * (std::mem::size_of::<u64>() as u64),
-                usage: wgpu::BufferUsages::STORAGE,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             }),
             partial_sums_buffer: device.create_buffer(&wgpu::BufferDescriptor {
@@ -151,13 +380,68 @@ impl GpuBuffers {
             }),
             triangle_meta_buffer: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Triangle Meta Buffer"),
-                size: num_tiles
-                    * max_triangles_per_tile
-                    * std::mem::size_of::<TriangleMeta>() as u64,
+                size: total_triangles.max(1) as u64 * std::mem::size_of::<TriangleMeta>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            coarse_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Coarse Tile Buffer"),
+                size: num_coarse_tiles * std::mem::size_of::<[u32; 4]>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            coarse_list_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Coarse Triangle List Buffer"),
+                size: max_coarse_entries * std::mem::size_of::<u32>() as u64,
                 usage: wgpu::BufferUsages::STORAGE,
                 mapped_at_creation: false,
             }),
+            // A count followed by one entry per triangle.
+            big_triangle_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Big Triangle Buffer"),
+                size: (total_triangles as u64 + 1) * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            stats_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Stats Buffer"),
+                size: std::mem::size_of::<PipelineStats>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            stats_readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Stats Readback Buffer"),
+                size: std::mem::size_of::<PipelineStats>() as u64,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
             output_view: output_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            output_texture,
+            depth_normal_view: depth_normal_texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            motion_view: motion_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            motion_texture,
+            albedo_view: albedo_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            material_view: material_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            object_id_view: object_id_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            object_id_texture,
+            tile_light_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Tile Light Buffer"),
+                size: tile_light_slots * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            overdraw_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Overdraw Buffer"),
+                size: overdraw_pixels * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            vertex_count: vertices.len() as u32,
         }
     }
 }
@@ -0,0 +1,355 @@
+use super::{
+    config::PipelineConfig,
+    util::{create_buffer_bind_group_layout_entry, create_texture_bind_group_layout_entry},
+    GpuBuffers,
+};
+
+/// Where the light culling pass finds the depth of the opaque surfaces in each pixel.
+pub enum DepthSource<'a> {
+    /// The custom pipeline's G-buffer, in deferred mode.
+    GBuffer(&'a wgpu::TextureView),
+    /// The custom pipeline's raster output, before the fragment pass shades it forward.
+    Fragments(&'a wgpu::Buffer),
+    VisibilityBuffer(&'a wgpu::Buffer),
+    /// The WGPU pipeline's depth prepass, and its sample count.
+    DepthTexture(&'a wgpu::TextureView, u32),
+}
+
+/// What the light culling pass reads and writes, shared by both backends.
+pub struct LightCullingInputs<'a> {
+    /// The screen size and tile counts, as the custom pipeline's `ScreenUniform`.
+    pub screen_buffer: &'a wgpu::Buffer,
+    pub camera_buffer: &'a wgpu::Buffer,
+    pub light_buffer: &'a wgpu::Buffer,
+    /// [`PipelineConfig::tile_light_len`] u32 of per-tile light lists to write.
+    pub tile_light_buffer: &'a wgpu::Buffer,
+    pub depth: DepthSource<'a>,
+}
+
+/// Lists the lights reaching each screen tile, from the lights' ranges and the depth bounds
+/// of the tile's opaque surfaces.
+pub struct LightCullingPass {
+    pipeline: wgpu::ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    config: PipelineConfig,
+}
+
+impl LightCullingPass {
+    /// Culls the lights of the custom pipeline, from its G-buffer in deferred mode and from
+    /// the raster pass's output otherwise.
+    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers, config: &PipelineConfig) -> Self {
+        let depth = if config.deferred {
+            DepthSource::GBuffer(&buffers.depth_normal_view)
+        } else if config.stores_visibility() {
+            DepthSource::VisibilityBuffer(&buffers.visibility_buffer)
+        } else {
+            DepthSource::Fragments(&buffers.fragment_buffer)
+        };
+        Self::with_inputs(
+            device,
+            config,
+            LightCullingInputs {
+                screen_buffer: &buffers.screen_buffer,
+                camera_buffer: &buffers.camera_buffer,
+                light_buffer: &buffers.light_buffer,
+                tile_light_buffer: &buffers.tile_light_buffer,
+                depth,
+            },
+        )
+    }
+
+    pub fn with_inputs(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        inputs: LightCullingInputs,
+    ) -> Self {
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let depth_texture_entry = |binding, multisampled| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled,
+            },
+            count: None,
+        };
+        let (depth_entry, depth_resource, entry_point) = match inputs.depth {
+            DepthSource::GBuffer(view) => (
+                create_texture_bind_group_layout_entry(3, false, wgpu::TextureViewDimension::D2),
+                wgpu::BindingResource::TextureView(view),
+                "cull_main",
+            ),
+            DepthSource::Fragments(buffer) => (
+                create_buffer_bind_group_layout_entry(5, true),
+                buffer.as_entire_binding(),
+                "cull_fragments_main",
+            ),
+            DepthSource::VisibilityBuffer(buffer) => (
+                create_buffer_bind_group_layout_entry(6, true),
+                buffer.as_entire_binding(),
+                "cull_visibility_main",
+            ),
+            DepthSource::DepthTexture(view, 1) => (
+                depth_texture_entry(7, false),
+                wgpu::BindingResource::TextureView(view),
+                "cull_depth_main",
+            ),
+            DepthSource::DepthTexture(view, _) => (
+                depth_texture_entry(8, true),
+                wgpu::BindingResource::TextureView(view),
+                "cull_depth_multisampled_main",
+            ),
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Culling Pass: Group0 Layout"),
+            entries: &[
+                uniform_entry(0),
+                uniform_entry(1),
+                create_buffer_bind_group_layout_entry(2, true),
+                create_buffer_bind_group_layout_entry(4, false),
+                depth_entry,
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Culling Pass: Group0"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: inputs.screen_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: inputs.camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: inputs.light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: inputs.tile_light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: depth_entry.binding,
+                    resource: depth_resource,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Culling Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = config.create_shader_module(
+            device,
+            "Light Culling Shader",
+            include_str!("shaders/light_culling.wgsl"),
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Culling Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some(entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            config: *config,
+        }
+    }
+
+    /// Culls the lights of every tile of a `width` by `height` image, one workgroup each.
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, width: u32, height: u32) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Culling Pass"),
+            timestamp_writes: None,
+        });
+
+        let (num_tiles_x, num_tiles_y) = self.config.num_tiles(width, height);
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &self.bind_group, &[]);
+        cpass.dispatch_workgroups(num_tiles_x, num_tiles_y, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::binning_pass::BinningPass;
+    use crate::custom_pipeline::test_util::{
+        half_to_f32, quad_model, read_buffer, read_texture, test_device,
+    };
+    use crate::custom_pipeline::{FragmentPass, RasterPass, VertexPass};
+    use crate::scene::{LightConfig, Scene};
+    use crate::vertex::CustomVertex;
+
+    const SIZE: u32 = 32;
+
+    /// A square over the middle half of the screen at a depth of 0.5, facing the camera,
+    /// so with the default camera its world positions are its NDC. Of its lights, the first
+    /// only reaches the tile around (-0.25, 0.25), the second is out of reach of every
+    /// tile, and the last two reach everything.
+    fn lit_quad_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_model(quad_model(0.5, |x, y| CustomVertex {
+            position: [x, y, 0.5],
+            normal: [0.0, 0.0, -1.0],
+            ..Default::default()
+        }));
+        scene.add_light(
+            LightConfig::point([-0.25, 0.25, 0.45], [1.0, 0.5, 0.2], 1.0).with_range(0.1),
+        );
+        scene.add_light(LightConfig::point([5.0, 5.0, 0.0], [1.0; 3], 1.0).with_range(1.0));
+        scene.add_light(LightConfig::point([0.0, 0.0, 0.0], [0.2, 0.4, 0.6], 1.0));
+        scene.add_light(LightConfig::directional([0.0, 0.0, 1.0], [0.3; 3], 1.0));
+        scene
+    }
+
+    /// Renders `scene` with `config`, culling its lights before the fragment pass when they
+    /// are culled for forward shading, and after it in deferred mode.
+    fn render(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        config: PipelineConfig,
+    ) -> GpuBuffers {
+        let buffers = GpuBuffers::new(device, SIZE, SIZE, scene, &config);
+        let vertex_pass = VertexPass::new(device, &buffers, &config);
+        let binning_pass = BinningPass::new(device, &buffers, &config);
+        let raster_pass = RasterPass::new(device, &buffers, &config);
+        let fragment_pass = FragmentPass::new(device, &buffers, &config);
+        let light_culling_pass = LightCullingPass::new(device, &buffers, &config);
+        let forward_culling = config.culls_lights() && !config.deferred;
+
+        let dispatch = config.dispatch_size(SIZE * SIZE);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        vertex_pass.execute(&mut encoder, buffers.vertex_count);
+        binning_pass.execute(&mut encoder, scene.gx_tris, scene.gy_tris, SIZE, SIZE);
+        raster_pass.execute(&mut encoder, SIZE, SIZE);
+        if forward_culling {
+            light_culling_pass.execute(&mut encoder, SIZE, SIZE);
+        }
+        fragment_pass.execute(&mut encoder, dispatch);
+        if config.deferred {
+            light_culling_pass.execute(&mut encoder, SIZE, SIZE);
+            fragment_pass.execute_lighting(&mut encoder, dispatch);
+        }
+        queue.submit(Some(encoder.finish()));
+        buffers
+    }
+
+    /// The ways of culling lights: from the G-buffer, the fragments, the visibility buffer,
+    /// and a visibility buffer of several samples per pixel.
+    fn culling_configs() -> [PipelineConfig; 5] {
+        let deferred = PipelineConfig {
+            deferred: true,
+            ..Default::default()
+        };
+        let forward = PipelineConfig {
+            light_culling: true,
+            ..Default::default()
+        };
+        [
+            deferred,
+            PipelineConfig {
+                visibility_buffer: true,
+                ..deferred
+            },
+            forward,
+            PipelineConfig {
+                visibility_buffer: true,
+                ..forward
+            },
+            PipelineConfig {
+                msaa_samples: 4,
+                ..forward
+            },
+        ]
+    }
+
+    #[test]
+    fn tiles_list_the_lights_reaching_their_surfaces() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping light culling test");
+            return;
+        };
+        for config in culling_configs() {
+            let buffers = render(&device, &queue, &lit_quad_scene(), config);
+
+            let tile_lights = read_buffer(&device, &queue, &buffers.tile_light_buffer);
+            let (num_tiles_x, num_tiles_y) = config.num_tiles(SIZE, SIZE);
+            let slots = PipelineConfig::MAX_TILE_LIGHTS as usize + 1;
+            for y in 0..num_tiles_y {
+                for x in 0..num_tiles_x {
+                    let slot = &tile_lights[(y * num_tiles_x + x) as usize * slots..];
+                    let mut lights = slot[1..=slot[0] as usize].to_vec();
+                    lights.sort();
+                    // The quad covers the middle two tiles along each side.
+                    let expected = match (x, y) {
+                        (1, 1) => vec![0, 2, 3],
+                        (1..=2, 1..=2) => vec![2, 3],
+                        _ => vec![],
+                    };
+                    assert_eq!(
+                        lights, expected,
+                        "lights of tile ({}, {}) with {:?}",
+                        x, y, config
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn culled_lighting_matches_shading_with_every_light() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping light culling test");
+            return;
+        };
+        let scene = lit_quad_scene();
+        let image = |config| {
+            let buffers = render(&device, &queue, &scene, config);
+            let output = read_texture(&device, &queue, &buffers.output_texture);
+            bytemuck::cast_slice::<u8, u16>(&output)
+                .iter()
+                .map(|&half| half_to_f32(half))
+                .collect::<Vec<f32>>()
+        };
+
+        for config in culling_configs() {
+            let unculled = image(PipelineConfig {
+                deferred: false,
+                light_culling: false,
+                ..config
+            });
+            let culled = image(config);
+            for (i, (a, b)) in unculled.iter().zip(&culled).enumerate() {
+                assert!(
+                    (a - b).abs() <= 0.01 * a.abs().max(1.0),
+                    "channel {} of pixel {} with {:?}: {} without culling but {} with it",
+                    i % 4,
+                    i / 4,
+                    config,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
@@ -1,11 +1,27 @@
 mod binning_pass;
+mod bloom_pass;
+pub mod capture;
+pub mod config;
+pub mod exposure_pass;
 mod fragment_pass;
-pub mod renderer;
 mod gpu_buffers;
+pub mod light_culling_pass;
+pub mod picking;
+pub mod post_chain;
+pub mod present_pass;
+mod profiler;
 mod raster_pass;
+pub mod renderer;
+mod shadow_pass;
+mod ssao_pass;
+mod taa_pass;
+#[cfg(test)]
+pub mod test_util;
 pub mod util;
-mod present_pass;
+mod vertex_pass;
 
 use fragment_pass::FragmentPass;
 use gpu_buffers::GpuBuffers;
 use raster_pass::RasterPass;
+use shadow_pass::ShadowPass;
+use vertex_pass::VertexPass;
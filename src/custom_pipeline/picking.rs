@@ -0,0 +1,205 @@
+//! Reads single pixels of the object ID image both backends can write, to find the triangle
+//! under the cursor without stalling the frames rendered meanwhile.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// ID of the opaque triangle in each pixel plus one, numbered across the scene's models in
+/// the order of their indices, so empty pixels hold zero.
+pub const OBJECT_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+/// The triangle found under a picked pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pick {
+    pub x: u32,
+    pub y: u32,
+    /// Index of the triangle across the scene's models, or none for an empty pixel.
+    pub triangle: Option<u32>,
+}
+
+/// Copies a requested pixel of the object ID image at the end of a frame and maps it once
+/// the frame has been submitted. One pick is read back at a time; requests made meanwhile
+/// replace each other.
+pub struct Picker {
+    readback_buffer: wgpu::Buffer,
+    request: Option<(u32, u32)>,
+    // The pixel copied into the readback buffer, and whether it has been mapped
+    copied: Option<(u32, u32)>,
+    mapped: Option<Arc<AtomicBool>>,
+}
+
+impl Picker {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: OBJECT_ID_FORMAT.block_copy_size(None).unwrap() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            readback_buffer,
+            request: None,
+            copied: None,
+            mapped: None,
+        }
+    }
+
+    /// Asks for the triangle at pixel (`x`, `y`) of the next frame rendered.
+    pub fn request(&mut self, x: u32, y: u32) {
+        self.request = Some((x, y));
+    }
+
+    /// Whether a requested pixel is waiting for a frame to be copied from.
+    pub fn is_requested(&self) -> bool {
+        self.request.is_some()
+    }
+
+    /// Copies the requested pixel out of `id_texture`, an [`OBJECT_ID_FORMAT`] texture, unless
+    /// the previous copy hasn't been read yet. Pixels past the edges are clamped to them.
+    pub fn record(&mut self, encoder: &mut wgpu::CommandEncoder, id_texture: &wgpu::Texture) {
+        if self.copied.is_some() {
+            return;
+        }
+        let Some((x, y)) = self.request.take() else {
+            return;
+        };
+        let (x, y) = (
+            x.min(id_texture.width() - 1),
+            y.min(id_texture.height() - 1),
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.copied = Some((x, y));
+    }
+
+    /// Starts mapping the copied pixel. Called once the frame it was copied in is submitted,
+    /// since a buffer can't be used by submitted commands while it is being mapped.
+    pub fn submitted(&mut self) {
+        if self.copied.is_none() || self.mapped.is_some() {
+            return;
+        }
+        let mapped = Arc::new(AtomicBool::new(false));
+        let callback_mapped = mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                callback_mapped.store(result.is_ok(), Ordering::Release);
+            });
+        self.mapped = Some(mapped);
+    }
+
+    /// The picked triangle, once the GPU has finished copying it. Doesn't block.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Pick> {
+        let mapped = self.mapped.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        if !mapped.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let id = *bytemuck::from_bytes::<u32>(&self.readback_buffer.slice(..).get_mapped_range());
+        self.readback_buffer.unmap();
+        self.mapped = None;
+        let (x, y) = self.copied.take()?;
+        Some(Pick {
+            x,
+            y,
+            triangle: id.checked_sub(1),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        custom_pipeline::{
+            config::PipelineConfig,
+            renderer::CustomRenderer,
+            test_util::{create_target, read_texture, split_quad_scene, test_device},
+        },
+        wgpu_pipeline::renderer::WgpuRenderer,
+    };
+
+    const SIZE: u32 = 32;
+
+    /// Pixels inside the lower right triangle, the upper left one and neither.
+    const PICKS: [([u32; 2], Option<u32>); 3] =
+        [([28, 20], Some(0)), ([3, 20], Some(1)), ([0, 0], None)];
+
+    /// Whether the presented pixel at (`x`, `y`) has the outline's colour, in BGRA.
+    fn is_outline(presented: &[u8], x: u32, y: u32) -> bool {
+        let pixel = ((x + y * SIZE) * 4) as usize;
+        presented[pixel..pixel + 4] == [0, 153, 255, 255]
+    }
+
+    #[test]
+    fn both_backends_pick_and_outline_the_triangle_under_a_pixel() {
+        if test_device().is_none() {
+            eprintln!("No adapter supports the custom pipeline, skipping picking test");
+            return;
+        }
+        let scene = split_quad_scene(SIZE);
+        let picked = scene.pick(1, [3, 20], SIZE, SIZE).unwrap();
+        assert_eq!(picked.model, 0);
+        assert_eq!(picked.mesh_name.as_deref(), Some("upper"));
+        assert_eq!(picked.selection, 1..2);
+
+        // Left of the square, the upper left triangle is within the outline's reach; right
+        // of it, only the lower right one is.
+        let config = PipelineConfig::default();
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let mut custom = pollster::block_on(CustomRenderer::new(
+            &instance, None, SIZE, SIZE, &scene, config,
+        ));
+        let target = create_target(&custom.device, SIZE, SIZE);
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        for ([x, y], triangle) in PICKS {
+            custom.pick(x, y);
+            custom.render_to(&view, &scene);
+            custom.device.poll(wgpu::Maintain::Wait);
+            assert_eq!(custom.poll_pick(), Some(Pick { x, y, triangle }));
+        }
+        custom.set_selection(Some(picked.selection.clone()));
+        custom.render_to(&view, &scene);
+        let presented = read_texture(&custom.device, &custom.queue, &target);
+        assert!(is_outline(&presented, 1, 20) && !is_outline(&presented, 30, 20));
+
+        let mut wgpu = pollster::block_on(WgpuRenderer::new(
+            &instance, None, SIZE, SIZE, &scene, &config,
+        ))
+        .unwrap();
+        let target = create_target(&wgpu.device, SIZE, SIZE);
+        let view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        for ([x, y], triangle) in PICKS {
+            wgpu.pick(x, y);
+            pollster::block_on(wgpu.render_to(&view, &scene));
+            wgpu.device.poll(wgpu::Maintain::Wait);
+            assert_eq!(wgpu.poll_pick(), Some(Pick { x, y, triangle }));
+        }
+        wgpu.set_selection(Some(picked.selection));
+        pollster::block_on(wgpu.render_to(&view, &scene));
+        let presented = read_texture(&wgpu.device, &wgpu.queue, &target);
+        assert!(is_outline(&presented, 1, 20) && !is_outline(&presented, 30, 20));
+    }
+}
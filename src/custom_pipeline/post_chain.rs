@@ -0,0 +1,620 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::{Camera, CameraUniform},
+    scene::Scene,
+};
+
+use super::{
+    bloom_pass::BloomPass,
+    config::{PipelineConfig, PostEffect, PostEffects},
+    present_pass::HDR_FORMAT,
+    profiler::GpuProfiler,
+    ssao_pass::SsaoPass,
+    taa_pass::TaaPass,
+    util::{
+        create_storage_texture_bind_group_layout_entry, create_texture_bind_group_layout_entry,
+    },
+    GpuBuffers,
+};
+
+/// Width and height of the workgroups of the post-processing shaders, one thread per pixel.
+pub const POST_WORKGROUP_SIZE: u32 = 8;
+
+/// The SSAO radius, as a fraction of the diagonal of the scene's bounds.
+const SSAO_RADIUS_SCALE: f32 = 0.015;
+
+/// Camera of the frame, for the passes that work from the depth and normal of each pixel.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PostViewUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub inv_view_proj: [[f32; 4]; 4],
+    pub view_position: [f32; 4],
+    pub ssao_radius: f32,
+    pub _padding: [f32; 3],
+}
+
+/// A 3D colour lookup table of `size` entries along each side, indexed by red fastest, then
+/// green, then blue, as in the .cube files it is loaded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLut {
+    pub size: u32,
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl ColorLut {
+    pub const MAX_SIZE: u32 = 256;
+
+    pub fn identity(size: u32) -> Self {
+        let scale = (size - 1) as f32;
+        let texels = (0..size.pow(3))
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                [r as f32 / scale, g as f32 / scale, b as f32 / scale, 1.0]
+            })
+            .collect();
+        Self { size, texels }
+    }
+
+    /// Loads a 3D LUT from an Adobe/Resolve .cube file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read LUT '{}': {}", path, e))?;
+        Self::parse(&source).map_err(|e| format!("Invalid LUT '{}': {}", path, e))
+    }
+
+    /// Parses the text of a .cube file. Only 3D LUTs over the default 0 to 1 domain are
+    /// supported.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut size = None;
+        let mut texels = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap_or_default();
+            let values: Result<Vec<f32>, _> = tokens.map(str::parse).collect();
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    let value = line["LUT_3D_SIZE".len()..].trim();
+                    match value.parse::<u32>() {
+                        Ok(value) if (2..=Self::MAX_SIZE).contains(&value) => size = Some(value),
+                        _ => {
+                            return Err(format!(
+                                "LUT_3D_SIZE must be between 2 and {}, not '{}'",
+                                Self::MAX_SIZE,
+                                value
+                            ))
+                        }
+                    }
+                }
+                "LUT_1D_SIZE" => return Err("1D LUTs are not supported".to_string()),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if values != Ok(vec![expected; 3]) {
+                        return Err(format!(
+                            "Only the default domain of 0 to 1 is supported, not {} '{}'",
+                            keyword, line
+                        ));
+                    }
+                }
+                _ => {
+                    let red = keyword.parse::<f32>();
+                    match (red, values) {
+                        (Ok(red), Ok(values)) if values.len() == 2 => {
+                            texels.push([red, values[0], values[1], 1.0])
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Line {} is not understood: '{}'",
+                                number + 1,
+                                line
+                            ))
+                        }
+                    }
+                }
+            }
+        }
+
+        let size = size.ok_or("LUT_3D_SIZE is missing")?;
+        if texels.len() != size.pow(3) as usize {
+            return Err(format!(
+                "Expected {} entries for a size of {}, but found {}",
+                size.pow(3),
+                size,
+                texels.len()
+            ));
+        }
+        Ok(Self { size, texels })
+    }
+}
+
+/// A bilinear, edge clamped sampler for the post passes.
+pub fn create_linear_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Post Linear Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+/// Group 0 of the post passes: the image read (0) with a linear sampler (1), and the texture
+/// the result is written to (2).
+pub fn create_image_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    input: &wgpu::TextureView,
+    output: &wgpu::TextureView,
+) -> (wgpu::BindGroupLayout, wgpu::BindGroup) {
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format!("{}: Group0 Layout (Image)", label)),
+        entries: &[
+            create_texture_bind_group_layout_entry(0, true, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            create_storage_texture_bind_group_layout_entry(2, HDR_FORMAT),
+        ],
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{}: Group0", label)),
+        layout: &layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&create_linear_sampler(device)),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(output),
+            },
+        ],
+    });
+    (layout, bind_group)
+}
+
+/// Dispatches one thread per pixel of a `width` by `height` image.
+pub fn dispatch_image(pass: &mut wgpu::ComputePass, width: u32, height: u32) {
+    pass.dispatch_workgroups(
+        width.div_ceil(POST_WORKGROUP_SIZE),
+        height.div_ceil(POST_WORKGROUP_SIZE),
+        1,
+    );
+}
+
+/// A post pass of a single dispatch over the image, with an optional second bind group of
+/// its own resources.
+pub struct ImagePass {
+    pipeline: wgpu::ComputePipeline,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl ImagePass {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        label: &str,
+        source: &str,
+        entry_point: &str,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        extra_group: Option<(wgpu::BindGroupLayout, wgpu::BindGroup)>,
+    ) -> Self {
+        let (image_layout, image_bind_group) =
+            create_image_bind_group(device, label, input, output);
+        let mut layouts = vec![&image_layout];
+        let mut bind_groups = vec![image_bind_group];
+        if let Some((layout, bind_group)) = &extra_group {
+            layouts.push(layout);
+            bind_groups.push(bind_group.clone());
+        }
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", label)),
+            bind_group_layouts: &layouts,
+            push_constant_ranges: &[],
+        });
+        let shader = config.create_shader_module(device, &format!("{} Shader", label), source);
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&format!("{} Pipeline", label)),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some(entry_point),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_groups,
+        }
+    }
+
+    pub fn fxaa(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) -> Self {
+        let source = include_str!("shaders/fxaa.wgsl");
+        Self::new(
+            device,
+            config,
+            "FXAA Pass",
+            source,
+            "fxaa_main",
+            input,
+            output,
+            None,
+        )
+    }
+
+    pub fn color_grading(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &PipelineConfig,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        lut: &ColorLut,
+    ) -> Self {
+        let lut_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Colour Grading LUT"),
+                size: wgpu::Extent3d {
+                    width: lut.size,
+                    height: lut.size,
+                    depth_or_array_layers: lut.size,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&lut.texels),
+        );
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Colour Grading Pass: Group1 Layout (LUT)"),
+            entries: &[create_texture_bind_group_layout_entry(
+                0,
+                false,
+                wgpu::TextureViewDimension::D3,
+            )],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Colour Grading Pass: Group1"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &lut_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            }],
+        });
+
+        let source = include_str!("shaders/color_grading.wgsl");
+        Self::new(
+            device,
+            config,
+            "Colour Grading Pass",
+            source,
+            "color_grading_main",
+            input,
+            output,
+            Some((layout, bind_group)),
+        )
+    }
+
+    pub fn vignette(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        input: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+    ) -> Self {
+        let source = include_str!("shaders/vignette.wgsl");
+        Self::new(
+            device,
+            config,
+            "Vignette Pass",
+            source,
+            "vignette_main",
+            input,
+            output,
+            None,
+        )
+    }
+
+    pub fn record(&self, pass: &mut wgpu::ComputePass, width: u32, height: u32) {
+        pass.set_pipeline(&self.pipeline);
+        for (index, bind_group) in self.bind_groups.iter().enumerate() {
+            pass.set_bind_group(index as u32, bind_group, &[]);
+        }
+        dispatch_image(pass, width, height);
+    }
+}
+
+/// The compute post-processing passes run on the custom pipeline's HDR output, between the
+/// fragment pass and the exposure and present passes. Each enabled pass reads the output
+/// and writes a scratch texture, which is copied back over the output for the next.
+pub struct PostChain {
+    /// Passes run every frame, in the order of [`PostEffect::ALL`].
+    pub enabled: PostEffects,
+    /// The camera, written every frame by the scene.
+    pub view_buffer: wgpu::Buffer,
+    ssao_radius: f32,
+    scratch_texture: wgpu::Texture,
+    ssao_pass: SsaoPass,
+    taa_pass: TaaPass,
+    bloom_pass: BloomPass,
+    fxaa_pass: ImagePass,
+    color_grading_pass: ImagePass,
+    vignette_pass: ImagePass,
+}
+
+impl PostChain {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &GpuBuffers,
+        scene: &Scene,
+        config: &PipelineConfig,
+    ) -> Self {
+        let size = buffers.output_texture.size();
+        let scratch_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Post Scratch Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let scratch_view = scratch_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post View Buffer"),
+            size: std::mem::size_of::<PostViewUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let output = &buffers.output_view;
+        let lut = scene
+            .color_lut
+            .clone()
+            .unwrap_or_else(|| ColorLut::identity(2));
+        let [min, max] = scene.bounds;
+
+        Self {
+            enabled: config.post_effects,
+            ssao_radius: (max - min).length() * SSAO_RADIUS_SCALE,
+            ssao_pass: SsaoPass::new(device, config, buffers, &view_buffer, &scratch_view),
+            taa_pass: TaaPass::new(device, config, buffers, &scratch_view),
+            bloom_pass: BloomPass::new(device, config, output, &scratch_view, size),
+            fxaa_pass: ImagePass::fxaa(device, config, output, &scratch_view),
+            color_grading_pass: ImagePass::color_grading(
+                device,
+                queue,
+                config,
+                output,
+                &scratch_view,
+                &lut,
+            ),
+            vignette_pass: ImagePass::vignette(device, config, output, &scratch_view),
+            view_buffer,
+            scratch_texture,
+        }
+    }
+
+    /// Turns `effect` on or off, returning whether it is now on.
+    pub fn toggle(&mut self, effect: PostEffect) -> bool {
+        self.enabled.toggle(effect)
+    }
+
+    /// Writes the camera of the frame, and with TAA on jitters `camera_uniform`, the
+    /// camera the main passes render with.
+    pub fn update_view(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        camera_uniform: &mut CameraUniform,
+    ) {
+        let size = self.scratch_texture.size();
+        self.taa_pass.update_camera(
+            queue,
+            camera_uniform,
+            self.enabled.contains(PostEffect::Taa),
+            size.width,
+            size.height,
+        );
+
+        let view_proj = camera.build_view_projection_matrix();
+        let uniform = PostViewUniform {
+            view_proj: view_proj.to_cols_array_2d(),
+            inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+            view_position: camera.eye.extend(1.0).to_array(),
+            ssao_radius: self.ssao_radius,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.view_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Runs the enabled passes over `output`, each timed by `profiler` as its own pass.
+    pub fn execute(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &mut GpuProfiler,
+        output: &wgpu::Texture,
+    ) {
+        let size = output.size();
+        for effect in self.enabled.iter() {
+            let label = effect.to_string();
+            {
+                let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some(&label),
+                    timestamp_writes: profiler.timestamp_writes(&label),
+                });
+                match effect {
+                    PostEffect::Ssao => self.ssao_pass.record(&mut pass, size.width, size.height),
+                    PostEffect::Taa => self.taa_pass.record(&mut pass, size.width, size.height),
+                    PostEffect::Bloom => self.bloom_pass.record(&mut pass),
+                    PostEffect::Fxaa => self.fxaa_pass.record(&mut pass, size.width, size.height),
+                    PostEffect::ColorGrading => {
+                        self.color_grading_pass
+                            .record(&mut pass, size.width, size.height)
+                    }
+                    PostEffect::Vignette => {
+                        self.vignette_pass
+                            .record(&mut pass, size.width, size.height)
+                    }
+                }
+            }
+            encoder.copy_texture_to_texture(
+                self.scratch_texture.as_image_copy(),
+                output.as_image_copy(),
+                size,
+            );
+            if effect == PostEffect::Taa {
+                encoder.copy_texture_to_texture(
+                    self.scratch_texture.as_image_copy(),
+                    self.taa_pass.history_texture.as_image_copy(),
+                    size,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::test_util::{half_to_f32, read_texture, test_device};
+
+    /// A 2x2x2 LUT inverting every channel.
+    const INVERT_CUBE: &str = "TITLE \"Invert\"\n\
+        # red changes fastest\n\
+        LUT_3D_SIZE 2\n\
+        DOMAIN_MIN 0 0 0\n\
+        DOMAIN_MAX 1.0 1.0 1.0\n\
+        1 1 1\n0 1 1\n1 0 1\n0 0 1\n\
+        1 1 0\n0 1 0\n1 0 0\n0 0 0\n";
+
+    #[test]
+    fn cube_files_parse_in_red_major_order() {
+        let lut = ColorLut::parse(INVERT_CUBE).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.texels[1], [0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(lut.texels[6], [1.0, 0.0, 0.0, 1.0]);
+
+        assert!(ColorLut::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(ColorLut::parse("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").is_err());
+        assert!(ColorLut::parse(&INVERT_CUBE.replace("DOMAIN_MAX 1.0", "DOMAIN_MAX 2.0")).is_err());
+    }
+
+    /// Half floats of 0, 0.25, 1 and 3.
+    const HALVES: [u16; 4] = [0x0000, 0x3400, 0x3c00, 0x4200];
+
+    /// Grades a row of grey pixels with `lut`, returning the red channel of each.
+    fn grade(lut: &ColorLut) -> Option<Vec<f32>> {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping colour grading test");
+            return None;
+        };
+        let size = wgpu::Extent3d {
+            width: HALVES.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        let texture = |label, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage,
+                view_formats: &[],
+            })
+        };
+        let input = texture(
+            "Test Input",
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        );
+        let output = texture(
+            "Test Output",
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        );
+        let texels: Vec<[u16; 4]> = HALVES.iter().map(|&h| [h, h, h, 0x3c00]).collect();
+        queue.write_texture(
+            input.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * 8),
+                rows_per_image: None,
+            },
+            size,
+        );
+
+        let view = |texture: &wgpu::Texture| texture.create_view(&Default::default());
+        let config = PipelineConfig::default();
+        let pass =
+            ImagePass::color_grading(&device, &queue, &config, &view(&input), &view(&output), lut);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.record(&mut cpass, size.width, size.height);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let graded = read_texture(&device, &queue, &output);
+        Some(
+            bytemuck::cast_slice::<u8, [u16; 4]>(&graded)
+                .iter()
+                .map(|texel| half_to_f32(texel[0]))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn color_grading_maps_hdr_through_the_lut() {
+        // An identity LUT leaves the image as it was, HDR values included.
+        let Some(identity) = grade(&ColorLut::identity(2)) else {
+            return;
+        };
+        for (graded, half) in identity.iter().zip(HALVES) {
+            let expected = half_to_f32(half);
+            assert!(
+                (graded - expected).abs() <= expected * 2e-3,
+                "identity LUT graded {} to {}",
+                expected,
+                graded
+            );
+        }
+
+        // Inverting in the compressed range x / (1 + x) maps x to 1 / x.
+        let inverted = grade(&ColorLut::parse(INVERT_CUBE).unwrap()).unwrap();
+        assert!((inverted[1] - 4.0).abs() < 1e-2);
+        assert!((inverted[2] - 1.0).abs() < 1e-2);
+        assert!((inverted[3] - 1.0 / 3.0).abs() < 1e-2);
+        // Black grades to the brightest value the pass keeps.
+        assert!(inverted[0] > 500.0);
+    }
+}
@@ -1,21 +1,49 @@
-use wgpu::PipelineCompilationOptions;
+use std::ops::Range;
 
-use super::gpu_buffers::GpuBuffers;
+use wgpu::{util::DeviceExt, PipelineCompilationOptions};
 
+use super::config::PipelineConfig;
+
+/// Format of the linear HDR image both backends render before it is presented.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Format of the surface both backends present to. It isn't an sRGB format, since the present
+/// pass encodes the image itself.
+pub const SURFACE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
+
+/// Draws the HDR image to the surface, multiplied by the exposure, tone mapped and sRGB
+/// encoded, and outlines the selected triangles. Shared by both backends, so they present
+/// their images the same way.
 pub struct PresentPass {
     pipeline: wgpu::RenderPipeline,
     bind_group_0: wgpu::BindGroup,
-    bind_group_1: wgpu::BindGroup,
+    outline_buffer: wgpu::Buffer,
 }
 
 impl PresentPass {
-    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers) -> Self {
-        // A simple sampler for reading the output texture
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        id_view: &wgpu::TextureView,
+        exposure_buffer: &wgpu::Buffer,
+        config: &PipelineConfig,
+    ) -> Self {
+        // A simple sampler for reading the HDR texture
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
 
+        // Nothing is selected until set_outline is called
+        let outline_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("PresentPass Outline Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; 4]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         // We create a bind group layout that has:
-        //   binding(0) -> our output texture (sampled)
+        //   binding(0) -> the HDR texture (sampled)
         //   binding(1) -> a sampler
+        //   binding(2) -> the exposure buffer
+        //   binding(3) -> the object ID texture (loaded)
+        //   binding(4) -> the range of triangles to outline
         let bind_group_layout_0 =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("PresentPass BGL"),
@@ -36,22 +64,37 @@ impl PresentPass {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
-                ],
-            });
-
-        let bind_group_layout_1 =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Screen Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Uint,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
         let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -60,35 +103,38 @@ impl PresentPass {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&buffers.output_view),
+                    resource: wgpu::BindingResource::TextureView(hdr_view),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(id_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: outline_buffer.as_entire_binding(),
+                },
             ],
         });
 
-        let bind_group_1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Present Pass: Group1"),
-            layout: &bind_group_layout_1,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: buffers.screen_buffer.as_entire_binding(),
-            }],
-        });
-
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("PresentPass pipeline layout"),
-            bind_group_layouts: &[&bind_group_layout_0, &bind_group_layout_1],
+            bind_group_layouts: &[&bind_group_layout_0],
             push_constant_ranges: &[],
         });
 
-        let shader_source = include_str!("shaders/present.wgsl");
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Raster Shader"),
-            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
-        });
+        let shader = config.create_shader_module(
+            device,
+            "Present Shader",
+            include_str!("shaders/present.wgsl"),
+        );
 
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("PresentPass pipeline"),
@@ -103,7 +149,7 @@ impl PresentPass {
                 module: &shader,
                 entry_point: Some("fs_main"),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format: wgpu::TextureFormat::Bgra8Unorm,
+                    format: SURFACE_FORMAT,
                     blend: Some(wgpu::BlendState::REPLACE),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
@@ -119,10 +165,21 @@ impl PresentPass {
         Self {
             pipeline,
             bind_group_0,
-            bind_group_1,
+            outline_buffer,
         }
     }
 
+    /// Outlines the pixels around the surfaces of `triangles`, numbered as in the object ID
+    /// texture, or nothing when `None`.
+    pub fn set_outline(&self, queue: &wgpu::Queue, triangles: Option<Range<u32>>) {
+        let triangles = triangles.unwrap_or(0..0);
+        queue.write_buffer(
+            &self.outline_buffer,
+            0,
+            bytemuck::cast_slice(&[triangles.start, triangles.end, 0, 0]),
+        );
+    }
+
     pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
         let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("PresentPass RenderPass"),
@@ -141,7 +198,6 @@ impl PresentPass {
 
         rpass.set_pipeline(&self.pipeline);
         rpass.set_bind_group(0, &self.bind_group_0, &[]);
-        rpass.set_bind_group(1, &self.bind_group_1, &[]);
         // Draw a single full screen triangle
         rpass.draw(0..3, 0..1);
     }
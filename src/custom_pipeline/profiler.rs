@@ -0,0 +1,125 @@
+/// Times compute passes on the GPU with timestamp queries, on devices that support them.
+/// Like the pipeline counters, timings are only read back on demand, for the most recently
+/// submitted frame.
+pub struct GpuProfiler {
+    queries: Option<ProfilerQueries>,
+    /// Labels of the passes timed this frame, in the order of their queries.
+    labels: Vec<String>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+}
+
+struct ProfilerQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+}
+
+impl GpuProfiler {
+    /// Passes timed per frame. Any after these run untimed.
+    pub const MAX_PASSES: u32 = 16;
+
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let queries = device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+            .then(|| {
+                let size = Self::MAX_PASSES as u64 * 2 * wgpu::QUERY_SIZE as u64;
+                ProfilerQueries {
+                    query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                        label: Some("Profiler Query Set"),
+                        ty: wgpu::QueryType::Timestamp,
+                        count: Self::MAX_PASSES * 2,
+                    }),
+                    resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Profiler Resolve Buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                        mapped_at_creation: false,
+                    }),
+                    readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Profiler Readback Buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                }
+            });
+
+        Self {
+            queries,
+            labels: Vec::new(),
+            timestamp_period: queue.get_timestamp_period(),
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.labels.clear();
+    }
+
+    /// Timestamp writes timing a compute pass under `label`, or None when the device has no
+    /// timestamp queries or the frame has used them all.
+    pub fn timestamp_writes(
+        &mut self,
+        label: &str,
+    ) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        let queries = self.queries.as_ref()?;
+        let index = self.labels.len() as u32;
+        if index == Self::MAX_PASSES {
+            return None;
+        }
+        self.labels.push(label.to_string());
+
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set: &queries.query_set,
+            beginning_of_pass_write_index: Some(index * 2),
+            end_of_pass_write_index: Some(index * 2 + 1),
+        })
+    }
+
+    /// Copies the frame's timestamps where [`Self::read_timings`] reads them from.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(queries) = &self.queries else {
+            return;
+        };
+        if self.labels.is_empty() {
+            return;
+        }
+        let count = self.labels.len() as u32 * 2;
+        encoder.resolve_query_set(&queries.query_set, 0..count, &queries.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &queries.resolve_buffer,
+            0,
+            &queries.readback_buffer,
+            0,
+            count as u64 * wgpu::QUERY_SIZE as u64,
+        );
+    }
+
+    /// GPU time of each pass of the most recently submitted frame, in milliseconds. Blocks
+    /// until the GPU is idle, so this is meant for reporting rather than for every frame.
+    pub fn read_timings(&self, device: &wgpu::Device) -> Vec<(String, f64)> {
+        let Some(queries) = &self.queries else {
+            return Vec::new();
+        };
+        if self.labels.is_empty() {
+            return Vec::new();
+        }
+
+        let slice = queries.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+        let timestamps: Vec<u64> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        queries.readback_buffer.unmap();
+
+        self.labels
+            .iter()
+            .zip(timestamps.chunks_exact(2))
+            .map(|(label, pass)| {
+                let ticks = pass[1].saturating_sub(pass[0]);
+                let millis = ticks as f64 * self.timestamp_period as f64 / 1_000_000.0;
+                (label.clone(), millis)
+            })
+            .collect()
+    }
+}
@@ -1,25 +1,32 @@
-use super::{util::create_buffer_bind_group_layout_entry, GpuBuffers};
-
-pub const TILE_SIZE: u32 = 8;
+use super::{config::PipelineConfig, util::create_buffer_bind_group_layout_entry, GpuBuffers};
 
 pub struct RasterPass {
-    pub pipeline: wgpu::ComputePipeline,
+    /// Dispatched over every tile in order. Only the two pass visibility resolve needs more than one.
+    pub pipelines: Vec<wgpu::ComputePipeline>,
     pub bind_group_0: wgpu::BindGroup,
     pub bind_group_1: wgpu::BindGroup,
     pub bind_group_2: wgpu::BindGroup,
+    config: PipelineConfig,
 }
 
 impl RasterPass {
-    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers) -> Self {
+    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers, config: &PipelineConfig) -> Self {
         let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Raster Pass: Group0 Layout"),
             entries: &[
                 create_buffer_bind_group_layout_entry(0, true),
                 create_buffer_bind_group_layout_entry(1, false),
-                create_buffer_bind_group_layout_entry(2, false),
+                create_buffer_bind_group_layout_entry(2, true),
                 create_buffer_bind_group_layout_entry(3, true),
                 create_buffer_bind_group_layout_entry(4, true),
-                create_buffer_bind_group_layout_entry(5, true)
+                create_buffer_bind_group_layout_entry(5, true),
+                create_buffer_bind_group_layout_entry(6, true),
+                create_buffer_bind_group_layout_entry(7, false),
+                create_buffer_bind_group_layout_entry(8, false),
+                create_buffer_bind_group_layout_entry(9, true),
+                create_buffer_bind_group_layout_entry(10, false),
+                create_buffer_bind_group_layout_entry(11, false),
+                create_buffer_bind_group_layout_entry(12, false),
             ],
         });
 
@@ -57,15 +64,61 @@ impl RasterPass {
             push_constant_ranges: &[],
         });
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shaders/rasteriser.wgsl"));
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("Raster Pass Pipeline"),
-            layout: Some(&pipeline_layout),
-            module: &shader,
-            entry_point: Some("raster_main"),
-            compilation_options: wgpu::PipelineCompilationOptions::default(),
-            cache: None,
-        });
+        // Both rasterisers share their coverage rules, so they fill exactly the same pixels,
+        // and the way they write their output.
+        let common = format!(
+            "{}\n{}",
+            include_str!("shaders/raster_common.wgsl"),
+            include_str!("shaders/raster_output.wgsl")
+        );
+        let (source, entry_points) = if !config.visibility_buffer {
+            (
+                format!("{}\n{}", common, include_str!("shaders/rasteriser.wgsl")),
+                &["raster_main"][..],
+            )
+        } else if supports_64_bit_atomics(device) {
+            (
+                format!(
+                    "{}\n{}\n{}",
+                    common,
+                    include_str!("shaders/visibility.wgsl"),
+                    include_str!("shaders/visibility_atomic64.wgsl")
+                ),
+                &["visibility_main"][..],
+            )
+        } else {
+            (
+                format!(
+                    "{}\n{}\n{}",
+                    common,
+                    include_str!("shaders/visibility.wgsl"),
+                    include_str!("shaders/visibility_two_pass.wgsl")
+                ),
+                &["visibility_depth_main", "visibility_triangle_main"][..],
+            )
+        };
+
+        let shader = config.create_shader_module(device, "Raster Shader", &source);
+        let pipelines = entry_points
+            .iter()
+            .map(|&entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("Raster Pass Pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    cache: None,
+                })
+            })
+            .collect();
+
+        // The raster output: full fragments, or the visibility buffer.
+        let output_buffer = if config.visibility_buffer {
+            &buffers.visibility_buffer
+        } else {
+            &buffers.fragment_buffer
+        };
 
         let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Raster Pass: Group0"),
@@ -77,7 +130,7 @@ impl RasterPass {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: buffers.fragment_buffer.as_entire_binding(),
+                    resource: output_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -95,6 +148,36 @@ impl RasterPass {
                     binding: 5,
                     resource: buffers.triangle_meta_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: buffers.big_triangle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: buffers.stats_buffer.as_entire_binding(),
+                },
+                // Where the standard rasteriser writes its resolved samples with MSAA.
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: buffers.visibility_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: buffers.material_buffer.as_entire_binding(),
+                },
+                // Transparent triangles are listed per pixel rather than resolved.
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: buffers.transparent_head_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: buffers.transparent_node_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: buffers.overdraw_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -117,10 +200,11 @@ impl RasterPass {
         });
 
         Self {
-            pipeline,
+            pipelines,
             bind_group_0,
             bind_group_1,
             bind_group_2,
+            config: *config,
         }
     }
 
@@ -130,15 +214,306 @@ impl RasterPass {
             timestamp_writes: None,
         });
 
-        cpass.set_pipeline(&self.pipeline);
         cpass.set_bind_group(0, &self.bind_group_0, &[]);
         cpass.set_bind_group(1, &self.bind_group_1, &[]);
         cpass.set_bind_group(2, &self.bind_group_2, &[]);
 
         // Calculate number of tiles needed in each dimension
-        let num_tiles_x = (width + TILE_SIZE - 1) / TILE_SIZE;
-        let num_tiles_y = (height + TILE_SIZE - 1) / TILE_SIZE;
+        let (num_tiles_x, num_tiles_y) = self.config.num_tiles(width, height);
 
-        cpass.dispatch_workgroups(num_tiles_x, num_tiles_y, 1);
+        for pipeline in &self.pipelines {
+            cpass.set_pipeline(pipeline);
+            cpass.dispatch_workgroups(num_tiles_x, num_tiles_y, 1);
+        }
+    }
+}
+
+/// Whether the visibility buffer can be resolved in a single pass with 64-bit atomic max.
+fn supports_64_bit_atomics(device: &wgpu::Device) -> bool {
+    device
+        .features()
+        .contains(wgpu::Features::SHADER_INT64 | wgpu::Features::SHADER_INT64_ATOMIC_MIN_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::binning_pass::BinningPass;
+    use crate::custom_pipeline::config::DebugView;
+    use crate::custom_pipeline::test_util::{
+        quads_model, read_buffer, split_quad_scene, test_device,
+    };
+    use crate::custom_pipeline::util::{Fragment, MaterialInfo, PipelineStats};
+    use crate::custom_pipeline::VertexPass;
+    use crate::scene::{LightConfig, Scene};
+    use crate::vertex::CustomVertex;
+
+    const SIZE: u32 = 32;
+
+    /// The split quad three times over at different depths: opaque in the middle, with a
+    /// transparent copy in front of it and another behind it. Triangles 0 and 1 are
+    /// opaque, 2 and 3 are in front and 4 and 5 behind.
+    fn layered_quads_scene() -> Scene {
+        let quad: [CustomVertex; 4] = split_quad_scene(SIZE).models[0]
+            .processed_vertices_custom
+            .clone()
+            .try_into()
+            .unwrap();
+        let layer = |depth, material| {
+            quad.map(|vertex| CustomVertex {
+                position: [vertex.position[0], vertex.position[1], depth],
+                material,
+                ..vertex
+            })
+        };
+        let model = quads_model(
+            [layer(0.5, 0), layer(0.25, 1), layer(0.75, 1)],
+            vec![
+                MaterialInfo::default(),
+                MaterialInfo {
+                    dissolve: 0.5,
+                    ..Default::default()
+                },
+            ],
+        );
+        let mut scene = Scene::new();
+        scene.add_model(model);
+        scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+        scene
+    }
+
+    /// Runs the vertex, binning and raster passes over the scene.
+    fn run_raster(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        config: &PipelineConfig,
+    ) -> GpuBuffers {
+        let buffers = GpuBuffers::new(device, SIZE, SIZE, scene, config);
+        let vertex_pass = VertexPass::new(device, &buffers, config);
+        let binning_pass = BinningPass::new(device, &buffers, config);
+        let raster_pass = RasterPass::new(device, &buffers, config);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.clear_buffer(&buffers.tile_buffer, 0, None);
+        encoder.clear_buffer(&buffers.coarse_buffer, 0, None);
+        encoder.clear_buffer(&buffers.big_triangle_buffer, 0, None);
+        encoder.clear_buffer(&buffers.stats_buffer, 0, None);
+        encoder.clear_buffer(&buffers.visibility_buffer, 0, None);
+        vertex_pass.execute(&mut encoder, buffers.vertex_count);
+        binning_pass.execute(&mut encoder, scene.gx_tris, scene.gy_tris, SIZE, SIZE);
+        raster_pass.execute(&mut encoder, SIZE, SIZE);
+        queue.submit(Some(encoder.finish()));
+        buffers
+    }
+
+    fn read_stats(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &GpuBuffers,
+    ) -> PipelineStats {
+        let stats = read_buffer(device, queue, &buffers.stats_buffer);
+        *bytemuck::from_bytes(bytemuck::cast_slice(&stats))
+    }
+
+    /// Rasterises the scene and returns, per sample, whether it ended up covered.
+    fn rasterise(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        config: &PipelineConfig,
+    ) -> (Vec<bool>, PipelineStats) {
+        let buffers = run_raster(device, queue, scene, config);
+        let covered = if config.stores_visibility() {
+            // The triangle key is the low word of each sample; zero means empty.
+            read_buffer(device, queue, &buffers.visibility_buffer)
+                .chunks_exact(2)
+                .map(|sample| sample[0] != 0)
+                .collect()
+        } else {
+            let fragments = read_buffer(device, queue, &buffers.fragment_buffer);
+            bytemuck::cast_slice::<u32, Fragment>(&fragments)
+                .iter()
+                .map(|fragment| fragment.flag != 0)
+                .collect()
+        };
+        (covered, read_stats(device, queue, &buffers))
+    }
+
+    /// Samples inside the square, in the same order as the raster output. Positions are
+    /// in sixteenths of a pixel, and the fill rule keeps the left and top edges of the
+    /// square but drops the right and bottom ones.
+    fn expected_coverage(config: &PipelineConfig) -> Vec<bool> {
+        let inside = |position: i32| (2 * 16 + 8..29 * 16 + 8).contains(&position);
+        (0..(SIZE * SIZE) as i32)
+            .flat_map(|pixel| {
+                let (x, y) = (pixel % SIZE as i32, pixel / SIZE as i32);
+                config
+                    .sample_offsets()
+                    .iter()
+                    .map(move |[dx, dy]| inside(x * 16 + 8 + dx) && inside(y * 16 + 8 + dy))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn split_quad_covers_every_sample_once() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping split quad test");
+            return;
+        };
+        let scene = split_quad_scene(SIZE);
+
+        for visibility_buffer in [false, true] {
+            for (tile_size, msaa_samples) in [(8, 1), (16, 1), (8, 4), (16, 8)] {
+                let config = PipelineConfig {
+                    tile_size,
+                    visibility_buffer,
+                    msaa_samples,
+                    ..Default::default()
+                };
+                let (covered, stats) = rasterise(&device, &queue, &scene, &config);
+                let expected = expected_coverage(&config);
+
+                let mode = format!(
+                    "{} raster, tile size {}, {}x MSAA",
+                    if visibility_buffer {
+                        "visibility buffer"
+                    } else {
+                        "standard"
+                    },
+                    tile_size,
+                    msaa_samples
+                );
+                assert_eq!(covered, expected, "{}", mode);
+                assert_eq!(stats.vertex_invocations, 4, "{}", mode);
+                // Every covered sample was covered by exactly one of the two triangles.
+                assert_eq!(
+                    stats.rasterised_samples,
+                    expected.iter().filter(|&&covered| covered).count() as u32,
+                    "{}",
+                    mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_fragments_are_listed_per_pixel() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping transparency test");
+            return;
+        };
+        let scene = layered_quads_scene();
+
+        for visibility_buffer in [false, true] {
+            for msaa_samples in [1, 4] {
+                let config = PipelineConfig {
+                    visibility_buffer,
+                    msaa_samples,
+                    ..Default::default()
+                };
+                let buffers = run_raster(&device, &queue, &scene, &config);
+                let heads = read_buffer(&device, &queue, &buffers.transparent_head_buffer);
+                let nodes = read_buffer(&device, &queue, &buffers.transparent_node_buffer);
+                let stats = read_stats(&device, &queue, &buffers);
+                let mode = format!(
+                    "{} raster, {}x MSAA",
+                    if visibility_buffer {
+                        "visibility buffer"
+                    } else {
+                        "standard"
+                    },
+                    msaa_samples
+                );
+
+                // Walk every pixel's list, as (triangle, coverage) sorted by triangle.
+                let expected = expected_coverage(&config);
+                let mut listed = 0;
+                for (pixel, &head) in heads.iter().enumerate() {
+                    let mut fragments = Vec::new();
+                    let mut next = head;
+                    while next != 0 {
+                        let node = &nodes[1 + (next as usize - 1) * 4..][..4];
+                        fragments.push((node[1], node[3]));
+                        next = node[0];
+                    }
+                    fragments.sort();
+                    listed += fragments.len() as u32;
+
+                    let samples = msaa_samples as usize;
+                    let coverage = (0..samples)
+                        .filter(|&sample| expected[pixel * samples + sample])
+                        .fold(0, |mask, sample| mask | 1 << sample);
+                    // Only the quad in front of the opaque one is listed.
+                    let quads: &[[u32; 2]] = if coverage == 0 { &[] } else { &[[2, 3]] };
+                    // Each listed quad covers the pixel's samples exactly once between its
+                    // two triangles, which may split a pixel on the diagonal.
+                    let quads_covered = quads.iter().all(|quad| {
+                        let masks = fragments
+                            .iter()
+                            .filter(|(triangle, _)| quad.contains(triangle))
+                            .map(|&(_, mask)| mask);
+                        masks.clone().fold(0, |union, mask| union | mask) == coverage
+                            && masks.map(u32::count_ones).sum::<u32>() == coverage.count_ones()
+                    });
+                    let only_quads = fragments.iter().all(|(triangle, mask)| {
+                        *mask != 0 && quads.iter().any(|q| q.contains(triangle))
+                    });
+                    assert!(
+                        quads_covered && only_quads,
+                        "{}: pixel {} lists {:?}",
+                        mode,
+                        pixel,
+                        fragments
+                    );
+                }
+                assert_eq!(stats.transparent_fragments, listed, "{}", mode);
+                // The opaque quad and the one in front cover each sample once, and the one
+                // behind isn't counted, whichever mode resolved them.
+                let covered = expected.iter().filter(|&&covered| covered).count() as u32;
+                assert_eq!(stats.rasterised_samples, 2 * covered, "{}", mode);
+            }
+        }
+    }
+
+    #[test]
+    fn overdraw_counts_every_depth_test() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping overdraw test");
+            return;
+        };
+        let scene = layered_quads_scene();
+
+        for visibility_buffer in [false, true] {
+            for msaa_samples in [1, 4] {
+                let config = PipelineConfig {
+                    visibility_buffer,
+                    msaa_samples,
+                    debug_view: DebugView::Overdraw,
+                    ..Default::default()
+                };
+                let buffers = run_raster(&device, &queue, &scene, &config);
+                let overdraw = read_buffer(&device, &queue, &buffers.overdraw_buffer);
+
+                // Each of the three quads is tested once at every sample it covers, whether
+                // it is transparent or not, and however many passes the resolve takes.
+                let expected: Vec<u32> = expected_coverage(&config)
+                    .chunks_exact(msaa_samples as usize)
+                    .map(|samples| 3 * samples.iter().filter(|&&covered| covered).count() as u32)
+                    .collect();
+                assert_eq!(
+                    overdraw,
+                    expected,
+                    "{} raster, {}x MSAA",
+                    if visibility_buffer {
+                        "visibility buffer"
+                    } else {
+                        "standard"
+                    },
+                    msaa_samples
+                );
+            }
+        }
     }
 }
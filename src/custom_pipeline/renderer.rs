@@ -1,8 +1,20 @@
+use std::ops::Range;
+
+use crate::overlay::Overlay;
 use crate::scene::{self, Scene};
 
 use super::{
-    binning_pass::BinningPass, present_pass::PresentPass, raster_pass::TILE_SIZE,
-    util::dispatch_size, FragmentPass, GpuBuffers, RasterPass,
+    binning_pass::BinningPass,
+    capture::{CaptureBuffer, FrameCapture, PendingCapture},
+    config::{DebugView, PipelineConfig},
+    exposure_pass::ExposurePass,
+    light_culling_pass::LightCullingPass,
+    picking::{Pick, Picker},
+    post_chain::PostChain,
+    present_pass::{PresentPass, SURFACE_FORMAT},
+    profiler::GpuProfiler,
+    util::PipelineStats,
+    FragmentPass, GpuBuffers, RasterPass, ShadowPass, VertexPass,
 };
 
 pub struct CustomRenderer {
@@ -10,32 +22,60 @@ pub struct CustomRenderer {
     pub queue: wgpu::Queue,
 
     pub surface_config: wgpu::SurfaceConfiguration,
+    pub pipeline_config: PipelineConfig,
 
     pub buffers: GpuBuffers,
 
+    pub vertex_pass: VertexPass,
     pub binning_pass: BinningPass,
     pub raster_pass: RasterPass,
     pub fragment_pass: FragmentPass,
 
+    // Lists the lights of each tile for the fragment pass to light opaque surfaces with,
+    // before it runs, or between writing and lighting the G-buffer in deferred mode. Debug
+    // views shade nothing with it
+    pub light_culling_pass: Option<LightCullingPass>,
+
+    // Renders the shadow maps before the main passes, when shadows are enabled
+    pub shadow_pass: Option<ShadowPass>,
+
+    // Post-processes the output in HDR, timing each pass it runs
+    pub post_chain: PostChain,
+    pub profiler: GpuProfiler,
+
+    // Meters the output for auto exposure before it is presented
+    pub exposure_pass: ExposurePass,
     pub present_pass: PresentPass,
 
     pub width: u32,
     pub height: u32,
+
+    // Buffers to capture after the raster pass of the next frame, and the copies of them
+    // waiting to be read back
+    capture_request: Option<Vec<CaptureBuffer>>,
+    pending_capture: Option<PendingCapture>,
+
+    // Reads back the triangle under picked pixels, and the triangles outlined as selected
+    picker: Picker,
+    selection: Option<Range<u32>>,
 }
 
 impl CustomRenderer {
+    /// Creates the renderer for `surface`, or for off-screen targets passed to
+    /// [`Self::render_to`] without one.
     pub async fn new(
         instance: &wgpu::Instance,
-        surface: &wgpu::Surface<'_>,
+        surface: Option<&wgpu::Surface<'_>>,
         width: u32,
         height: u32,
         scene: &Scene,
+        pipeline_config: PipelineConfig,
     ) -> Self {
         // Choose adapter
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
+                compatible_surface: surface,
                 force_fallback_adapter: false,
             })
             .await
@@ -55,13 +95,15 @@ impl CustomRenderer {
             .await
             .expect("Failed to create device");
 
-        let format = wgpu::TextureFormat::Bgra8Unorm;
+        let format = SURFACE_FORMAT;
 
-        let surface_caps = surface.get_capabilities(&adapter);
-        let present_mode = if surface_caps.present_modes.contains(&wgpu::PresentMode::Immediate) {
+        let present_modes = surface.map_or(vec![wgpu::PresentMode::Fifo], |surface| {
+            surface.get_capabilities(&adapter).present_modes
+        });
+        let present_mode = if present_modes.contains(&wgpu::PresentMode::Immediate) {
             &wgpu::PresentMode::Immediate
         } else {
-            surface_caps.present_modes.first().unwrap()
+            present_modes.first().unwrap()
         };
 
         let surface_config = wgpu::SurfaceConfiguration {
@@ -74,31 +116,61 @@ impl CustomRenderer {
             view_formats: vec![],
             desired_maximum_frame_latency: 1,
         };
-        surface.configure(&device, &surface_config);
+        if let Some(surface) = surface {
+            surface.configure(&device, &surface_config);
+        }
 
         // Create the GpuBuffers and passes
         let width = surface_config.width;
         let height = surface_config.height;
-        let buffers = GpuBuffers::new(&device, width, height, scene);
+        let buffers = GpuBuffers::new(&device, width, height, scene, &pipeline_config);
+
+        let vertex_pass = VertexPass::new(&device, &buffers, &pipeline_config);
+        let binning_pass = BinningPass::new(&device, &buffers, &pipeline_config);
+        let raster_pass = RasterPass::new(&device, &buffers, &pipeline_config);
+        let fragment_pass = FragmentPass::new(&device, &buffers, &pipeline_config);
+        let light_culling_pass = (pipeline_config.culls_lights()
+            && !pipeline_config.shows_debug_view())
+        .then(|| LightCullingPass::new(&device, &buffers, &pipeline_config));
+        let shadow_pass = (pipeline_config.shadow_views(scene) > 0)
+            .then(|| ShadowPass::new(&device, scene, &pipeline_config));
 
-        let binning_pass = BinningPass::new(&device, &buffers);
-        let raster_pass = RasterPass::new(&device, &buffers);
-        let fragment_pass = FragmentPass::new(&device, &buffers);
+        let post_chain = PostChain::new(&device, &queue, &buffers, scene, &pipeline_config);
+        let profiler = GpuProfiler::new(&device, &queue);
 
-        // Create the final pass that samples from the output texture
-        let present_pass = PresentPass::new(&device, &buffers);
+        // Create the final passes that meter and sample from the output texture
+        let exposure_pass = ExposurePass::new(&device, &buffers.output_view, &pipeline_config);
+        let present_pass = PresentPass::new(
+            &device,
+            &buffers.output_view,
+            &buffers.object_id_view,
+            &exposure_pass.exposure_buffer,
+            &pipeline_config,
+        );
+        let picker = Picker::new(&device);
 
         Self {
             device,
             queue,
             surface_config,
+            pipeline_config,
             buffers,
+            vertex_pass,
             binning_pass,
             raster_pass,
             fragment_pass,
+            light_culling_pass,
+            shadow_pass,
+            post_chain,
+            profiler,
+            exposure_pass,
             present_pass,
             width,
             height,
+            capture_request: None,
+            pending_capture: None,
+            picker,
+            selection: None,
         }
     }
 
@@ -106,6 +178,7 @@ impl CustomRenderer {
         &mut self,
         surface: &wgpu::Surface<'_>,
         scene: &scene::Scene,
+        overlay: Option<&mut Overlay>,
     ) -> Result<(), wgpu::SurfaceError> {
         let frame = match surface.get_current_texture() {
             Ok(frame) => frame,
@@ -115,45 +188,184 @@ impl CustomRenderer {
         let frame_view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to(&frame_view, scene);
+        if let Some(overlay) = overlay {
+            overlay.paint(
+                &self.device,
+                &self.queue,
+                &frame_view,
+                frame.texture.width(),
+                frame.texture.height(),
+            );
+        }
+        frame.present();
 
+        Ok(())
+    }
+
+    /// Renders a frame into `frame_view`, a [`SURFACE_FORMAT`] texture of the surface's size.
+    pub fn render_to(&mut self, frame_view: &wgpu::TextureView, scene: &scene::Scene) {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Command Encoder"),
             });
 
-        let num_tiles_x =
-            (self.surface_config.width as usize + TILE_SIZE as usize - 1) / TILE_SIZE as usize;
-        let num_tiles_y =
-            (self.surface_config.height as usize + TILE_SIZE as usize - 1) / TILE_SIZE as usize;
+        let total_pixel_dispatch = self
+            .pipeline_config
+            .dispatch_size(self.surface_config.width * self.surface_config.height);
 
-        let total_tile_dispatch = dispatch_size((num_tiles_x * num_tiles_y) as u32);
+        // Tile bins, the big triangle list and counters are rebuilt from scratch every frame.
+        encoder.clear_buffer(&self.buffers.tile_buffer, 0, None);
+        encoder.clear_buffer(&self.buffers.coarse_buffer, 0, None);
+        encoder.clear_buffer(&self.buffers.big_triangle_buffer, 0, None);
+        encoder.clear_buffer(&self.buffers.stats_buffer, 0, None);
+        if self.pipeline_config.stores_visibility() {
+            encoder.clear_buffer(&self.buffers.visibility_buffer, 0, None);
+        }
+        // Emptying the transparent fragment lists only takes their heads and the node count.
+        encoder.clear_buffer(&self.buffers.transparent_head_buffer, 0, None);
+        encoder.clear_buffer(&self.buffers.transparent_node_buffer, 0, Some(4));
+        if self.pipeline_config.debug_view == DebugView::Overdraw {
+            encoder.clear_buffer(&self.buffers.overdraw_buffer, 0, None);
+        }
 
-        let total_pixel_dispatch =
-            dispatch_size(self.surface_config.width * self.surface_config.height);
+        if let Some(shadow_pass) = &self.shadow_pass {
+            shadow_pass.execute(&mut encoder, scene, &self.buffers);
+        }
 
+        self.vertex_pass
+            .execute(&mut encoder, self.buffers.vertex_count);
         self.binning_pass.execute(
             &mut encoder,
             scene.gx_tris,
             scene.gy_tris,
-            total_tile_dispatch,
+            self.surface_config.width,
+            self.surface_config.height,
         );
         self.raster_pass.execute(
             &mut encoder,
             self.surface_config.width,
             self.surface_config.height,
         );
+        if let Some(captured) = self.capture_request.take() {
+            self.pending_capture = Some(PendingCapture::record(
+                &self.device,
+                &mut encoder,
+                &self.buffers,
+                &self.pipeline_config,
+                self.surface_config.width,
+                self.surface_config.height,
+                &captured,
+            ));
+        }
+        // Forward shading culls the lights from the raster output, deferred shading from the
+        // G-buffer the fragment pass writes.
+        let light_culling_pass = self.light_culling_pass.as_ref();
+        if let Some(light_culling_pass) =
+            light_culling_pass.filter(|_| !self.pipeline_config.deferred)
+        {
+            light_culling_pass.execute(
+                &mut encoder,
+                self.surface_config.width,
+                self.surface_config.height,
+            );
+        }
         self.fragment_pass
             .execute(&mut encoder, total_pixel_dispatch);
+        if let Some(light_culling_pass) =
+            light_culling_pass.filter(|_| self.pipeline_config.deferred)
+        {
+            light_culling_pass.execute(
+                &mut encoder,
+                self.surface_config.width,
+                self.surface_config.height,
+            );
+            self.fragment_pass
+                .execute_lighting(&mut encoder, total_pixel_dispatch);
+        }
+
+        // Debug views are shown as the fragment pass wrote them.
+        self.profiler.begin_frame();
+        if !self.pipeline_config.shows_debug_view() {
+            self.post_chain.execute(
+                &mut encoder,
+                &mut self.profiler,
+                &self.buffers.output_texture,
+            );
+        }
+
+        self.picker
+            .record(&mut encoder, &self.buffers.object_id_texture);
 
+        self.exposure_pass.execute(
+            &self.queue,
+            &mut encoder,
+            self.surface_config.width,
+            self.surface_config.height,
+        );
         self.present_pass
-            .execute(&mut encoder, &frame_view);
+            .set_outline(&self.queue, self.selection.clone());
+        self.present_pass.execute(&mut encoder, frame_view);
+
+        encoder.copy_buffer_to_buffer(
+            &self.buffers.stats_buffer,
+            0,
+            &self.buffers.stats_readback_buffer,
+            0,
+            self.buffers.stats_buffer.size(),
+        );
+        self.profiler.resolve(&mut encoder);
 
         self.queue.submit(Some(encoder.finish()));
+        self.picker.submitted();
+    }
 
-        frame.present();
+    /// Reads back the counters of the most recently submitted frame. Blocks until the GPU is idle,
+    /// so this is meant for reporting rather than for every frame.
+    pub fn read_stats(&self) -> PipelineStats {
+        let slice = self.buffers.stats_readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
 
-        Ok(())
+        let stats = *bytemuck::from_bytes(&slice.get_mapped_range());
+        self.buffers.stats_readback_buffer.unmap();
+        stats
+    }
+
+    /// GPU time of each post pass of the most recently submitted frame, in milliseconds, or
+    /// none when the device can't time passes. Blocks like [`Self::read_stats`].
+    pub fn read_timings(&self) -> Vec<(String, f64)> {
+        self.profiler.read_timings(&self.device)
+    }
+
+    /// Captures `buffers` as the raster pass leaves them in the next frame rendered.
+    pub fn capture_next_frame(&mut self, buffers: &[CaptureBuffer]) {
+        self.capture_request = Some(buffers.to_vec());
+    }
+
+    /// The buffers captured in the last frame rendered after [`Self::capture_next_frame`],
+    /// or none if it hasn't been rendered yet. Blocks like [`Self::read_stats`].
+    pub fn read_capture(&mut self) -> Option<FrameCapture> {
+        let pending = self.pending_capture.take()?;
+        Some(pending.read(&self.device))
+    }
+
+    /// Reads back the triangle at pixel (`x`, `y`) of the next frame rendered, returned by
+    /// [`Self::poll_pick`] once the GPU has copied it.
+    pub fn pick(&mut self, x: u32, y: u32) {
+        self.picker.request(x, y);
+    }
+
+    /// The triangle picked with [`Self::pick`], if it has been read back. Doesn't block.
+    pub fn poll_pick(&mut self) -> Option<Pick> {
+        self.picker.poll(&self.device)
+    }
+
+    /// Outlines the surfaces of `triangles`, numbered across the scene's models, from the
+    /// next frame on.
+    pub fn set_selection(&mut self, triangles: Option<Range<u32>>) {
+        self.selection = triangles;
     }
 
     pub fn resize(&mut self, config: &wgpu::SurfaceConfiguration, scene: &Scene) {
@@ -161,11 +373,49 @@ impl CustomRenderer {
         self.width = config.width;
         self.height = config.height;
 
-        // Recreate the output texture and present pass
-        self.buffers = GpuBuffers::new(&self.device, self.width, self.height, scene);
-        self.binning_pass = BinningPass::new(&self.device, &self.buffers);
-        self.raster_pass = RasterPass::new(&self.device, &self.buffers);
-        self.fragment_pass = FragmentPass::new(&self.device, &self.buffers);
-        self.present_pass = PresentPass::new(&self.device, &self.buffers);
+        // Recreate the output texture and the passes reading it
+        self.buffers = GpuBuffers::new(
+            &self.device,
+            self.width,
+            self.height,
+            scene,
+            &self.pipeline_config,
+        );
+        self.vertex_pass = VertexPass::new(&self.device, &self.buffers, &self.pipeline_config);
+        self.binning_pass = BinningPass::new(&self.device, &self.buffers, &self.pipeline_config);
+        self.raster_pass = RasterPass::new(&self.device, &self.buffers, &self.pipeline_config);
+        self.fragment_pass = FragmentPass::new(&self.device, &self.buffers, &self.pipeline_config);
+        self.light_culling_pass = (self.pipeline_config.culls_lights()
+            && !self.pipeline_config.shows_debug_view())
+        .then(|| LightCullingPass::new(&self.device, &self.buffers, &self.pipeline_config));
+        // Toggled passes stay as they were
+        let enabled = self.post_chain.enabled;
+        self.post_chain = PostChain::new(
+            &self.device,
+            &self.queue,
+            &self.buffers,
+            scene,
+            &self.pipeline_config,
+        );
+        self.post_chain.enabled = enabled;
+        self.exposure_pass = ExposurePass::new(
+            &self.device,
+            &self.buffers.output_view,
+            &self.pipeline_config,
+        );
+        self.present_pass = PresentPass::new(
+            &self.device,
+            &self.buffers.output_view,
+            &self.buffers.object_id_view,
+            &self.exposure_pass.exposure_buffer,
+            &self.pipeline_config,
+        );
+    }
+
+    /// Switches what the fragment pass shows, rebuilding the passes for it.
+    pub fn set_debug_view(&mut self, debug_view: DebugView, scene: &Scene) {
+        self.pipeline_config.debug_view = debug_view;
+        let config = self.surface_config.clone();
+        self.resize(&config, scene);
     }
 }
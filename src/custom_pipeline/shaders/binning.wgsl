@@ -1,11 +1,18 @@
-// Define a tile size constant
-const TILE_SIZE = 8u;
+// TILE_SIZE, WORKGROUP_SIZE, BINNING_THREADS, COARSE_FACTOR and BIG_TRIANGLE_COARSE_TILES
+// are injected by PipelineConfig::shader_header.
+//
+// Binning is hierarchical: triangles are first binned into coarse tiles of
+// COARSE_FACTOR x COARSE_FACTOR fine tiles, then each coarse tile bins its own
+// triangles into the fine tiles the rasteriser works on. Triangles spanning more
+// than BIG_TRIANGLE_COARSE_TILES coarse tiles skip both levels and go into a
+// single big triangle list that the rasteriser checks from every tile.
 
 struct Vertex {
     world_pos: vec3<f32>,
     screen_pos: vec4<f32>,
     normal: vec3<f32>,
     uv: vec2<f32>,
+    material: u32,
 };
 
 struct TileTriangles {
@@ -14,6 +21,21 @@ struct TileTriangles {
     write_index: atomic<u32>,
 };
 
+// Triangles too big to bin, stored as their base index in the index buffer.
+struct BigTriangles {
+    count: atomic<u32>,
+    triangles: array<u32>,
+};
+
+// Counters read back by CustomRenderer::read_stats.
+struct PipelineStats {
+    binned_tile_pairs: atomic<u32>,
+    rejected_tile_pairs: atomic<u32>,
+    vertex_invocations: atomic<u32>,
+    rasterised_samples: atomic<u32>,
+    transparent_fragments: atomic<u32>,
+};
+
 struct UniformBinning {
     width: f32,
     height: f32,
@@ -41,17 +63,6 @@ struct EffectUniform {
     _padding: vec2<f32>,
 };
 
-struct VertexIn {
-    world_pos: vec3<f32>,
-    normal: vec3<f32>,
-    uv: vec2<f32>,
-};
-
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-};
-
 // universal buffers
 @group(0) @binding(0) var<storage, read_write> tile_buffer: array<TileTriangles>;
 @group(0) @binding(1) var<storage, read_write> triangle_binning_buffer: array<TriangleBinningData>;
@@ -61,15 +72,17 @@ struct Camera {
 @group(1) @binding(0) var<storage, read_write> partial_sums: array<u32>;
 
 @group(2) @binding(0) var<storage, read> index_buffer: array<u32>;
-@group(2) @binding(1) var<storage, read> vertex_buffer: array<VertexIn>;
-@group(2) @binding(2) var<storage, read_write> projected_buffer: array<Vertex>;
-@group(2) @binding(3) var<uniform> camera: Camera;
+@group(2) @binding(1) var<storage, read> projected_buffer: array<Vertex>;
 
 @group(3) @binding(0) var<storage, read_write> triangle_list_buffer: array<u32>;
+@group(3) @binding(1) var<storage, read_write> coarse_buffer: array<TileTriangles>;
+@group(3) @binding(2) var<storage, read_write> coarse_list_buffer: array<u32>;
+@group(3) @binding(3) var<storage, read_write> big_triangles: BigTriangles;
+@group(3) @binding(4) var<storage, read_write> stats: PipelineStats;
 
 
 // Use workgroup shared memory for the local scan:
-var<workgroup> shared_data: array<u32, 256u>;
+var<workgroup> shared_data: array<u32, WORKGROUP_SIZE>;
 
 fn get_min_max(v1: vec3<f32>, v2: vec3<f32>, v3: vec3<f32>) -> vec4<f32> {
     let min_x = min(min(v1.x, v2.x), v3.x);
@@ -90,16 +103,17 @@ fn clip_bbox_to_screen(bbox: vec4<f32>) -> vec4<f32> {
     );
 }
 
-fn compute_triangle_meta(triangle_index: u32) {
-    let v1 = shared_v[0];
-    let v2 = shared_v[1];
-    let v3 = shared_v[2];
+fn compute_triangle_meta(base_idx: u32) -> TriangleBinningData {
+    let v1 = projected_buffer[index_buffer[base_idx]];
+    let v2 = projected_buffer[index_buffer[base_idx + 1u]];
+    let v3 = projected_buffer[index_buffer[base_idx + 2u]];
+
+    let culled = TriangleBinningData(vec4<f32>(0.0), vec2<u32>(0u, 0u), vec2<u32>(0u, 0u));
     
     // First, perform a simple clip test in clip/screen space:
     // Discard triangles with any vertex behind the near plane.
     if v1.screen_pos.w < 0.0 || v2.screen_pos.w < 0.0 || v3.screen_pos.w < 0.0 {
-        triangle_binning_buffer[triangle_index].tile_range = vec2<u32>(0u, 0u);
-        return;
+        return culled;
     }
     
     // Compute the 2D bounding box in screen space.
@@ -108,8 +122,7 @@ fn compute_triangle_meta(triangle_index: u32) {
     // Quick cull: if the triangle’s bbox is completely outside the screen,
     // then discard it.
     if bbox.z < 0.0 || bbox.x >= screen_dims.width || bbox.w < 0.0 || bbox.y >= screen_dims.height {
-        triangle_binning_buffer[triangle_index].tile_range = vec2<u32>(0u, 0u);
-        return;
+        return culled;
     }
 
     // Back-face culling (unless the effect requires both sides).
@@ -117,84 +130,56 @@ fn compute_triangle_meta(triangle_index: u32) {
     let b = vec2<f32>(v3.screen_pos.x - v1.screen_pos.x, v3.screen_pos.y - v1.screen_pos.y);
     let cross_z = a.x * b.y - a.y * b.x;
     if effect.effect_type != 3u && cross_z >= 0.0 {
-        triangle_binning_buffer[triangle_index].tile_range = vec2<u32>(0u, 0u);
-        return;
+        return culled;
     }
     
     // Now “clip” the bbox to the screen dimensions.
     let clipped_bbox = clip_bbox_to_screen(bbox);
     
-    // Use the clipped bbox to compute tile indices.
+    // Use the clipped bbox to compute tile indices. The end tile is the one holding the
+    // bbox maximum, so a maximum lying exactly on a tile border still includes that tile.
     let start_tile_x = u32(max(floor(clipped_bbox.x / f32(TILE_SIZE)), 0.0));
     let start_tile_y = u32(max(floor(clipped_bbox.y / f32(TILE_SIZE)), 0.0));
-    let end_tile_x = min(u32(ceil(clipped_bbox.z / f32(TILE_SIZE))),
+    let end_tile_x = min(u32(floor(clipped_bbox.z / f32(TILE_SIZE))) + 1u,
         screen_dims.num_tiles_x);
-    let end_tile_y = min(u32(ceil(clipped_bbox.w / f32(TILE_SIZE))),
+    let end_tile_y = min(u32(floor(clipped_bbox.w / f32(TILE_SIZE))) + 1u,
         screen_dims.num_tiles_y);
 
     let tile_range_x = end_tile_x - start_tile_x;
     let tile_range_y = end_tile_y - start_tile_y;
     
-    // Store the computed metadata.
-    triangle_binning_buffer[triangle_index].min_max = clipped_bbox;
-    triangle_binning_buffer[triangle_index].start_tile = vec2<u32>(start_tile_x, start_tile_y);
-    triangle_binning_buffer[triangle_index].tile_range = vec2<u32>(tile_range_x, tile_range_y);
+    return TriangleBinningData(
+        clipped_bbox,
+        vec2<u32>(start_tile_x, start_tile_y),
+        vec2<u32>(tile_range_x, tile_range_y)
+    );
 }
 
-fn apply_wave_effect(pos: vec3<f32>, effect: EffectUniform) -> vec3<f32> {
-    var modified_pos = pos;
-    let amplitude = effect.param1;
-    let frequency = effect.param2;
-    let phase = effect.param3;
-    let direction = effect.param4;
-
-    if direction < 0.5 { // Vertical
-        modified_pos.y += amplitude * sin(frequency * pos.x + phase);
-    } else if direction < 1.5 { // Horizontal
-        modified_pos.x += amplitude * sin(frequency * pos.y + phase);
-    } else { // Radial
-        let dist = length(pos.xy);
-        modified_pos.z += amplitude * sin(frequency * dist + phase);
-    }
-
-    return modified_pos;
+fn coarse_tiles_x() -> u32 {
+    return (screen_dims.num_tiles_x + COARSE_FACTOR - 1u) / COARSE_FACTOR;
 }
 
-fn compute_screen_pos(clip_pos: vec4<f32>) -> vec4<f32> {
-    let ndc_pos = clip_pos.xyz / clip_pos.w;
-
-    return vec4<f32>(
-        ((ndc_pos.x + 1.0) * 0.5) * screen_dims.width,
-        ((1.0 - ndc_pos.y) * 0.5) * screen_dims.height,
-        clip_pos.z / clip_pos.w,
-        clip_pos.w
-    );
+fn coarse_tiles_y() -> u32 {
+    return (screen_dims.num_tiles_y + COARSE_FACTOR - 1u) / COARSE_FACTOR;
 }
 
-fn geometry_pipeline(idx: u32) -> Vertex {
-    // Load original vertex data.
-    let v_in = vertex_buffer[idx];
-
-    // Apply any effects if needed.
-    var world_pos = v_in.world_pos;
-    if effect.effect_type == 1u {
-        world_pos = apply_wave_effect(world_pos, effect);
-    }
-        
-    // Transform to clip space and then compute screen positions.
-    let clip = camera.view_proj * vec4<f32>(world_pos, 1.0);
-    let screen_pos = compute_screen_pos(clip);
-
-    return Vertex(world_pos, screen_pos, v_in.normal, v_in.uv);
+// Coarse tiles overlapped by a triangle: (start_x, start_y, range_x, range_y).
+fn coarse_range(tri: TriangleBinningData) -> vec4<u32> {
+    let start = tri.start_tile / COARSE_FACTOR;
+    let end = (tri.start_tile + tri.tile_range - vec2<u32>(1u)) / COARSE_FACTOR + vec2<u32>(1u);
+    return vec4<u32>(start, end - start);
 }
 
-// Use workgroup shared memory for the transformed vertices.
-var<workgroup> shared_v: array<Vertex, 3>;
+fn is_big_triangle(coarse: vec4<u32>) -> bool {
+    return coarse.z * coarse.w > BIG_TRIANGLE_COARSE_TILES;
+}
 
-const Z_DISPATCHES = 3u;
-@compute @workgroup_size(1, 1, Z_DISPATCHES)
+//---------------------------------------------------------------------
+// Kernel 1: Compute a triangle's binning metadata from its projected vertices and
+// count it into every coarse tile its bounding box overlaps. Big triangles are
+// instead appended to the big triangle list and never enter the per-tile lists.
+@compute @workgroup_size(1, 1, BINNING_THREADS)
 fn count_triangles(
-    @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(workgroup_id) wg: vec3<u32>,
     @builtin(local_invocation_id) lid: vec3<u32>,
     @builtin(num_workgroups) num_workgroups: vec3<u32>
@@ -209,46 +194,46 @@ fn count_triangles(
     // Each workgroup handles one triangle.
     let base_idx = triangle_index * 3u;
 
-    let idx1 = index_buffer[base_idx];
-    let idx2 = index_buffer[base_idx + 1u];
-    let idx3 = index_buffer[base_idx + 2u];
-
-    var vertex = geometry_pipeline(index_buffer[base_idx + lid.z]);
-
-    shared_v[lid.z] = vertex;
-    projected_buffer[index_buffer[base_idx + lid.z]] = vertex;
-
-    // 1) Compute metadata for this triangle
-    compute_triangle_meta(triangle_index);
-    let triangle_meta = triangle_binning_buffer[triangle_index];
+    // 1) Compute metadata for this triangle. Every thread derives the same result,
+    // so only the first one needs to store it.
+    let triangle_meta = compute_triangle_meta(base_idx);
+    if lid.z == 0u {
+        triangle_binning_buffer[triangle_index] = triangle_meta;
+    }
 
-    let start_tile_x = triangle_meta.start_tile.x;
-    let start_tile_y = triangle_meta.start_tile.y;
-    let tile_range_x = triangle_meta.tile_range.x;
-    let tile_range_y = triangle_meta.tile_range.y;
-    let num_tiles = tile_range_x * tile_range_y;
+    let num_tiles = triangle_meta.tile_range.x * triangle_meta.tile_range.y;
 
     // If there's nothing to bin, exit early
     if num_tiles == 0u {
         return;
     }
 
-    // 2) Each thread will loop over some subset of tiles
-    let num_tiles_x = screen_dims.num_tiles_x;
+    let coarse = coarse_range(triangle_meta);
+    if is_big_triangle(coarse) {
+        if lid.z == 0u {
+            let slot = atomicAdd(&big_triangles.count, 1u);
+            if slot < arrayLength(&big_triangles.triangles) {
+                big_triangles.triangles[slot] = base_idx;
+            }
+        }
+        return;
+    }
 
-    for (var ty = 0u; ty < tile_range_y; ty++) {
-        let tile_y = start_tile_y + ty;
-        for (var tx = lid.z; tx < tile_range_x; tx += Z_DISPATCHES) {
-            let tile_x = start_tile_x + tx;
-            let tile_index = tile_x + tile_y * num_tiles_x;
-            atomicAdd(&tile_buffer[tile_index].count, 1u);
+    // 2) Each thread will loop over some subset of coarse tiles
+    let num_coarse_x = coarse_tiles_x();
+
+    for (var cy = 0u; cy < coarse.w; cy++) {
+        let coarse_y = coarse.y + cy;
+        for (var cx = lid.z; cx < coarse.z; cx += BINNING_THREADS) {
+            let coarse_x = coarse.x + cx;
+            atomicAdd(&coarse_buffer[coarse_x + coarse_y * num_coarse_x].count, 1u);
         }
     }
 }
 
 //---------------------------------------------------------------------
 // Workgroup-based exclusive scan routine.
-// This routine uses shared memory to compute prefix sums over 256 values.
+// This routine uses shared memory to compute prefix sums over WORKGROUP_SIZE values.
 fn workgroup_scan_exclusive(tid: u32, workgroup_size: u32) -> u32 {
     var offset = 1u;
     var d = workgroup_size >> 1u;
@@ -262,6 +247,7 @@ fn workgroup_scan_exclusive(tid: u32, workgroup_size: u32) -> u32 {
         offset *= 2u;
         d >>= 1u;
     }
+    workgroupBarrier();
     if tid == workgroup_size - 1u {
         shared_data[tid] = 0u;
     }
@@ -282,70 +268,49 @@ fn workgroup_scan_exclusive(tid: u32, workgroup_size: u32) -> u32 {
     return shared_data[tid];
 }
 
-//---------------------------------------------------------------------
-// Kernel 2a: First pass of scan over per–tile triangle counts.
-// Each workgroup loads up to 256 tile counts, computes an exclusive scan,
-// and the last thread writes the workgroup's total to the partial_sums array.
-@compute @workgroup_size(256)
-fn scan_first_pass(
-    @builtin(global_invocation_id) global_id: vec3<u32>,
-    @builtin(local_invocation_id) local_id: vec3<u32>,
-    @builtin(workgroup_id) workgroup_id: vec3<u32>
-) {
-    let num_tiles_x = screen_dims.num_tiles_x;
-    let num_tiles_y = screen_dims.num_tiles_y;
-    let total_tiles = num_tiles_x * num_tiles_y;
-
-    let tile_index = global_id.x;
+var<workgroup> chunk_total: u32;
 
-    if tile_index >= total_tiles {
-        return;
+// Scans one WORKGROUP_SIZE chunk of a longer sequence from a single workgroup.
+// Returns this thread's exclusive offset within the chunk and the chunk's total,
+// which every thread needs to carry the running sum into the next chunk.
+fn scan_chunk(tid: u32, value: u32) -> vec2<u32> {
+    shared_data[tid] = value;
+    let offset = workgroup_scan_exclusive(tid, WORKGROUP_SIZE);
+    if tid == WORKGROUP_SIZE - 1u {
+        chunk_total = offset + value;
     }
-
-    let tid = local_id.x;
-
-    shared_data[tid] = tile_buffer[tile_index].count;
-
-    let scan_result = workgroup_scan_exclusive(tid, 256u);
-
-    if tid == 255u {
-        let workgroup_sum = scan_result + shared_data[tid];
-        partial_sums[workgroup_id.x] = workgroup_sum;
-    }
-
-    tile_buffer[tile_index].offset = scan_result;
+    return vec2<u32>(offset, workgroupUniformLoad(&chunk_total));
 }
 
 //---------------------------------------------------------------------
-// Kernel 2b: Second pass for scan.
-// Each thread adds the sum of all previous workgroups to its tile offset.
-@compute @workgroup_size(256)
-fn scan_second_pass(
-    @builtin(global_invocation_id) global_id: vec3<u32>,
-    @builtin(workgroup_id) workgroup_id: vec3<u32>
-) {
-    let num_tiles_x = screen_dims.num_tiles_x;
-    let num_tiles_y = screen_dims.num_tiles_y;
-    let total_tiles = num_tiles_x * num_tiles_y;
-    let tile_index = global_id.x;
-    if tile_index >= total_tiles {
-        return;
-    }
+// Kernel 2: Exclusive scan over the coarse tile counts.
+// There are few coarse tiles, so a single workgroup walks over them in
+// WORKGROUP_SIZE chunks, carrying the running total between chunks.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn scan_coarse(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    let total_coarse = coarse_tiles_x() * coarse_tiles_y();
+    let tid = local_id.x;
+
+    var carry = 0u;
+    for (var chunk = 0u; chunk < total_coarse; chunk += WORKGROUP_SIZE) {
+        let coarse_index = chunk + tid;
+        var count = 0u;
+        if coarse_index < total_coarse {
+            count = atomicLoad(&coarse_buffer[coarse_index].count);
+        }
 
-    var workgroup_offset = 0u;
-    let current_group = workgroup_id.x;
-    for (var i = 0u; i < current_group; i++) {
-        workgroup_offset += partial_sums[i];
+        let scan_result = scan_chunk(tid, count);
+        if coarse_index < total_coarse {
+            coarse_buffer[coarse_index].offset = carry + scan_result.x;
+        }
+        carry += scan_result.y;
     }
-    tile_buffer[tile_index].offset += workgroup_offset;
 }
 
 //---------------------------------------------------------------------
-// Kernel 3: Store triangle indices into the triangle list buffer.
-// Each thread processes one triangle and inlines the triangle logic.
-@compute @workgroup_size(1, 1, Z_DISPATCHES)
-fn store_triangles(
-    @builtin(global_invocation_id) global_id: vec3<u32>,
+// Kernel 3: Store triangle indices into the coarse tile lists.
+@compute @workgroup_size(1, 1, BINNING_THREADS)
+fn store_coarse(
     @builtin(workgroup_id) wg: vec3<u32>,
     @builtin(local_invocation_id) lid: vec3<u32>,
     @builtin(num_workgroups) num_workgroups: vec3<u32>
@@ -361,28 +326,261 @@ fn store_triangles(
 
     // Pull precomputed metadata:
     let triangle_meta = triangle_binning_buffer[triangle_index];
-    let start_tile_x = triangle_meta.start_tile.x;
-    let start_tile_y = triangle_meta.start_tile.y;
-    let tile_range_x = triangle_meta.tile_range.x;
-    let tile_range_y = triangle_meta.tile_range.y;
-    let total_tiles = tile_range_x * tile_range_y;
+    if triangle_meta.tile_range.x * triangle_meta.tile_range.y == 0u {
+        return;
+    }
+
+    let coarse = coarse_range(triangle_meta);
+    if is_big_triangle(coarse) {
+        return;
+    }
 
     // The base index of this triangle in index_buffer
     let base_idx = triangle_index * 3u;
 
-    // We'll again split the tile iteration among 64 threads (in z).
-    let thread_id = lid.z;
+    let num_coarse_x = coarse_tiles_x();
+    for (var cy = 0u; cy < coarse.w; cy++) {
+        let coarse_y = coarse.y + cy;
+        for (var cx = lid.z; cx < coarse.z; cx += BINNING_THREADS) {
+            let coarse_index = coarse.x + cx + coarse_y * num_coarse_x;
+            let write_index = atomicAdd(&coarse_buffer[coarse_index].write_index, 1u);
+            let entry = coarse_buffer[coarse_index].offset + write_index;
+            if entry < arrayLength(&coarse_list_buffer) {
+                coarse_list_buffer[entry] = base_idx;
+            }
+        }
+    }
+}
+
+// Fine tiles covered by coarse tile `coarse_tile`: (start_x, start_y, end_x, end_y).
+fn coarse_tile_bounds(coarse_tile: vec2<u32>) -> vec4<u32> {
+    let start = coarse_tile * COARSE_FACTOR;
+    let end = min(start + vec2<u32>(COARSE_FACTOR),
+        vec2<u32>(screen_dims.num_tiles_x, screen_dims.num_tiles_y));
+    return vec4<u32>(start, end);
+}
+
+// Edge functions of a triangle, oriented so that points inside have a non-negative
+// value for every edge. Each row holds (normal.x, normal.y, offset) so that
+// E(p) = dot(normal, p) + offset.
+fn triangle_edges(base_idx: u32) -> mat3x3<f32> {
+    let v1 = projected_buffer[index_buffer[base_idx]].screen_pos.xy;
+    let v2 = projected_buffer[index_buffer[base_idx + 1u]].screen_pos.xy;
+    let v3 = projected_buffer[index_buffer[base_idx + 2u]].screen_pos.xy;
+
+    let area = (v2.x - v1.x) * (v3.y - v1.y) - (v2.y - v1.y) * (v3.x - v1.x);
+    let orientation = select(-1.0, 1.0, area >= 0.0);
+
+    return mat3x3<f32>(
+        edge_function(v1, v2, orientation),
+        edge_function(v2, v3, orientation),
+        edge_function(v3, v1, orientation)
+    );
+}
+
+fn edge_function(a: vec2<f32>, b: vec2<f32>, orientation: f32) -> vec3<f32> {
+    let normal = vec2<f32>(a.y - b.y, b.x - a.x) * orientation;
+    return vec3<f32>(normal, -dot(normal, a));
+}
+
+// A tile is rejected when, for some edge, even the tile corner furthest inside
+// that edge lies outside it. The test is conservative: it never rejects a tile
+// the triangle covers, but may keep a few near its corners.
+fn triangle_overlaps_tile(edges: mat3x3<f32>, tile: vec2<u32>) -> bool {
+    // The rasteriser's pixel expansion effect has no tight edge test, so keep the bbox.
+    if effect.effect_type == 3u {
+        return true;
+    }
+
+    // Pixels are sampled at their centres, so testing the tile's full bounds leaves
+    // half a pixel of slack around its samples. That covers the rasteriser snapping
+    // vertices to sub-pixel precision and samples lying exactly on an edge.
+    var tile_min = vec2<f32>(tile * TILE_SIZE);
+    var tile_max = vec2<f32>((tile + 1u) * TILE_SIZE);
+
+    // The wave effect moves sample positions by up to param1 pixels.
+    if effect.effect_type == 4u {
+        tile_min -= vec2<f32>(abs(effect.param1));
+        tile_max += vec2<f32>(abs(effect.param1));
+    }
+
+    for (var i = 0u; i < 3u; i++) {
+        let edge = edges[i];
+        let corner = select(tile_min, tile_max, edge.xy > vec2<f32>(0.0));
+        if dot(edge.xy, corner) + edge.z < 0.0 {
+            return false;
+        }
+    }
+    return true;
+}
+
+//---------------------------------------------------------------------
+// Kernel 4: Fine binning count. One workgroup per coarse tile distributes the
+// coarse tile's triangles over its threads; each thread counts its triangle
+// into the fine tiles it overlaps inside this coarse tile.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn count_fine(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>
+) {
+    let num_coarse_x = coarse_tiles_x();
+    if wg.x >= num_coarse_x || wg.y >= coarse_tiles_y() {
+        return;
+    }
+
+    let coarse_index = wg.x + wg.y * num_coarse_x;
+    let count = atomicLoad(&coarse_buffer[coarse_index].count);
+    let offset = coarse_buffer[coarse_index].offset;
+    let bounds = coarse_tile_bounds(wg.xy);
     let num_tiles_x = screen_dims.num_tiles_x;
-    for (var ty = 0u; ty < tile_range_y; ty ++) {
-        let tile_y = start_tile_y + ty;
-        for (var tx = thread_id; tx < tile_range_x; tx += Z_DISPATCHES) {
-            let tile_x = start_tile_x + tx;
-            let tile_index = tile_x + tile_y * num_tiles_x;
 
-            let count = tile_buffer[tile_index].count;
-            let write_index = atomicAdd(&tile_buffer[tile_index].write_index, 1u);
+    for (var i = local_id.x; i < count; i += WORKGROUP_SIZE) {
+        let entry = offset + i;
+        if entry >= arrayLength(&coarse_list_buffer) {
+            break;
+        }
+        let base_idx = coarse_list_buffer[entry];
+        let triangle_meta = triangle_binning_buffer[base_idx / 3u];
+        let start = max(triangle_meta.start_tile, bounds.xy);
+        let end = min(triangle_meta.start_tile + triangle_meta.tile_range, bounds.zw);
+
+        let edges = triangle_edges(base_idx);
+
+        var binned = 0u;
+        var rejected = 0u;
+        for (var tile_y = start.y; tile_y < end.y; tile_y++) {
+            for (var tile_x = start.x; tile_x < end.x; tile_x++) {
+                if !triangle_overlaps_tile(edges, vec2<u32>(tile_x, tile_y)) {
+                    rejected += 1u;
+                    continue;
+                }
+                atomicAdd(&tile_buffer[tile_x + tile_y * num_tiles_x].count, 1u);
+                binned += 1u;
+            }
+        }
+
+        if binned > 0u {
+            atomicAdd(&stats.binned_tile_pairs, binned);
+        }
+        if rejected > 0u {
+            atomicAdd(&stats.rejected_tile_pairs, rejected);
+        }
+    }
+}
+
+//---------------------------------------------------------------------
+// Kernels 5a-5c: Exclusive scan over the per-tile triangle counts, giving each
+// tile its offset into the triangle list. This is a three level scan:
+// 5a scans each block of WORKGROUP_SIZE tiles and records the block's total,
+// 5b scans the block totals from a single workgroup, and 5c adds each block's
+// scanned total back onto its tiles. Every kernel does a constant amount of work
+// per tile, so the scan stays linear in the number of tiles.
+fn total_tiles() -> u32 {
+    return screen_dims.num_tiles_x * screen_dims.num_tiles_y;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn scan_tile_blocks(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>
+) {
+    let tile_index = global_id.x;
+    let tid = local_id.x;
+    let in_range = tile_index < total_tiles();
+
+    // Threads past the last tile still take part in the scan's barriers.
+    var count = 0u;
+    if in_range {
+        count = atomicLoad(&tile_buffer[tile_index].count);
+    }
+    shared_data[tid] = count;
+
+    let scan_result = workgroup_scan_exclusive(tid, WORKGROUP_SIZE);
 
-            triangle_list_buffer[tile_buffer[tile_index].offset + write_index] = base_idx;
+    if in_range {
+        tile_buffer[tile_index].offset = scan_result;
+    }
+    if tid == WORKGROUP_SIZE - 1u {
+        partial_sums[workgroup_id.x] = scan_result + count;
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn scan_block_sums(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    let num_blocks = (total_tiles() + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+    let tid = local_id.x;
+
+    var carry = 0u;
+    for (var chunk = 0u; chunk < num_blocks; chunk += WORKGROUP_SIZE) {
+        let block = chunk + tid;
+        var block_sum = 0u;
+        if block < num_blocks {
+            block_sum = partial_sums[block];
+        }
+
+        let scan_result = scan_chunk(tid, block_sum);
+        if block < num_blocks {
+            partial_sums[block] = carry + scan_result.x;
         }
+        carry += scan_result.y;
     }
-}
\ No newline at end of file
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn add_block_offsets(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>
+) {
+    let tile_index = global_id.x;
+    if tile_index >= total_tiles() {
+        return;
+    }
+    tile_buffer[tile_index].offset += partial_sums[workgroup_id.x];
+}
+
+//---------------------------------------------------------------------
+// Kernel 6: Store triangle indices into the fine tile lists, mirroring count_fine.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn store_fine(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>
+) {
+    let num_coarse_x = coarse_tiles_x();
+    if wg.x >= num_coarse_x || wg.y >= coarse_tiles_y() {
+        return;
+    }
+
+    let coarse_index = wg.x + wg.y * num_coarse_x;
+    let count = atomicLoad(&coarse_buffer[coarse_index].count);
+    let offset = coarse_buffer[coarse_index].offset;
+    let bounds = coarse_tile_bounds(wg.xy);
+    let num_tiles_x = screen_dims.num_tiles_x;
+
+    for (var i = local_id.x; i < count; i += WORKGROUP_SIZE) {
+        let entry = offset + i;
+        if entry >= arrayLength(&coarse_list_buffer) {
+            break;
+        }
+        let base_idx = coarse_list_buffer[entry];
+        let triangle_meta = triangle_binning_buffer[base_idx / 3u];
+        let start = max(triangle_meta.start_tile, bounds.xy);
+        let end = min(triangle_meta.start_tile + triangle_meta.tile_range, bounds.zw);
+
+        let edges = triangle_edges(base_idx);
+
+        for (var tile_y = start.y; tile_y < end.y; tile_y++) {
+            for (var tile_x = start.x; tile_x < end.x; tile_x++) {
+                if !triangle_overlaps_tile(edges, vec2<u32>(tile_x, tile_y)) {
+                    continue;
+                }
+                let tile_index = tile_x + tile_y * num_tiles_x;
+                let write_index = atomicAdd(&tile_buffer[tile_index].write_index, 1u);
+                let list_index = tile_buffer[tile_index].offset + write_index;
+                if list_index < arrayLength(&triangle_list_buffer) {
+                    triangle_list_buffer[list_index] = base_idx;
+                }
+            }
+        }
+    }
+}
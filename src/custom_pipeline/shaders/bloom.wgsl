@@ -0,0 +1,104 @@
+// Bloom: the bright parts of the image are downsampled into a chain of mips, which are
+// upsampled and added back up the chain, then over the image.
+
+// The texture sampled, the mip level written, and the one the result is added to.
+@group(0) @binding(0) var source_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var target_tex: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(3) var base_tex: texture_2d<f32>;
+
+// Linear values above the threshold bloom, with a soft knee below it.
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_KNEE: f32 = 0.5;
+// Fraction of the upsampled chain added to the image.
+const BLOOM_INTENSITY: f32 = 0.04;
+
+fn source(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source_tex, linear_sampler, uv, 0.0).rgb;
+}
+
+// The 13 tap downsample of Jimenez's "Next Generation Post Processing in Call of Duty:
+// Advanced Warfare": four overlapping 2x2 boxes and a centre box weighted by half.
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_tex));
+    let a = source(uv + texel * vec2<f32>(-2.0, -2.0));
+    let b = source(uv + texel * vec2<f32>(0.0, -2.0));
+    let c = source(uv + texel * vec2<f32>(2.0, -2.0));
+    let d = source(uv + texel * vec2<f32>(-1.0, -1.0));
+    let e = source(uv + texel * vec2<f32>(1.0, -1.0));
+    let f = source(uv + texel * vec2<f32>(-2.0, 0.0));
+    let g = source(uv);
+    let h = source(uv + texel * vec2<f32>(2.0, 0.0));
+    let i = source(uv + texel * vec2<f32>(-1.0, 1.0));
+    let j = source(uv + texel * vec2<f32>(1.0, 1.0));
+    let k = source(uv + texel * vec2<f32>(-2.0, 2.0));
+    let l = source(uv + texel * vec2<f32>(0.0, 2.0));
+    let m = source(uv + texel * vec2<f32>(2.0, 2.0));
+    return (d + e + i + j) * 0.125
+        + (a + b + f + g) * 0.03125
+        + (b + c + g + h) * 0.03125
+        + (f + g + k + l) * 0.03125
+        + (g + h + l + m) * 0.03125;
+}
+
+// A 3x3 tent filter over the source texels.
+fn upsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_tex));
+    var sum = source(uv) * 4.0;
+    sum += (source(uv + texel * vec2<f32>(0.0, -1.0))
+        + source(uv + texel * vec2<f32>(-1.0, 0.0))
+        + source(uv + texel * vec2<f32>(1.0, 0.0))
+        + source(uv + texel * vec2<f32>(0.0, 1.0))) * 2.0;
+    sum += source(uv + texel * vec2<f32>(-1.0, -1.0))
+        + source(uv + texel * vec2<f32>(1.0, -1.0))
+        + source(uv + texel * vec2<f32>(-1.0, 1.0))
+        + source(uv + texel * vec2<f32>(1.0, 1.0));
+    return sum / 16.0;
+}
+
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let soft = clamp(brightness - BLOOM_THRESHOLD + BLOOM_KNEE, 0.0, 2.0 * BLOOM_KNEE);
+    let knee = soft * soft / (4.0 * BLOOM_KNEE + 1e-4);
+    return color * max(knee, brightness - BLOOM_THRESHOLD) / max(brightness, 1e-4);
+}
+
+fn target_uv(id: vec2<u32>) -> vec2<f32> {
+    return (vec2<f32>(id) + 0.5) / vec2<f32>(textureDimensions(target_tex));
+}
+
+@compute @workgroup_size(8, 8)
+fn prefilter_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(target_tex)) {
+        return;
+    }
+    let color = threshold(downsample(target_uv(global_id.xy)));
+    textureStore(target_tex, global_id.xy, vec4<f32>(color, 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn downsample_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(target_tex)) {
+        return;
+    }
+    textureStore(target_tex, global_id.xy, vec4<f32>(downsample(target_uv(global_id.xy)), 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn upsample_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(target_tex)) {
+        return;
+    }
+    let color = textureLoad(base_tex, global_id.xy, 0).rgb + upsample(target_uv(global_id.xy));
+    textureStore(target_tex, global_id.xy, vec4<f32>(color, 1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn composite_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(target_tex)) {
+        return;
+    }
+    let base = textureLoad(base_tex, global_id.xy, 0);
+    let color = base.rgb + upsample(target_uv(global_id.xy)) * BLOOM_INTENSITY;
+    textureStore(target_tex, global_id.xy, vec4<f32>(color, base.a));
+}
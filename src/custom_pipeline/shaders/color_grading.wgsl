@@ -0,0 +1,45 @@
+// Colour grading through a 3D lookup table. LUTs map display range colours, so the HDR
+// image is compressed into that range with x / (1 + x) per channel, graded and expanded
+// back. An identity LUT leaves the image as it was.
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var output_tex: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(0) var lut_tex: texture_3d<f32>;
+
+// Graded values are capped below one, which expands back to about a thousand.
+const MAX_GRADED: f32 = 0.999;
+
+// Trilinear interpolation by hand, as 32-bit float textures aren't filterable everywhere.
+fn lookup(color: vec3<f32>) -> vec3<f32> {
+    let size = textureDimensions(lut_tex).x;
+    let position = saturate(color) * f32(size - 1u);
+    let base = min(vec3<u32>(position), vec3<u32>(size - 2u));
+    let t = position - vec3<f32>(base);
+
+    let c000 = textureLoad(lut_tex, base, 0).rgb;
+    let c100 = textureLoad(lut_tex, base + vec3<u32>(1u, 0u, 0u), 0).rgb;
+    let c010 = textureLoad(lut_tex, base + vec3<u32>(0u, 1u, 0u), 0).rgb;
+    let c110 = textureLoad(lut_tex, base + vec3<u32>(1u, 1u, 0u), 0).rgb;
+    let c001 = textureLoad(lut_tex, base + vec3<u32>(0u, 0u, 1u), 0).rgb;
+    let c101 = textureLoad(lut_tex, base + vec3<u32>(1u, 0u, 1u), 0).rgb;
+    let c011 = textureLoad(lut_tex, base + vec3<u32>(0u, 1u, 1u), 0).rgb;
+    let c111 = textureLoad(lut_tex, base + vec3<u32>(1u, 1u, 1u), 0).rgb;
+
+    let c00 = mix(c000, c100, t.x);
+    let c10 = mix(c010, c110, t.x);
+    let c01 = mix(c001, c101, t.x);
+    let c11 = mix(c011, c111, t.x);
+    return mix(mix(c00, c10, t.y), mix(c01, c11, t.y), t.z);
+}
+
+@compute @workgroup_size(8, 8)
+fn color_grading_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id.xy >= textureDimensions(input_tex)) {
+        return;
+    }
+    let color = textureLoad(input_tex, global_id.xy, 0);
+    let graded = min(lookup(color.rgb / (1.0 + color.rgb)), vec3<f32>(MAX_GRADED));
+    textureStore(output_tex, global_id.xy, vec4<f32>(graded / (1.0 - graded), color.a));
+}
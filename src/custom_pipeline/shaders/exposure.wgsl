@@ -0,0 +1,100 @@
+// Auto exposure: a histogram of the log2 luminance of the HDR image, then its average,
+// which the metered luminance eases towards over time.
+
+struct Exposure {
+    // Metered average luminance, or zero before the first frame.
+    luminance: f32,
+    // What the present pass multiplies the image by.
+    exposure: f32,
+};
+
+struct ExposureFrame {
+    // Seconds since the previous metering.
+    delta_time: f32,
+    _padding: vec3<f32>,
+};
+
+@group(0) @binding(0) var hdr_tex: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, HISTOGRAM_BINS>;
+@group(0) @binding(2) var<storage, read_write> exposure: Exposure;
+@group(0) @binding(3) var<uniform> frame: ExposureFrame;
+
+// One bin per thread of both entry points. Bin 0 holds the pixels too dark to meter, like
+// the background, and the others span MIN_LOG_LUMINANCE to MIN_LOG_LUMINANCE plus
+// LOG_LUMINANCE_RANGE stops.
+const HISTOGRAM_BINS: u32 = 256u;
+const MIN_LOG_LUMINANCE: f32 = -10.0;
+const LOG_LUMINANCE_RANGE: f32 = 16.0;
+
+// Luminance auto exposure maps the average to, and how fast it adapts (per second).
+const MIDDLE_GREY: f32 = 0.18;
+const ADAPTATION_RATE: f32 = 1.5;
+
+var<workgroup> local_bins: array<atomic<u32>, HISTOGRAM_BINS>;
+var<workgroup> weighted_bins: array<f32, HISTOGRAM_BINS>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < exp2(MIN_LOG_LUMINANCE) {
+        return 0u;
+    }
+    let position = saturate((log2(luminance) - MIN_LOG_LUMINANCE) / LOG_LUMINANCE_RANGE);
+    return u32(position * f32(HISTOGRAM_BINS - 2u)) + 1u;
+}
+
+// Each workgroup counts a 16x16 block of pixels in workgroup memory first, so the global
+// histogram takes a single atomic per bin and workgroup.
+@compute @workgroup_size(16, 16)
+fn histogram_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) bin: u32
+) {
+    atomicStore(&local_bins[bin], 0u);
+    workgroupBarrier();
+
+    if all(global_id.xy < textureDimensions(hdr_tex)) {
+        let color = textureLoad(hdr_tex, global_id.xy, 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[bin], atomicLoad(&local_bins[bin]));
+}
+
+// Averages the histogram's log luminance, leaving out bin 0, and clears it for the next
+// frame.
+@compute @workgroup_size(HISTOGRAM_BINS)
+fn average_main(@builtin(local_invocation_index) bin: u32) {
+    let count = atomicExchange(&histogram[bin], 0u);
+    weighted_bins[bin] = f32(count) * f32(bin);
+    workgroupBarrier();
+
+    for (var stride = HISTOGRAM_BINS / 2u; stride > 0u; stride /= 2u) {
+        if bin < stride {
+            weighted_bins[bin] += weighted_bins[bin + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if bin == 0u {
+        let dims = textureDimensions(hdr_tex);
+        // The first thread's count is that of bin 0.
+        let metered_pixels = f32(dims.x * dims.y - count);
+        var luminance = exposure.luminance;
+        if metered_pixels > 0.0 {
+            let average_bin = weighted_bins[0] / metered_pixels - 1.0;
+            let log_luminance = (average_bin + 0.5) / f32(HISTOGRAM_BINS - 2u) * LOG_LUMINANCE_RANGE + MIN_LOG_LUMINANCE;
+            let metered = exp2(log_luminance);
+            if luminance > 0.0 {
+                luminance += (metered - luminance) * (1.0 - exp(-frame.delta_time * ADAPTATION_RATE));
+            } else {
+                luminance = metered;
+            }
+        }
+
+        exposure.luminance = luminance;
+        if luminance > 0.0 {
+            exposure.exposure = exp2(EXPOSURE) * MIDDLE_GREY / luminance;
+        }
+    }
+}
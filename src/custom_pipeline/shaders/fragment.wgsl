@@ -1,22 +1,41 @@
 struct Uniform {
     width: f32,
     height: f32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
 };
 
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
+    // The previous frame's unjittered view_proj and the jitter of this frame's (xy), for
+    // motion vectors.
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec4<f32>,
+    // Rebuilds world positions from the G-buffer's depth in deferred mode.
+    inv_view_proj: mat4x4<f32>,
 };
 
 struct Light {
     world_position: vec3<f32>,
-    _padding1: f32,
+    light_type: u32,
     view_position: vec3<f32>,
-    _padding2: f32,
+    range: f32,
     color: vec3<f32>,
     intensity: f32,
+    direction: vec3<f32>,
+    cos_inner_angle: f32,
+    cos_outer_angle: f32,
+    shadow_view: u32,
+    _padding: vec2<f32>,
 };
 
+// Light types and the shadow view of lights without shadow maps, as in scene.rs.
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+const NO_SHADOW_VIEW: u32 = 0xffffffffu;
+
 struct EffectUniform {
     effect_type: u32,
     param1: f32,
@@ -24,70 +43,785 @@ struct EffectUniform {
     param3: f32,
     param4: f32,
     time: f32,
-    _padding: vec2<f32>,
+    // param3 of the frame before, for the motion of geometry the wave effect animates.
+    prev_param3: f32,
+    _padding: f32,
 };
 
 struct Fragment {
     uv: vec2<f32>,
     normal: vec3<f32>,
     position: vec3<f32>,
-    flag: u32
+    flag: u32,
+    material: u32,
+    triangle: u32,
+};
+
+struct Vertex {
+    world_pos: vec3<f32>,
+    screen_pos: vec4<f32>,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+    material: u32,
 };
 
 
-@group(0) @binding(0) var output_tex: texture_storage_2d<bgra8unorm, write>;
+@group(0) @binding(0) var output_tex: texture_storage_2d<rgba16float, write>;
+// Normal and depth of the opaque surface, or of the first sample's with MSAA, for the
+// post-processing passes.
+@group(0) @binding(1) var depth_normal_tex: texture_storage_2d<rgba32float, write>;
+// How far the surface moved in UV since the previous frame, for TAA.
+@group(0) @binding(2) var motion_tex: texture_storage_2d<rgba32float, write>;
+// The rest of the G-buffer, written instead of the output in deferred mode: the base
+// colour and the material index plus one of the opaque surface, zero for empty pixels.
+@group(0) @binding(3) var albedo_tex: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(4) var material_tex: texture_storage_2d<r32uint, write>;
+// The whole G-buffer, read back by the deferred lighting pass.
+@group(0) @binding(5) var gbuffer_depth_normal: texture_2d<f32>;
+@group(0) @binding(6) var gbuffer_albedo: texture_2d<f32>;
+@group(0) @binding(7) var gbuffer_material: texture_2d<u32>;
+// The opaque surface's triangle ID plus one, zero for empty pixels, for picking.
+@group(0) @binding(8) var object_id_tex: texture_storage_2d<r32uint, write>;
 
 @group(1) @binding(0) var<uniform> screen_dims: Uniform;
 @group(2) @binding(0) var<uniform> camera: Camera;
 
 @group(3) @binding(0) var<storage, read> lights: array<Light>;
 
+// With shadows on, the camera of each shadow map of the shadowed lights (six cube faces
+// per point or spot light, one per directional light, from each light's shadow_view) and
+// the visibility buffer its depth pass rendered, SHADOW_MAP_SIZE squared texels each.
+// Otherwise a single unused placeholder each.
+@group(3) @binding(1) var<storage, read> shadow_views: array<Camera>;
+@group(3) @binding(2) var<storage, read> shadow_maps: array<u32>;
+
+// With LIGHT_CULLING, the lights reaching each tile from the light culling pass: a count,
+// then that many light indices, in slots of MAX_TILE_LIGHTS plus one.
+@group(3) @binding(3) var<storage, read> tile_lights: array<u32>;
+
 @group(4) @binding(0) var<uniform> effect: EffectUniform;
 
 // The fragment data & count from the raster pass
 @group(5) @binding(0) var<storage, read_write> fragment_buffer: array<Fragment>;
 
-@compute @workgroup_size(256)
-fn fragment_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+// In visibility buffer mode, and with MSAA, the raster pass only stores the nearest
+// opaque triangle per sample, as (inverted triangle ID, inverted depth), MSAA_SAMPLES per
+// pixel, and attributes are rebuilt here.
+@group(5) @binding(1) var<storage, read> visibility_buffer: array<u32>;
+@group(5) @binding(2) var<storage, read> projected_buffer: array<Vertex>;
+@group(5) @binding(3) var<storage, read> indices: array<u32>;
+@group(5) @binding(4) var<storage, read> materials: array<Material>;
+
+// Per-pixel lists of transparent fragments appended by the raster pass.
+struct TransparentNodes {
+    count: u32,
+    nodes: array<TransparentNode>,
+};
+
+@group(5) @binding(5) var<storage, read> transparent_heads: array<u32>;
+@group(5) @binding(6) var<storage, read> transparent_nodes: TransparentNodes;
+
+// What the debug views show besides the surfaces: the triangles binned into each tile,
+// from the binning pass, and with DEBUG_OVERDRAW the depth tests in each pixel, from the
+// raster pass.
+struct TileTriangles {
+    count: u32,
+    offset: u32,
+    write_index: u32,
+}
+
+@group(5) @binding(7) var<storage, read> tile_buffer: array<TileTriangles>;
+@group(5) @binding(8) var<storage, read> overdraw: array<u32>;
+
+// Texels of the materials' base colour textures, as sRGB RGBA8 (TextureInfo).
+@group(5) @binding(9) var<storage, read> textures: array<u32>;
+
+// The colour of empty pixels.
+const BACKGROUND = vec3<f32>(0.0, 0.0, 1.0);
+
+// How far shadow map lookups are moved out along the surface normal, in texels at the
+// fragment's distance from the light, so lit surfaces don't shadow themselves.
+const SHADOW_NORMAL_OFFSET: f32 = 1.5;
+
+// The fraction of a light that reaches a surface, from 3x3 percentage closer filtering
+// of its shadow map, or of the cube face a point or spot light sees it in. Lights without
+// shadow maps always reach it.
+fn shadow_factor(light: Light, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow_view == NO_SHADOW_VIEW {
+        return 1.0;
+    }
+
+    let size = f32(SHADOW_MAP_SIZE);
+    var view = light.shadow_view;
+    var texel_size: f32;
+    if light.light_type == LIGHT_DIRECTIONAL {
+        // Orthographic texels are the same size everywhere: the first row of the matrix
+        // scales world units to NDC.
+        let view_proj = shadow_views[view].view_proj;
+        let scale = length(vec3<f32>(view_proj[0].x, view_proj[1].x, view_proj[2].x));
+        texel_size = 2.0 / (scale * size);
+    } else {
+        let to_surface = abs(position - light.world_position);
+        texel_size = 2.0 * max(to_surface.x, max(to_surface.y, to_surface.z)) / size;
+    }
+    let lookup = position + normalize(normal) * texel_size * SHADOW_NORMAL_OFFSET;
+
+    // Faces are ordered +X, -X, +Y, -Y, +Z, -Z, like Light::shadow_views.
+    if light.light_type != LIGHT_DIRECTIONAL {
+        let direction = lookup - light.world_position;
+        let distance = abs(direction);
+        var face = select(5u, 4u, direction.z > 0.0);
+        if distance.x >= distance.y && distance.x >= distance.z {
+            face = select(1u, 0u, direction.x > 0.0);
+        } else if distance.y >= distance.z {
+            face = select(3u, 2u, direction.y > 0.0);
+        }
+        view += face;
+    }
+
+    let clip = shadow_views[view].view_proj * vec4<f32>(lookup, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let texel = vec2<i32>(floor(vec2<f32>(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * size));
+
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let t = vec2<u32>(clamp(texel + vec2<i32>(x, y), vec2<i32>(0), vec2<i32>(i32(SHADOW_MAP_SIZE) - 1)));
+            let depth = shadow_maps[((view * SHADOW_MAP_SIZE + t.y) * SHADOW_MAP_SIZE + t.x) * 2u + 1u];
+            // A cleared texel has nothing in front of the light.
+            if depth == 0u || ndc.z <= bitcast<f32>(~depth) {
+                lit += 1.0;
+            }
+        }
+    }
+    return lit / 9.0;
+}
+
+// The direction from a surface to a light, and the fraction of the light that reaches it
+// before shadowing: point and spot lights fade out smoothly by their range, if they have
+// one, and spot lights between their inner and outer cone.
+fn light_incidence(light: Light, position: vec3<f32>) -> vec4<f32> {
+    if light.light_type == LIGHT_DIRECTIONAL {
+        return vec4<f32>(-normalize(light.direction), 1.0);
+    }
+
+    let to_light = light.world_position - position;
+    let distance = length(to_light);
+    let direction = to_light / distance;
+    var attenuation = 1.0;
+    if light.range > 0.0 {
+        let falloff = saturate(1.0 - pow(distance / light.range, 4.0));
+        attenuation = falloff * falloff;
+    }
+    if light.light_type == LIGHT_SPOT {
+        let cos_angle = dot(-direction, normalize(light.direction));
+        let cone = saturate((cos_angle - light.cos_outer_angle)
+            / max(light.cos_inner_angle - light.cos_outer_angle, 1e-4));
+        attenuation *= cone * cone;
+    }
+    return vec4<f32>(direction, attenuation);
+}
+
+const PI: f32 = 3.14159265;
+
+// Roughness is clamped so the highlights of smooth materials stay wider than a pixel.
+const MIN_ROUGHNESS: f32 = 0.045;
+
+// Light reflected towards the viewer per unit of light arriving from `light_dir`, with the
+// cosine term. A white diffuse highlight of the material's specular colour and exponent.
+fn phong_reflectance(material: Material, normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>) -> vec3<f32> {
+    let diff = max(dot(normal, light_dir), 0.0);
+    let reflect_dir = reflect(-light_dir, normal);
+    let spec = pow(max(dot(view_dir, reflect_dir), 0.0), max(material.shininess, 1.0));
+    return diff + spec * material.specular;
+}
+
+// The texel of the material's base colour texture nearest `uv`, wrapping around, as stored:
+// sRGB colour and linear alpha. Opaque white for materials without a texture.
+fn texture_texel(material: Material, uv: vec2<f32>) -> vec4<f32> {
+    let texture = material.texture_info;
+    if texture.offset == 0xffffffffu {
+        return vec4<f32>(1.0);
+    }
+    let size = vec2<u32>(texture.width, texture.height);
+    let texel = min(vec2<u32>(fract(uv) * vec2<f32>(size)), size - 1u);
+    return unpack4x8unorm(textures[texture.offset + texel.y * texture.width + texel.x]);
+}
 
+// The texel's colour in linear RGB.
+fn texture_color(material: Material, uv: vec2<f32>) -> vec3<f32> {
+    let srgb = texture_texel(material, uv).rgb;
+    return select(pow((srgb + 0.055) / 1.055, vec3<f32>(2.4)), srgb / 12.92, srgb <= vec3<f32>(0.04045));
+}
+
+// The opacity transparent surfaces are blended with: the dissolve, times the texture's
+// alpha for glTF materials, which masked ones then cut out at their alpha cutoff.
+fn material_alpha(material: Material, uv: vec2<f32>) -> f32 {
+    if material.alpha_mode == ALPHA_DISSOLVE {
+        return material.dissolve;
+    }
+    let alpha = material.dissolve * texture_texel(material, uv).a;
+    if material.alpha_mode == ALPHA_MASK {
+        return select(0.0, 1.0, alpha >= material.alpha_cutoff);
+    }
+    return alpha;
+}
+
+// Metals are tinted by their specular colour, like the MTL files they come from, and both
+// by the material's texture.
+fn base_color(material: Material, uv: vec2<f32>) -> vec3<f32> {
+    return mix(material.diffuse, material.specular, material.metallic) * texture_color(material, uv);
+}
+
+// The metallic-roughness counterpart of phong_reflectance: Lambertian diffuse plus a GGX
+// specular lobe with the Smith geometry term and Schlick's Fresnel, for a surface of base
+// colour `color`. It is scaled by pi, so a white diffuse surface facing a light is as
+// bright as with the Phong model.
+fn pbr_reflectance(material: Material, color: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = dot(normal, light_dir);
+    if n_dot_l <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    // Interpolated normals can face slightly away from the viewer at silhouettes.
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    let half_dir = normalize(view_dir + light_dir);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+    let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    let f0 = mix(vec3<f32>(0.04), color, material.metallic);
+    let alpha = pow(max(material.roughness, MIN_ROUGHNESS), 2.0);
+    let alpha2 = alpha * alpha;
+
+    let d_denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (PI * d_denom * d_denom);
+    let k = alpha * 0.5;
+    let geometry = n_dot_l / (n_dot_l * (1.0 - k) + k) * n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+
+    let specular = distribution * geometry * fresnel / (4.0 * n_dot_l * n_dot_v);
+    let diffuse = (1.0 - fresnel) * (1.0 - material.metallic) * color / PI;
+    return (diffuse + specular) * n_dot_l * PI;
+}
+
+// What shading needs of a surface, whether it comes from a fragment or the G-buffer.
+struct Surface {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    albedo: vec3<f32>,
+    material: u32,
+};
+
+fn fragment_surface(in: Fragment) -> Surface {
+    return Surface(in.position, in.normal, base_color(materials[in.material], in.uv), in.material);
+}
+
+// Light arriving at a surface from a single light and reflected towards `view_dir`, with
+// the shading model of the PBR constant.
+fn light_contribution(light: Light, surface: Surface, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let material = materials[surface.material];
+    let incidence = light_incidence(light, surface.position);
+    var reflectance: vec3<f32>;
+    if PBR {
+        reflectance = pbr_reflectance(material, surface.albedo, normal, view_dir, incidence.xyz);
+    } else {
+        reflectance = phong_reflectance(material, normal, view_dir, incidence.xyz);
+    }
+    let shadow = shadow_factor(light, surface.position, surface.normal);
+    return reflectance * incidence.w * shadow * light.color * light.intensity;
+}
+
+// The ambient term every surface starts from, and the normal it is lit with.
+fn ambient(surface: Surface) -> vec3<f32> {
+    if PBR {
+        return vec3<f32>(0.1) * surface.albedo;
+    }
+    return vec3<f32>(0.1);
+}
+
+fn shading_normal(surface: Surface) -> vec3<f32> {
+    if PBR {
+        return normalize(surface.normal);
+    }
+    return surface.normal;
+}
+
+// Lit colour of a fragment in linear HDR, from every light.
+fn shade_linear(in: Fragment) -> vec3<f32> {
+    let surface = fragment_surface(in);
+    let normal = shading_normal(surface);
+    let view_dir = normalize(camera.view_pos.xyz - surface.position);
+    var final_color = ambient(surface);
+    let num_lights = arrayLength(&lights);
+    for (var i = 0u; i < num_lights; i++) {
+        final_color += light_contribution(lights[i], surface, normal, view_dir);
+    }
+    return final_color;
+}
+
+// Lit colour of an opaque surface in linear HDR, from the lights listed for the tile of
+// pixel (x, y).
+fn shade_tile(surface: Surface, x: u32, y: u32) -> vec3<f32> {
+    let tile = (y / TILE_SIZE) * screen_dims.num_tiles_x + x / TILE_SIZE;
+    let normal = shading_normal(surface);
+    let view_dir = normalize(camera.view_pos.xyz - surface.position);
+    var final_color = ambient(surface);
+    let slot = tile * (MAX_TILE_LIGHTS + 1u);
+    let count = tile_lights[slot];
+    for (var i = 0u; i < count; i++) {
+        final_color += light_contribution(lights[tile_lights[slot + 1u + i]], surface, normal, view_dir);
+    }
+    return final_color;
+}
+
+// Lit colour of the opaque fragment in pixel (x, y). Transparent fragments can be in front
+// of the surfaces the lights were culled against, so they are lit with shade_linear.
+fn shade_opaque(in: Fragment, x: u32, y: u32) -> vec3<f32> {
+    if LIGHT_CULLING {
+        return shade_tile(fragment_surface(in), x, y);
+    }
+    return shade_linear(in);
+}
+
+// Where the wave effect had moved `position` the frame before. Its displacement only
+// depends on coordinates it leaves unchanged, so it can be redone at the previous phase.
+fn previous_position(position: vec3<f32>) -> vec3<f32> {
+    if effect.effect_type != 1u {
+        return position;
+    }
+    let amplitude = effect.param1;
+    let frequency = effect.param2;
+    let direction = effect.param4;
+    var previous = position;
+    if direction < 0.5 {
+        previous.y += amplitude * (sin(frequency * position.x + effect.prev_param3) - sin(frequency * position.x + effect.param3));
+    } else if direction < 1.5 {
+        previous.x += amplitude * (sin(frequency * position.y + effect.prev_param3) - sin(frequency * position.y + effect.param3));
+    } else {
+        let dist = length(position.xy);
+        previous.z += amplitude * (sin(frequency * dist + effect.prev_param3) - sin(frequency * dist + effect.param3));
+    }
+    return previous;
+}
+
+fn clip_to_uv(clip: vec4<f32>) -> vec2<f32> {
+    return vec2<f32>(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5);
+}
+
+// Keeps what the post-processing passes need of the pixel's surface: its normal and
+// depth, and its motion since the previous frame without the jitter of either. Empty
+// pixels show the background, which doesn't move. Also keeps the surface's triangle.
+fn store_surface(x: u32, y: u32, fragment: Fragment, is_empty: bool) {
+    var depth_normal = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    var motion = vec2<f32>(0.0);
+    var object_id = 0u;
+    if !is_empty {
+        object_id = fragment.triangle + 1u;
+        let clip = camera.view_proj * vec4<f32>(fragment.position, 1.0);
+        depth_normal = vec4<f32>(normalize(fragment.normal), clip.z / clip.w);
+        let unjittered = clip - vec4<f32>(camera.jitter.xy * clip.w, 0.0, 0.0);
+        let previous = camera.prev_view_proj * vec4<f32>(previous_position(fragment.position), 1.0);
+        if previous.w > 0.0 {
+            motion = clip_to_uv(unjittered) - clip_to_uv(previous);
+        }
+    }
+    textureStore(depth_normal_tex, vec2<i32>(i32(x), i32(y)), depth_normal);
+    textureStore(motion_tex, vec2<i32>(i32(x), i32(y)), vec4<f32>(motion, 0.0, 0.0));
+    textureStore(object_id_tex, vec2<i32>(i32(x), i32(y)), vec4<u32>(object_id, 0u, 0u, 0u));
+}
+
+// The output stays linear and unclamped: the present pass exposes, tone maps and encodes it.
+fn encode_output(color: vec3<f32>) -> vec4<f32> {
+    return vec4<f32>(color, 1.0);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn fragment_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     let idx = global_id.x;
     let tex_width = u32(screen_dims.width);  // Use the actual texture width
-    let x = i32(idx % tex_width);
-    let y = i32(idx / tex_width);
+    let num_pixels = tex_width * u32(screen_dims.height);
+    if idx >= num_pixels {
+        return;
+    }
+    let x = idx % tex_width;
+    let y = idx / tex_width;
+
+    // Without MSAA there is a single sample, and the raster pass has already left out
+    // the transparent fragments behind its opaque fragment, so every depth stays zero.
+    var colors: array<vec3<f32>, MSAA_SAMPLES>;
+    var depths: array<u32, MSAA_SAMPLES>;
+    colors[0] = BACKGROUND;
+    let fragment = fragment_buffer[idx];
+    store_surface(x, y, fragment, fragment.flag == 0u);
+    if fragment.flag != 0u {
+        colors[0] = shade_opaque(fragment, x, y);
+        fragment_buffer[idx].flag = 0u;
+    }
 
     textureStore(
         output_tex,
-        vec2<i32>(x, y),
-        vec4<f32>(0.0, 0.0, 1.0, 1.0)
+        vec2<i32>(i32(x), i32(y)),
+        encode_output(blend_transparent(idx, x, y, colors, depths))
     );
+}
 
-    // Early-out if there's no valid fragment
-    if idx >= arrayLength(&fragment_buffer) || fragment_buffer[idx].flag == 0u {
-        return;
+// Whether transparent fragment `a` is drawn over `b`. Fragments at the same depth are
+// ordered by triangle ID like opaque ones, so the result doesn't depend on the order
+// the rasteriser appended them in.
+fn in_front(a: TransparentNode, b: TransparentNode) -> bool {
+    return a.depth > b.depth || (a.depth == b.depth && a.triangle < b.triangle);
+}
+
+// Blends a pixel's transparent fragments over the opaque colour of each of its samples,
+// from back to front, and averages the samples before tone mapping. `depths` holds the depth
+// key of each sample's opaque surface, which hides the fragments at or behind it.
+fn blend_transparent(
+    pixel_index: u32,
+    x: u32,
+    y: u32,
+    opaque_colors: array<vec3<f32>, MSAA_SAMPLES>,
+    depths: array<u32, MSAA_SAMPLES>
+) -> vec3<f32> {
+    // Insertion sort the list into the nearest MAX_TRANSPARENT_LAYERS fragments, farthest first.
+    var layers: array<TransparentNode, MAX_TRANSPARENT_LAYERS>;
+    var count = 0u;
+    var next = transparent_heads[pixel_index];
+    while next != 0u {
+        let node = transparent_nodes.nodes[next - 1u];
+        next = node.next;
+
+        if count == MAX_TRANSPARENT_LAYERS {
+            // The farthest fragment makes way, unless the new one is even farther.
+            if !in_front(node, layers[0]) {
+                continue;
+            }
+            for (var i = 1u; i < count; i++) {
+                layers[i - 1u] = layers[i];
+            }
+            count -= 1u;
+        }
+
+        var i = count;
+        while i > 0u && in_front(layers[i - 1u], node) {
+            layers[i] = layers[i - 1u];
+            i -= 1u;
+        }
+        layers[i] = node;
+        count += 1u;
     }
 
+    // Each fragment is shaded once, at the first sample it covers.
+    var colors = opaque_colors;
+    for (var i = 0u; i < count; i++) {
+        let layer = layers[i];
+        let fragment = reconstruct_fragment(layer.triangle, x, y, firstTrailingBit(layer.coverage));
+        let alpha = material_alpha(materials[fragment.material], fragment.uv);
+        let color = shade_linear(fragment);
+        for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+            if (layer.coverage & (1u << sample)) != 0u && layer.depth > depths[sample] {
+                colors[sample] = mix(colors[sample], color, alpha);
+            }
+        }
+    }
 
-    let in = fragment_buffer[idx];
-    var final_color = vec3<f32>(0.1);
+    var color = vec3<f32>(0.0);
+    for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+        color += colors[sample];
+    }
+    return color / f32(MSAA_SAMPLES);
+}
 
-    let num_lights = arrayLength(&lights);
-    for (var i = 0u; i < num_lights; i++) {
-        let light = lights[i];
-        let light_dir = normalize(light.world_position - in.position);
-        let diff = max(dot(in.normal, light_dir), 0.0);
-        let view_dir = normalize(camera.view_pos.xyz - in.position);
-        let reflect_dir = reflect(-light_dir, in.normal);
-        let spec = pow(max(dot(view_dir, reflect_dir), 0.0), 32.0);
-        final_color += (diff + spec * 0.5) * light.color * light.intensity;
+// Interpolates the attributes of the triangle the raster pass found nearest, at the
+// same sample position and with the same triangle setup (raster_common.wgsl).
+fn reconstruct_fragment(triangle_index: u32, x: u32, y: u32, sample: u32) -> Fragment {
+    let base_idx = triangle_index * 3u;
+    let v1 = projected_buffer[indices[base_idx]];
+    let v2 = projected_buffer[indices[base_idx + 1u]];
+    let v3 = projected_buffer[indices[base_idx + 2u]];
+
+    let setup = setup_triangle(v1, v2, v3);
+    let bc = barycentric(setup, edge_values(setup, sample_position(x, y, sample)));
+    return Fragment(
+        bc.x * v1.uv + bc.y * v2.uv + bc.z * v3.uv,
+        bc.x * v1.normal + bc.y * v2.normal + bc.z * v3.normal,
+        bc.x * v1.world_pos + bc.y * v2.world_pos + bc.z * v3.world_pos,
+        1u,
+        v1.material,
+        triangle_index
+    );
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn fragment_visibility_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    let num_pixels = tex_width * u32(screen_dims.height);
+    if idx >= num_pixels {
+        return;
     }
+    let x = idx % tex_width;
+    let y = idx / tex_width;
 
-    fragment_buffer[idx].flag = 0u;
-    final_color = clamp(final_color, vec3<f32>(0.0), vec3<f32>(1.0));
-    let srgb_color = pow(final_color, vec3<f32>(1.0 / 2.2));
+    // A cleared sample holds zero in both words; a stored triangle key never is zero.
+    var triangle_keys: array<u32, MSAA_SAMPLES>;
+    var depths: array<u32, MSAA_SAMPLES>;
+    for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+        triangle_keys[sample] = visibility_buffer[(idx * MSAA_SAMPLES + sample) * 2u];
+        depths[sample] = visibility_buffer[(idx * MSAA_SAMPLES + sample) * 2u + 1u];
+    }
+
+    // Each triangle in the pixel is shaded once, at the first sample it covers.
+    var colors: array<vec3<f32>, MSAA_SAMPLES>;
+    for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+        let key = triangle_keys[sample];
+        if key == 0u {
+            if sample == 0u {
+                store_surface(x, y, Fragment(), true);
+            }
+            colors[sample] = BACKGROUND;
+            continue;
+        }
+
+        var first = true;
+        for (var other = 0u; other < sample; other++) {
+            first = first && triangle_keys[other] != key;
+        }
+        if first {
+            let fragment = reconstruct_fragment(~key, x, y, sample);
+            if sample == 0u {
+                store_surface(x, y, fragment, false);
+            }
+            let color = shade_opaque(fragment, x, y);
+            for (var other = sample; other < MSAA_SAMPLES; other++) {
+                if triangle_keys[other] == key {
+                    colors[other] = color;
+                }
+            }
+        }
+    }
 
     textureStore(
         output_tex,
-        vec2<i32>(x, y),
-        vec4<f32>(srgb_color, 1.0)
+        vec2<i32>(i32(x), i32(y)),
+        encode_output(blend_transparent(idx, x, y, colors, depths))
     );
-}
\ No newline at end of file
+}
+
+// In deferred mode the fragment pass keeps each pixel's opaque surface in the G-buffer
+// instead of shading it. The transparent fragments are left for the lighting pass.
+fn store_gbuffer(x: u32, y: u32, fragment: Fragment, is_empty: bool) {
+    store_surface(x, y, fragment, is_empty);
+    var albedo = vec4<f32>(0.0);
+    var material = 0u;
+    if !is_empty {
+        albedo = vec4<f32>(base_color(materials[fragment.material], fragment.uv), 1.0);
+        material = fragment.material + 1u;
+    }
+    textureStore(albedo_tex, vec2<i32>(i32(x), i32(y)), albedo);
+    textureStore(material_tex, vec2<i32>(i32(x), i32(y)), vec4<u32>(material, 0u, 0u, 0u));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn gbuffer_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+
+    let fragment = fragment_buffer[idx];
+    store_gbuffer(idx % tex_width, idx / tex_width, fragment, fragment.flag == 0u);
+    fragment_buffer[idx].flag = 0u;
+}
+
+// Deferred mode has no MSAA, so the visibility buffer holds a single sample per pixel.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn gbuffer_visibility_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+    let x = idx % tex_width;
+    let y = idx / tex_width;
+
+    let key = visibility_buffer[idx * 2u];
+    if key == 0u {
+        store_gbuffer(x, y, Fragment(), true);
+    } else {
+        store_gbuffer(x, y, reconstruct_fragment(~key, x, y, 0u), false);
+    }
+}
+
+// Lights the G-buffer surface of a pixel with its tile's lights, then blends the pixel's
+// transparent fragments over it. These are shaded forward, with every light, as the
+// tile's lights are only culled against the depth of its opaque surfaces.
+fn shade_deferred(idx: u32, x: u32, y: u32, depths: array<u32, MSAA_SAMPLES>) {
+    let pixel = vec2<i32>(i32(x), i32(y));
+    var colors: array<vec3<f32>, MSAA_SAMPLES>;
+    colors[0] = BACKGROUND;
+    let material = textureLoad(gbuffer_material, pixel, 0).x;
+    if material != 0u {
+        // The surface's position is rebuilt from its depth at the pixel centre.
+        let dims = vec2<f32>(screen_dims.width, screen_dims.height);
+        let uv = (vec2<f32>(f32(x), f32(y)) + 0.5) / dims;
+        let depth_normal = textureLoad(gbuffer_depth_normal, pixel, 0);
+        let clip = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth_normal.w, 1.0);
+        let world = camera.inv_view_proj * clip;
+        let surface = Surface(
+            world.xyz / world.w,
+            depth_normal.xyz,
+            textureLoad(gbuffer_albedo, pixel, 0).rgb,
+            material - 1u
+        );
+        colors[0] = shade_tile(surface, x, y);
+    }
+
+    textureStore(output_tex, pixel, encode_output(blend_transparent(idx, x, y, colors, depths)));
+}
+
+// As in fragment_main, the raster pass has left out the transparent fragments behind the
+// opaque one.
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn deferred_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+
+    var depths: array<u32, MSAA_SAMPLES>;
+    shade_deferred(idx, idx % tex_width, idx / tex_width, depths);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn deferred_visibility_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+
+    var depths: array<u32, MSAA_SAMPLES>;
+    depths[0] = visibility_buffer[idx * 2u + 1u];
+    shade_deferred(idx, idx % tex_width, idx / tex_width, depths);
+}
+
+// Debug views replace shading with a view of the pipeline's internals (DebugView in
+// config.rs). They show the nearest opaque surface of each pixel, or of its first sample
+// with MSAA, and leave transparent fragments out. The output is shown as it is, so these
+// are display colours rather than linear HDR.
+
+// The counts mapped to the hottest colour of the heatmaps.
+const DEBUG_MAX_TILE_TRIANGLES: f32 = 64.0;
+const DEBUG_MAX_OVERDRAW: f32 = 16.0;
+
+// View distance shown as black by the depth view, in world units.
+const DEBUG_DEPTH_RANGE: f32 = 100.0;
+
+// Wireframe lines are about this many pixels wide.
+const DEBUG_LINE_WIDTH: f32 = 1.0;
+const DEBUG_LINE_COLOR = vec3<f32>(1.0, 1.0, 1.0);
+
+// Black for zero, then blue through green and yellow to red at `t` = 1.
+fn heatmap(t: f32) -> vec3<f32> {
+    if t <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let x = saturate(t) * 4.0;
+    return saturate(vec3<f32>(1.5 - abs(x - 3.0), 1.5 - abs(x - 2.0), 1.5 - abs(x - 1.0)));
+}
+
+// A bright colour per triangle, from a hash of its ID, so neighbouring triangles differ.
+fn triangle_color(triangle: u32) -> vec3<f32> {
+    var h = triangle * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    h = (h >> 22u) ^ h;
+    let rgb = vec3<f32>(vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u)) / 255.0;
+    return 0.25 + 0.75 * rgb;
+}
+
+// Distance in pixels from the centre of pixel (x, y) to the nearest edge of the triangle,
+// from its edge functions divided by the length of each edge, in the rasteriser's fixed point.
+fn edge_distance(triangle: u32, x: u32, y: u32) -> f32 {
+    let base_idx = triangle * 3u;
+    let setup = setup_triangle(
+        projected_buffer[indices[base_idx]],
+        projected_buffer[indices[base_idx + 1u]],
+        projected_buffer[indices[base_idx + 2u]]
+    );
+    let edges = wide_to_float(edge_values(setup, pixel_centre(x, y)));
+    let lengths = sqrt(vec3<f32>(setup.normal_x) * vec3<f32>(setup.normal_x)
+        + vec3<f32>(setup.normal_y) * vec3<f32>(setup.normal_y));
+    let distances = abs(edges) / max(lengths, vec3<f32>(1.0)) / SUBPIXEL_SCALE;
+    return min(distances.x, min(distances.y, distances.z));
+}
+
+fn debug_color(idx: u32, x: u32, y: u32, fragment: Fragment, is_empty: bool) -> vec3<f32> {
+    if DEBUG_VIEW == DEBUG_TILE_TRIANGLES {
+        let tile = (y / TILE_SIZE) * screen_dims.num_tiles_x + x / TILE_SIZE;
+        return heatmap(f32(tile_buffer[tile].count) / DEBUG_MAX_TILE_TRIANGLES);
+    }
+    if DEBUG_VIEW == DEBUG_OVERDRAW {
+        return heatmap(f32(overdraw[idx]) / f32(MSAA_SAMPLES) / DEBUG_MAX_OVERDRAW);
+    }
+    if is_empty {
+        return vec3<f32>(0.0);
+    }
+    switch DEBUG_VIEW {
+        case DEBUG_DEPTH: {
+            let view_depth = (camera.view_proj * vec4<f32>(fragment.position, 1.0)).w;
+            return vec3<f32>(1.0 - saturate(view_depth / DEBUG_DEPTH_RANGE));
+        }
+        case DEBUG_NORMALS: {
+            return normalize(fragment.normal) * 0.5 + 0.5;
+        }
+        case DEBUG_UVS: {
+            return vec3<f32>(fract(fragment.uv), 0.0);
+        }
+        case DEBUG_TRIANGLE_IDS: {
+            return triangle_color(fragment.triangle);
+        }
+        default: {
+            let line = 1.0 - saturate(edge_distance(fragment.triangle, x, y) - DEBUG_LINE_WIDTH * 0.5);
+            return mix(saturate(shade_linear(fragment)), DEBUG_LINE_COLOR, line);
+        }
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn debug_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+    let x = idx % tex_width;
+    let y = idx / tex_width;
+
+    let fragment = fragment_buffer[idx];
+    store_surface(x, y, fragment, fragment.flag == 0u);
+    fragment_buffer[idx].flag = 0u;
+    textureStore(output_tex, vec2<i32>(i32(x), i32(y)), encode_output(debug_color(idx, x, y, fragment, fragment.flag == 0u)));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn debug_visibility_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    let tex_width = u32(screen_dims.width);
+    if idx >= tex_width * u32(screen_dims.height) {
+        return;
+    }
+    let x = idx % tex_width;
+    let y = idx / tex_width;
+
+    let key = visibility_buffer[idx * MSAA_SAMPLES * 2u];
+    var fragment = Fragment();
+    if key != 0u {
+        fragment = reconstruct_fragment(~key, x, y, 0u);
+    }
+    store_surface(x, y, fragment, key == 0u);
+    textureStore(output_tex, vec2<i32>(i32(x), i32(y)), encode_output(debug_color(idx, x, y, fragment, key == 0u)));
+}
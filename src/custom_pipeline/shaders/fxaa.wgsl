@@ -0,0 +1,145 @@
+// FXAA, after Timothy Lottes' FXAA 3.11 quality preset: pixels on a luma edge are
+// resampled across it, by how far along the edge they are from its ends.
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var output_tex: texture_storage_2d<rgba16float, write>;
+
+// Local contrast needed for an edge, relative to the brightest neighbour and absolute.
+const EDGE_THRESHOLD: f32 = 0.125;
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+// How much single pixel features are smoothed.
+const SUBPIXEL_QUALITY: f32 = 0.75;
+// Steps along the edge searching for its ends, in pixels, growing further out.
+const SEARCH_STEPS: u32 = 12u;
+const SEARCH_STEP_SIZES = array<f32, 12>(1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0);
+
+// Perceptual luma of the tone compressed colour, so edges are found where they are
+// visible however bright the HDR image is.
+fn luma(color: vec3<f32>) -> f32 {
+    let l = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    return sqrt(l / (1.0 + l));
+}
+
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(input_tex, linear_sampler, uv, 0.0).rgb);
+}
+
+fn load_luma(pixel: vec2<i32>, offset: vec2<i32>) -> f32 {
+    let dims = vec2<i32>(textureDimensions(input_tex));
+    return luma(textureLoad(input_tex, clamp(pixel + offset, vec2<i32>(0), dims - 1), 0).rgb);
+}
+
+@compute @workgroup_size(8, 8)
+fn fxaa_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(input_tex);
+    if any(global_id.xy >= dims) {
+        return;
+    }
+    let pixel = vec2<i32>(global_id.xy);
+    let texel = 1.0 / vec2<f32>(dims);
+    let uv = (vec2<f32>(global_id.xy) + 0.5) * texel;
+    let center = textureLoad(input_tex, pixel, 0);
+
+    let luma_center = luma(center.rgb);
+    let luma_n = load_luma(pixel, vec2<i32>(0, -1));
+    let luma_s = load_luma(pixel, vec2<i32>(0, 1));
+    let luma_e = load_luma(pixel, vec2<i32>(1, 0));
+    let luma_w = load_luma(pixel, vec2<i32>(-1, 0));
+    let luma_max = max(luma_center, max(max(luma_n, luma_s), max(luma_e, luma_w)));
+    let luma_min = min(luma_center, min(min(luma_n, luma_s), min(luma_e, luma_w)));
+    let luma_range = luma_max - luma_min;
+    if luma_range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+        textureStore(output_tex, pixel, center);
+        return;
+    }
+
+    let luma_nw = load_luma(pixel, vec2<i32>(-1, -1));
+    let luma_ne = load_luma(pixel, vec2<i32>(1, -1));
+    let luma_sw = load_luma(pixel, vec2<i32>(-1, 1));
+    let luma_se = load_luma(pixel, vec2<i32>(1, 1));
+
+    // The edge runs along whichever direction luma changes least in.
+    let edge_horizontal = abs(luma_n + luma_s - 2.0 * luma_center) * 2.0
+        + abs(luma_nw + luma_sw - 2.0 * luma_w)
+        + abs(luma_ne + luma_se - 2.0 * luma_e);
+    let edge_vertical = abs(luma_w + luma_e - 2.0 * luma_center) * 2.0
+        + abs(luma_nw + luma_ne - 2.0 * luma_n)
+        + abs(luma_sw + luma_se - 2.0 * luma_s);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on: the neighbour across it that differs most.
+    let luma_1 = select(luma_w, luma_n, is_horizontal);
+    let luma_2 = select(luma_e, luma_s, is_horizontal);
+    let gradient_1 = luma_1 - luma_center;
+    let gradient_2 = luma_2 - luma_center;
+    let is_1_steepest = abs(gradient_1) >= abs(gradient_2);
+    let gradient_scaled = 0.25 * max(abs(gradient_1), abs(gradient_2));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average: f32;
+    if is_1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_2 + luma_center);
+    }
+
+    // Search both ways along the edge, half a pixel across it, for where it ends.
+    var edge_uv = uv;
+    if is_horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+    var uv_1 = edge_uv - offset;
+    var uv_2 = edge_uv + offset;
+    var luma_end_1 = 0.0;
+    var luma_end_2 = 0.0;
+    var reached_1 = false;
+    var reached_2 = false;
+    for (var i = 0u; i < SEARCH_STEPS; i++) {
+        if !reached_1 {
+            luma_end_1 = luma_at(uv_1) - luma_local_average;
+            reached_1 = abs(luma_end_1) >= gradient_scaled;
+        }
+        if !reached_2 {
+            luma_end_2 = luma_at(uv_2) - luma_local_average;
+            reached_2 = abs(luma_end_2) >= gradient_scaled;
+        }
+        if reached_1 && reached_2 {
+            break;
+        }
+        if !reached_1 {
+            uv_1 -= offset * SEARCH_STEP_SIZES[i];
+        }
+        if !reached_2 {
+            uv_2 += offset * SEARCH_STEP_SIZES[i];
+        }
+    }
+
+    let distance_1 = select(uv.y - uv_1.y, uv.x - uv_1.x, is_horizontal);
+    let distance_2 = select(uv_2.y - uv.y, uv_2.x - uv.x, is_horizontal);
+    let is_direction_1 = distance_1 < distance_2;
+    let distance_final = min(distance_1, distance_2);
+    let edge_length = distance_1 + distance_2;
+
+    // Only pixels on the side of the nearer end that the edge bends towards are blended.
+    let luma_end = select(luma_end_2, luma_end_1, is_direction_1);
+    let correct_variation = (luma_end < 0.0) != (luma_center < luma_local_average);
+    var pixel_offset = select(0.0, 0.5 - distance_final / edge_length, correct_variation);
+
+    let luma_average = (2.0 * (luma_n + luma_s + luma_e + luma_w) + luma_nw + luma_ne + luma_sw + luma_se) / 12.0;
+    let subpixel_1 = saturate(abs(luma_average - luma_center) / luma_range);
+    let subpixel_2 = (-2.0 * subpixel_1 + 3.0) * subpixel_1 * subpixel_1;
+    pixel_offset = max(pixel_offset, subpixel_2 * subpixel_2 * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += pixel_offset * step_length;
+    } else {
+        final_uv.x += pixel_offset * step_length;
+    }
+    textureStore(output_tex, pixel, textureSampleLevel(input_tex, linear_sampler, final_uv, 0.0));
+}
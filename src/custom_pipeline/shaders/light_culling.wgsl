@@ -0,0 +1,233 @@
+// Tiled light culling: one workgroup per screen tile finds the depth bounds of the tile's
+// opaque surfaces, and lists the lights whose range reaches the box those bounds cut out
+// of the view frustum. Each entry point reads the depths from a different source: the
+// G-buffer in deferred mode, the raster pass's fragments or visibility buffer before
+// forward shading, or the WGPU pipeline's depth prepass.
+
+struct Uniform {
+    width: f32,
+    height: f32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
+};
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+};
+
+struct Light {
+    world_position: vec3<f32>,
+    light_type: u32,
+    view_position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+    direction: vec3<f32>,
+    cos_inner_angle: f32,
+    cos_outer_angle: f32,
+    shadow_view: u32,
+    _padding: vec2<f32>,
+};
+
+struct Fragment {
+    uv: vec2<f32>,
+    normal: vec3<f32>,
+    position: vec3<f32>,
+    flag: u32,
+    material: u32,
+    triangle: u32,
+};
+
+// As in scene.rs.
+const LIGHT_DIRECTIONAL: u32 = 1u;
+
+const CULLING_THREADS: u32 = 64u;
+
+@group(0) @binding(0) var<uniform> screen_dims: Uniform;
+@group(0) @binding(1) var<uniform> camera: Camera;
+@group(0) @binding(2) var<storage, read> lights: array<Light>;
+// A count, then that many light indices, in slots of MAX_TILE_LIGHTS plus one per tile.
+@group(0) @binding(4) var<storage, read_write> tile_lights: array<u32>;
+
+// The depth sources, only one of which is bound. Empty pixels are at depth one in the
+// textures, have a zero flag in the fragments and a zero triangle key in the visibility
+// buffer, whose depth keys are inverted depth bits (raster_common.wgsl).
+@group(0) @binding(3) var depth_normal_tex: texture_2d<f32>;
+@group(0) @binding(5) var<storage, read> fragment_buffer: array<Fragment>;
+@group(0) @binding(6) var<storage, read> visibility_buffer: array<u32>;
+@group(0) @binding(7) var depth_tex: texture_depth_2d;
+@group(0) @binding(8) var depth_multisampled_tex: texture_depth_multisampled_2d;
+
+// Depths are between zero and one, so their bits order like the depths themselves.
+var<workgroup> min_depth: atomic<u32>;
+var<workgroup> max_depth: atomic<u32>;
+var<workgroup> light_count: atomic<u32>;
+
+fn world_position(ndc: vec3<f32>) -> vec3<f32> {
+    let world = camera.inv_view_proj * vec4<f32>(ndc, 1.0);
+    return world.xyz / world.w;
+}
+
+// Resets the tile's depth bounds and light count before any thread includes its pixels.
+fn begin_tile(local_index: u32) {
+    if local_index == 0u {
+        atomicStore(&min_depth, bitcast<u32>(1.0));
+        atomicStore(&max_depth, 0u);
+        atomicStore(&light_count, 0u);
+    }
+    workgroupBarrier();
+}
+
+// The pixel a thread looks at on its way through the tile, and whether it is on screen.
+fn tile_pixel(tile: vec2<u32>, i: u32) -> vec2<u32> {
+    return tile * TILE_SIZE + vec2<u32>(i % TILE_SIZE, i / TILE_SIZE);
+}
+
+fn on_screen(pixel: vec2<u32>) -> bool {
+    return pixel.x < u32(screen_dims.width) && pixel.y < u32(screen_dims.height);
+}
+
+fn include_depth(depth: f32) {
+    if depth < 1.0 {
+        atomicMin(&min_depth, bitcast<u32>(max(depth, 0.0)));
+        atomicMax(&max_depth, bitcast<u32>(max(depth, 0.0)));
+    }
+}
+
+// Lists the lights of the tile once every thread has included its pixels' depths.
+fn list_lights(tile: vec2<u32>, local_index: u32) {
+    workgroupBarrier();
+    let near = bitcast<f32>(atomicLoad(&min_depth));
+    let far = bitcast<f32>(atomicLoad(&max_depth));
+
+    let dims = vec2<u32>(u32(screen_dims.width), u32(screen_dims.height));
+    let origin = tile * TILE_SIZE;
+    // The world space bounds of the tile's corners at the nearest and farthest depth.
+    let tile_min = vec2<f32>(origin) / vec2<f32>(dims);
+    let tile_max = vec2<f32>(min(origin + TILE_SIZE, dims)) / vec2<f32>(dims);
+    var box_min = vec3<f32>(3.4e38);
+    var box_max = vec3<f32>(-3.4e38);
+    for (var corner = 0u; corner < 8u; corner++) {
+        let uv = select(tile_min, tile_max, vec2<bool>((corner & 1u) != 0u, (corner & 2u) != 0u));
+        let depth = select(near, far, (corner & 4u) != 0u);
+        let position = world_position(vec3<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth));
+        box_min = min(box_min, position);
+        box_max = max(box_max, position);
+    }
+
+    // Tiles without a surface need no lights. Directional lights and lights without a
+    // range reach everything.
+    let slot = (tile.y * screen_dims.num_tiles_x + tile.x) * (MAX_TILE_LIGHTS + 1u);
+    let num_lights = select(arrayLength(&lights), 0u, far < near);
+    for (var i = local_index; i < num_lights; i += CULLING_THREADS) {
+        let light = lights[i];
+        var reaches = light.light_type == LIGHT_DIRECTIONAL || light.range <= 0.0;
+        if !reaches {
+            let nearest = clamp(light.world_position, box_min, box_max);
+            let offset = nearest - light.world_position;
+            reaches = dot(offset, offset) <= light.range * light.range;
+        }
+        if reaches {
+            let index = atomicAdd(&light_count, 1u);
+            if index < MAX_TILE_LIGHTS {
+                tile_lights[slot + 1u + index] = i;
+            }
+        }
+    }
+
+    workgroupBarrier();
+    if local_index == 0u {
+        tile_lights[slot] = min(atomicLoad(&light_count), MAX_TILE_LIGHTS);
+    }
+}
+
+@compute @workgroup_size(CULLING_THREADS)
+fn cull_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32
+) {
+    begin_tile(local_index);
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += CULLING_THREADS) {
+        let pixel = tile_pixel(tile.xy, i);
+        if on_screen(pixel) {
+            include_depth(textureLoad(depth_normal_tex, pixel, 0).w);
+        }
+    }
+    list_lights(tile.xy, local_index);
+}
+
+@compute @workgroup_size(CULLING_THREADS)
+fn cull_fragments_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32
+) {
+    begin_tile(local_index);
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += CULLING_THREADS) {
+        let pixel = tile_pixel(tile.xy, i);
+        if on_screen(pixel) {
+            let fragment = fragment_buffer[pixel.y * u32(screen_dims.width) + pixel.x];
+            if fragment.flag != 0u {
+                let clip = camera.view_proj * vec4<f32>(fragment.position, 1.0);
+                include_depth(clip.z / clip.w);
+            }
+        }
+    }
+    list_lights(tile.xy, local_index);
+}
+
+@compute @workgroup_size(CULLING_THREADS)
+fn cull_visibility_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32
+) {
+    begin_tile(local_index);
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += CULLING_THREADS) {
+        let pixel = tile_pixel(tile.xy, i);
+        if on_screen(pixel) {
+            let idx = pixel.y * u32(screen_dims.width) + pixel.x;
+            for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+                let key = (idx * MSAA_SAMPLES + sample) * 2u;
+                if visibility_buffer[key] != 0u {
+                    include_depth(bitcast<f32>(~visibility_buffer[key + 1u]));
+                }
+            }
+        }
+    }
+    list_lights(tile.xy, local_index);
+}
+
+@compute @workgroup_size(CULLING_THREADS)
+fn cull_depth_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32
+) {
+    begin_tile(local_index);
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += CULLING_THREADS) {
+        let pixel = tile_pixel(tile.xy, i);
+        if on_screen(pixel) {
+            include_depth(textureLoad(depth_tex, pixel, 0));
+        }
+    }
+    list_lights(tile.xy, local_index);
+}
+
+@compute @workgroup_size(CULLING_THREADS)
+fn cull_depth_multisampled_main(
+    @builtin(workgroup_id) tile: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32
+) {
+    begin_tile(local_index);
+    for (var i = local_index; i < TILE_SIZE * TILE_SIZE; i += CULLING_THREADS) {
+        let pixel = tile_pixel(tile.xy, i);
+        if on_screen(pixel) {
+            for (var sample = 0u; sample < textureNumSamples(depth_multisampled_tex); sample++) {
+                include_depth(textureLoad(depth_multisampled_tex, pixel, i32(sample)));
+            }
+        }
+    }
+    list_lights(tile.xy, local_index);
+}
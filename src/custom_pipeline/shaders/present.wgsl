@@ -1,16 +1,39 @@
-struct ScreenUniform {
-    width: f32,
-    height: f32,
+// Values of TONE_MAPPING, as in config.rs.
+const TONE_MAPPING_NONE: u32 = 0u;
+const TONE_MAPPING_REINHARD: u32 = 1u;
+const TONE_MAPPING_ACES: u32 = 2u;
+const TONE_MAPPING_AGX: u32 = 3u;
+
+// The metered luminance and the exposure the image is multiplied by (exposure.wgsl).
+struct Exposure {
+    luminance: f32,
+    exposure: f32,
 };
 
 @group(0) @binding(0)
-var my_tex: texture_2d<f32>;
+var hdr_tex: texture_2d<f32>;
 
 @group(0) @binding(1)
-var my_sampler: sampler;
+var hdr_sampler: sampler;
+
+@group(0) @binding(2)
+var<storage, read> exposure: Exposure;
+
+// ID of the opaque triangle in each pixel plus one, zero for empty pixels (picking.rs).
+@group(0) @binding(3)
+var id_tex: texture_2d<u32>;
+
+// The selected triangles, outlined in OUTLINE_COLOR. An empty range selects nothing.
+struct Outline {
+    first_triangle: u32,
+    end_triangle: u32,
+};
 
-@group(1) @binding(0)
-var<uniform> screen_dims: ScreenUniform;
+@group(0) @binding(4)
+var<uniform> outline: Outline;
+
+const OUTLINE_COLOR: vec3<f32> = vec3<f32>(1.0, 0.6, 0.0);
+const OUTLINE_RADIUS: i32 = 2;
 
 @vertex
 fn vs_main(@builtin(vertex_index) vertex_index: u32)
@@ -22,9 +45,107 @@ fn vs_main(@builtin(vertex_index) vertex_index: u32)
     return vec4<f32>(x * 2.0 - 1.0, 1.0 - y * 2.0, 0.0, 1.0);
 }
 
+// Stephen Hill's fit of ACES: sRGB to the RRT's input space, the RRT and ODT curve, and
+// back to sRGB. WGSL matrices are built from columns.
+const ACES_INPUT = mat3x3<f32>(
+    vec3<f32>(0.59719, 0.07600, 0.02840),
+    vec3<f32>(0.35458, 0.90834, 0.13383),
+    vec3<f32>(0.04823, 0.01566, 0.83777)
+);
+const ACES_OUTPUT = mat3x3<f32>(
+    vec3<f32>(1.60475, -0.10208, -0.00327),
+    vec3<f32>(-0.53108, 1.10813, -0.07276),
+    vec3<f32>(-0.07367, -0.00605, 1.07602)
+);
+
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let v = ACES_INPUT * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return ACES_OUTPUT * (a / b);
+}
+
+// AgX: an inset of the primaries, a log2 encoding between AGX_MIN_EV and AGX_MAX_EV stops
+// around middle grey, the default contrast curve (as a polynomial fit) and the outset back.
+// The curve's output is display encoded with a 2.2 gamma, which is undone to keep the
+// result linear.
+const AGX_INSET = mat3x3<f32>(
+    vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+    vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+    vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104)
+);
+const AGX_OUTSET = mat3x3<f32>(
+    vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+    vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+    vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116)
+);
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let log_color = clamp(log2(max(AGX_INSET * color, vec3<f32>(1e-10))), vec3<f32>(AGX_MIN_EV), vec3<f32>(AGX_MAX_EV));
+    let x = (log_color - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    return pow(max(AGX_OUTSET * curve, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn tone_map(color: vec3<f32>) -> vec3<f32> {
+    switch TONE_MAPPING {
+        case TONE_MAPPING_REINHARD: {
+            return color / (1.0 + color);
+        }
+        case TONE_MAPPING_ACES: {
+            return aces(color);
+        }
+        case TONE_MAPPING_AGX: {
+            return agx(color);
+        }
+        default: {
+            return color;
+        }
+    }
+}
+
+// The sRGB transfer function, so both backends encode the same way whatever the surface.
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let c = saturate(color);
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn is_selected(pixel: vec2<i32>) -> bool {
+    let dims = vec2<i32>(textureDimensions(id_tex, 0));
+    let id = textureLoad(id_tex, clamp(pixel, vec2<i32>(0), dims - 1), 0).r;
+    return id > outline.first_triangle && id <= outline.end_triangle;
+}
+
+// Whether the pixel lies outside the selection but within OUTLINE_RADIUS of it.
+fn on_outline(pixel: vec2<i32>) -> bool {
+    if outline.first_triangle == outline.end_triangle || is_selected(pixel) {
+        return false;
+    }
+    for (var dy = -OUTLINE_RADIUS; dy <= OUTLINE_RADIUS; dy++) {
+        for (var dx = -OUTLINE_RADIUS; dx <= OUTLINE_RADIUS; dx++) {
+            if is_selected(pixel + vec2<i32>(dx, dy)) {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
 @fragment
 fn fs_main(@builtin(position) coord: vec4<f32>) -> @location(0) vec4<f32> {
-    let dims = vec2<f32>(textureDimensions(my_tex, 0));
-    let uv = coord.xy / vec2<f32>(screen_dims.width, screen_dims.height);
-    return textureSample(my_tex, my_sampler, uv);
+    let dims = vec2<f32>(textureDimensions(hdr_tex, 0));
+    let uv = coord.xy / dims;
+    if on_outline(vec2<i32>(coord.xy)) {
+        return vec4<f32>(OUTLINE_COLOR, 1.0);
+    }
+    // Debug views (DEBUG_VIEW in config.rs) are already display colours.
+    if DEBUG_VIEW != 0u {
+        return vec4<f32>(saturate(textureSample(hdr_tex, hdr_sampler, uv).rgb), 1.0);
+    }
+    let color = textureSample(hdr_tex, hdr_sampler, uv).rgb * exposure.exposure;
+    return vec4<f32>(linear_to_srgb(tone_map(color)), 1.0);
 }
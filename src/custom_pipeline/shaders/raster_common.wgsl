@@ -0,0 +1,303 @@
+// Coverage and resolve rules shared by the standard and visibility buffer
+// rasterisers, and by the fragment pass when it rebuilds visibility buffer samples.
+// Expects `effect`, `materials` and the Vertex struct from the file it is combined with.
+//
+// Triangle setup snaps the screen space vertices to fixed point with SUBPIXEL_BITS
+// of sub-pixel precision. Pixels are sampled at their centres, or at the standard
+// MSAA pattern around them when MSAA_SAMPLES is above one. Edge functions
+// are then exact integers, evaluated once per row and stepped across it, so two
+// triangles sharing an edge always get exactly opposite values at every sample.
+//
+// A sample is inside a triangle when all three of its edge functions are positive.
+// A sample exactly on an edge only belongs to the triangle if that edge is a top or
+// a left edge (the usual top-left fill rule), so a sample on an edge shared by two
+// triangles is covered by exactly one of them rather than by both or neither.
+
+// Matches MaterialInfo on the CPU.
+struct TextureInfo {
+    offset: u32,
+    width: u32,
+    height: u32,
+    _padding: u32,
+};
+
+struct Material {
+    texture_info: TextureInfo,
+    ambient: vec3<f32>,
+    _padding1: f32,
+    specular: vec3<f32>,
+    _padding2: f32,
+    diffuse: vec3<f32>,
+    shininess: f32,
+    dissolve: f32,
+    optical_density: f32,
+    roughness: f32,
+    metallic: f32,
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+    _padding3: vec2<f32>,
+};
+
+// Values of alpha_mode, as in util.rs.
+const ALPHA_DISSOLVE: u32 = 0u;
+const ALPHA_BLEND: u32 = 1u;
+const ALPHA_MASK: u32 = 2u;
+
+// Transparent fragments are kept in a linked list per pixel, in the order the rasteriser
+// happened to append them. `next` is the index of the following node plus one, zero at
+// the end of the list. Coverage has a bit per MSAA sample, and the fragment is shaded at
+// the first sample it covers.
+struct TransparentNode {
+    next: u32,
+    triangle: u32,
+    depth: u32,
+    coverage: u32,
+};
+
+// Values of DEBUG_VIEW, as in config.rs. The rasterisers only count overdraw, and the
+// fragment pass only reads it, with DEBUG_OVERDRAW.
+const DEBUG_NONE: u32 = 0u;
+const DEBUG_TILE_TRIANGLES: u32 = 1u;
+const DEBUG_OVERDRAW: u32 = 2u;
+const DEBUG_DEPTH: u32 = 3u;
+const DEBUG_NORMALS: u32 = 4u;
+const DEBUG_UVS: u32 = 5u;
+const DEBUG_TRIANGLE_IDS: u32 = 6u;
+const DEBUG_WIREFRAME: u32 = 7u;
+
+// Transparent triangles are left out of the opaque resolve and blended over it instead,
+// as are glTF materials with alpha, masked ones included. All vertices of a triangle
+// share its material.
+fn is_transparent(v: Vertex) -> bool {
+    let material = materials[v.material];
+    return material.dissolve < 1.0 || material.alpha_mode != ALPHA_DISSOLVE;
+}
+
+const SUBPIXEL_BITS: u32 = 8u;
+const SUBPIXEL_SCALE: f32 = 256.0;
+
+// Vertices further than this many pixels off screen can't be snapped without
+// overflowing the edge setup, so their triangles aren't rasterised.
+const GUARD_BAND: f32 = 2097152.0;
+
+// Edge function values need more than 32 bits, so they are kept as 64-bit two's
+// complement integers split into low and high words, one lane per edge.
+struct Wide3 {
+    lo: vec3<u32>,
+    hi: vec3<u32>,
+};
+
+fn wide_add(a: Wide3, b: Wide3) -> Wide3 {
+    let lo = a.lo + b.lo;
+    let carry = select(vec3<u32>(0u), vec3<u32>(1u), lo < a.lo);
+    return Wide3(lo, a.hi + b.hi + carry);
+}
+
+fn wide_negate(a: Wide3) -> Wide3 {
+    let lo = ~a.lo + 1u;
+    let carry = select(vec3<u32>(0u), vec3<u32>(1u), lo == vec3<u32>(0u));
+    return Wide3(lo, ~a.hi + carry);
+}
+
+// Full 64-bit product of two signed 32-bit values, built from 16-bit halves.
+fn wide_mul(a: vec3<i32>, b: vec3<i32>) -> Wide3 {
+    let x = vec3<u32>(abs(a));
+    let y = vec3<u32>(abs(b));
+    let low = (x & vec3<u32>(0xFFFFu)) * (y & vec3<u32>(0xFFFFu));
+    let cross_1 = (x & vec3<u32>(0xFFFFu)) * (y >> vec3<u32>(16u));
+    let cross_2 = (x >> vec3<u32>(16u)) * (y & vec3<u32>(0xFFFFu));
+    let high = (x >> vec3<u32>(16u)) * (y >> vec3<u32>(16u));
+
+    let middle = (low >> vec3<u32>(16u))
+        + (cross_1 & vec3<u32>(0xFFFFu))
+        + (cross_2 & vec3<u32>(0xFFFFu));
+    let product = Wide3(
+        (low & vec3<u32>(0xFFFFu)) | (middle << vec3<u32>(16u)),
+        high + (cross_1 >> vec3<u32>(16u)) + (cross_2 >> vec3<u32>(16u)) + (middle >> vec3<u32>(16u))
+    );
+
+    let negate = (a < vec3<i32>(0)) != (b < vec3<i32>(0));
+    let negated = wide_negate(product);
+    return Wide3(select(product.lo, negated.lo, negate), select(product.hi, negated.hi, negate));
+}
+
+fn wide_positive(a: Wide3) -> vec3<bool> {
+    let hi = bitcast<vec3<i32>>(a.hi);
+    return (hi > vec3<i32>(0)) | ((hi == vec3<i32>(0)) & (a.lo > vec3<u32>(0u)));
+}
+
+fn wide_zero(a: Wide3) -> vec3<bool> {
+    return (a.hi == vec3<u32>(0u)) & (a.lo == vec3<u32>(0u));
+}
+
+fn wide_to_float(a: Wide3) -> vec3<f32> {
+    return vec3<f32>(bitcast<vec3<i32>>(a.hi)) * 4294967296.0 + vec3<f32>(a.lo);
+}
+
+struct TriangleSetup {
+    // Per edge, in the order of the edges opposite vertices 1, 2 and 3: the snapped
+    // start of the edge, and the edge normal pointing into the triangle, so that
+    // E(p) = normal_x * (p.x - start_x) + normal_y * (p.y - start_y).
+    start_x: vec3<i32>,
+    start_y: vec3<i32>,
+    normal_x: vec3<i32>,
+    normal_y: vec3<i32>,
+    // Change of the edge functions between neighbouring pixel centres.
+    step_x: Wide3,
+    step_y: Wide3,
+    // Twice the unsigned area in sub-pixel units. Zero for degenerate triangles and
+    // triangles outside the guard band, which cover nothing.
+    area: f32,
+    // Whether each edge owns the samples lying exactly on it.
+    top_left: vec3<bool>,
+};
+
+fn snap(p: vec2<f32>) -> vec2<i32> {
+    return vec2<i32>(round(p * SUBPIXEL_SCALE));
+}
+
+fn setup_triangle(v1: Vertex, v2: Vertex, v3: Vertex) -> TriangleSetup {
+    var setup: TriangleSetup;
+    let positions = mat3x2<f32>(v1.screen_pos.xy, v2.screen_pos.xy, v3.screen_pos.xy);
+    // Also false for NaN positions.
+    if !all(abs(positions[0]) <= vec2<f32>(GUARD_BAND))
+        || !all(abs(positions[1]) <= vec2<f32>(GUARD_BAND))
+        || !all(abs(positions[2]) <= vec2<f32>(GUARD_BAND)) {
+        setup.area = 0.0;
+        return setup;
+    }
+
+    let p1 = snap(positions[0]);
+    let p2 = snap(positions[1]);
+    let p3 = snap(positions[2]);
+    setup.start_x = vec3<i32>(p2.x, p3.x, p1.x);
+    setup.start_y = vec3<i32>(p2.y, p3.y, p1.y);
+    let end_x = vec3<i32>(p3.x, p1.x, p2.x);
+    let end_y = vec3<i32>(p3.y, p1.y, p2.y);
+    setup.normal_x = setup.start_y - end_y;
+    setup.normal_y = end_x - setup.start_x;
+
+    // The edge opposite vertex 3 evaluated at vertex 3 gives twice the signed area.
+    let at_p3 = edge_values(setup, p3);
+    if wide_zero(at_p3).z {
+        setup.area = 0.0;
+        return setup;
+    }
+    if !wide_positive(at_p3).z {
+        setup.normal_x = -setup.normal_x;
+        setup.normal_y = -setup.normal_y;
+    }
+    setup.area = abs(wide_to_float(at_p3).z);
+
+    let pixel = vec3<i32>(1 << SUBPIXEL_BITS);
+    setup.step_x = wide_mul(setup.normal_x, pixel);
+    setup.step_y = wide_mul(setup.normal_y, pixel);
+
+    // Screen y points down, so the inward normal of a left edge points right and that
+    // of a top (horizontal) edge points down.
+    setup.top_left = (setup.normal_x > vec3<i32>(0))
+        | ((setup.normal_x == vec3<i32>(0)) & (setup.normal_y > vec3<i32>(0)));
+    return setup;
+}
+
+// Edge functions at a snapped sample position, positive inside the triangle.
+fn edge_values(setup: TriangleSetup, p: vec2<i32>) -> Wide3 {
+    return wide_add(
+        wide_mul(setup.normal_x, vec3<i32>(p.x) - setup.start_x),
+        wide_mul(setup.normal_y, vec3<i32>(p.y) - setup.start_y)
+    );
+}
+
+// The centre of pixel (x, y) in sub-pixel units.
+fn pixel_centre(x: u32, y: u32) -> vec2<i32> {
+    return (vec2<i32>(i32(x), i32(y)) << vec2<u32>(SUBPIXEL_BITS)) + vec2<i32>(1 << (SUBPIXEL_BITS - 1u));
+}
+
+// Offset of a sample from its pixel centre in sub-pixel units. SAMPLE_OFFSETS holds
+// the MSAA pattern in sixteenths of a pixel.
+fn sample_offset(sample: u32) -> vec2<i32> {
+    var offsets = SAMPLE_OFFSETS;
+    return offsets[sample] * (1 << (SUBPIXEL_BITS - 4u));
+}
+
+// Where a sample of pixel (x, y) lies. Effect 4 wobbles the sample positions, which
+// moves them off the pixel grid the edge functions are normally stepped along.
+fn sample_position(x: u32, y: u32, sample: u32) -> vec2<i32> {
+    let position = pixel_centre(x, y) + sample_offset(sample);
+    if effect.effect_type != 4u {
+        return position;
+    }
+    let offset = vec2<f32>(
+        effect.param1 * sin(effect.param2 * f32(x) + effect.time + effect.param3),
+        effect.param1 * cos(effect.param2 * f32(y) + effect.time + effect.param3)
+    );
+    return position + snap(offset);
+}
+
+// Edge functions at a sample of pixel (x, y), given `stepped`, the values at the pixel
+// centre carried across from its neighbour. Samples off the pixel grid are evaluated
+// from scratch instead.
+fn sample_edge_values(setup: TriangleSetup, stepped: Wide3, x: u32, y: u32, sample: u32) -> Wide3 {
+    if effect.effect_type == 4u {
+        return edge_values(setup, sample_position(x, y, sample));
+    }
+    if MSAA_SAMPLES == 1u {
+        return stepped;
+    }
+    let offset = sample_offset(sample);
+    return wide_add(
+        stepped,
+        wide_add(wide_mul(setup.normal_x, vec3<i32>(offset.x)), wide_mul(setup.normal_y, vec3<i32>(offset.y)))
+    );
+}
+
+fn barycentric(setup: TriangleSetup, edges: Wide3) -> vec3<f32> {
+    return wide_to_float(edges) / setup.area;
+}
+
+fn sample_covered(setup: TriangleSetup, edges: Wide3) -> bool {
+    if setup.area == 0.0 {
+        return false;
+    }
+
+    let bc = barycentric(setup, edges);
+    if effect.effect_type == 3u {
+        // Effect 3 grows every triangle past its edges, so neighbours overlap anyway.
+        if any(bc < vec3<f32>(-effect.param1)) {
+            return false;
+        }
+    } else if !all(wide_positive(edges) | (wide_zero(edges) & setup.top_left)) {
+        return false;
+    }
+
+    if effect.effect_type == 2u {
+        let amplitude = effect.param1;
+        let phase = effect.param2;
+        let wave = 0.5 + 0.5 * sin(effect.time + phase);
+        let meltdownThreshold = amplitude * wave;
+        let min_bc = min(bc.x, min(bc.y, bc.z));
+        if min_bc < meltdownThreshold {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn interpolate_depth(v1: Vertex, v2: Vertex, v3: Vertex, bc: vec3<f32>) -> f32 {
+    return bc.x * v1.screen_pos.z + bc.y * v2.screen_pos.z + bc.z * v3.screen_pos.z;
+}
+
+// Depth and triangle ID are resolved bit-inverted, so a cleared (all zero) value means
+// "empty" and an atomic max keeps the nearest depth and, between samples at exactly
+// the same depth, the lowest triangle ID. The result is then independent of the order
+// threads run in.
+//
+// Samples at or behind the camera's z = 0 would have negative depths, whose bit
+// patterns don't order as unsigned integers, so they are clamped first.
+fn depth_key(depth: f32) -> u32 {
+    return ~bitcast<u32>(max(depth, 0.0));
+}
+
+fn triangle_key(triangle_index: u32) -> u32 {
+    return ~triangle_index;
+}
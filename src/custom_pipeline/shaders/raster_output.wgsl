@@ -0,0 +1,22 @@
+// Writes shared by the standard and visibility buffer rasterisers, which the fragment
+// pass only reads. Expects `stats`, `transparent_heads`, `transparent_nodes` and `overdraw`
+// from the file it is combined with.
+
+// Pushes a transparent fragment onto the front of its pixel's list. Fragments that don't
+// fit in the node storage are dropped.
+fn append_transparent(pixel_index: u32, triangle_index: u32, depth: u32, coverage: u32) {
+    atomicAdd(&stats.transparent_fragments, 1u);
+    let node = atomicAdd(&transparent_nodes.count, 1u);
+    if node >= arrayLength(&transparent_nodes.nodes) {
+        return;
+    }
+    let next = atomicExchange(&transparent_heads[pixel_index], node + 1u);
+    transparent_nodes.nodes[node] = TransparentNode(next, triangle_index, depth, coverage);
+}
+
+// Counts a depth test of a sample in the pixel for the overdraw debug view.
+fn count_overdraw(pixel_index: u32) {
+    if DEBUG_VIEW == DEBUG_OVERDRAW {
+        atomicAdd(&overdraw[pixel_index], 1u);
+    }
+}
@@ -1,4 +1,6 @@
-const TILE_SIZE: u32 = 8u;
+// TILE_SIZE and RASTER_THREADS are injected by PipelineConfig::shader_header.
+// Coverage rules and resolve keys come from raster_common.wgsl, and the transparent
+// fragment lists and overdraw counts are written by raster_output.wgsl.
 
 struct UniformRaster {
     width: f32,
@@ -22,6 +24,7 @@ struct Vertex {
     screen_pos: vec4<f32>,
     normal: vec3<f32>,
     uv: vec2<f32>,
+    material: u32,
 };
 
 struct Fragment {
@@ -29,6 +32,8 @@ struct Fragment {
     normal: vec3<f32>,
     position: vec3<f32>,
     flag: u32,
+    material: u32,
+    triangle: u32,
 };
 
 struct TileTriangles {
@@ -37,12 +42,30 @@ struct TileTriangles {
     write_index: u32,
 }
 
+struct PipelineStats {
+    binned_tile_pairs: atomic<u32>,
+    rejected_tile_pairs: atomic<u32>,
+    vertex_invocations: atomic<u32>,
+    rasterised_samples: atomic<u32>,
+    transparent_fragments: atomic<u32>,
+};
+
+struct BigTriangles {
+    count: u32,
+    triangles: array<u32>,
+};
+
 struct TriangleBinningData {
     min_max: vec4<f32>,
     start_tile: vec2<u32>,
     tile_range: vec2<u32>,
 };
 
+struct TransparentNodes {
+    count: atomic<u32>,
+    nodes: array<TransparentNode>,
+};
+
 @group(0) @binding(0)
 var<storage, read> projected_buffer: array<Vertex>;
 
@@ -50,7 +73,7 @@ var<storage, read> projected_buffer: array<Vertex>;
 var<storage, read_write> fragment_buffer: array<Fragment>;
 
 @group(0) @binding(2)
-var<storage, read_write> tile_buffer: array<TileTriangles>;
+var<storage, read> tile_buffer: array<TileTriangles>;
 
 @group(0) @binding(3)
 var<storage, read> triangle_list_buffer: array<u32>;
@@ -61,135 +84,198 @@ var<storage, read> indices: array<u32>;
 @group(0) @binding(5)
 var<storage, read> tile_binning_data: array<TriangleBinningData>;
 
+@group(0) @binding(6)
+var<storage, read> big_triangles: BigTriangles;
+
+@group(0) @binding(7)
+var<storage, read_write> stats: PipelineStats;
+
+// With MSAA, fragments are shaded per pixel from every sample's triangle, so the
+// resolved samples go to the visibility buffer instead of fragment_buffer: the
+// triangle key then the depth key of each sample, as in visibility buffer mode.
+@group(0) @binding(8)
+var<storage, read_write> visibility_buffer: array<u32>;
+
+@group(0) @binding(9)
+var<storage, read> materials: array<Material>;
+
+@group(0) @binding(10)
+var<storage, read_write> transparent_heads: array<atomic<u32>>;
+
+@group(0) @binding(11)
+var<storage, read_write> transparent_nodes: TransparentNodes;
+
+// With DEBUG_OVERDRAW, the depth tests of every pixel's samples. A placeholder otherwise.
+@group(0) @binding(12)
+var<storage, read_write> overdraw: array<atomic<u32>>;
+
 @group(1) @binding(0)
 var<uniform> screen_dims: UniformRaster;
 
 @group(2) @binding(0)
 var<uniform> effect: EffectUniform;
 
-// Compute barycentric coordinates for point p (in 2D screen space)
-fn barycentric(v1: vec3<f32>, v2: vec3<f32>, v3: vec3<f32>, p: vec2<f32>) -> vec3<f32> {
-    let u = cross(
-        vec3<f32>(v3.x - v1.x, v2.x - v1.x, v1.x - p.x),
-        vec3<f32>(v3.y - v1.y, v2.y - v1.y, v1.y - p.y)
-    );
-    return vec3<f32>(
-        1.0 - (u.x + u.y) / u.z,
-        u.y / u.z,
-        u.x / u.z
-    );
-}
-
-// Pack and unpack functions for depth values.
-fn pack_float_to_u32(value: f32) -> u32 {
-    return bitcast<u32>(value);
-}
+const MAX_TILES: u32 = TILE_SIZE * TILE_SIZE;
+const MAX_TILE_SAMPLES: u32 = MAX_TILES * MSAA_SAMPLES;
 
-fn unpack_u32_to_float(bits: u32) -> f32 {
-    return bitcast<f32>(bits);
-}
+// Per-sample resolve for the tile, keyed like the visibility buffer: the nearest depth,
+// then the lowest triangle ID among the samples at that depth.
+var<workgroup> local_depth: array<atomic<u32>, MAX_TILE_SAMPLES>;
+var<workgroup> local_triangle: array<atomic<u32>, MAX_TILE_SAMPLES>;
 
-const MAX_TILES: u32 = TILE_SIZE * TILE_SIZE;
-var<workgroup> local_depth: array<atomic<u32>, MAX_TILES>;
+const RESOLVE_DEPTH: u32 = 0u;
+const RESOLVE_TRIANGLE: u32 = 1u;
+const RESOLVE_TRANSPARENT: u32 = 2u;
 
 // ---------------------------------------------------------------------
 // Rasterization function: rasterise a triangle into one tile.
 // The triangle’s vertices are in screen space and already have their
 // perspective divide (and attributes pre–divided by w) applied.
+// Returns how many samples of the tile the triangle covers.
+//
+// Transparent triangles are rasterised once the opaque ones are resolved, and
+// append a fragment for each pixel where they cover a sample in front of the
+// opaque surface.
 // ---------------------------------------------------------------------
-fn rasterise_triangle_in_tile(v1: Vertex, v2: Vertex, v3: Vertex, tile_x: u32, tile_y: u32) {
+fn rasterise_triangle_in_tile(
+    triangle_index: u32,
+    v1: Vertex,
+    v2: Vertex,
+    v3: Vertex,
+    tile_x: u32,
+    tile_y: u32,
+    resolve: u32
+) -> u32 {
     // Compute the pixel bounds for the tile.
     let tile_start_x = tile_x * TILE_SIZE;
     let tile_end_x = min(tile_start_x + TILE_SIZE, u32(screen_dims.width));
     let tile_start_y = tile_y * TILE_SIZE;
     let tile_end_y = min(tile_start_y + TILE_SIZE, u32(screen_dims.height));
-    
+
+    let setup = setup_triangle(v1, v2, v3);
+    if setup.area == 0.0 {
+        return 0u;
+    }
+
+    // Edge functions are evaluated once at the tile's first pixel centre, then stepped.
+    var row = edge_values(setup, pixel_centre(tile_start_x, tile_start_y));
+    var samples = 0u;
+
     // Loop over the pixels in the tile.
     for (var y = tile_start_y; y < tile_end_y; y++) {
+        var stepped = row;
         for (var x = tile_start_x; x < tile_end_x; x++) {
-            var pos = vec2<f32>(f32(x), f32(y));
-
-            if effect.effect_type == 4u {
-                let offset = vec2<f32>(
-                    effect.param1 * sin(effect.param2 * f32(x) + effect.time + effect.param3),
-                    effect.param1 * cos(effect.param2 * f32(y) + effect.time + effect.param3)
-                );
-                pos += offset;
+            let local_pixel = (x - tile_start_x) + (y - tile_start_y) * TILE_SIZE;
+            var coverage = 0u;
+            var nearest = 0u;
+            for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+                let edges = sample_edge_values(setup, stepped, x, y, sample);
+                if !sample_covered(setup, edges) {
+                    continue;
+                }
+
+                let interpolated_z = interpolate_depth(v1, v2, v3, barycentric(setup, edges));
+                let local_index = local_pixel * MSAA_SAMPLES + sample;
+                let depth = depth_key(interpolated_z);
+                if resolve == RESOLVE_DEPTH {
+                    count_overdraw(x + y * u32(screen_dims.width));
+                    atomicMax(&local_depth[local_index], depth);
+                    samples += 1u;
+                } else if resolve == RESOLVE_TRANSPARENT {
+                    count_overdraw(x + y * u32(screen_dims.width));
+                    if depth > atomicLoad(&local_depth[local_index]) {
+                        coverage |= 1u << sample;
+                        nearest = max(nearest, depth);
+                        samples += 1u;
+                    }
+                } else if atomicLoad(&local_depth[local_index]) == depth {
+                    atomicMax(&local_triangle[local_index], triangle_key(triangle_index));
+                }
             }
+            if coverage != 0u {
+                append_transparent(x + y * u32(screen_dims.width), triangle_index, nearest, coverage);
+            }
+            stepped = wide_add(stepped, setup.step_x);
+        }
+        row = wide_add(row, setup.step_y);
+    }
+    return samples;
+}
 
-            let bc = barycentric(
-                v1.screen_pos.xyz,
-                v2.screen_pos.xyz,
-                v3.screen_pos.xyz,
-                pos
-            );
+fn rasterise_triangle(base_idx: u32, tile_x: u32, tile_y: u32, resolve: u32) -> u32 {
+    let v1 = projected_buffer[indices[base_idx]];
+    if is_transparent(v1) != (resolve == RESOLVE_TRANSPARENT) {
+        return 0u;
+    }
+    let v2 = projected_buffer[indices[base_idx + 1u]];
+    let v3 = projected_buffer[indices[base_idx + 2u]];
+    return rasterise_triangle_in_tile(base_idx / 3u, v1, v2, v3, tile_x, tile_y, resolve);
+}
 
-            var threshold = 0.0;
-            if effect.effect_type == 3u {
-                threshold = -effect.param1;
-            }
+// Runs one resolve step for every triangle binned into the tile, plus the big
+// triangles covering it. Returns the number of samples this thread covered.
+fn rasterise_tile(tile_x: u32, tile_y: u32, thread: u32, resolve: u32) -> u32 {
+    let tile_idx = tile_x + tile_y * screen_dims.num_tiles_x;
+    let triangle_count = tile_buffer[tile_idx].count;
+    let triangle_offset = tile_buffer[tile_idx].offset;
+    var samples = 0u;
 
-            if bc.x < threshold || bc.y < threshold || bc.z < threshold {
-                continue;
-            }
+    // Use the third dimension of the local invocation to split work.
+    for (var i = thread; i < triangle_count; i += RASTER_THREADS) {
+        // Get the triangle's base index from the triangle list.
+        let base_idx = triangle_list_buffer[triangle_offset + i];
 
-            if effect.effect_type == 2u {
-                let amplitude = effect.param1;
-                let phase = effect.param2;
-                let wave = 0.5 + 0.5 * sin(effect.time + phase);
-                let meltdownThreshold = amplitude * wave;
-                let min_bc = min(bc.x, min(bc.y, bc.z));
-                if min_bc < meltdownThreshold {
-                    continue;
-                }
-            }
-            
-            // Interpolate depth.
-            let interpolated_z = bc.x * v1.screen_pos.z + bc.y * v2.screen_pos.z + bc.z * v3.screen_pos.z;
-
-            let local_index = (x - tile_start_x) + (y - tile_start_y) * TILE_SIZE;
-            let pixel_index = x + y * u32(screen_dims.width);
-            // Convert our computed depth to a packed u32.
-            let packed_depth = pack_float_to_u32(interpolated_z);
-            // Get the pointer for the current pixel.
-            let pixel_ptr = &local_depth[local_index];
-
-            // Attempt an atomic update in a loop.
-            var old = atomicLoad(pixel_ptr);
-
-            loop {
-                if packed_depth >= old {
-                    // Our computed depth is not closer.
-                    break;
-                }
+        let triangle_meta = tile_binning_data[base_idx / 3u];
+        if (triangle_meta.tile_range.x * triangle_meta.tile_range.y) == 0u {
+            continue;
+        }
 
-                // sporadic atomic change failures occur if there is no barrier
-                storageBarrier();
-
-                let result = atomicCompareExchangeWeak(pixel_ptr, old, packed_depth);
-
-                // Try to atomically update the depth.
-                if result.exchanged {
-                    // We won the race: update the fragment data.
-                    fragment_buffer[pixel_index] = Fragment(
-                        bc.x * v1.uv + bc.y * v2.uv + bc.z * v3.uv,
-                        bc.x * v1.normal + bc.y * v2.normal + bc.z * v3.normal,
-                        bc.x * v1.world_pos + bc.y * v2.world_pos + bc.z * v3.world_pos,
-                        1u
-                    );
-                    break;
-                }
-                
-                // Otherwise, update old and try again.
-                old = result.old_value;
-            }
+        samples += rasterise_triangle(base_idx, tile_x, tile_y, resolve);
+    }
+
+    // Big triangles were never binned, so every tile checks them against its own position.
+    let big_count = min(big_triangles.count, arrayLength(&big_triangles.triangles));
+    for (var i = thread; i < big_count; i += RASTER_THREADS) {
+        let base_idx = big_triangles.triangles[i];
+        let triangle_meta = tile_binning_data[base_idx / 3u];
+        let tile = vec2<u32>(tile_x, tile_y);
+        if any(tile < triangle_meta.start_tile)
+            || any(tile >= triangle_meta.start_tile + triangle_meta.tile_range) {
+            continue;
         }
+
+        samples += rasterise_triangle(base_idx, tile_x, tile_y, resolve);
     }
+    return samples;
 }
 
-const Z_DISPATCHES = TILE_SIZE * TILE_SIZE;
+// Interpolates the winning triangle's attributes at a pixel. The edge functions are
+// exact, so evaluating them directly gives the same values the resolve stepped to.
+fn write_fragment(triangle_index: u32, x: u32, y: u32) {
+    let base_idx = triangle_index * 3u;
+    let v1 = projected_buffer[indices[base_idx]];
+    let v2 = projected_buffer[indices[base_idx + 1u]];
+    let v3 = projected_buffer[indices[base_idx + 2u]];
+    let setup = setup_triangle(v1, v2, v3);
+    let bc = barycentric(setup, edge_values(setup, sample_position(x, y, 0u)));
+
+    fragment_buffer[x + y * u32(screen_dims.width)] = Fragment(
+        bc.x * v1.uv + bc.y * v2.uv + bc.z * v3.uv,
+        bc.x * v1.normal + bc.y * v2.normal + bc.z * v3.normal,
+        bc.x * v1.world_pos + bc.y * v2.world_pos + bc.z * v3.world_pos,
+        1u,
+        v1.material,
+        triangle_index
+    );
+}
 
-@compute @workgroup_size(1, 1, Z_DISPATCHES)
+// Each tile is resolved in three steps separated by barriers: the nearest depth per
+// sample, then the lowest triangle ID at that depth, then a single fragment write by
+// the winner (or, with MSAA, a write of every sample's triangle). Which triangle ends
+// up in a pixel therefore never depends on the order the threads happened to run in.
+// Transparent fragments are listed alongside the second step, as they only need the
+// nearest opaque depth.
+@compute @workgroup_size(1, 1, RASTER_THREADS)
 fn raster_main(
     @builtin(workgroup_id) wg: vec3<u32>,
     @builtin(local_invocation_id) lid: vec3<u32>
@@ -197,47 +283,53 @@ fn raster_main(
     // Determine the tile for this workgroup.
     let tile_x = wg.x;
     let tile_y = wg.y;
-    let num_tiles_x = screen_dims.num_tiles_x;
-    let num_tiles_y = screen_dims.num_tiles_y;
-
-    local_depth[lid.z] = 0xFFFFFFFFu;
 
     // Early exit if this tile is out of range.
-    if tile_x >= num_tiles_x || tile_y >= num_tiles_y {
+    if tile_x >= screen_dims.num_tiles_x || tile_y >= screen_dims.num_tiles_y {
         return;
     }
 
-    let tile_idx = tile_x + tile_y * num_tiles_x;
-    let triangle_count = tile_buffer[tile_idx].count;
-    let triangle_offset = tile_buffer[tile_idx].offset;
-    
-    // Use the third dimension of the local invocation to split work.
-    for (var i = lid.z; i < triangle_count; i += Z_DISPATCHES) {
-        // Get the triangle's base index from the triangle list.
-        let base_idx = triangle_list_buffer[triangle_offset + i];
+    // Large tiles have fewer threads than samples, so each thread clears a strided subset.
+    for (var i = lid.z; i < MAX_TILE_SAMPLES; i += RASTER_THREADS) {
+        atomicStore(&local_depth[i], 0u);
+        atomicStore(&local_triangle[i], 0u);
+    }
+    workgroupBarrier();
 
-        // Compute the triangle index from the base index.
-        let triangle_index = base_idx / 3u;
+    let samples = rasterise_tile(tile_x, tile_y, lid.z, RESOLVE_DEPTH);
+    if samples > 0u {
+        atomicAdd(&stats.rasterised_samples, samples);
+    }
+    workgroupBarrier();
 
-        let triangle_meta = tile_binning_data[triangle_index];
-        if (triangle_meta.tile_range.x * triangle_meta.tile_range.y) == 0u {
-            continue;
+    _ = rasterise_tile(tile_x, tile_y, lid.z, RESOLVE_TRIANGLE);
+    let transparent_samples = rasterise_tile(tile_x, tile_y, lid.z, RESOLVE_TRANSPARENT);
+    if transparent_samples > 0u {
+        atomicAdd(&stats.rasterised_samples, transparent_samples);
+    }
+    workgroupBarrier();
+
+    let tile_start = vec2<u32>(tile_x, tile_y) * TILE_SIZE;
+    if MSAA_SAMPLES > 1u {
+        // Tiles on the right and bottom edges have samples past the edge of the screen.
+        let tile_end = min(tile_start + TILE_SIZE, vec2<u32>(u32(screen_dims.width), u32(screen_dims.height)));
+        for (var i = lid.z; i < MAX_TILE_SAMPLES; i += RASTER_THREADS) {
+            let pixel = tile_start + vec2<u32>((i / MSAA_SAMPLES) % TILE_SIZE, i / MSAA_SAMPLES / TILE_SIZE);
+            if any(pixel >= tile_end) {
+                continue;
+            }
+            let sample_index = (pixel.x + pixel.y * u32(screen_dims.width)) * MSAA_SAMPLES + i % MSAA_SAMPLES;
+            visibility_buffer[sample_index * 2u] = atomicLoad(&local_triangle[i]);
+            visibility_buffer[sample_index * 2u + 1u] = atomicLoad(&local_depth[i]);
         }
-
-        // Retrieve the vertex indices.
-        let idx1 = indices[base_idx];
-        let idx2 = indices[base_idx + 1u];
-        let idx3 = indices[base_idx + 2u];
-        let v1 = projected_buffer[idx1];
-        let v2 = projected_buffer[idx2];
-        let v3 = projected_buffer[idx3];
-
-        // Now rasterise the triangle into this tile.
-        rasterise_triangle_in_tile(v1, v2, v3, tile_x, tile_y);
+        return;
     }
 
-    storageBarrier();
-    tile_buffer[tile_idx].count = 0u;
-    tile_buffer[tile_idx].offset = 0u;
-    tile_buffer[tile_idx].write_index = 0u;
-}
\ No newline at end of file
+    for (var i = lid.z; i < MAX_TILES; i += RASTER_THREADS) {
+        let key = atomicLoad(&local_triangle[i]);
+        if key == 0u {
+            continue;
+        }
+        write_fragment(~key, tile_start.x + i % TILE_SIZE, tile_start.y + i / TILE_SIZE);
+    }
+}
@@ -0,0 +1,130 @@
+// Screen space ambient occlusion from the depth and normal the fragment pass keeps for
+// each pixel: points of a hemisphere around the surface are projected to the screen, and
+// count as occluded where the surface there is nearer the camera than they are.
+
+struct PostView {
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    view_position: vec4<f32>,
+    // World space radius of the hemisphere. The struct is padded to 16 bytes after it.
+    ssao_radius: f32,
+};
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var output_tex: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(0) var depth_normal_tex: texture_2d<f32>;
+@group(1) @binding(1) var<uniform> view: PostView;
+// Written by ssao_main, then blurred and applied by ssao_apply_main.
+@group(1) @binding(2) var ao_out: texture_storage_2d<r32float, write>;
+@group(1) @binding(3) var ao_tex: texture_2d<f32>;
+
+const PI: f32 = 3.14159265358979;
+const GOLDEN_ANGLE: f32 = 2.39996322972865;
+const SSAO_SAMPLES: u32 = 16u;
+// Occluders must be this far in front of a sample, as a fraction of the radius, so flat
+// surfaces don't occlude themselves.
+const SSAO_BIAS: f32 = 0.05;
+
+// A 4x4 Bayer matrix, so neighbouring pixels rotate the kernel as differently as possible.
+const KERNEL_ROTATIONS = array<u32, 16>(0u, 8u, 2u, 10u, 12u, 4u, 14u, 6u, 3u, 11u, 1u, 9u, 15u, 7u, 13u, 5u);
+
+fn world_position(pixel: vec2<i32>, depth: f32, dims: vec2<f32>) -> vec3<f32> {
+    let uv = (vec2<f32>(pixel) + 0.5) / dims;
+    let world = view.inv_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// A point of the unit hemisphere around +z, cosine distributed, and nearer the centre for
+// the first samples.
+fn kernel_sample(i: u32, rotation: f32) -> vec3<f32> {
+    let t = (f32(i) + 0.5) / f32(SSAO_SAMPLES);
+    let phi = f32(i) * GOLDEN_ANGLE + rotation;
+    let sin_theta = sqrt(t);
+    let cos_theta = sqrt(1.0 - t);
+    let scale = mix(0.1, 1.0, t * t);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta) * scale;
+}
+
+@compute @workgroup_size(8, 8)
+fn ssao_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(depth_normal_tex);
+    if any(global_id.xy >= dims) {
+        return;
+    }
+    let pixel = vec2<i32>(global_id.xy);
+    let depth_normal = textureLoad(depth_normal_tex, pixel, 0);
+    if depth_normal.w >= 1.0 {
+        textureStore(ao_out, pixel, vec4<f32>(1.0));
+        return;
+    }
+
+    let fdims = vec2<f32>(dims);
+    let position = world_position(pixel, depth_normal.w, fdims);
+    let normal = depth_normal.xyz;
+    let up = select(vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), abs(normal.z) > 0.9);
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+
+    let eye = view.view_position.xyz;
+    let distance_to_eye = distance(eye, position);
+    let rotation = f32(KERNEL_ROTATIONS[(global_id.x % 4u) + (global_id.y % 4u) * 4u]) / 16.0 * 2.0 * PI;
+    var occlusion = 0.0;
+    for (var i = 0u; i < SSAO_SAMPLES; i++) {
+        let k = kernel_sample(i, rotation);
+        let sample_position = position + (tangent * k.x + bitangent * k.y + normal * k.z) * view.ssao_radius;
+        let clip = view.view_proj * vec4<f32>(sample_position, 1.0);
+        if clip.w <= 0.0 {
+            continue;
+        }
+        let uv = vec2<f32>(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            continue;
+        }
+        let occluder_pixel = vec2<i32>(uv * fdims);
+        let occluder_depth = textureLoad(depth_normal_tex, occluder_pixel, 0).w;
+        if occluder_depth >= 1.0 {
+            continue;
+        }
+
+        let occluder_distance = distance(eye, world_position(occluder_pixel, occluder_depth, fdims));
+        // Surfaces far in front of the point, like a silhouette against the background,
+        // fade out rather than darkening it.
+        let range = smoothstep(0.0, 1.0, view.ssao_radius / abs(distance_to_eye - occluder_distance));
+        if occluder_distance < distance(eye, sample_position) - SSAO_BIAS * view.ssao_radius {
+            occlusion += range;
+        }
+    }
+
+    textureStore(ao_out, pixel, vec4<f32>(1.0 - occlusion / f32(SSAO_SAMPLES)));
+}
+
+// Averages the occlusion over the 4x4 block of kernel rotations, leaving out empty pixels,
+// and darkens the image by it.
+@compute @workgroup_size(8, 8)
+fn ssao_apply_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(input_tex);
+    if any(global_id.xy >= dims) {
+        return;
+    }
+    let pixel = vec2<i32>(global_id.xy);
+    var color = textureLoad(input_tex, pixel, 0);
+
+    if textureLoad(depth_normal_tex, pixel, 0).w < 1.0 {
+        var ao = 0.0;
+        var count = 0.0;
+        for (var y = -2; y < 2; y++) {
+            for (var x = -2; x < 2; x++) {
+                let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), vec2<i32>(dims) - 1);
+                if textureLoad(depth_normal_tex, neighbour, 0).w < 1.0 {
+                    ao += textureLoad(ao_tex, neighbour, 0).x;
+                    count += 1.0;
+                }
+            }
+        }
+        color = vec4<f32>(color.rgb * ao / count, color.a);
+    }
+
+    textureStore(output_tex, pixel, color);
+}
@@ -0,0 +1,73 @@
+// Temporal anti-aliasing: the jittered image of each frame is blended into a history of
+// the previous ones, reprojected by the motion of each pixel and clamped to the colours
+// around it, so what the history shows that the pixel can't is dropped.
+
+struct TaaFrame {
+    // Non-zero when there is no history to blend, like on the first frame.
+    reset: u32,
+};
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var output_tex: texture_storage_2d<rgba16float, write>;
+
+@group(1) @binding(0) var motion_tex: texture_2d<f32>;
+@group(1) @binding(1) var history_tex: texture_2d<f32>;
+@group(1) @binding(2) var<uniform> frame: TaaFrame;
+
+// Weight of the current frame in the history.
+const TAA_BLEND: f32 = 0.1;
+
+fn rgb_to_ycocg(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        0.25 * c.r + 0.5 * c.g + 0.25 * c.b,
+        0.5 * c.r - 0.5 * c.b,
+        -0.25 * c.r + 0.5 * c.g - 0.25 * c.b
+    );
+}
+
+fn ycocg_to_rgb(c: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(c.x + c.y - c.z, c.x + c.z, c.x - c.y - c.z);
+}
+
+// Colours are blended weighted by 1 / (1 + luma), so single bright HDR samples don't
+// flicker through the history.
+fn luma_weight(ycocg: vec3<f32>) -> f32 {
+    return 1.0 / (1.0 + max(ycocg.x, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn taa_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(input_tex);
+    if any(global_id.xy >= dims) {
+        return;
+    }
+    let pixel = vec2<i32>(global_id.xy);
+    let current = textureLoad(input_tex, pixel, 0);
+
+    // The bounds of the 3x3 neighbourhood, in YCoCg, which separates brightness from hue.
+    var low = vec3<f32>(1e30);
+    var high = vec3<f32>(-1e30);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), vec2<i32>(dims) - 1);
+            let color = rgb_to_ycocg(textureLoad(input_tex, neighbour, 0).rgb);
+            low = min(low, color);
+            high = max(high, color);
+        }
+    }
+
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(dims);
+    let history_uv = uv - textureLoad(motion_tex, pixel, 0).xy;
+    if frame.reset != 0u || any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+        textureStore(output_tex, pixel, current);
+        return;
+    }
+
+    let history = clamp(rgb_to_ycocg(textureSampleLevel(history_tex, linear_sampler, history_uv, 0.0).rgb), low, high);
+    let color = rgb_to_ycocg(current.rgb);
+    let history_weight = (1.0 - TAA_BLEND) * luma_weight(history);
+    let current_weight = TAA_BLEND * luma_weight(color);
+    let blended = (history * history_weight + color * current_weight) / (history_weight + current_weight);
+    textureStore(output_tex, pixel, vec4<f32>(ycocg_to_rgb(blended), current.a));
+}
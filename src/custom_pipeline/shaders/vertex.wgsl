@@ -0,0 +1,138 @@
+// WORKGROUP_SIZE is injected by PipelineConfig::shader_header.
+//
+// Transforms every vertex exactly once into projected_buffer, which the binning
+// and raster stages then read by index.
+
+struct VertexIn {
+    world_pos: vec3<f32>,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+    material: u32,
+};
+
+struct Vertex {
+    world_pos: vec3<f32>,
+    screen_pos: vec4<f32>,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+    material: u32,
+};
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    // Only used by the fragment and light culling passes.
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+};
+
+struct UniformScreen {
+    width: f32,
+    height: f32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
+};
+
+struct EffectUniform {
+    effect_type: u32,
+    param1: f32,
+    param2: f32,
+    param3: f32,
+    param4: f32,
+    time: f32,
+    _padding: vec2<f32>,
+};
+
+// Counters read back by CustomRenderer::read_stats.
+struct PipelineStats {
+    binned_tile_pairs: atomic<u32>,
+    rejected_tile_pairs: atomic<u32>,
+    vertex_invocations: atomic<u32>,
+    rasterised_samples: atomic<u32>,
+    transparent_fragments: atomic<u32>,
+};
+
+@group(0) @binding(0) var<storage, read> vertex_buffer: array<VertexIn>;
+@group(0) @binding(1) var<storage, read_write> projected_buffer: array<Vertex>;
+@group(0) @binding(2) var<storage, read_write> stats: PipelineStats;
+
+// Invocations of the workgroup that shaded a vertex.
+var<workgroup> shaded_vertices: atomic<u32>;
+
+@group(1) @binding(0) var<uniform> camera: Camera;
+@group(1) @binding(1) var<uniform> screen_dims: UniformScreen;
+@group(1) @binding(2) var<uniform> effect: EffectUniform;
+
+fn apply_wave_effect(pos: vec3<f32>, effect: EffectUniform) -> vec3<f32> {
+    var modified_pos = pos;
+    let amplitude = effect.param1;
+    let frequency = effect.param2;
+    let phase = effect.param3;
+    let direction = effect.param4;
+
+    if direction < 0.5 { // Vertical
+        modified_pos.y += amplitude * sin(frequency * pos.x + phase);
+    } else if direction < 1.5 { // Horizontal
+        modified_pos.x += amplitude * sin(frequency * pos.y + phase);
+    } else { // Radial
+        let dist = length(pos.xy);
+        modified_pos.z += amplitude * sin(frequency * dist + phase);
+    }
+
+    return modified_pos;
+}
+
+fn compute_screen_pos(clip_pos: vec4<f32>) -> vec4<f32> {
+    let ndc_pos = clip_pos.xyz / clip_pos.w;
+
+    return vec4<f32>(
+        ((ndc_pos.x + 1.0) * 0.5) * screen_dims.width,
+        ((1.0 - ndc_pos.y) * 0.5) * screen_dims.height,
+        clip_pos.z / clip_pos.w,
+        clip_pos.w
+    );
+}
+
+fn geometry_pipeline(idx: u32) -> Vertex {
+    // Load original vertex data.
+    let v_in = vertex_buffer[idx];
+
+    // Apply any effects if needed.
+    var world_pos = v_in.world_pos;
+    if effect.effect_type == 1u {
+        world_pos = apply_wave_effect(world_pos, effect);
+    }
+        
+    // Transform to clip space and then compute screen positions.
+    let clip = camera.view_proj * vec4<f32>(world_pos, 1.0);
+    let screen_pos = compute_screen_pos(clip);
+
+    return Vertex(world_pos, screen_pos, v_in.normal, v_in.uv, v_in.material);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn vertex_main(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>
+) {
+    // Large meshes need more workgroups than fit in one dispatch dimension.
+    let first_vertex = (wg.x + wg.y * num_workgroups.x) * WORKGROUP_SIZE;
+    let num_vertices = arrayLength(&vertex_buffer);
+    if first_vertex >= num_vertices {
+        return;
+    }
+
+    let idx = first_vertex + lid.x;
+    if idx < num_vertices {
+        projected_buffer[idx] = geometry_pipeline(idx);
+        atomicAdd(&shaded_vertices, 1u);
+    }
+
+    // The first thread adds up the vertices the workgroup shaded with a single global atomic.
+    workgroupBarrier();
+    if lid.x == 0u {
+        atomicAdd(&stats.vertex_invocations, atomicLoad(&shaded_vertices));
+    }
+}
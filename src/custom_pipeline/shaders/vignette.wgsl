@@ -0,0 +1,24 @@
+// Darkens the image smoothly from partway out to its corners.
+
+@group(0) @binding(0) var input_tex: texture_2d<f32>;
+@group(0) @binding(1) var linear_sampler: sampler;
+@group(0) @binding(2) var output_tex: texture_storage_2d<rgba16float, write>;
+
+// Light lost in the corners, and where the falloff starts and ends, as a fraction of the
+// distance from the centre to a corner.
+const VIGNETTE_STRENGTH: f32 = 0.5;
+const VIGNETTE_START: f32 = 0.4;
+const VIGNETTE_END: f32 = 1.0;
+
+@compute @workgroup_size(8, 8)
+fn vignette_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dims = textureDimensions(input_tex);
+    if any(global_id.xy >= dims) {
+        return;
+    }
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(dims);
+    let radius = length(uv * 2.0 - 1.0) / sqrt(2.0);
+    let factor = 1.0 - VIGNETTE_STRENGTH * smoothstep(VIGNETTE_START, VIGNETTE_END, radius);
+    let color = textureLoad(input_tex, global_id.xy, 0);
+    textureStore(output_tex, global_id.xy, vec4<f32>(color.rgb * factor, color.a));
+}
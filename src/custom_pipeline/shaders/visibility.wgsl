@@ -0,0 +1,254 @@
+// TILE_SIZE and RASTER_THREADS are injected by PipelineConfig::shader_header.
+//
+// Visibility buffer rasteriser. Rather than writing interpolated attributes for
+// every sample that wins a depth test, it only records which triangle is nearest
+// at each sample (MSAA_SAMPLES per pixel); the fragment pass reconstructs the
+// attributes from that triangle.
+//
+// Depth and triangle ID are stored with depth_key and triangle_key from
+// raster_common.wgsl, so a cleared (all zero) buffer means "empty" and an atomic max
+// keeps the nearest sample, breaking depth ties by the lowest triangle ID.
+//
+// Transparent triangles skip the visibility buffer. Once a tile's opaque triangles are
+// resolved, they append each pixel where they cover a sample in front of the opaque
+// surface to that pixel's transparent fragment list, as in the standard rasteriser. Depth
+// only passes, which render shadow maps, leave transparent triangles out altogether.
+// append_transparent and count_overdraw come from raster_output.wgsl, shared with the
+// standard rasteriser.
+//
+// write_visibility and the entry points come from the variant this file is combined
+// with: visibility_atomic64.wgsl or visibility_two_pass.wgsl.
+
+struct UniformRaster {
+    width: f32,
+    height: f32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
+};
+
+struct EffectUniform {
+    effect_type: u32,
+    param1: f32,
+    param2: f32,
+    param3: f32,
+    param4: f32,
+    time: f32,
+    _padding: vec2<f32>,
+};
+
+struct Vertex {
+    world_pos: vec3<f32>,
+    screen_pos: vec4<f32>,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+    material: u32,
+};
+
+struct TileTriangles {
+    count: u32,
+    offset: u32,
+    write_index: u32,
+}
+
+struct PipelineStats {
+    binned_tile_pairs: atomic<u32>,
+    rejected_tile_pairs: atomic<u32>,
+    vertex_invocations: atomic<u32>,
+    rasterised_samples: atomic<u32>,
+    transparent_fragments: atomic<u32>,
+};
+
+struct BigTriangles {
+    count: u32,
+    triangles: array<u32>,
+};
+
+struct TriangleBinningData {
+    min_max: vec4<f32>,
+    start_tile: vec2<u32>,
+    tile_range: vec2<u32>,
+};
+
+struct TransparentNodes {
+    count: atomic<u32>,
+    nodes: array<TransparentNode>,
+};
+
+@group(0) @binding(0)
+var<storage, read> projected_buffer: array<Vertex>;
+
+@group(0) @binding(2)
+var<storage, read> tile_buffer: array<TileTriangles>;
+
+@group(0) @binding(3)
+var<storage, read> triangle_list_buffer: array<u32>;
+
+@group(0) @binding(4)
+var<storage, read> indices: array<u32>;
+
+@group(0) @binding(5)
+var<storage, read> tile_binning_data: array<TriangleBinningData>;
+
+@group(0) @binding(6)
+var<storage, read> big_triangles: BigTriangles;
+
+@group(0) @binding(7)
+var<storage, read_write> stats: PipelineStats;
+
+@group(0) @binding(9)
+var<storage, read> materials: array<Material>;
+
+@group(0) @binding(10)
+var<storage, read_write> transparent_heads: array<atomic<u32>>;
+
+@group(0) @binding(11)
+var<storage, read_write> transparent_nodes: TransparentNodes;
+
+// With DEBUG_OVERDRAW, the depth tests of every pixel's samples. A placeholder otherwise.
+@group(0) @binding(12)
+var<storage, read_write> overdraw: array<atomic<u32>>;
+
+@group(1) @binding(0)
+var<uniform> screen_dims: UniformRaster;
+
+@group(2) @binding(0)
+var<uniform> effect: EffectUniform;
+
+const MAX_TILE_SAMPLES: u32 = TILE_SIZE * TILE_SIZE * MSAA_SAMPLES;
+
+// The nearest opaque depth of each sample of the tile, which hides the transparent
+// fragments at or behind it.
+var<workgroup> local_depth: array<atomic<u32>, MAX_TILE_SAMPLES>;
+
+// Returns how many samples of the tile the triangle covers. Transparent triangles are
+// rasterised once the opaque ones are resolved, and only cover the samples in front of the
+// opaque surface. Depth tests are only counted for the overdraw debug view when
+// `count_tests` is set, so a resolve split over several passes counts them once.
+fn rasterise_triangle_in_tile(
+    triangle_index: u32,
+    v1: Vertex,
+    v2: Vertex,
+    v3: Vertex,
+    tile_x: u32,
+    tile_y: u32,
+    transparent: bool,
+    count_tests: bool
+) -> u32 {
+    // Compute the pixel bounds for the tile.
+    let tile_start_x = tile_x * TILE_SIZE;
+    let tile_end_x = min(tile_start_x + TILE_SIZE, u32(screen_dims.width));
+    let tile_start_y = tile_y * TILE_SIZE;
+    let tile_end_y = min(tile_start_y + TILE_SIZE, u32(screen_dims.height));
+
+    let setup = setup_triangle(v1, v2, v3);
+    if setup.area == 0.0 {
+        return 0u;
+    }
+
+    // Edge functions are evaluated once at the tile's first pixel centre, then stepped.
+    var row = edge_values(setup, pixel_centre(tile_start_x, tile_start_y));
+    var samples = 0u;
+    for (var y = tile_start_y; y < tile_end_y; y++) {
+        var stepped = row;
+        for (var x = tile_start_x; x < tile_end_x; x++) {
+            let pixel_index = x + y * u32(screen_dims.width);
+            let local_pixel = (x - tile_start_x) + (y - tile_start_y) * TILE_SIZE;
+            var coverage = 0u;
+            var nearest = 0u;
+            for (var sample = 0u; sample < MSAA_SAMPLES; sample++) {
+                let edges = sample_edge_values(setup, stepped, x, y, sample);
+                if !sample_covered(setup, edges) {
+                    continue;
+                }
+
+                let interpolated_z = interpolate_depth(v1, v2, v3, barycentric(setup, edges));
+                let local_index = local_pixel * MSAA_SAMPLES + sample;
+                let depth = depth_key(interpolated_z);
+                if count_tests {
+                    count_overdraw(pixel_index);
+                }
+                if !transparent {
+                    write_visibility(pixel_index * MSAA_SAMPLES + sample, depth, triangle_key(triangle_index));
+                    atomicMax(&local_depth[local_index], depth);
+                    samples += 1u;
+                } else if depth > atomicLoad(&local_depth[local_index]) {
+                    coverage |= 1u << sample;
+                    nearest = max(nearest, depth);
+                    samples += 1u;
+                }
+            }
+            if coverage != 0u {
+                append_transparent(pixel_index, triangle_index, nearest, coverage);
+            }
+            stepped = wide_add(stepped, setup.step_x);
+        }
+        row = wide_add(row, setup.step_y);
+    }
+    return samples;
+}
+
+fn rasterise_triangle(
+    base_idx: u32,
+    tile_x: u32,
+    tile_y: u32,
+    transparent: bool,
+    count_tests: bool
+) -> u32 {
+    let v1 = projected_buffer[indices[base_idx]];
+    if is_transparent(v1) != transparent {
+        return 0u;
+    }
+    let v2 = projected_buffer[indices[base_idx + 1u]];
+    let v3 = projected_buffer[indices[base_idx + 2u]];
+    return rasterise_triangle_in_tile(base_idx / 3u, v1, v2, v3, tile_x, tile_y, transparent, count_tests);
+}
+
+// Rasterises either the opaque or the transparent triangles binned into a tile, plus the
+// big triangles covering it. Returns the number of samples this thread covered.
+fn rasterise_tile(tile_x: u32, tile_y: u32, thread: u32, transparent: bool, count_tests: bool) -> u32 {
+    let num_tiles_x = screen_dims.num_tiles_x;
+    if tile_x >= num_tiles_x || tile_y >= screen_dims.num_tiles_y {
+        return 0u;
+    }
+
+    let tile_idx = tile_x + tile_y * num_tiles_x;
+    let triangle_count = tile_buffer[tile_idx].count;
+    let triangle_offset = tile_buffer[tile_idx].offset;
+
+    var samples = 0u;
+    for (var i = thread; i < triangle_count; i += RASTER_THREADS) {
+        samples += rasterise_triangle(triangle_list_buffer[triangle_offset + i], tile_x, tile_y, transparent, count_tests);
+    }
+
+    let big_count = min(big_triangles.count, arrayLength(&big_triangles.triangles));
+    for (var i = thread; i < big_count; i += RASTER_THREADS) {
+        let base_idx = big_triangles.triangles[i];
+        let triangle_meta = tile_binning_data[base_idx / 3u];
+        let tile = vec2<u32>(tile_x, tile_y);
+        if any(tile < triangle_meta.start_tile)
+            || any(tile >= triangle_meta.start_tile + triangle_meta.tile_range) {
+            continue;
+        }
+        samples += rasterise_triangle(base_idx, tile_x, tile_y, transparent, count_tests);
+    }
+    return samples;
+}
+
+// Resolves the tile's opaque triangles, then lists the transparent fragments in front of
+// them. Transparent triangles are skipped unless `list_transparent` is set, so a resolve
+// split over several passes lists them, and counts overdraw, only once. Returns the number
+// of samples this thread covered.
+fn resolve_tile(tile_x: u32, tile_y: u32, thread: u32, list_transparent: bool) -> u32 {
+    for (var i = thread; i < MAX_TILE_SAMPLES; i += RASTER_THREADS) {
+        atomicStore(&local_depth[i], 0u);
+    }
+    workgroupBarrier();
+
+    var samples = rasterise_tile(tile_x, tile_y, thread, false, list_transparent);
+    workgroupBarrier();
+
+    if list_transparent && !DEPTH_ONLY {
+        samples += rasterise_tile(tile_x, tile_y, thread, true, true);
+    }
+    return samples;
+}
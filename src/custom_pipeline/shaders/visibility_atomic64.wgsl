@@ -0,0 +1,20 @@
+// Single pass visibility resolve using 64-bit atomics. The depth key sits in the
+// high word, so it takes priority over the triangle key in the low word.
+
+@group(0) @binding(1)
+var<storage, read_write> visibility_buffer: array<atomic<u64>>;
+
+fn write_visibility(sample_index: u32, depth: u32, triangle: u32) {
+    atomicMax(&visibility_buffer[sample_index], (u64(depth) << 32u) | u64(triangle));
+}
+
+@compute @workgroup_size(1, 1, RASTER_THREADS)
+fn visibility_main(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>
+) {
+    let samples = resolve_tile(wg.x, wg.y, lid.z, true);
+    if samples > 0u {
+        atomicAdd(&stats.rasterised_samples, samples);
+    }
+}
@@ -0,0 +1,40 @@
+// Two pass visibility resolve for devices without 64-bit atomics. The first pass
+// finds the nearest depth in each pixel; the second picks the lowest triangle ID
+// among the samples at exactly that depth. Each sample holds its triangle key then
+// its depth key, the same layout as the little-endian 64-bit value.
+
+@group(0) @binding(1)
+var<storage, read_write> visibility_buffer: array<atomic<u32>>;
+
+var<private> resolve_triangles: bool;
+
+fn write_visibility(sample_index: u32, depth: u32, triangle: u32) {
+    let depth_ptr = &visibility_buffer[sample_index * 2u + 1u];
+    if !resolve_triangles {
+        atomicMax(depth_ptr, depth);
+    } else if atomicLoad(depth_ptr) == depth {
+        atomicMax(&visibility_buffer[sample_index * 2u], triangle);
+    }
+}
+
+@compute @workgroup_size(1, 1, RASTER_THREADS)
+fn visibility_depth_main(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>
+) {
+    resolve_triangles = false;
+    let samples = resolve_tile(wg.x, wg.y, lid.z, true);
+    if samples > 0u {
+        atomicAdd(&stats.rasterised_samples, samples);
+    }
+}
+
+@compute @workgroup_size(1, 1, RASTER_THREADS)
+fn visibility_triangle_main(
+    @builtin(workgroup_id) wg: vec3<u32>,
+    @builtin(local_invocation_id) lid: vec3<u32>
+) {
+    // Samples were already counted, and transparent fragments listed, by the depth pass.
+    resolve_triangles = true;
+    _ = resolve_tile(wg.x, wg.y, lid.z, false);
+}
@@ -0,0 +1,218 @@
+use crate::{camera::CameraUniform, scene::Scene};
+
+use super::{
+    binning_pass::BinningPass, config::PipelineConfig, GpuBuffers, RasterPass, VertexPass,
+};
+
+/// Renders the shadow maps of every shadowed light with the regular vertex, binning and
+/// raster passes, run once per shadow map view (a cube face, or a directional light's
+/// single view) at shadow map resolution in depth only mode. There is no fragment stage:
+/// each view's visibility buffer is copied into the shadow map buffer of the main passes,
+/// which the fragment pass samples.
+pub struct ShadowPass {
+    /// Buffers of the depth passes, sized for a single shadow map and reused by every view.
+    pub buffers: GpuBuffers,
+    pub vertex_pass: VertexPass,
+    pub binning_pass: BinningPass,
+    pub raster_pass: RasterPass,
+    config: PipelineConfig,
+    num_views: usize,
+}
+
+impl ShadowPass {
+    pub fn new(device: &wgpu::Device, scene: &Scene, config: &PipelineConfig) -> Self {
+        let shadow_config = config.shadow_map_config();
+        let size = config.shadow_map_size;
+        let buffers = GpuBuffers::new(device, size, size, scene, &shadow_config);
+
+        Self {
+            vertex_pass: VertexPass::new(device, &buffers, &shadow_config),
+            binning_pass: BinningPass::new(device, &buffers, &shadow_config),
+            raster_pass: RasterPass::new(device, &buffers, &shadow_config),
+            buffers,
+            config: shadow_config,
+            num_views: config.shadow_views(scene),
+        }
+    }
+
+    /// Renders every view into the shadow map buffer of the main passes' `buffers`,
+    /// from the views in their shadow view buffer.
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, scene: &Scene, buffers: &GpuBuffers) {
+        let size = self.config.shadow_map_size;
+        let view_size = std::mem::size_of::<CameraUniform>() as u64;
+        let map_size = self.buffers.visibility_buffer.size();
+
+        // Shadows follow the geometry effects of the image they are cast in.
+        encoder.copy_buffer_to_buffer(
+            &buffers.effect_buffer,
+            0,
+            &self.buffers.effect_buffer,
+            0,
+            buffers.effect_buffer.size(),
+        );
+        encoder.clear_buffer(&self.buffers.stats_buffer, 0, None);
+        for view in 0..self.num_views as u64 {
+            // Copies are ordered with the passes around them, so the view's camera can be
+            // swapped in on the GPU without a separate submission per view.
+            encoder.copy_buffer_to_buffer(
+                &buffers.shadow_view_buffer,
+                view * view_size,
+                &self.buffers.camera_buffer,
+                0,
+                view_size,
+            );
+            encoder.clear_buffer(&self.buffers.tile_buffer, 0, None);
+            encoder.clear_buffer(&self.buffers.coarse_buffer, 0, None);
+            encoder.clear_buffer(&self.buffers.big_triangle_buffer, 0, None);
+            encoder.clear_buffer(&self.buffers.visibility_buffer, 0, None);
+
+            self.vertex_pass.execute(encoder, self.buffers.vertex_count);
+            self.binning_pass
+                .execute(encoder, scene.gx_tris, scene.gy_tris, size, size);
+            self.raster_pass.execute(encoder, size, size);
+
+            encoder.copy_buffer_to_buffer(
+                &self.buffers.visibility_buffer,
+                0,
+                &buffers.shadow_map_buffer,
+                view * map_size,
+                map_size,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::test_util::{quads_model, read_buffer, test_device};
+    use crate::custom_pipeline::util::MaterialInfo;
+    use crate::scene::LightConfig;
+    use crate::vertex::CustomVertex;
+
+    const SIZE: u32 = 32;
+
+    /// A wide floor, with an opaque square 1 above it and a wide transparent square 0.5
+    /// above that, all facing up.
+    fn shadow_scene() -> Scene {
+        let quad = |height, half_size: f32, material| {
+            [(-1.0, 1.0), (1.0, 1.0), (1.0, -1.0), (-1.0, -1.0)].map(|(x, z)| CustomVertex {
+                position: [x * half_size, height, z * half_size],
+                normal: [0.0, 1.0, 0.0],
+                material,
+                ..Default::default()
+            })
+        };
+        let model = quads_model(
+            [quad(0.0, 4.0, 0), quad(1.0, 0.5, 0), quad(1.5, 4.0, 1)],
+            vec![
+                MaterialInfo::default(),
+                MaterialInfo {
+                    dissolve: 0.5,
+                    ..Default::default()
+                },
+            ],
+        );
+
+        let mut scene = Scene::new();
+        scene.add_model(model);
+        scene
+    }
+
+    /// Renders the shadow maps of the scene lit by `light`, returning them and their views.
+    fn render_shadow_maps(light: LightConfig) -> Option<(Vec<u32>, Vec<CameraUniform>)> {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping shadow map test");
+            return None;
+        };
+        let mut scene = shadow_scene();
+        scene.add_light(light);
+        scene.cast_shadows();
+        let config = PipelineConfig {
+            shadows: true,
+            shadow_map_size: SIZE,
+            ..Default::default()
+        };
+        let buffers = GpuBuffers::new(&device, SIZE, SIZE, &scene, &config);
+        let shadow_pass = ShadowPass::new(&device, &scene, &config);
+        let views = scene.shadow_views();
+        queue.write_buffer(&buffers.shadow_view_buffer, 0, bytemuck::cast_slice(&views));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        shadow_pass.execute(&mut encoder, &scene, &buffers);
+        queue.submit(Some(encoder.finish()));
+        let shadow_maps = read_buffer(&device, &queue, &buffers.shadow_map_buffer);
+        Some((shadow_maps, views))
+    }
+
+    /// Depth of a texel of a view, or None where the map is empty. Texels hold a triangle
+    /// key then a depth key, a view after another.
+    fn depth(shadow_maps: &[u32], view: usize, x: u32, y: u32) -> Option<f32> {
+        let texels = (SIZE * SIZE) as usize;
+        let key = shadow_maps[(view * texels + (x + y * SIZE) as usize) * 2 + 1];
+        (key != 0).then(|| f32::from_bits(!key))
+    }
+
+    /// Checks that texels of `view` hold the depth of the surface at the given height,
+    /// within `tolerance`.
+    fn assert_depths(
+        shadow_maps: &[u32],
+        views: &[CameraUniform],
+        view: usize,
+        texels: &[(u32, u32, f32)],
+        tolerance: f32,
+    ) {
+        let view_proj = glam::Mat4::from_cols_array_2d(&views[view].view_proj);
+        for &(x, y, height) in texels {
+            let rendered = depth(shadow_maps, view, x, y).expect("the light sees the floor here");
+            let expected = view_proj
+                .project_point3(glam::Vec3::new(0.0, height, 0.0))
+                .z;
+            assert!(
+                (rendered - expected).abs() < tolerance,
+                "texel ({}, {}) of view {} has depth {}, expected {} of the surface at height {}",
+                x,
+                y,
+                view,
+                rendered,
+                expected,
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn depth_passes_render_each_cube_face() {
+        let Some((shadow_maps, views)) =
+            render_shadow_maps(LightConfig::point([0.0, 2.0, 0.0], [1.0; 3], 1.0))
+        else {
+            return;
+        };
+        assert_eq!(views.len(), 6);
+
+        // Looking down, the square hides the middle of the floor. The transparent square
+        // in front of both casts no shadow, so it isn't in the map.
+        let texels = [(SIZE / 2, SIZE / 2, 1.0), (1, 1, 0.0), (SIZE - 2, 3, 0.0)];
+        assert_depths(&shadow_maps, &views, 3, &texels, 1e-6);
+        // Nothing is above the light.
+        assert!((0..SIZE * SIZE).all(|i| depth(&shadow_maps, 2, i % SIZE, i / SIZE).is_none()));
+    }
+
+    #[test]
+    fn depth_pass_renders_directional_light_over_scene_bounds() {
+        let Some((shadow_maps, views)) =
+            render_shadow_maps(LightConfig::directional([0.0, -1.0, 0.0], [1.0; 3], 1.0))
+        else {
+            return;
+        };
+        assert_eq!(views.len(), 1);
+
+        // The map covers the circle around the scene's bounds, so the floor ends short of
+        // its edges.
+        let texels = [(SIZE / 2, SIZE / 2, 1.0), (6, 6, 0.0), (SIZE - 7, 8, 0.0)];
+        // Depth is interpolated with barycentrics from the fixed-point edge functions, which
+        // only sum to one within float precision.
+        assert_depths(&shadow_maps, &views, 0, &texels, 1e-4);
+        assert!(depth(&shadow_maps, 0, 0, 0).is_none());
+    }
+}
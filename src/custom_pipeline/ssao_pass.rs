@@ -0,0 +1,136 @@
+use super::{
+    config::PipelineConfig,
+    post_chain::{create_image_bind_group, dispatch_image},
+    util::{
+        create_storage_texture_bind_group_layout_entry, create_texture_bind_group_layout_entry,
+    },
+    GpuBuffers,
+};
+
+/// Darkens the output by screen space ambient occlusion, from the depth and normal the
+/// fragment pass keeps for each pixel. The occlusion of each pixel is computed into a
+/// texture of its own, then blurred over the noise of its kernel as it is applied.
+pub struct SsaoPass {
+    ao_pipeline: wgpu::ComputePipeline,
+    apply_pipeline: wgpu::ComputePipeline,
+    image_bind_group: wgpu::BindGroup,
+    ao_bind_group: wgpu::BindGroup,
+    apply_bind_group: wgpu::BindGroup,
+}
+
+impl SsaoPass {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        buffers: &GpuBuffers,
+        view_buffer: &wgpu::Buffer,
+        output: &wgpu::TextureView,
+    ) -> Self {
+        let ao_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Ambient Occlusion Texture"),
+            size: buffers.output_texture.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let ao_view = ao_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let (image_layout, image_bind_group) =
+            create_image_bind_group(device, "SSAO Pass", &buffers.output_view, output);
+
+        // Both entry points read the depth and normal and the camera; one writes the
+        // occlusion and the other reads it.
+        let depth_normal_entry =
+            create_texture_bind_group_layout_entry(0, false, wgpu::TextureViewDimension::D2);
+        let view_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let ao_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Pass: Group1 Layout (Occlusion)"),
+            entries: &[
+                depth_normal_entry,
+                view_entry,
+                create_storage_texture_bind_group_layout_entry(2, wgpu::TextureFormat::R32Float),
+            ],
+        });
+        let apply_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSAO Pass: Group1 Layout (Apply)"),
+            entries: &[
+                depth_normal_entry,
+                view_entry,
+                create_texture_bind_group_layout_entry(3, false, wgpu::TextureViewDimension::D2),
+            ],
+        });
+
+        let bind_group = |label, layout, index, ao_view| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&buffers.depth_normal_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: view_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: index,
+                        resource: wgpu::BindingResource::TextureView(ao_view),
+                    },
+                ],
+            })
+        };
+        let ao_bind_group = bind_group("SSAO Pass: Group1 (Occlusion)", &ao_layout, 2, &ao_view);
+        let apply_bind_group = bind_group("SSAO Pass: Group1 (Apply)", &apply_layout, 3, &ao_view);
+
+        let shader =
+            config.create_shader_module(device, "SSAO Shader", include_str!("shaders/ssao.wgsl"));
+        let pipeline = |label: &str, layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&format!("{} Layout", label)),
+                bind_group_layouts: &[&image_layout, layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        Self {
+            ao_pipeline: pipeline("SSAO Pipeline", &ao_layout, "ssao_main"),
+            apply_pipeline: pipeline("SSAO Apply Pipeline", &apply_layout, "ssao_apply_main"),
+            image_bind_group,
+            ao_bind_group,
+            apply_bind_group,
+        }
+    }
+
+    pub fn record(&self, pass: &mut wgpu::ComputePass, width: u32, height: u32) {
+        pass.set_bind_group(0, &self.image_bind_group, &[]);
+
+        pass.set_pipeline(&self.ao_pipeline);
+        pass.set_bind_group(1, &self.ao_bind_group, &[]);
+        dispatch_image(pass, width, height);
+
+        pass.set_pipeline(&self.apply_pipeline);
+        pass.set_bind_group(1, &self.apply_bind_group, &[]);
+        dispatch_image(pass, width, height);
+    }
+}
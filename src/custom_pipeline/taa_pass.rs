@@ -0,0 +1,257 @@
+use glam::{Mat4, Vec2};
+
+use crate::camera::CameraUniform;
+
+use super::{
+    config::PipelineConfig,
+    post_chain::{create_image_bind_group, dispatch_image},
+    present_pass::HDR_FORMAT,
+    util::create_texture_bind_group_layout_entry,
+    GpuBuffers,
+};
+
+/// Frames of the jitter sequence before it repeats.
+const JITTER_SAMPLES: u32 = 8;
+
+/// The `index`th element of the Halton sequence of `base`, in [0, 1).
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Temporal anti-aliasing. While it is on, the camera is jittered by a different sub-pixel
+/// offset every frame, and each frame is blended into a history of the previous ones,
+/// reprojected by the motion vectors of the fragment pass.
+pub struct TaaPass {
+    pipeline: wgpu::ComputePipeline,
+    image_bind_group: wgpu::BindGroup,
+    taa_bind_group: wgpu::BindGroup,
+    frame_buffer: wgpu::Buffer,
+    /// The anti-aliased image of the previous frame, copied from the pass's output.
+    pub history_texture: wgpu::Texture,
+    /// Whether the history holds the previous frame, which it doesn't after TAA was off.
+    history_valid: bool,
+    frame_index: u32,
+    prev_view_proj: Option<Mat4>,
+}
+
+impl TaaPass {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &PipelineConfig,
+        buffers: &GpuBuffers,
+        output: &wgpu::TextureView,
+    ) -> Self {
+        let history_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("TAA History Texture"),
+            size: buffers.output_texture.size(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TAA Frame Buffer"),
+            size: 16,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let (image_layout, image_bind_group) =
+            create_image_bind_group(device, "TAA Pass", &buffers.output_view, output);
+        let taa_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Pass: Group1 Layout"),
+            entries: &[
+                create_texture_bind_group_layout_entry(0, false, wgpu::TextureViewDimension::D2),
+                create_texture_bind_group_layout_entry(1, true, wgpu::TextureViewDimension::D2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let taa_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TAA Pass: Group1"),
+            layout: &taa_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&buffers.motion_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &history_texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: frame_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Pipeline Layout"),
+            bind_group_layouts: &[&image_layout, &taa_layout],
+            push_constant_ranges: &[],
+        });
+        let shader =
+            config.create_shader_module(device, "TAA Shader", include_str!("shaders/taa.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("TAA Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("taa_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            image_bind_group,
+            taa_bind_group,
+            frame_buffer,
+            history_texture,
+            history_valid: false,
+            frame_index: 0,
+            prev_view_proj: None,
+        }
+    }
+
+    /// Gives `camera` the previous frame's view for motion vectors and, when TAA is
+    /// `enabled`, this frame's jitter for a `width` by `height` image.
+    pub fn update_camera(
+        &mut self,
+        queue: &wgpu::Queue,
+        camera: &mut CameraUniform,
+        enabled: bool,
+        width: u32,
+        height: u32,
+    ) {
+        let view_proj = Mat4::from_cols_array_2d(&camera.view_proj);
+        if let Some(prev_view_proj) = self.prev_view_proj {
+            camera.prev_view_proj = prev_view_proj.to_cols_array_2d();
+        }
+        self.prev_view_proj = Some(view_proj);
+
+        if enabled {
+            // Halton (2, 3) offsets within the pixel, skipping the first at its corner.
+            self.frame_index = self.frame_index % JITTER_SAMPLES + 1;
+            let offset = Vec2::new(halton(self.frame_index, 2), halton(self.frame_index, 3)) - 0.5;
+            camera.apply_jitter(offset * 2.0 / Vec2::new(width as f32, height as f32));
+        }
+
+        let reset = !(enabled && self.history_valid) as u32;
+        queue.write_buffer(
+            &self.frame_buffer,
+            0,
+            bytemuck::cast_slice(&[reset, 0, 0, 0]),
+        );
+        self.history_valid = enabled;
+    }
+
+    pub fn record(&self, pass: &mut wgpu::ComputePass, width: u32, height: u32) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.image_bind_group, &[]);
+        pass.set_bind_group(1, &self.taa_bind_group, &[]);
+        dispatch_image(pass, width, height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::binning_pass::BinningPass;
+    use crate::custom_pipeline::test_util::{quad_model, read_texture, test_device};
+    use crate::custom_pipeline::{FragmentPass, RasterPass, VertexPass};
+    use crate::scene::{LightConfig, Scene};
+    use crate::vertex::CustomVertex;
+
+    const SIZE: u32 = 32;
+
+    #[test]
+    fn jitter_covers_the_pixel_evenly() {
+        let offsets: Vec<f32> = (1..=JITTER_SAMPLES).map(|i| halton(i, 2)).collect();
+        assert_eq!(&offsets[..4], [0.5, 0.25, 0.75, 0.125]);
+
+        // Every eighth of the pixel is sampled once along each axis.
+        for base in [2, 3] {
+            let mut cells: Vec<u32> = (1..=JITTER_SAMPLES)
+                .map(|i| (halton(i, base) * JITTER_SAMPLES as f32) as u32)
+                .collect();
+            cells.sort();
+            cells.dedup();
+            assert_eq!(cells.len(), JITTER_SAMPLES as usize);
+        }
+    }
+
+    /// A square over the middle half of the screen, in NDC.
+    fn quad_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_model(quad_model(0.5, |x, y| CustomVertex {
+            position: [x, y, 0.5],
+            ..Default::default()
+        }));
+        scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+        scene
+    }
+
+    #[test]
+    fn motion_vectors_leave_out_the_jitter() {
+        let Some((device, queue)) = test_device() else {
+            eprintln!("No adapter supports the custom pipeline, skipping motion vector test");
+            return;
+        };
+        let scene = quad_scene();
+        let config = PipelineConfig::default();
+        let buffers = GpuBuffers::new(&device, SIZE, SIZE, &scene, &config);
+
+        // The quad is drawn in NDC with a jitter of a quarter pixel each way, and was a
+        // quarter of the screen further right the frame before.
+        let mut camera = CameraUniform {
+            prev_view_proj: Mat4::from_translation(glam::Vec3::new(0.5, 0.0, 0.0))
+                .to_cols_array_2d(),
+            ..Default::default()
+        };
+        camera.apply_jitter(Vec2::splat(0.5 / SIZE as f32));
+        queue.write_buffer(&buffers.camera_buffer, 0, bytemuck::bytes_of(&camera));
+
+        let vertex_pass = VertexPass::new(&device, &buffers, &config);
+        let binning_pass = BinningPass::new(&device, &buffers, &config);
+        let raster_pass = RasterPass::new(&device, &buffers, &config);
+        let fragment_pass = FragmentPass::new(&device, &buffers, &config);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        vertex_pass.execute(&mut encoder, buffers.vertex_count);
+        binning_pass.execute(&mut encoder, scene.gx_tris, scene.gy_tris, SIZE, SIZE);
+        raster_pass.execute(&mut encoder, SIZE, SIZE);
+        fragment_pass.execute(&mut encoder, config.dispatch_size(SIZE * SIZE));
+        queue.submit(Some(encoder.finish()));
+
+        let motion = read_texture(&device, &queue, &buffers.motion_texture);
+        let motion: &[[f32; 4]] = bytemuck::cast_slice(&motion);
+        let at = |x: u32, y: u32| motion[(x + y * SIZE) as usize];
+        // Inside the quad, the surface moved a quarter of the screen left.
+        for (x, y) in [(10, 10), (16, 16), (21, 12)] {
+            let [u, v, ..] = at(x, y);
+            assert!((u + 0.25).abs() < 1e-5, "motion {} at ({}, {})", u, x, y);
+            assert!(v.abs() < 1e-5, "motion {} at ({}, {})", v, x, y);
+        }
+        // The background doesn't move.
+        assert_eq!(at(2, 2), [0.0; 4]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Helpers shared by the GPU tests of the custom pipeline passes.
+
+use crate::custom_pipeline::util::{Index, MaterialInfo};
+use crate::model::{Mesh, Model};
+use crate::scene::{LightConfig, Scene};
+use crate::vertex::{CustomVertex, WgpuVertex};
+
+/// The off-screen target frames are presented into, standing in for the surface.
+pub(crate) use crate::replay::create_target;
+
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter =
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Test Device"),
+            required_features: adapter.features(),
+            required_limits: adapter.limits(),
+            memory_hints: wgpu::MemoryHints::default(),
+        },
+        None,
+    ))
+    .ok()
+}
+
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u32> {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Test Staging Buffer"),
+        size: buffer.size(),
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    data
+}
+
+/// Reads back a single layer texture, its rows packed without the padding of the copy.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Vec<u8> {
+    let size = texture.size();
+    let row_size = size.width * texture.format().block_copy_size(None).unwrap();
+    let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Test Staging Buffer"),
+        size: (padded_row_size * size.height) as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &staging,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_size),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice
+        .get_mapped_range()
+        .chunks(padded_row_size as usize)
+        .flat_map(|row| row[..row_size as usize].to_vec())
+        .collect();
+    data
+}
+
+/// Decodes a half float, like those of the HDR output, without infinities or NaNs.
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f32 / 1024.0;
+    sign * match exponent {
+        0 => mantissa * (-14f32).exp2(),
+        _ => (1.0 + mantissa) * (exponent as f32 - 15.0).exp2(),
+    }
+}
+
+/// A model of squares, each given by its corners counter-clockwise and split along the
+/// diagonal from the first into two triangles, numbered in order. Both backends get its
+/// vertices.
+pub fn quads_model(
+    quads: impl IntoIterator<Item = [CustomVertex; 4]>,
+    materials: Vec<MaterialInfo>,
+) -> Model {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for quad in quads {
+        let first = vertices.len() as u32;
+        vertices.extend(quad);
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| Index(first + i)));
+    }
+    Model {
+        processed_vertices_wgpu: vertices.iter().map(WgpuVertex::from).collect(),
+        processed_vertices_custom: vertices,
+        processed_indices: indices,
+        materials,
+        meshes: vec![],
+        textures: vec![],
+    }
+}
+
+/// A square with corners at (±`corner`, ±`corner`), made by `vertex` from each corner's
+/// x and y counter-clockwise from the bottom left, and split along the diagonal from there
+/// into triangles 0 and 1. It has the default material.
+pub fn quad_model(corner: f32, vertex: impl Fn(f32, f32) -> CustomVertex) -> Model {
+    let quad = [
+        (-corner, -corner),
+        (corner, -corner),
+        (corner, corner),
+        (-corner, corner),
+    ]
+    .map(|(x, y)| vertex(x, y));
+    quads_model([quad], vec![MaterialInfo::default()])
+}
+
+/// A square facing the camera between the centres of pixels (2, 2) and (`size` - 3,
+/// `size` - 3), so all of its edges pass exactly through pixel sample positions. It is
+/// split along a diagonal into a "lower" and an "upper" mesh of a triangle each, and lit
+/// from in front, with the identity as the camera.
+pub fn split_quad_scene(size: u32) -> Scene {
+    let mut model = quad_model(1.0 - 5.0 / size as f32, |x, y| CustomVertex {
+        position: [x, y, 0.5],
+        normal: [0.0, 0.0, 1.0],
+        ..Default::default()
+    });
+    let mesh = |name: &str, first_triangle| Mesh {
+        name: name.to_string(),
+        first_triangle,
+        triangle_count: 1,
+    };
+    model.meshes = vec![mesh("lower", 0), mesh("upper", 1)];
+    let mut scene = Scene::new();
+    scene.add_model(model);
+    scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+    scene
+}
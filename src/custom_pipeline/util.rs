@@ -1,13 +1,5 @@
 use bytemuck::{Pod, Zeroable};
 
-use super::raster_pass::TILE_SIZE;
-
-pub(crate) const WORKGROUP_SIZE: u32 = 256;
-pub(crate) const fn dispatch_size(len: u32) -> u32 {
-    let subgroup_size = WORKGROUP_SIZE;
-    (len + subgroup_size - 1) / subgroup_size
-}
-
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub(crate) struct ScreenUniform {
@@ -18,12 +10,12 @@ pub(crate) struct ScreenUniform {
 }
 
 impl ScreenUniform {
-    pub fn new(screen_width: f32, screen_height: f32) -> Self {
+    pub fn new(screen_width: f32, screen_height: f32, tile_size: u32) -> Self {
         Self {
             screen_width,
             screen_height,
-            num_tiles_x: (screen_width as u32 + TILE_SIZE - 1) / TILE_SIZE,
-            num_tiles_y: (screen_height as u32 + TILE_SIZE - 1) / TILE_SIZE,
+            num_tiles_x: (screen_width as u32).div_ceil(tile_size),
+            num_tiles_y: (screen_height as u32).div_ceil(tile_size),
         }
     }
 }
@@ -44,26 +36,142 @@ pub struct MaterialInfo {
     pub shininess: f32,
     pub dissolve: f32,
     pub optical_density: f32,
+    /// Perceptual roughness and metalness of the metallic-roughness shading model.
+    pub roughness: f32,
+    pub metallic: f32,
+    /// How the alpha of glTF materials' base colour applies, the texture's included: one of
+    /// the `ALPHA_` constants. `alpha_cutoff` is the alpha masked surfaces are cut out below.
+    pub alpha_mode: u32,
+    pub alpha_cutoff: f32,
     pub _padding3: [f32; 2],
 }
 
 impl Default for MaterialInfo {
     fn default() -> Self {
+        let specular = [0.5; 3];
+        let diffuse = [0.5, 0.5, 0.5];
+        let shininess = 32.0;
         Self {
             texture_info: TextureInfo::default(),
             ambient: [1.0, 1.0, 1.0],
             _padding1: 0.0,
-            specular: [0.0; 3],
+            specular,
             _padding2: 0.0,
-            diffuse: [0.5, 0.5, 0.5],
-            shininess: 0.0,
+            diffuse,
+            shininess,
             dissolve: 1.0,
             optical_density: 0.0,
+            roughness: Self::roughness_from_shininess(shininess),
+            metallic: Self::metallic_from_colors(specular, diffuse),
+            alpha_mode: Self::ALPHA_DISSOLVE,
+            alpha_cutoff: 0.0,
             _padding3: [0.0; 2],
         }
     }
 }
 
+impl From<&tobj::Material> for MaterialInfo {
+    /// Takes the MTL colours and coefficients, keeping the defaults for any the file leaves out.
+    /// Roughness and metalness come from the PBR extension's `Pr` and `Pm` when present, and
+    /// are otherwise derived from the Phong coefficients.
+    fn from(material: &tobj::Material) -> Self {
+        let default = Self::default();
+        let specular = material.specular.unwrap_or(default.specular);
+        let diffuse = material.diffuse.unwrap_or(default.diffuse);
+        let shininess = material.shininess.unwrap_or(default.shininess);
+        let pbr_param = |name: &str| {
+            material
+                .unknown_param
+                .get(name)
+                .and_then(|value| value.trim().parse::<f32>().ok())
+        };
+        Self {
+            ambient: material.ambient.unwrap_or(default.ambient),
+            specular,
+            diffuse,
+            shininess,
+            dissolve: material.dissolve.unwrap_or(default.dissolve),
+            optical_density: material.optical_density.unwrap_or(default.optical_density),
+            roughness: pbr_param("Pr").unwrap_or_else(|| Self::roughness_from_shininess(shininess)),
+            metallic: pbr_param("Pm")
+                .unwrap_or_else(|| Self::metallic_from_colors(specular, diffuse)),
+            ..default
+        }
+    }
+}
+
+impl MaterialInfo {
+    /// Blended by the dissolve alone, as OBJ materials are.
+    pub const ALPHA_DISSOLVE: u32 = 0;
+    /// Blended by the dissolve times the texture's alpha.
+    pub const ALPHA_BLEND: u32 = 1;
+    /// Drawn where the dissolve times the texture's alpha reaches `alpha_cutoff`, through the
+    /// transparent path, and cut out elsewhere.
+    pub const ALPHA_MASK: u32 = 2;
+
+    /// A metallic-roughness material of base colour `base_color`, like glTF's, blended with
+    /// `dissolve`. The Phong model's specular colour is its reflectance at normal incidence,
+    /// and its exponent the one whose highlight matches the roughness.
+    pub fn from_metallic_roughness(
+        base_color: [f32; 3],
+        dissolve: f32,
+        metallic: f32,
+        roughness: f32,
+    ) -> Self {
+        let specular = base_color.map(|channel| 0.04 + (channel - 0.04) * metallic);
+        Self {
+            specular,
+            diffuse: base_color,
+            shininess: Self::shininess_from_roughness(roughness),
+            dissolve,
+            roughness,
+            metallic,
+            ..Self::default()
+        }
+    }
+
+    /// The material with its texture moved along by `first_texel`, for when the texels of
+    /// several models are put one after another. Materials without a texture keep none.
+    pub fn with_first_texel(mut self, first_texel: u32) -> Self {
+        if !self.texture_info.is_none() {
+            self.texture_info.offset += first_texel;
+        }
+        self
+    }
+
+    /// Whether surfaces with this material are blended over what is behind them.
+    pub fn is_transparent(&self) -> bool {
+        self.dissolve < 1.0 || self.alpha_mode != Self::ALPHA_DISSOLVE
+    }
+
+    /// Roughness giving a highlight like a Phong exponent: a Blinn-Phong lobe of exponent n
+    /// is close to a microfacet lobe of alpha squared 2 / (n + 2), and alpha is the
+    /// square of perceptual roughness.
+    fn roughness_from_shininess(shininess: f32) -> f32 {
+        (2.0 / (shininess.max(0.0) + 2.0)).powf(0.25)
+    }
+
+    /// The inverse of [`Self::roughness_from_shininess`], with roughness clamped to the
+    /// shaders' minimum.
+    fn shininess_from_roughness(roughness: f32) -> f32 {
+        2.0 / roughness.max(0.045).powi(4) - 2.0
+    }
+
+    /// Metalness of a Phong material, from how much of its reflection is specular. Equal
+    /// specular and diffuse colours, or less specular, make a dielectric, and a specular
+    /// colour with no diffuse a metal.
+    fn metallic_from_colors(specular: [f32; 3], diffuse: [f32; 3]) -> f32 {
+        let specular = specular.into_iter().fold(0.0, f32::max);
+        let diffuse = diffuse.into_iter().fold(0.0, f32::max);
+        if specular <= 0.0 {
+            return 0.0;
+        }
+        (2.0 * specular / (specular + diffuse) - 1.0).clamp(0.0, 1.0)
+    }
+}
+
+/// Where a material's base colour texture is among the texels of its model, row by row
+/// from the top, or an offset of `u32::MAX` for none.
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct TextureInfo {
@@ -73,6 +181,23 @@ pub struct TextureInfo {
     pub _padding: u32,
 }
 
+impl TextureInfo {
+    /// A texture of `width` by `height` texels, starting at texel `offset` of its model's.
+    pub fn new(offset: u32, width: u32, height: u32) -> Self {
+        Self {
+            offset,
+            width,
+            height,
+            _padding: 0,
+        }
+    }
+
+    /// Whether this stands for no texture at all.
+    pub fn is_none(&self) -> bool {
+        self.offset == u32::MAX
+    }
+}
+
 impl Default for TextureInfo {
     fn default() -> Self {
         Self {
@@ -85,12 +210,80 @@ impl Default for TextureInfo {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod, serde::Serialize)]
 pub struct Fragment {
     pub uv: [f32; 2],
+    #[serde(skip)]
+    pub _padding1: [f32; 2],
     pub normal: [f32; 3],
+    #[serde(skip)]
+    pub _padding2: f32,
+    pub world_pos: [f32; 3],
+    /// Non-zero for a pixel the raster pass wrote.
+    pub flag: u32,
+    pub material: u32,
+    pub triangle: u32,
+    #[serde(skip)]
+    pub _padding3: [u32; 2],
+}
+
+/// A vertex after the vertex pass, as the binning and raster passes read it.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod, serde::Serialize)]
+pub struct ProjectedVertex {
     pub world_pos: [f32; 3],
-    pub padding: [f32; 4],
+    #[serde(skip)]
+    pub _padding1: f32,
+    /// Screen position in pixels (xy), NDC depth (z) and clip space w.
+    pub screen_pos: [f32; 4],
+    pub normal: [f32; 3],
+    #[serde(skip)]
+    pub _padding2: f32,
+    pub uv: [f32; 2],
+    pub material: u32,
+    #[serde(skip)]
+    pub _padding3: u32,
+}
+
+/// A tile's slice of the triangle list, written by the binning pass.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod, serde::Serialize)]
+pub struct TileTriangles {
+    /// Triangles binned into the tile.
+    pub count: u32,
+    /// Index of the tile's first triangle in the triangle list.
+    pub offset: u32,
+    /// Next free entry of the tile's slice while the triangles are written.
+    pub write_index: u32,
+}
+
+/// A triangle's screen bounds and the tiles they overlap, written by the binning pass.
+/// Culled triangles have an empty tile range.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod, serde::Serialize)]
+pub struct TriangleMeta {
+    /// Minimum (xy) and maximum (zw) screen position in pixels.
+    pub min_max: [f32; 4],
+    pub start_tile: [u32; 2],
+    pub tile_range: [u32; 2],
+}
+
+/// Counters accumulated by the custom pipeline shaders over a single frame.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct PipelineStats {
+    /// Tile/triangle pairs written to the fine tile lists.
+    pub binned_tile_pairs: u32,
+    /// Pairs inside a triangle's bounding box that the edge test showed it never touches.
+    pub rejected_tile_pairs: u32,
+    /// Vertices transformed by the vertex pass.
+    pub vertex_invocations: u32,
+    /// Samples covered by any triangle, counting each overlapping triangle separately.
+    /// Transparent triangles only count the samples in front of the opaque surface.
+    pub rasterised_samples: u32,
+    /// Transparent fragments appended to the per-pixel lists, including any dropped
+    /// because the list storage was full.
+    pub transparent_fragments: u32,
 }
 
 pub fn create_buffer_bind_group_layout_entry(
@@ -108,3 +301,36 @@ pub fn create_buffer_bind_group_layout_entry(
         count: None,
     }
 }
+
+pub fn create_texture_bind_group_layout_entry(
+    index: u32,
+    filterable: bool,
+    view_dimension: wgpu::TextureViewDimension,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: index,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+            view_dimension,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+pub fn create_storage_texture_bind_group_layout_entry(
+    index: u32,
+    format: wgpu::TextureFormat,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: index,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
@@ -0,0 +1,121 @@
+use super::{config::PipelineConfig, util::create_buffer_bind_group_layout_entry, GpuBuffers};
+
+/// Largest workgroup count WebGPU allows in a single dispatch dimension.
+const MAX_DISPATCH_DIMENSION: u32 = 65535;
+
+pub struct VertexPass {
+    pub pipeline: wgpu::ComputePipeline,
+    pub bind_group_0: wgpu::BindGroup,
+    pub bind_group_1: wgpu::BindGroup,
+    config: PipelineConfig,
+}
+
+impl VertexPass {
+    pub fn new(device: &wgpu::Device, buffers: &GpuBuffers, config: &PipelineConfig) -> Self {
+        let group0_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Vertex Pass: Group0 Layout"),
+            entries: &[
+                create_buffer_bind_group_layout_entry(0, true),
+                create_buffer_bind_group_layout_entry(1, false),
+                create_buffer_bind_group_layout_entry(2, false),
+            ],
+        });
+
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let group1_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Vertex Pass: Group1 Layout (Camera, Screen, Effect)"),
+            entries: &[uniform_entry(0), uniform_entry(1), uniform_entry(2)],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Vertex Pipeline Layout"),
+            bind_group_layouts: &[&group0_layout, &group1_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = config.create_shader_module(
+            device,
+            "Vertex Shader",
+            include_str!("shaders/vertex.wgsl"),
+        );
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Vertex Pass Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: Some("vertex_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let bind_group_0 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Vertex Pass: Group0"),
+            layout: &group0_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.projected_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.stats_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let bind_group_1 = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Vertex Pass: Group1"),
+            layout: &group1_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffers.camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffers.screen_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: buffers.effect_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            bind_group_0,
+            bind_group_1,
+            config: *config,
+        }
+    }
+
+    pub fn execute(&self, encoder: &mut wgpu::CommandEncoder, vertex_count: u32) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Vertex Pass"),
+            timestamp_writes: None,
+        });
+
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &self.bind_group_0, &[]);
+        cpass.set_bind_group(1, &self.bind_group_1, &[]);
+
+        // Spill into the second dimension for meshes with more vertices than one row of workgroups covers.
+        let workgroups = self.config.dispatch_size(vertex_count);
+        let workgroups_x = workgroups.clamp(1, MAX_DISPATCH_DIMENSION);
+        cpass.dispatch_workgroups(workgroups_x, workgroups.div_ceil(workgroups_x), 1);
+    }
+}
@@ -40,7 +40,7 @@ pub struct MirageEffect {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum WaveDirection {
     Vertical,
     Horizontal,
@@ -59,6 +59,50 @@ impl Effect {
         }
     }
 
+    /// Builds an effect from its command line name and parameters. `none` yields no effect.
+    pub fn from_args(
+        name: &str,
+        param1: f32,
+        param2: f32,
+        param3: f32,
+        param4: u32,
+    ) -> Result<Option<Self>, String> {
+        match name {
+            "voxelize" => Ok(Some(Effect::voxelize(param1, param2))),
+            "edge_melt" => Ok(Some(Effect::edge_melt(param1, param2))),
+            "mirage" => Ok(Some(Effect::mirage(param1, param2, param3))),
+            "wave" => {
+                // For the 'wave' effect, determine its direction based on user input to ensure the correct visual transformation.
+                let direction = match param4 {
+                    0 => WaveDirection::Vertical,
+                    1 => WaveDirection::Horizontal,
+                    2 => WaveDirection::Radial,
+                    _ => {
+                        return Err(format!(
+                            "Invalid wave direction {}. Use 0 for Vertical, 1 for Horizontal, or 2 for Radial.",
+                            param4
+                        ))
+                    }
+                };
+                Ok(Some(Effect::wave(param1, param2, param3, direction)))
+            }
+            "none" => Ok(None),
+            other => Err(format!(
+                "Invalid effect '{}'. Use 'voxelize', 'edge_melt', 'mirage', 'wave', or 'none'.",
+                other
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Wave(_) => "wave",
+            Effect::EdgeMelt(_) => "edge_melt",
+            Effect::Voxelize(_) => "voxelize",
+            Effect::Mirage(_) => "mirage",
+        }
+    }
+
     // Factory functions for creating effects
     pub fn wave(amplitude: f32, frequency: f32, speed: f32, direction: WaveDirection) -> Self {
         Effect::Wave(WaveEffect {
@@ -132,7 +176,10 @@ pub struct EffectUniform {
     pub param3: f32,
     pub param4: f32,
     pub time: f32,
-    _padding: [f32; 2],
+    /// param3 of the frame before, so the custom pipeline's motion vectors follow the
+    /// geometry the wave effect animates.
+    pub prev_param3: f32,
+    _padding: f32,
 }
 
 impl Default for EffectUniform {
@@ -144,12 +191,20 @@ impl Default for EffectUniform {
             param3: 0.0,
             param4: 0.0,
             time: 0.0,
-            _padding: [0.0; 2],
+            prev_param3: 0.0,
+            _padding: 0.0,
         }
     }
 }
 
 impl EffectUniform {
+    /// Keeps param3 of `previous`, the effect as it was the frame before.
+    pub fn set_previous(&mut self, previous: &Effect) {
+        let mut uniform = Self::default();
+        uniform.update(previous, self.time);
+        self.prev_param3 = uniform.param3;
+    }
+
     pub fn update(&mut self, effect: &Effect, time: f32) {
         self.time = time;
         match effect {
@@ -182,4 +237,4 @@ impl EffectUniform {
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,583 @@
+//! Loads glTF 2.0 models, as `.gltf` files with their buffers and images embedded or beside
+//! them or as binary `.glb` files, into the same processed vertices as OBJ models. Node
+//! transforms are baked into the vertices, every triangle primitive becomes a mesh, and
+//! metallic-roughness materials keep their base colour texture and alpha mode. Their other
+//! textures are left out, with a warning.
+
+use std::path::Path;
+
+use glam::{Mat3, Mat4, Vec3};
+use gltf::{image::Format, material::AlphaMode, mesh::Mode};
+
+use crate::{
+    custom_pipeline::util::{Index, MaterialInfo, TextureInfo},
+    model::{Mesh, Model},
+    util::get_asset_path,
+    vertex::{CustomVertex, WgpuVertex},
+    window::BackendType,
+};
+
+/// Textures wider or taller than this are box filtered down to fit, as every texel of the
+/// scene is kept in a single storage buffer.
+const MAX_TEXTURE_SIZE: u32 = 512;
+
+/// Whether `file_name` names a glTF model rather than an OBJ one.
+pub fn is_gltf(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb")
+        })
+}
+
+/// Loads the default scene of a glTF file, or its first scene if none is the default.
+pub fn load_gltf(file_name: &str, backend_type: BackendType) -> Model {
+    let path = get_asset_path(file_name);
+    let (document, buffers, images) = gltf::import(&path)
+        .unwrap_or_else(|e| panic!("Failed to load model {}: {}", path.display(), e));
+
+    // Images are converted once, however many materials use them.
+    let mut textures = Vec::new();
+    let mut image_textures = vec![None; images.len()];
+    let mut materials: Vec<MaterialInfo> = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            let [red, green, blue, alpha] = pbr.base_color_factor();
+            let (alpha_mode, alpha_cutoff) = match material.alpha_mode() {
+                AlphaMode::Opaque => (MaterialInfo::ALPHA_DISSOLVE, 0.0),
+                AlphaMode::Blend => (MaterialInfo::ALPHA_BLEND, 0.0),
+                AlphaMode::Mask => (
+                    MaterialInfo::ALPHA_MASK,
+                    material.alpha_cutoff().unwrap_or(0.5),
+                ),
+            };
+            let dissolve = if material.alpha_mode() == AlphaMode::Opaque {
+                1.0
+            } else {
+                alpha
+            };
+
+            let ignored: Vec<&str> = [
+                ("metallic-roughness", pbr.metallic_roughness_texture().is_some()),
+                ("normal", material.normal_texture().is_some()),
+                ("occlusion", material.occlusion_texture().is_some()),
+                ("emissive", material.emissive_texture().is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(name, present)| present.then_some(name))
+            .collect();
+            if !ignored.is_empty() {
+                eprintln!(
+                    "Ignoring the {} textures of material {}: only base colour textures are supported",
+                    ignored.join(", "),
+                    material.name().map_or_else(
+                        || material.index().unwrap_or_default().to_string(),
+                        str::to_string
+                    )
+                );
+            }
+
+            let texture_info = pbr
+                .base_color_texture()
+                .map_or_else(TextureInfo::default, |info| {
+                    let image = info.texture().source().index();
+                    *image_textures[image]
+                        .get_or_insert_with(|| append_texture(&images[image], &mut textures))
+                });
+            MaterialInfo {
+                texture_info,
+                alpha_mode,
+                alpha_cutoff,
+                ..MaterialInfo::from_metallic_roughness(
+                    [red, green, blue],
+                    dissolve,
+                    pbr.metallic_factor(),
+                    pbr.roughness_factor(),
+                )
+            }
+        })
+        .collect();
+    let default_material = materials.len() as u32;
+    materials.push(MaterialInfo::default());
+
+    let mut primitives = Primitives {
+        buffers: &buffers,
+        default_material,
+        vertices: Vec::new(),
+        indices: Vec::new(),
+        meshes: Vec::new(),
+    };
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    for node in scene.iter().flat_map(|scene| scene.nodes()) {
+        primitives.add_node(&node, Mat4::IDENTITY);
+    }
+
+    let Primitives {
+        vertices,
+        indices,
+        meshes,
+        ..
+    } = primitives;
+    let (processed_vertices_custom, processed_vertices_wgpu) = match backend_type {
+        BackendType::CustomPipeline => (vertices, Vec::new()),
+        BackendType::WgpuPipeline => (Vec::new(), vertices.iter().map(WgpuVertex::from).collect()),
+    };
+    Model {
+        processed_vertices_custom,
+        processed_vertices_wgpu,
+        processed_indices: indices,
+        materials,
+        meshes,
+        textures,
+    }
+}
+
+/// The primitives of a glTF scene gathered into one list of vertices and indices.
+struct Primitives<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    default_material: u32,
+    vertices: Vec<CustomVertex>,
+    indices: Vec<Index>,
+    meshes: Vec<Mesh>,
+}
+
+impl Primitives<'_> {
+    /// Adds the primitives of `node` and its descendants, with `parent` the transform of
+    /// the node's parent to world space.
+    fn add_node(&mut self, node: &gltf::Node, parent: Mat4) {
+        let transform = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            let name = mesh
+                .name()
+                .or(node.name())
+                .map_or_else(|| format!("mesh {}", mesh.index()), str::to_string);
+            let primitive_count = mesh.primitives().len();
+            for primitive in mesh.primitives() {
+                let name = if primitive_count > 1 {
+                    format!("{} #{}", name, primitive.index())
+                } else {
+                    name.clone()
+                };
+                self.add_primitive(&primitive, transform, name);
+            }
+        }
+        for child in node.children() {
+            self.add_node(&child, transform);
+        }
+    }
+
+    fn add_primitive(&mut self, primitive: &gltf::Primitive, transform: Mat4, name: String) {
+        if primitive.mode() != Mode::Triangles {
+            eprintln!(
+                "Skipping {}: only triangle lists are supported, not {:?}",
+                name,
+                primitive.mode()
+            );
+            return;
+        }
+        let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            return;
+        };
+        let positions: Vec<Vec3> = positions
+            .map(|position| transform.transform_point3(Vec3::from(position)))
+            .collect();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        // Transforms that mirror the primitive also turn its triangles around, so they are
+        // wound back.
+        let mirrored = transform.determinant() < 0.0;
+        let normals: Vec<Vec3> = match reader.read_normals() {
+            Some(normals) => {
+                let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+                normals
+                    .map(|normal| (normal_matrix * Vec3::from(normal)).normalize_or_zero())
+                    .collect()
+            }
+            None => smooth_normals(&positions, &indices, mirrored),
+        };
+
+        let material = primitive.material();
+        let tex_coord_set = material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .map_or(0, |info| info.tex_coord());
+        let tex_coords: Vec<[f32; 2]> = match reader.read_tex_coords(tex_coord_set) {
+            Some(tex_coords) => tex_coords.into_f32().collect(),
+            None => vec![[0.0; 2]; positions.len()],
+        };
+        let material = material
+            .index()
+            .map_or(self.default_material, |index| index as u32);
+
+        let first_vertex = self.vertices.len() as u32;
+        self.vertices
+            .extend(positions.iter().zip(&normals).zip(&tex_coords).map(
+                |((position, normal), &tex_coords)| CustomVertex {
+                    position: position.to_array(),
+                    normal: normal.to_array(),
+                    tex_coords,
+                    material,
+                    ..Default::default()
+                },
+            ));
+        self.meshes.push(Mesh {
+            name,
+            first_triangle: (self.indices.len() / 3) as u32,
+            triangle_count: (indices.len() / 3) as u32,
+        });
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] =
+                [triangle[0], triangle[1], triangle[2]].map(|i| Index(i + first_vertex));
+            self.indices
+                .extend(if mirrored { [a, c, b] } else { [a, b, c] });
+        }
+    }
+}
+
+/// Normals for a primitive without any: the normals of the triangles around each vertex,
+/// weighted by their area, facing the way the triangles are wound once `mirrored` ones are
+/// turned around.
+fn smooth_normals(positions: &[Vec3], indices: &[u32], mirrored: bool) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+        let normal = (b - a).cross(c - a);
+        for &index in triangle {
+            normals[index as usize] += if mirrored { -normal } else { normal };
+        }
+    }
+    normals
+        .iter()
+        .map(|normal| normal.normalize_or_zero())
+        .collect()
+}
+
+/// Appends the texels of `image` to `textures`, packed as RGBA8, and returns where they
+/// are. Images are halved, averaging each square of texels, until they fit in
+/// [`MAX_TEXTURE_SIZE`]. Images of one or two channels are grey, with alpha for two, and
+/// 16 bit channels keep their top byte. Floating point images aren't colours and are left
+/// out.
+fn append_texture(image: &gltf::image::Data, textures: &mut Vec<u32>) -> TextureInfo {
+    let (channels, channel_bytes) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT => {
+            eprintln!(
+                "Skipping a texture of floating point format {:?}",
+                image.format
+            );
+            return TextureInfo::default();
+        }
+    };
+    let rgba = |x: u32, y: u32| {
+        let texel = ((y * image.width + x) * channels * channel_bytes) as usize;
+        let channel = |index: u32| {
+            let start = texel + (index * channel_bytes) as usize;
+            match channel_bytes {
+                1 => image.pixels[start],
+                _ => {
+                    (u16::from_ne_bytes([image.pixels[start], image.pixels[start + 1]]) >> 8) as u8
+                }
+            }
+        };
+        match channels {
+            1 => [channel(0), channel(0), channel(0), u8::MAX],
+            2 => [channel(0), channel(0), channel(0), channel(1)],
+            3 => [channel(0), channel(1), channel(2), u8::MAX],
+            _ => [channel(0), channel(1), channel(2), channel(3)],
+        }
+    };
+
+    let mut step = 1;
+    while image.width.max(image.height) / step > MAX_TEXTURE_SIZE {
+        step *= 2;
+    }
+    let (width, height) = ((image.width / step).max(1), (image.height / step).max(1));
+    if step > 1 {
+        eprintln!(
+            "Downsampling a {}x{} texture to {}x{}, the largest size kept is {}",
+            image.width, image.height, width, height, MAX_TEXTURE_SIZE
+        );
+    }
+
+    let offset = textures.len() as u32;
+    for y in 0..height {
+        for x in 0..width {
+            // The square of texels this one covers, cut short at the image's edges.
+            let xs = x * step..((x + 1) * step).min(image.width);
+            let ys = y * step..((y + 1) * step).min(image.height);
+            let count = xs.len() * ys.len();
+            let mut sum = [0usize; 4];
+            for source_y in ys {
+                for source_x in xs.clone() {
+                    for (total, channel) in sum.iter_mut().zip(rgba(source_x, source_y)) {
+                        *total += channel as usize;
+                    }
+                }
+            }
+            textures.push(u32::from_le_bytes(
+                sum.map(|total| ((total + count / 2) / count) as u8),
+            ));
+        }
+    }
+    TextureInfo::new(offset, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        custom_pipeline::{
+            config::{PipelineConfig, ShadingModel},
+            renderer::CustomRenderer,
+            test_util::{create_target, read_texture, test_device},
+        },
+        scene::{LightConfig, Scene},
+        wgpu_pipeline::renderer::WgpuRenderer,
+    };
+    use std::path::PathBuf;
+
+    const SIZE: u32 = 32;
+
+    /// A 2x2 PNG: blue, then blue with an alpha of zero on the top row, and red then green
+    /// on the bottom row.
+    const QUADRANTS_PNG: [u8; 74] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x06, 0x00, 0x00, 0x00, 0x72,
+        0xb6, 0x0d, 0x24, 0x00, 0x00, 0x00, 0x11, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x60,
+        0x60, 0xf8, 0xff, 0x1f, 0x88, 0xa1, 0x08, 0x08, 0x01, 0x38, 0xd9, 0x06, 0xfa, 0x29, 0x36,
+        0xa9, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+
+    /// A directory of its own for each test run, removed with its files when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "compute-renderer-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A unit square facing +z, moved by a parent node and scaled by its child to cover
+    /// most of the screen at a depth of 0.5. Its two triangles are separate primitives,
+    /// the first with a masked material textured by [`QUADRANTS_PNG`] across the square
+    /// and the second without a material. The buffer and the image are files beside the
+    /// model.
+    fn write_split_quad_gltf(dir: &Path) -> String {
+        let positions: [[f32; 3]; 4] = [
+            [-1.0, -1.0, 0.0],
+            [1.0, -1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+        ];
+        let normals = [[0.0f32, 0.0, 1.0]; 4];
+        let tex_coords: [[f32; 2]; 4] = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(bytemuck::cast_slice(&positions));
+        buffer.extend_from_slice(bytemuck::cast_slice(&normals));
+        buffer.extend_from_slice(bytemuck::cast_slice(&tex_coords));
+        buffer.extend_from_slice(bytemuck::cast_slice(&indices));
+        std::fs::write(dir.join("quad.bin"), &buffer).unwrap();
+        std::fs::write(dir.join("quadrants.png"), QUADRANTS_PNG).unwrap();
+
+        let json = serde_json::json!({
+            "asset": { "version": "2.0" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "name": "parent", "translation": [0.0, 0.0, 0.5], "children": [1] },
+                { "name": "child", "scale": [0.75, 0.75, 1.0], "mesh": 0 }
+            ],
+            "meshes": [{
+                "name": "quad",
+                "primitives": [
+                    {
+                        "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                        "indices": 3,
+                        "material": 0
+                    },
+                    {
+                        "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                        "indices": 4
+                    }
+                ]
+            }],
+            "materials": [{
+                "pbrMetallicRoughness": {
+                    "baseColorTexture": { "index": 0 },
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 1.0
+                },
+                "alphaMode": "MASK"
+            }],
+            "textures": [{ "source": 0 }],
+            "images": [{ "uri": "quadrants.png" }],
+            "buffers": [{ "uri": "quad.bin", "byteLength": buffer.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 96, "byteLength": 32 },
+                { "buffer": 0, "byteOffset": 128, "byteLength": 12 }
+            ],
+            "accessors": [
+                {
+                    "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                    "min": [-1.0, -1.0, 0.0], "max": [1.0, 1.0, 0.0]
+                },
+                { "bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC3" },
+                { "bufferView": 2, "componentType": 5126, "count": 4, "type": "VEC2" },
+                { "bufferView": 3, "componentType": 5123, "count": 3, "type": "SCALAR" },
+                {
+                    "bufferView": 3, "byteOffset": 6, "componentType": 5123, "count": 3,
+                    "type": "SCALAR"
+                }
+            ]
+        });
+        let path = dir.join("quad.gltf");
+        std::fs::write(&path, json.to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// The presented pixel at (`x`, `y`), from BGRA to RGB.
+    fn rgb(presented: &[u8], x: u32, y: u32) -> [u8; 3] {
+        let pixel = ((x + y * SIZE) * 4) as usize;
+        [presented[pixel + 2], presented[pixel + 1], presented[pixel]]
+    }
+
+    /// Checks the textured triangle shows the texture's bottom row at the bottom of the
+    /// screen, red then green, and its top right texel cut out, at the top right.
+    fn assert_quadrants(presented: &[u8]) {
+        let brightest = |[r, g, b]: [u8; 3]| {
+            if r > g.max(b) {
+                'r'
+            } else if g > r.max(b) {
+                'g'
+            } else {
+                'b'
+            }
+        };
+        assert_eq!(brightest(rgb(presented, 12, 26)), 'r');
+        assert_eq!(brightest(rgb(presented, 26, 26)), 'g');
+        assert_eq!(rgb(presented, 27, 8), rgb(presented, 1, 1));
+    }
+
+    #[test]
+    fn gltf_primitives_are_transformed_and_textured_in_both_backends() {
+        let dir = TempDir::new("gltf-test");
+        let file_name = write_split_quad_gltf(&dir.0);
+        assert!(is_gltf(&file_name) && !is_gltf("suzanne.obj"));
+
+        let model = load_gltf(&file_name, BackendType::CustomPipeline);
+        let positions: Vec<[f32; 3]> = model
+            .processed_vertices_custom
+            .iter()
+            .map(|vertex| vertex.position)
+            .collect();
+        assert_eq!(
+            &positions[..4],
+            &[
+                [-0.75, -0.75, 0.5],
+                [0.75, -0.75, 0.5],
+                [0.75, 0.75, 0.5],
+                [-0.75, 0.75, 0.5]
+            ]
+        );
+        let names: Vec<&str> = model.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+        assert_eq!(names, ["quad #0", "quad #1"]);
+        assert_eq!(model.processed_indices.len(), 6);
+        // The untextured triangle falls back to the default material after the file's.
+        assert_eq!(model.materials.len(), 2);
+        assert_eq!(model.processed_vertices_custom[4].material, 1);
+        let material = model.materials[0];
+        assert_eq!(material.alpha_mode, MaterialInfo::ALPHA_MASK);
+        assert_eq!(material.alpha_cutoff, 0.5);
+        assert!(material.is_transparent() && !model.materials[1].is_transparent());
+        let texture = material.texture_info;
+        assert_eq!((texture.offset, texture.width, texture.height), (0, 2, 2));
+        assert_eq!(
+            model.textures,
+            [
+                u32::from_le_bytes([0, 0, 255, 255]),
+                u32::from_le_bytes([0, 0, 255, 0]),
+                u32::from_le_bytes([255, 0, 0, 255]),
+                u32::from_le_bytes([0, 255, 0, 255])
+            ]
+        );
+
+        if test_device().is_none() {
+            eprintln!("No adapter supports the custom pipeline, skipping glTF render test");
+            return;
+        }
+        let config = PipelineConfig {
+            shading: ShadingModel::Pbr,
+            ..Default::default()
+        };
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let mut scene = Scene::new();
+        scene.add_model(model);
+        scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+        let mut custom = pollster::block_on(CustomRenderer::new(
+            &instance, None, SIZE, SIZE, &scene, config,
+        ));
+        let target = create_target(&custom.device, SIZE, SIZE);
+        custom.render_to(&target.create_view(&Default::default()), &scene);
+        assert_quadrants(&read_texture(&custom.device, &custom.queue, &target));
+
+        let mut scene = Scene::new();
+        scene.add_model(load_gltf(&file_name, BackendType::WgpuPipeline));
+        scene.add_light(LightConfig::point([0.0, 0.0, 1.0], [1.0; 3], 1.0));
+        let mut wgpu = pollster::block_on(WgpuRenderer::new(
+            &instance, None, SIZE, SIZE, &scene, &config,
+        ))
+        .unwrap();
+        let target = create_target(&wgpu.device, SIZE, SIZE);
+        pollster::block_on(wgpu.render_to(&target.create_view(&Default::default()), &scene));
+        assert_quadrants(&read_texture(&wgpu.device, &wgpu.queue, &target));
+    }
+
+    #[test]
+    fn large_textures_are_box_filtered() {
+        // Halving a 1024 texel wide image averages each pair of columns and rows.
+        let image = gltf::image::Data {
+            format: Format::R8,
+            width: 1024,
+            height: 2,
+            pixels: (0..2048)
+                .map(|i| if i % 2 == 0 { 0 } else { 200 })
+                .collect(),
+        };
+        let mut textures = Vec::new();
+        let texture = append_texture(&image, &mut textures);
+        assert_eq!((texture.width, texture.height), (512, 1));
+        assert!(textures
+            .iter()
+            .all(|&texel| texel == u32::from_le_bytes([100, 100, 100, 255])));
+    }
+}
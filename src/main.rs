@@ -1,13 +1,25 @@
+use benchmark::{BenchmarkReport, SweepConfig, SweepState};
 use clap::{Parser, Subcommand};
-use scene::{CameraConfig, SceneConfig};
+use custom_pipeline::config::{
+    DebugView, PipelineConfig, PostEffect, PostEffects, ShadingModel, ToneMapping,
+};
+use custom_pipeline::post_chain::ColorLut;
+use replay::{FrameRecording, ReplayBackend};
+use scene::{CameraConfig, LightConfig, SceneConfig};
+use wgpu_pipeline::renderer::WgpuRenderer;
 use window::{BackendType, Window};
 use winit::event_loop::{ControlFlow, EventLoop};
 
+mod benchmark;
 mod camera;
 mod custom_pipeline;
 mod effect;
+mod gltf_loader;
 mod model;
+mod overlay;
 mod performance;
+mod procedural;
+mod replay;
 mod scene;
 mod util;
 mod vertex;
@@ -25,31 +37,199 @@ mod window;
 )]
 struct Cli {
     /// Window width in pixels (default: 1024)
-    #[arg(long, default_value_t = 1024, help = "Set the width of the application window (in pixels)")]
+    #[arg(
+        long,
+        default_value_t = 1024,
+        help = "Set the width of the application window (in pixels)"
+    )]
     width: u32,
 
     /// Window height in pixels (default: 768)
-    #[arg(long, default_value_t = 768, help = "Set the height of the application window (in pixels)")]
+    #[arg(
+        long,
+        default_value_t = 768,
+        help = "Set the height of the application window (in pixels)"
+    )]
     height: u32,
 
-    /// Path to the 3D model (OBJ format, default: suzanne.obj)
-    #[arg(long, default_value = "suzanne.obj", help = "Specify the path to a 3D model file in .obj format")]
+    /// Path to the 3D model (OBJ or glTF format, default: suzanne.obj)
+    #[arg(
+        long,
+        default_value = "suzanne.obj",
+        help = "Specify the path to a 3D model file in .obj, .gltf or .glb format"
+    )]
     model_path: String,
 
     /// Camera mode selection (default: first-person)
     /// Options:
     /// - first-person: Controls behave like an FPS game (WASD + mouse)
     /// - orbit: Rotates around the object with mouse drag
-    #[arg(long, default_value = "first-person", help = "Choose camera mode: 'first-person' or 'orbit'")]
+    #[arg(
+        long,
+        default_value = "first-person",
+        help = "Choose camera mode: 'first-person' or 'orbit'"
+    )]
     camera_mode: String,
 
     /// Rendering backend selection (default: custom)
     /// Options:
     /// - custom: Software rasterization using compute shaders
     /// - wgpu: Hardware-accelerated rendering via WGPU
-    #[arg(long, default_value = "custom", help = "Select rendering backend: 'wgpu' or 'custom'")]
+    #[arg(
+        long,
+        default_value = "custom",
+        help = "Select rendering backend: 'wgpu' or 'custom'"
+    )]
     backend_type: String,
 
+    /// Tile size in pixels used by the custom pipeline's binning and rasterisation (default: 8)
+    #[arg(
+        long,
+        default_value_t = 8,
+        help = "Tile size in pixels for the custom pipeline (e.g. 8, 16 or 32)"
+    )]
+    tile_size: u32,
+
+    /// Rasterise into a visibility buffer (depth + triangle ID) and rebuild attributes when shading
+    #[arg(long, help = "Use the custom pipeline's visibility buffer raster mode")]
+    visibility_buffer: bool,
+
+    /// Write a G-buffer and light it in a separate pass, with the lights culled per tile
+    #[arg(
+        long,
+        help = "Use the custom pipeline's deferred shading mode with tiled light culling"
+    )]
+    deferred: bool,
+
+    /// List the lights reaching each screen tile before shading, and light opaque surfaces with only those
+    #[arg(
+        long,
+        help = "Cull the lights of each screen tile before forward shading, in both backends"
+    )]
+    light_culling: bool,
+
+    /// Samples per pixel for multisample anti-aliasing, applied to both backends (default: 1)
+    #[arg(
+        long,
+        default_value_t = 1,
+        help = "MSAA sample count for both backends: 1, 2, 4 or 8"
+    )]
+    msaa: u32,
+
+    /// Cast shadows from the first lights of the scene with shadow maps, in both backends
+    #[arg(
+        long,
+        help = "Render shadow maps for the first four lights, in both backends"
+    )]
+    shadows: bool,
+
+    /// Width and height of each shadow map, or cube face, in texels (default: 512)
+    #[arg(
+        long,
+        default_value_t = 512,
+        help = "Shadow map size in texels, per cube face"
+    )]
+    shadow_map_size: u32,
+
+    /// Lighting model of both backends (default: phong)
+    /// Options:
+    /// - phong: Diffuse plus a Phong highlight from the MTL specular colour and exponent
+    /// - pbr: Metallic-roughness microfacet shading (GGX, Smith, Schlick)
+    #[arg(
+        long,
+        default_value = "phong",
+        help = "Select shading model: 'phong' or 'pbr'"
+    )]
+    shading: String,
+
+    /// Operator mapping the HDR image into display range in both backends (default: aces)
+    /// Options:
+    /// - none: Clip at white
+    /// - reinhard: x / (1 + x) per channel
+    /// - aces: Stephen Hill's fit of the ACES filmic curve
+    /// - agx: AgX with its default contrast look
+    #[arg(
+        long,
+        default_value = "aces",
+        help = "Select tone mapping: 'none', 'reinhard', 'aces' or 'agx'"
+    )]
+    tone_mapping: String,
+
+    /// Exposure in stops, or a compensation on top of the metered exposure with --auto-exposure (default: 0)
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        help = "Exposure in stops (EV)"
+    )]
+    exposure: f32,
+
+    /// Meter the exposure from a luminance histogram of every frame, adapting over time
+    #[arg(
+        long,
+        help = "Adapt the exposure to the image's average luminance, in both backends"
+    )]
+    auto_exposure: bool,
+
+    /// Compute post-processing passes run on the custom pipeline's output, in this order,
+    /// as a comma separated list (default: none). F1 to F6 toggle them while it runs.
+    /// Options:
+    /// - ssao: Screen space ambient occlusion
+    /// - taa: Temporal anti-aliasing, to compare with --msaa
+    /// - bloom: Downsampled and upsampled glow around bright areas
+    /// - fxaa: Fast approximate anti-aliasing
+    /// - lut: Colour grading through a 3D LUT (see --lut)
+    /// - vignette: Darkened corners
+    #[arg(
+        long,
+        default_value = "none",
+        help = "Custom pipeline post passes, e.g. 'ssao,taa,bloom'"
+    )]
+    post: String,
+
+    /// A 3D LUT in .cube format to colour grade the custom pipeline's output with
+    #[arg(
+        long,
+        help = "Colour grade the custom pipeline's output with a .cube LUT (enables 'lut')"
+    )]
+    lut: Option<String>,
+
+    /// View of the custom pipeline's internals shown instead of the shaded image (default: none).
+    /// F7 cycles through them while it runs.
+    /// Options:
+    /// - tiles: Heatmap of the triangles binned into each tile
+    /// - overdraw: Heatmap of the depth tests in each pixel
+    /// - depth: Linear distance from the camera
+    /// - normals: World space normals
+    /// - uvs: Texture coordinates
+    /// - triangles: A colour per triangle ID
+    /// - wireframe: Triangle edges over the shaded image
+    #[arg(
+        long,
+        default_value = "none",
+        help = "Custom pipeline debug view, e.g. 'overdraw' or 'wireframe'"
+    )]
+    debug_view: String,
+
+    /// Directory to write the custom pipeline's intermediate buffers to after the first frame it
+    /// renders: the tile bins, triangle list, triangle metadata, projected vertices and
+    /// fragments, each as raw bytes (.bin) and decoded (.json)
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Dump the custom pipeline's intermediate buffers of the first frame to DIR"
+    )]
+    dump_frame: Option<std::path::PathBuf>,
+
+    /// File to record the inputs of the first frame rendered to, for the `replay` command: the
+    /// screen size, camera, effect and lights, and the scene's geometry and materials
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Record the inputs of the first frame to FILE, for 'replay'"
+    )]
+    record_frame: Option<std::path::PathBuf>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -67,11 +247,59 @@ enum Commands {
     /// 5 - Suzanne (WGPU pipeline)
     /// 6 - Vokselia Spawn (custom pipeline)
     /// 7 - Vokselia Spawn (WGPU pipeline)
+    /// 8 - Glass panes, transparency (custom pipeline)
+    /// 9 - Glass panes, transparency (WGPU pipeline)
+    /// 10 - Glass panes, every light type (custom pipeline)
+    /// 11 - Glass panes, every light type (WGPU pipeline)
+    /// 12 - San Miguel, PBR shading (custom pipeline)
+    /// 13 - San Miguel, PBR shading (WGPU pipeline)
+    /// 14 - Light field, 256 moving lights (custom pipeline, deferred)
+    /// 15 - Light field, 256 moving lights (WGPU pipeline, forward)
+    /// 16 - Light field, 1000 moving lights (custom pipeline, light culling)
+    /// 17 - Light field, 1000 moving lights (WGPU pipeline, light culling)
     Benchmarks {
-        /// Offset to start benchmarks (valid values: 0-7)
-        #[arg(long, default_value_t = 0, help = "Scene index to start benchmarks from (0-7)")]
+        /// Offset to start benchmarks (valid values: 0-17)
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Scene index to start benchmarks from (0-17)"
+        )]
         offset: usize,
     },
+    /// Run an unattended benchmark sweep described by a TOML file
+    ///
+    /// Every combination of the listed scenes, backends, resolutions, tile sizes and effects
+    /// is run in turn. Results are appended to the report CSV named in the file, and runs
+    /// already present in the report are skipped, so an interrupted sweep can be restarted.
+    Sweep {
+        /// Path to the sweep configuration file
+        #[arg(long, help = "Path to the sweep configuration (TOML)")]
+        config: String,
+    },
+    /// Render a frame recorded with --record-frame headlessly, without its model files
+    ///
+    /// The frame is rendered with the pipeline options given before the command, such as
+    /// --tile-size or --debug-view, and written to a PPM image. The CPU reference shows the
+    /// normals of the nearest opaque surfaces, like --debug-view normals.
+    Replay {
+        /// The recorded frame
+        #[arg(help = "Frame recorded with --record-frame")]
+        frame: std::path::PathBuf,
+        /// Backend to render it with (default: custom)
+        #[arg(
+            long,
+            default_value = "custom",
+            help = "Render with 'custom', 'wgpu' or 'cpu' (the CPU reference)"
+        )]
+        backend: String,
+        /// Image to write the frame to
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "PPM image to write the rendered frame to"
+        )]
+        output: std::path::PathBuf,
+    },
     /// Apply a visual effect to the scene
     ///
     /// Available effects:
@@ -82,29 +310,148 @@ enum Commands {
     /// - none: Disables effects
     Effect {
         /// Effect type (default: voxelize)
-        #[arg(long, default_value = "voxelize", help = "Choose effect: 'voxelize', 'edge_melt', 'mirage', 'wave', or 'none'")]
+        #[arg(
+            long,
+            default_value = "voxelize",
+            help = "Choose effect: 'voxelize', 'edge_melt', 'mirage', 'wave', or 'none'"
+        )]
         effect: String,
         /// Parameter 1: Controls voxel size, amplitude, or intensity (default: 3.0)
-        #[arg(long, default_value_t = 3.0, help = "Primary effect parameter (varies by effect type)")]
+        #[arg(
+            long,
+            default_value_t = 3.0,
+            help = "Primary effect parameter (varies by effect type)"
+        )]
         param1: f32,
         /// Parameter 2: Controls speed or frequency (default: 0.2)
-        #[arg(long, default_value_t = 0.2, help = "Secondary effect parameter (varies by effect type)")]
+        #[arg(
+            long,
+            default_value_t = 0.2,
+            help = "Secondary effect parameter (varies by effect type)"
+        )]
         param2: f32,
         /// Parameter 3: For wave & mirage effects (default: 1.0)
-        #[arg(long, default_value_t = 1.0, help = "Third effect parameter (for wave & mirage effects)")]
+        #[arg(
+            long,
+            default_value_t = 1.0,
+            help = "Third effect parameter (for wave & mirage effects)"
+        )]
         param3: f32,
         /// Parameter 4: Wave effect direction (0 = Vertical, 1 = Horizontal, 2 = Radial) (default: 0)
-        #[arg(long, default_value_t = 0, help = "Wave direction (0=Vertical, 1=Horizontal, 2=Radial)")]
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Wave direction (0=Vertical, 1=Horizontal, 2=Radial)"
+        )]
         param4: u32,
     },
 }
 
-
-fn main() {    
+fn main() {
     let cli = Cli::parse();
 
-    let width = cli.width as usize;
-    let height = cli.height as usize;
+    let mut width = cli.width as usize;
+    let mut height = cli.height as usize;
+
+    let shading = match cli.shading.parse::<ShadingModel>() {
+        Ok(shading) => shading,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let tone_mapping = match cli.tone_mapping.parse::<ToneMapping>() {
+        Ok(tone_mapping) => tone_mapping,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let debug_view = match cli.debug_view.parse::<DebugView>() {
+        Ok(debug_view) => debug_view,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut post_effects = match cli.post.parse::<PostEffects>() {
+        Ok(post_effects) => post_effects,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let color_lut = cli.lut.as_ref().map(|path| match ColorLut::load(path) {
+        Ok(lut) => lut,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    });
+    if color_lut.is_some() {
+        post_effects.insert(PostEffect::ColorGrading);
+    }
+    let pipeline_config = PipelineConfig {
+        tile_size: cli.tile_size,
+        visibility_buffer: cli.visibility_buffer,
+        msaa_samples: cli.msaa,
+        deferred: cli.deferred,
+        light_culling: cli.light_culling,
+        shadows: cli.shadows,
+        shadow_map_size: cli.shadow_map_size,
+        shading,
+        tone_mapping,
+        exposure: cli.exposure,
+        auto_exposure: cli.auto_exposure,
+        post_effects,
+        debug_view,
+        ..Default::default()
+    };
+    if let Err(e) = pipeline_config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    // Replaying a recorded frame renders it off-screen and exits, without opening a window.
+    if let Some(Commands::Replay {
+        frame,
+        backend,
+        output,
+    }) = &cli.command
+    {
+        let backend = match backend.parse::<ReplayBackend>() {
+            Ok(backend) => backend,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let recording = match FrameRecording::read(frame) {
+            Ok(recording) => recording,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", frame.display(), e);
+                std::process::exit(1);
+            }
+        };
+        let pixels = match pollster::block_on(recording.replay(backend, &pipeline_config)) {
+            Ok(pixels) => pixels,
+            Err(e) => {
+                eprintln!("Failed to replay {}: {}", frame.display(), e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = replay::write_ppm(output, recording.width, recording.height, &pixels) {
+            eprintln!("Failed to write {}: {}", output.display(), e);
+            std::process::exit(1);
+        }
+        println!(
+            "Replayed {} through the {} backend into {}",
+            frame.display(),
+            backend,
+            output.display()
+        );
+        return;
+    }
 
     // Determine if a visual effect is requested and configure it accordingly. This enables dynamic scene modifications.
     let effect = match &cli.command {
@@ -114,32 +461,10 @@ fn main() {
             param2,
             param3,
             param4,
-        }) => match effect.as_str() {
-            "voxelize" => Some(effect::Effect::voxelize(*param1, *param2)),
-            "edge_melt" => Some(effect::Effect::edge_melt(*param1, *param2)),
-            "mirage" => Some(effect::Effect::mirage(*param1, *param2, *param3)),
-            "wave" => {
-                // For the 'wave' effect, determine its direction based on user input to ensure the correct visual transformation.
-                let direction = match param4 {
-                    0 => effect::WaveDirection::Vertical,
-                    1 => effect::WaveDirection::Horizontal,
-                    2 => effect::WaveDirection::Radial,
-                    _ => {
-                        eprintln!(
-                                "Invalid wave direction {}. Use 0 for Vertical, 1 for Horizontal, or 2 for Radial.",
-                                param4
-                            );
-                        std::process::exit(1);
-                    }
-                };
-                Some(effect::Effect::wave(*param1, *param2, *param3, direction))
-            }
-            "none" => None,
-            other => {
-                eprintln!(
-                        "Invalid effect '{}'. Use 'voxelize', 'edge_melt', 'mirage', 'wave', or 'none'.",
-                        other
-                    );
+        }) => match effect::Effect::from_args(effect, *param1, *param2, *param3, *param4) {
+            Ok(effect) => effect,
+            Err(e) => {
+                eprintln!("{}", e);
                 std::process::exit(1);
             }
         },
@@ -147,7 +472,7 @@ fn main() {
     };
 
     // Decide between benchmark mode and regular mode. Benchmark mode evaluates performance over predefined scenes.
-    let (scenes, start_offset) = match cli.command {
+    let (scenes, start_offset, sweep) = match cli.command {
         Some(Commands::Benchmarks { offset }) => {
             // Set benchmark duration and initialize various scene configurations to test both custom and WGPU pipelines.
             let benchmark_duration_secs = 30;
@@ -159,6 +484,7 @@ fn main() {
                     ..Default::default()
                 },
                 benchmark_duration_secs,
+                pipeline_config,
                 ..Default::default()
             };
 
@@ -171,6 +497,7 @@ fn main() {
                     ..Default::default()
                 },
                 benchmark_duration_secs,
+                pipeline_config,
                 ..Default::default()
             };
 
@@ -182,6 +509,7 @@ fn main() {
                     ..Default::default()
                 },
                 benchmark_duration_secs,
+                pipeline_config,
                 ..Default::default()
             };
 
@@ -193,9 +521,89 @@ fn main() {
                     ..Default::default()
                 },
                 benchmark_duration_secs,
+                pipeline_config,
                 ..Default::default()
             };
 
+            // Overlapping transparent panes: per-pixel lists in the custom pipeline against
+            // sorted alpha blending in the WGPU pipeline.
+            let glass_panes_scene = SceneConfig {
+                model_path: "procedural:glass_panes".to_string(),
+                camera_config: CameraConfig {
+                    position: [0.0, 1.5, 3.0],
+                    mode: camera::CameraMode::FirstPerson,
+                    ..Default::default()
+                },
+                lights: vec![
+                    LightConfig::point([0.0, 5.0, 4.0], [1.0, 0.9, 0.8], 1.0),
+                    LightConfig::point([-5.0, 3.0, -6.0], [0.3, 0.4, 0.5], 0.5),
+                ],
+                benchmark_duration_secs,
+                pipeline_config,
+                ..Default::default()
+            };
+
+            // The same panes under every light type: a low sun, a spot light on the panes
+            // and a short ranged point light behind them.
+            let light_types_scene = SceneConfig {
+                lights: vec![
+                    LightConfig::directional([-0.4, -1.0, -0.6], [1.0, 0.95, 0.85], 0.6),
+                    LightConfig::spot(
+                        [0.0, 4.0, 2.0],
+                        [0.0, -1.0, -1.0],
+                        20f32.to_radians(),
+                        30f32.to_radians(),
+                        [1.0, 0.9, 0.7],
+                        1.0,
+                    )
+                    .with_range(12.0),
+                    LightConfig::point([2.0, 1.0, -6.0], [0.3, 0.5, 1.0], 1.0).with_range(5.0),
+                ],
+                ..glass_panes_scene.clone()
+            };
+
+            // San Miguel again with metallic-roughness shading, to weigh heavier shading
+            // in the compute fragment pass against the hardware pipeline.
+            let san_miguel_pbr_scene = SceneConfig {
+                pipeline_config: PipelineConfig {
+                    shading: ShadingModel::Pbr,
+                    ..pipeline_config
+                },
+                ..san_miguel_scene.clone()
+            };
+
+            // Hundreds of small moving lights over a field of pillars: deferred shading with
+            // tiled light culling in the custom pipeline against forward shading with every
+            // light in the WGPU pipeline.
+            let light_field_scene = SceneConfig {
+                model_path: "procedural:light_field".to_string(),
+                camera_config: CameraConfig {
+                    position: [0.0, 6.0, 22.0],
+                    mode: camera::CameraMode::FirstPerson,
+                    ..Default::default()
+                },
+                lights: procedural::light_field_lights(256),
+                benchmark_duration_secs,
+                pipeline_config: PipelineConfig {
+                    deferred: true,
+                    msaa_samples: 1,
+                    ..pipeline_config
+                },
+                ..Default::default()
+            };
+
+            // The same field under a thousand lights, culled per tile before forward shading
+            // in both backends.
+            let thousand_lights_scene = SceneConfig {
+                lights: procedural::light_field_lights(1000),
+                pipeline_config: PipelineConfig {
+                    deferred: false,
+                    light_culling: true,
+                    ..light_field_scene.pipeline_config
+                },
+                ..light_field_scene.clone()
+            };
+
             let scenes = vec![
                 san_miguel_scene.clone(),
                 SceneConfig {
@@ -217,6 +625,35 @@ fn main() {
                     backend_type: BackendType::WgpuPipeline,
                     ..vokselia_spawn_scene
                 },
+                glass_panes_scene.clone(),
+                SceneConfig {
+                    backend_type: BackendType::WgpuPipeline,
+                    ..glass_panes_scene
+                },
+                light_types_scene.clone(),
+                SceneConfig {
+                    backend_type: BackendType::WgpuPipeline,
+                    ..light_types_scene
+                },
+                san_miguel_pbr_scene.clone(),
+                SceneConfig {
+                    backend_type: BackendType::WgpuPipeline,
+                    ..san_miguel_pbr_scene
+                },
+                light_field_scene.clone(),
+                SceneConfig {
+                    backend_type: BackendType::WgpuPipeline,
+                    pipeline_config: PipelineConfig {
+                        deferred: false,
+                        ..light_field_scene.pipeline_config
+                    },
+                    ..light_field_scene
+                },
+                thousand_lights_scene.clone(),
+                SceneConfig {
+                    backend_type: BackendType::WgpuPipeline,
+                    ..thousand_lights_scene
+                },
             ];
 
             if offset >= scenes.len() {
@@ -228,7 +665,45 @@ fn main() {
                 std::process::exit(1);
             }
 
-            (scenes, offset)
+            (scenes, offset, None)
+        }
+        Some(Commands::Sweep { config }) => {
+            let (runs, report) = match SweepConfig::load(&config)
+                .and_then(|sweep| Ok((sweep.runs()?, BenchmarkReport::open(&sweep.report)?)))
+            {
+                Ok(sweep) => sweep,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+
+            // Resume: skip every run that already has a row in the report.
+            let total_runs = runs.len();
+            let (keys, scenes): (Vec<_>, Vec<_>) = runs
+                .into_iter()
+                .filter(|run| !report.is_completed(&run.key))
+                .map(|run| (run.key, run.scene_config))
+                .unzip();
+
+            if scenes.is_empty() {
+                println!("All {} runs of the sweep are already complete.", total_runs);
+                report.print_table();
+                return;
+            }
+            println!(
+                "Running {} of {} sweep runs ({} already complete).",
+                scenes.len(),
+                total_runs,
+                total_runs - scenes.len()
+            );
+
+            if let Some([w, h]) = scenes[0].resolution {
+                width = w as usize;
+                height = h as usize;
+            }
+
+            (scenes, 0, Some(SweepState { report, keys }))
         }
         _ => {
             // Regular mode: Build a scene using user-specified camera mode, backend, and model path.
@@ -244,11 +719,10 @@ fn main() {
                 }
             };
 
-            let backend_type = match cli.backend_type.as_str() {
-                "wgpu" => BackendType::WgpuPipeline,
-                "custom" => BackendType::CustomPipeline,
-                other => {
-                    eprintln!("Invalid backend type '{}'. Use 'wgpu' or 'custom'.", other);
+            let backend_type = match cli.backend_type.parse::<BackendType>() {
+                Ok(backend_type) => backend_type,
+                Err(e) => {
+                    eprintln!("{}", e);
                     std::process::exit(1);
                 }
             };
@@ -261,13 +735,41 @@ fn main() {
                 },
                 backend_type,
                 effect,
+                pipeline_config,
+                color_lut,
                 ..Default::default()
             };
 
-            (vec![scene_config], 0)
+            (vec![scene_config], 0, None)
         }
     };
 
+    // Reject MSAA sample counts the GPU can't render, and shadow maps too large for it to bind,
+    // before the first scene starts rather than part way through a benchmark or sweep.
+    let adapter = pollster::block_on(wgpu::Instance::default().request_adapter(
+        &wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        },
+    ));
+    if let Some(adapter) = adapter {
+        let max_binding_size = adapter.limits().max_storage_buffer_binding_size as u64;
+        for scene_config in &scenes[start_offset..] {
+            let supported = match scene_config.backend_type {
+                BackendType::WgpuPipeline => {
+                    WgpuRenderer::check_support(&adapter, &scene_config.pipeline_config)
+                }
+                BackendType::CustomPipeline => scene_config
+                    .pipeline_config
+                    .check_shadow_maps(scene_config.shadow_view_count(), max_binding_size),
+            };
+            if let Err(e) = supported {
+                eprintln!("{}: {}", scene_config.scene_name(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Create a centralized event loop for rendering and event handling, crucial for a responsive application.
     let event_loop = EventLoop::new().expect("Failed to create event loop");
     event_loop.set_control_flow(ControlFlow::Poll);
@@ -293,7 +795,16 @@ fn main() {
     };
 
     // Store all scene configurations in the window to enable switching between scenes during runtime.
-    window.set_scene_configs(scenes);
+    window.set_scene_configs(scenes, start_offset);
+    if let Some(sweep) = sweep {
+        window.set_sweep(sweep);
+    }
+    if let Some(dir) = cli.dump_frame {
+        window.set_dump_frame(dir);
+    }
+    if let Some(path) = cli.record_frame {
+        window.set_record_frame(path);
+    }
 
     // Start the event loop which continuously renders the scene and processes user input.
     event_loop
@@ -1,7 +1,8 @@
 use std::{fs::File, io::BufReader};
 
 use crate::{
-    custom_pipeline::util::Index,
+    custom_pipeline::util::{Index, MaterialInfo},
+    gltf_loader, procedural,
     util::get_asset_path,
     vertex::{CustomVertex, WgpuVertex},
     window::BackendType,
@@ -11,44 +12,59 @@ pub struct Model {
     pub processed_vertices_custom: Vec<CustomVertex>,
     pub processed_vertices_wgpu: Vec<WgpuVertex>,
     pub processed_indices: Vec<Index>,
+    /// The model's MTL or glTF materials, followed by a default one for meshes without a
+    /// material. Vertices refer to these by index.
+    pub materials: Vec<MaterialInfo>,
+    /// The meshes of the file, in the order their triangles were added. Empty for models
+    /// built in code.
+    pub meshes: Vec<Mesh>,
+    /// Texels of the materials' base colour textures, one texture after another, each
+    /// packed as sRGB RGBA8 with red in the lowest byte. Only glTF models have textures.
+    pub textures: Vec<u32>,
+}
+
+/// A named run of a model's triangles, from one mesh of its file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub name: String,
+    pub first_triangle: u32,
+    pub triangle_count: u32,
 }
 
 impl Model {
+    /// Loads a model file, as glTF for a `.gltf` or `.glb` extension and as OBJ otherwise,
+    /// or generates a procedural one.
     pub async fn new(file_name: &str, backend_type: BackendType) -> Model {
-        // Load OBJ text
-        let obj_text = get_asset_path(file_name);
-        let directory = obj_text.parent().unwrap();
-        let mut obj_reader = BufReader::new(File::open(obj_text.as_path()).unwrap());
-
-        // tobj async: loads .obj + .mtl
-        let (m, _m_materials) = tobj::load_obj_buf(
-            &mut obj_reader,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-            |p| {
-                let mat_text = File::open(directory.join(p.to_path_buf()));
-                if let Ok(mat_text) = mat_text {
-                    tobj::load_mtl_buf(&mut BufReader::new(mat_text))
-                } else {
-                    Err(tobj::LoadError::OpenFileFailed)
-                }
-            },
-        )
-        .expect("Failed to load model");
+        if gltf_loader::is_gltf(file_name) {
+            return gltf_loader::load_gltf(file_name, backend_type);
+        }
+
+        let (m, m_materials) = match file_name.strip_prefix(procedural::PREFIX) {
+            Some(name) => procedural::generate(name)
+                .unwrap_or_else(|| panic!("Unknown procedural scene: {}", name)),
+            None => load_obj(file_name),
+        };
+
+        let mut materials: Vec<MaterialInfo> = m_materials.iter().map(MaterialInfo::from).collect();
+        let default_material = materials.len() as u32;
+        materials.push(MaterialInfo::default());
 
         // Pre-allocate vectors for processed data
         let mut processed_vertices_custom = Vec::new();
         let mut processed_vertices_wgpu = Vec::new();
         let mut processed_indices = Vec::new();
+        let mut meshes = Vec::new();
 
         // Keep track of vertex count for index offsetting
         let mut current_vertex_count = 0;
 
         // Process meshes and their vertices/indices
         for m in m {
+            let material = m
+                .mesh
+                .material_id
+                .filter(|&id| id < m_materials.len())
+                .map_or(default_material, |id| id as u32);
             match backend_type {
                 BackendType::CustomPipeline => {
                     let vertices = (0..m.mesh.positions.len() / 3)
@@ -72,6 +88,7 @@ impl Model {
                                     m.mesh.normals[i * 3 + 2],
                                 ]
                             },
+                            material,
                             ..Default::default()
                         })
                         .collect::<Vec<_>>();
@@ -99,6 +116,7 @@ impl Model {
                                     m.mesh.normals[i * 3 + 2],
                                 ]
                             },
+                            material,
                         })
                         .collect::<Vec<_>>();
                     processed_vertices_wgpu.extend(vertices);
@@ -114,6 +132,11 @@ impl Model {
                 .collect();
 
             // Update processed data
+            meshes.push(Mesh {
+                name: m.name,
+                first_triangle: (processed_indices.len() / 3) as u32,
+                triangle_count: (indices.len() / 3) as u32,
+            });
             processed_indices.extend(indices);
             current_vertex_count = match backend_type {
                 BackendType::CustomPipeline => processed_vertices_custom.len() as u32,
@@ -125,6 +148,81 @@ impl Model {
             processed_vertices_custom,
             processed_vertices_wgpu,
             processed_indices,
+            materials,
+            meshes,
+            textures: vec![],
         }
     }
+
+    /// Position of vertex `index`, from whichever backend's vertices the model holds.
+    pub fn vertex_position(&self, index: u32) -> Option<glam::Vec3> {
+        let index = index as usize;
+        self.processed_vertices_custom
+            .get(index)
+            .map(|vertex| vertex.position)
+            .or_else(|| {
+                self.processed_vertices_wgpu
+                    .get(index)
+                    .map(|vertex| vertex.position)
+            })
+            .map(glam::Vec3::from)
+    }
+
+    /// The mesh triangle `triangle` of the model belongs to, if the model has meshes.
+    pub fn mesh_of(&self, triangle: u32) -> Option<&Mesh> {
+        self.meshes.iter().find(|mesh| {
+            (mesh.first_triangle..mesh.first_triangle + mesh.triangle_count).contains(&triangle)
+        })
+    }
+
+    /// Minimum and maximum corners of the box around every vertex, or the origin for a
+    /// model without vertices.
+    pub fn bounds(&self) -> [glam::Vec3; 2] {
+        let positions = self
+            .processed_vertices_custom
+            .iter()
+            .map(|vertex| vertex.position)
+            .chain(
+                self.processed_vertices_wgpu
+                    .iter()
+                    .map(|vertex| vertex.position),
+            )
+            .map(glam::Vec3::from);
+        positions
+            .fold(None::<[glam::Vec3; 2]>, |bounds, position| match bounds {
+                None => Some([position; 2]),
+                Some([min, max]) => Some([min.min(position), max.max(position)]),
+            })
+            .unwrap_or([glam::Vec3::ZERO; 2])
+    }
+}
+
+/// Loads an OBJ file and its MTL materials. A missing or broken MTL file leaves the
+/// model without materials rather than failing.
+fn load_obj(file_name: &str) -> (Vec<tobj::Model>, Vec<tobj::Material>) {
+    // Load OBJ text
+    let obj_text = get_asset_path(file_name);
+    let directory = obj_text.parent().unwrap();
+    let mut obj_reader = BufReader::new(File::open(obj_text.as_path()).unwrap());
+
+    // tobj async: loads .obj + .mtl
+    let (m, m_materials) = tobj::load_obj_buf(
+        &mut obj_reader,
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |p| {
+            let mat_text = File::open(directory.join(p));
+            if let Ok(mat_text) = mat_text {
+                tobj::load_mtl_buf(&mut BufReader::new(mat_text))
+            } else {
+                Err(tobj::LoadError::OpenFileFailed)
+            }
+        },
+    )
+    .expect("Failed to load model");
+
+    (m, m_materials.unwrap_or_default())
 }
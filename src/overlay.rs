@@ -0,0 +1,438 @@
+//! An egui window drawn over either backend's frame, showing the frame rate, the custom
+//! pipeline's pass timings and counters and the scene's size, with controls for the effect,
+//! the lights, the camera and the debug views. F8 shows and hides it.
+
+use std::time::{Duration, Instant};
+
+use egui::{Color32, Stroke};
+use winit::event::WindowEvent;
+use winit::window::Window as WinitWindow;
+
+use crate::custom_pipeline::config::{DebugView, PostEffect};
+use crate::custom_pipeline::present_pass::SURFACE_FORMAT;
+use crate::custom_pipeline::util::PipelineStats;
+use crate::effect::{Effect, WaveDirection};
+use crate::performance::PerformanceCollector;
+use crate::scene::{Scene, LIGHT_DIRECTIONAL, LIGHT_POINT, LIGHT_SPOT};
+use crate::window::RenderBackend;
+
+/// Frames shown in the frame time graph.
+const GRAPH_FRAMES: usize = 240;
+
+/// How often the custom pipeline's counters and timings are read back. Reading them waits
+/// for the GPU, so doing it every frame would skew the frame times shown next to them.
+const READBACK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A laid out UI waiting to be painted over the next frame.
+struct OverlayFrame {
+    primitives: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    pixels_per_point: f32,
+}
+
+/// The custom pipeline's counters and pass timings as last read back.
+#[derive(Default)]
+struct Readback {
+    stats: Option<PipelineStats>,
+    timings: Vec<(String, f64)>,
+    read_at: Option<Instant>,
+}
+
+pub struct Overlay {
+    pub visible: bool,
+    context: egui::Context,
+    state: egui_winit::State,
+    painter: egui_wgpu::Renderer,
+    frame: Option<OverlayFrame>,
+    readback: Readback,
+}
+
+impl Overlay {
+    /// Creates the overlay for `window`, painting with `device`, the active backend's.
+    pub fn new(window: &WinitWindow, device: &wgpu::Device, visible: bool) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            Some(device.limits().max_texture_dimension_2d as usize),
+        );
+        let painter = egui_wgpu::Renderer::new(device, SURFACE_FORMAT, None, 1, false);
+        Self {
+            visible,
+            context,
+            state,
+            painter,
+            frame: None,
+            readback: Readback::default(),
+        }
+    }
+
+    /// Passes `event` on to the UI while it is shown. Returns whether the UI used it, in which
+    /// case it shouldn't also move the camera or pick.
+    pub fn on_window_event(&mut self, window: &WinitWindow, event: &WindowEvent) -> bool {
+        self.visible && self.state.on_window_event(window, event).consumed
+    }
+
+    /// Lays out the UI for the next frame, applying whatever was changed in the last one to
+    /// `scene` and `backend`.
+    pub fn run(
+        &mut self,
+        window: &WinitWindow,
+        scene: &mut Scene,
+        backend: &mut RenderBackend,
+        collector: &PerformanceCollector,
+    ) {
+        if let RenderBackend::CustomPipeline { renderer } = backend {
+            if self
+                .readback
+                .read_at
+                .is_none_or(|read_at| read_at.elapsed() >= READBACK_INTERVAL)
+            {
+                self.readback = Readback {
+                    stats: Some(renderer.read_stats()),
+                    timings: renderer.read_timings(),
+                    read_at: Some(Instant::now()),
+                };
+            }
+        }
+
+        let input = self.state.take_egui_input(window);
+        let readback = &self.readback;
+        let output = self.context.run(input, |context| {
+            egui::Window::new("Debug")
+                .default_width(300.0)
+                .show(context, |ui| {
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        performance_ui(ui, backend, collector, readback);
+                        scene_ui(ui, scene, backend);
+                        camera_ui(ui, scene);
+                        effect_ui(ui, scene);
+                        lights_ui(ui, scene);
+                        debug_view_ui(ui, scene, backend);
+                    });
+                });
+        });
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        self.frame = Some(OverlayFrame {
+            primitives: self
+                .context
+                .tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        });
+    }
+
+    /// Paints the UI laid out by [`Self::run`] over `view`, a [`SURFACE_FORMAT`] texture of
+    /// `width` by `height` pixels. Does nothing if the UI hasn't been laid out since.
+    pub fn paint(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let Some(frame) = self.frame.take() else {
+            return;
+        };
+        for (id, delta) in &frame.textures_delta.set {
+            self.painter.update_texture(device, queue, *id, delta);
+        }
+
+        let screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [width, height],
+            pixels_per_point: frame.pixels_per_point,
+        };
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Overlay Encoder"),
+        });
+        let commands =
+            self.painter
+                .update_buffers(device, queue, &mut encoder, &frame.primitives, &screen);
+        {
+            let mut pass = encoder
+                .begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Overlay Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                })
+                .forget_lifetime();
+            self.painter.render(&mut pass, &frame.primitives, &screen);
+        }
+        queue.submit(commands.into_iter().chain([encoder.finish()]));
+
+        for id in &frame.textures_delta.free {
+            self.painter.free_texture(id);
+        }
+    }
+}
+
+fn performance_ui(
+    ui: &mut egui::Ui,
+    backend: &RenderBackend,
+    collector: &PerformanceCollector,
+    readback: &Readback,
+) {
+    egui::CollapsingHeader::new("Performance")
+        .default_open(true)
+        .show(ui, |ui| {
+            let frame_times = collector.recent_frame_times(GRAPH_FRAMES);
+            if frame_times.is_empty() {
+                ui.label("Measuring once the scene has settled");
+            } else {
+                let average = frame_times.iter().sum::<f64>() / frame_times.len() as f64;
+                ui.label(format!(
+                    "{:.1} FPS, {:.2} ms per frame",
+                    1.0 / average,
+                    average * 1000.0
+                ));
+                frame_time_graph(ui, frame_times);
+            }
+
+            if matches!(backend, RenderBackend::WgpuPipeline { .. }) {
+                ui.label("Pass timings and counters are only read from the custom pipeline");
+                return;
+            }
+            if readback.timings.is_empty() {
+                ui.label("This adapter has no timestamp queries to time passes with");
+            } else {
+                egui::Grid::new("pass_timings").show(ui, |ui| {
+                    for (label, milliseconds) in &readback.timings {
+                        ui.label(label);
+                        ui.label(format!("{:.3} ms", milliseconds));
+                        ui.end_row();
+                    }
+                });
+            }
+            if let Some(stats) = &readback.stats {
+                egui::Grid::new("pipeline_stats").show(ui, |ui| {
+                    let rows = [
+                        ("Vertex invocations", stats.vertex_invocations),
+                        ("Binned tile pairs", stats.binned_tile_pairs),
+                        ("Rejected tile pairs", stats.rejected_tile_pairs),
+                        ("Rasterised samples", stats.rasterised_samples),
+                        ("Transparent fragments", stats.transparent_fragments),
+                    ];
+                    for (label, value) in rows {
+                        ui.label(label);
+                        ui.label(value.to_string());
+                        ui.end_row();
+                    }
+                });
+            }
+        });
+}
+
+/// Draws `frame_times`, in seconds, as a line over the graph's height, which is the slowest
+/// of them.
+fn frame_time_graph(ui: &mut egui::Ui, frame_times: &[f64]) {
+    let (rect, _) =
+        ui.allocate_exact_size(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(96));
+
+    let slowest = frame_times.iter().copied().fold(f64::EPSILON, f64::max);
+    let step = rect.width() / (GRAPH_FRAMES - 1) as f32;
+    let points = frame_times
+        .iter()
+        .enumerate()
+        .map(|(i, &time)| {
+            egui::pos2(
+                rect.left() + i as f32 * step,
+                rect.bottom() - (time / slowest) as f32 * rect.height(),
+            )
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        points,
+        Stroke::new(1.0, Color32::LIGHT_GREEN),
+    ));
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        egui::Align2::LEFT_TOP,
+        format!("{:.2} ms", slowest * 1000.0),
+        egui::FontId::monospace(10.0),
+        Color32::WHITE,
+    );
+}
+
+fn scene_ui(ui: &mut egui::Ui, scene: &Scene, backend: &RenderBackend) {
+    let (backend_name, width, height) = match backend {
+        RenderBackend::WgpuPipeline { renderer } => {
+            ("WGPU", renderer.config.width, renderer.config.height)
+        }
+        RenderBackend::CustomPipeline { renderer } => (
+            "Custom",
+            renderer.surface_config.width,
+            renderer.surface_config.height,
+        ),
+    };
+    let triangles: usize = scene
+        .models
+        .iter()
+        .map(|model| model.processed_indices.len() / 3)
+        .sum();
+    let vertices: usize = scene
+        .models
+        .iter()
+        .map(|model| model.processed_vertices_custom.len())
+        .sum();
+
+    egui::CollapsingHeader::new("Scene").show(ui, |ui| {
+        egui::Grid::new("scene_stats").show(ui, |ui| {
+            let rows = [
+                ("Backend", backend_name.to_string()),
+                ("Resolution", format!("{}x{}", width, height)),
+                ("Models", scene.models.len().to_string()),
+                ("Triangles", triangles.to_string()),
+                ("Vertices", vertices.to_string()),
+                ("Lights", scene.lights.len().to_string()),
+            ];
+            for (label, value) in rows {
+                ui.label(label);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+    });
+}
+
+fn camera_ui(ui: &mut egui::Ui, scene: &mut Scene) {
+    let Some(camera) = scene.get_active_camera_mut() else {
+        return;
+    };
+    egui::CollapsingHeader::new("Camera").show(ui, |ui| {
+        ui.add(
+            egui::Slider::new(&mut camera.movement_speed, 0.1..=100.0)
+                .logarithmic(true)
+                .text("Movement speed"),
+        );
+        ui.add(egui::Slider::new(&mut camera.orbit_speed, 0.0..=5.0).text("Orbit speed"));
+        ui.add(
+            egui::Slider::new(&mut camera.mouse_sensitivity, 0.01..=1.0)
+                .logarithmic(true)
+                .text("Mouse sensitivity"),
+        );
+    });
+}
+
+fn effect_ui(ui: &mut egui::Ui, scene: &mut Scene) {
+    let Some(effect) = &mut scene.effect else {
+        return;
+    };
+    egui::CollapsingHeader::new("Effect").show(ui, |ui| match effect {
+        Effect::Wave(wave) => {
+            ui.add(egui::Slider::new(&mut wave.amplitude, 0.0..=1.0).text("Amplitude"));
+            ui.add(egui::Slider::new(&mut wave.frequency, 0.0..=50.0).text("Frequency"));
+            ui.add(egui::Slider::new(&mut wave.speed, 0.0..=10.0).text("Speed"));
+            egui::ComboBox::from_label("Direction")
+                .selected_text(format!("{:?}", wave.direction))
+                .show_ui(ui, |ui| {
+                    for direction in [
+                        WaveDirection::Vertical,
+                        WaveDirection::Horizontal,
+                        WaveDirection::Radial,
+                    ] {
+                        let text = format!("{:?}", direction);
+                        ui.selectable_value(&mut wave.direction, direction, text);
+                    }
+                });
+        }
+        Effect::EdgeMelt(edge_melt) => {
+            ui.add(egui::Slider::new(&mut edge_melt.amplitude, 0.0..=0.33).text("Amplitude"));
+            ui.add(egui::Slider::new(&mut edge_melt.speed, 0.0..=10.0).text("Speed"));
+        }
+        Effect::Voxelize(voxelize) => {
+            ui.add(egui::Slider::new(&mut voxelize.speed, 0.0..=10.0).text("Speed"));
+        }
+        Effect::Mirage(mirage) => {
+            ui.add(egui::Slider::new(&mut mirage.amplitude, 0.0..=1.0).text("Amplitude"));
+            ui.add(egui::Slider::new(&mut mirage.frequency, 0.0..=50.0).text("Frequency"));
+            ui.add(egui::Slider::new(&mut mirage.speed, 0.0..=10.0).text("Speed"));
+        }
+    });
+}
+
+fn lights_ui(ui: &mut egui::Ui, scene: &mut Scene) {
+    if scene.lights.is_empty() {
+        return;
+    }
+    // Positions range over the scene's bounds and as far again around them.
+    let [min, max] = scene.bounds;
+    let margin = (max - min).max_element().max(1.0);
+    egui::CollapsingHeader::new("Lights").show(ui, |ui| {
+        for (index, light) in scene.lights.iter_mut().enumerate() {
+            let kind = match light.light_type {
+                LIGHT_POINT => "point",
+                LIGHT_DIRECTIONAL => "directional",
+                LIGHT_SPOT => "spot",
+                _ => "unknown",
+            };
+            egui::CollapsingHeader::new(format!("Light {} ({})", index, kind))
+                .id_salt(("light", index))
+                .show(ui, |ui| {
+                    if light.light_type != LIGHT_DIRECTIONAL {
+                        for (axis, label) in ["X", "Y", "Z"].into_iter().enumerate() {
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut light.world_position[axis],
+                                    min[axis] - margin..=max[axis] + margin,
+                                )
+                                .text(label),
+                            );
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_rgb(&mut light.color);
+                        ui.label("Colour");
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut light.intensity, 0.0..=100.0)
+                            .logarithmic(true)
+                            .text("Intensity"),
+                    );
+                });
+        }
+    });
+}
+
+fn debug_view_ui(ui: &mut egui::Ui, scene: &Scene, backend: &mut RenderBackend) {
+    let RenderBackend::CustomPipeline { renderer } = backend else {
+        return;
+    };
+    egui::CollapsingHeader::new("Debug views").show(ui, |ui| {
+        let mut debug_view = renderer.pipeline_config.debug_view;
+        egui::ComboBox::from_label("View")
+            .selected_text(debug_view.to_string())
+            .show_ui(ui, |ui| {
+                for view in DebugView::ALL {
+                    ui.selectable_value(&mut debug_view, view, view.to_string());
+                }
+            });
+        if debug_view != renderer.pipeline_config.debug_view {
+            renderer.set_debug_view(debug_view, scene);
+        }
+    });
+    egui::CollapsingHeader::new("Post passes").show(ui, |ui| {
+        for effect in PostEffect::ALL {
+            let mut enabled = renderer.post_chain.enabled.contains(effect);
+            if ui.checkbox(&mut enabled, effect.to_string()).changed() {
+                renderer.post_chain.toggle(effect);
+            }
+        }
+    });
+}
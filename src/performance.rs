@@ -1,5 +1,7 @@
 use std::time::{Duration, Instant};
 use sysinfo::{get_current_pid, System};
+
+use crate::custom_pipeline::util::PipelineStats;
 // Define structures to hold performance metrics for benchmarking the rendering process.
 pub struct PerformanceData {
     pub avg_fps: f64,
@@ -26,6 +28,8 @@ pub struct PerformanceCollector {
     scene_index: usize,
     has_started: bool,
     has_printed: bool,
+    pipeline_stats: Option<PipelineStats>,
+    pass_timings: Vec<(String, f64)>,
 }
 
 // PerformanceCollector gathers runtime performance metrics over a set duration for a given scene, enabling analysis of rendering performance.
@@ -45,6 +49,8 @@ impl PerformanceCollector {
             has_started: false,
             has_printed: false,
             set_in_period: 2.0,
+            pipeline_stats: None,
+            pass_timings: Vec::new(),
         }
     }
 
@@ -87,6 +93,22 @@ impl PerformanceCollector {
                 .saturating_add(Duration::from_secs_f32(self.set_in_period))
     }
 
+    /// The last `count` frame times measured, in seconds, oldest first. Frames rendered
+    /// while the scene settles aren't measured.
+    pub fn recent_frame_times(&self, count: usize) -> &[f64] {
+        &self.frame_times[self.frame_times.len().saturating_sub(count)..]
+    }
+
+    /// Custom pipeline counters from the last rendered frame, printed alongside the results.
+    pub fn set_pipeline_stats(&mut self, stats: PipelineStats) {
+        self.pipeline_stats = Some(stats);
+    }
+
+    /// GPU time of each timed pass of the last rendered frame, in milliseconds.
+    pub fn set_pass_timings(&mut self, timings: Vec<(String, f64)>) {
+        self.pass_timings = timings;
+    }
+
     pub fn finalise(&mut self) -> PerformanceData {
         if self.has_printed {
             return self.calculate_metrics();
@@ -187,6 +209,30 @@ impl PerformanceCollector {
             "Average Memory Usage: {:.2} MB",
             data.memory_usage as f64 / (1024.0 * 1024.0)
         );
+        if let Some(stats) = &self.pipeline_stats {
+            println!(
+                "Vertex Shader Invocations (last frame): {}",
+                stats.vertex_invocations
+            );
+            let considered = stats.binned_tile_pairs + stats.rejected_tile_pairs;
+            println!(
+                "Binned Tile/Triangle Pairs (last frame): {} ({} rejected by edge test, {:.1}%)",
+                stats.binned_tile_pairs,
+                stats.rejected_tile_pairs,
+                100.0 * stats.rejected_tile_pairs as f64 / considered.max(1) as f64
+            );
+            println!(
+                "Rasterised Samples (last frame): {}",
+                stats.rasterised_samples
+            );
+            println!(
+                "Transparent Fragments (last frame): {}",
+                stats.transparent_fragments
+            );
+        }
+        for (label, millis) in &self.pass_timings {
+            println!("GPU Time, {} (last frame): {:.3} ms", label, millis);
+        }
         println!("----------------------------------------");
     }
 }
@@ -0,0 +1,225 @@
+//! Scenes generated in code rather than loaded from an OBJ file. They are selected with a
+//! model path of `procedural:<name>` and produce the same meshes and materials tobj would,
+//! so they go through the regular model loading.
+
+use glam::Vec3;
+
+use crate::scene::LightConfig;
+
+pub const PREFIX: &str = "procedural:";
+
+/// Returns the meshes and materials of the named scene, or None if there is no such scene.
+pub fn generate(name: &str) -> Option<(Vec<tobj::Model>, Vec<tobj::Material>)> {
+    match name {
+        "glass_panes" => Some(glass_panes()),
+        "light_field" => Some(light_field()),
+        _ => None,
+    }
+}
+
+/// Rows of tinted, partly transparent glass panes standing on an opaque floor in front of
+/// an opaque wall. Looking down -Z from around (0, 1.5, 2), most pixels are covered by
+/// several panes, which stresses transparency sorting in both backends.
+fn glass_panes() -> (Vec<tobj::Model>, Vec<tobj::Material>) {
+    const ROWS: usize = 6;
+    const COLUMNS: usize = 7;
+    const TINTS: [[f32; 3]; 4] = [
+        [0.6, 0.8, 1.0],
+        [1.0, 0.7, 0.6],
+        [0.7, 1.0, 0.7],
+        [1.0, 1.0, 0.6],
+    ];
+
+    let mut materials = vec![tobj::Material {
+        name: "stone".to_string(),
+        diffuse: Some([0.6, 0.6, 0.6]),
+        dissolve: Some(1.0),
+        ..Default::default()
+    }];
+    materials.extend(TINTS.iter().enumerate().map(|(i, &tint)| tobj::Material {
+        name: format!("glass_{}", i),
+        diffuse: Some(tint),
+        dissolve: Some(0.4),
+        ..Default::default()
+    }));
+
+    let mut stone = mesh(0);
+    let far = -2.0 * (ROWS as f32 + 1.0);
+    let floor_centre = Vec3::new(0.0, 0.0, (far + 2.0) / 2.0);
+    add_quad(
+        &mut stone,
+        floor_centre,
+        Vec3::X * 12.0,
+        Vec3::NEG_Z * (2.0 - far) / 2.0,
+    );
+    add_quad(
+        &mut stone,
+        Vec3::new(0.0, 3.0, far),
+        Vec3::X * 12.0,
+        Vec3::Y * 3.0,
+    );
+
+    let mut glass: Vec<tobj::Mesh> = (1..materials.len()).map(mesh).collect();
+    for row in 0..ROWS {
+        for column in 0..COLUMNS {
+            // Alternate rows are offset by half a pane and every pane is turned a little,
+            // so panes overlap at different depths and catch the light differently.
+            let offset = if row % 2 == 0 { 0.0 } else { 1.0 };
+            let centre = Vec3::new(
+                (column as f32 - COLUMNS as f32 / 2.0) * 2.0 + offset,
+                1.5,
+                -2.0 * row as f32,
+            );
+            let angle = ((row * COLUMNS + column) % 5) as f32 * 0.15 - 0.3;
+            let right = Vec3::new(angle.cos(), 0.0, -angle.sin()) * 0.8;
+            let up = Vec3::Y * 1.2;
+
+            // Both sides of a pane are visible, and back faces are culled.
+            let pane = &mut glass[(row + column) % TINTS.len()];
+            add_quad(pane, centre, right, up);
+            add_quad(pane, centre, -right, up);
+        }
+    }
+
+    let models = std::iter::once(stone)
+        .chain(glass)
+        .enumerate()
+        .map(|(i, mesh)| tobj::Model::new(mesh, format!("glass_panes_{}", i)))
+        .collect();
+    (models, materials)
+}
+
+/// Half the width of the light field's floor.
+pub const LIGHT_FIELD_EXTENT: f32 = 20.0;
+
+/// A wide floor with a grid of pillars of a few colours on it, for many small lights to
+/// move between. Seen from around (0, 6, 22) looking down -Z.
+fn light_field() -> (Vec<tobj::Model>, Vec<tobj::Material>) {
+    const PILLARS: usize = 8;
+    const COLORS: [[f32; 3]; 3] = [[0.8, 0.8, 0.8], [0.8, 0.6, 0.5], [0.5, 0.6, 0.8]];
+
+    let materials: Vec<tobj::Material> = COLORS
+        .iter()
+        .enumerate()
+        .map(|(i, &diffuse)| tobj::Material {
+            name: format!("field_{}", i),
+            diffuse: Some(diffuse),
+            dissolve: Some(1.0),
+            ..Default::default()
+        })
+        .collect();
+
+    let mut meshes: Vec<tobj::Mesh> = (0..materials.len()).map(mesh).collect();
+    add_quad(
+        &mut meshes[0],
+        Vec3::ZERO,
+        Vec3::X * LIGHT_FIELD_EXTENT,
+        Vec3::NEG_Z * LIGHT_FIELD_EXTENT,
+    );
+    let spacing = 2.0 * LIGHT_FIELD_EXTENT / PILLARS as f32;
+    for row in 0..PILLARS {
+        for column in 0..PILLARS {
+            let centre = Vec3::new(
+                (column as f32 + 0.5) * spacing - LIGHT_FIELD_EXTENT,
+                1.5,
+                (row as f32 + 0.5) * spacing - LIGHT_FIELD_EXTENT,
+            );
+            add_box(
+                &mut meshes[(row + column) % COLORS.len()],
+                centre,
+                Vec3::new(0.5, 1.5, 0.5),
+            );
+        }
+    }
+
+    let models = meshes
+        .into_iter()
+        .enumerate()
+        .map(|(i, mesh)| tobj::Model::new(mesh, format!("light_field_{}", i)))
+        .collect();
+    (models, materials)
+}
+
+/// `count` small coloured point lights circling over the light field, each around its own
+/// centre at its own speed. They are spread over the floor in a sunflower pattern, with
+/// their ranges set so each lights a few pillars.
+pub fn light_field_lights(count: usize) -> Vec<LightConfig> {
+    const GOLDEN_ANGLE: f32 = 2.399_963;
+    (0..count)
+        .map(|i| {
+            let t = (i as f32 + 0.5) / count as f32;
+            let angle = i as f32 * GOLDEN_ANGLE;
+            let radius = t.sqrt() * LIGHT_FIELD_EXTENT * 0.9;
+            let centre = [radius * angle.cos(), 0.0, radius * angle.sin()];
+            let orbit = 1.0 + (i % 3) as f32;
+            let position = [centre[0] + orbit, 0.5 + (i % 4) as f32 * 0.5, centre[2]];
+            // Hues around the colour wheel, as the light's index steps by the golden angle.
+            let hue = angle / std::f32::consts::TAU;
+            let color = [0.0, 1.0, 2.0].map(|offset: f32| {
+                0.5 + 0.5 * (std::f32::consts::TAU * (hue + offset / 3.0)).cos()
+            });
+            let speed = if i % 2 == 0 { 0.5 } else { -0.7 };
+            LightConfig::point(position, color, 2.0)
+                .with_range(4.0)
+                .with_orbit(centre, speed)
+        })
+        .collect()
+}
+
+fn mesh(material_id: usize) -> tobj::Mesh {
+    tobj::Mesh {
+        material_id: Some(material_id),
+        ..Default::default()
+    }
+}
+
+/// Adds a rectangle centred on `centre` with half extents `right` and `up`. It faces along
+/// `right x up`, and is wound counter-clockwise when seen from that side.
+fn add_quad(mesh: &mut tobj::Mesh, centre: Vec3, right: Vec3, up: Vec3) {
+    let normal = right.cross(up).normalize();
+    let first = (mesh.positions.len() / 3) as u32;
+    for (u, v) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+        let position = centre + right * u + up * v;
+        mesh.positions.extend_from_slice(&position.to_array());
+        mesh.normals.extend_from_slice(&normal.to_array());
+        mesh.texcoords
+            .extend_from_slice(&[(u + 1.0) / 2.0, (v + 1.0) / 2.0]);
+    }
+    mesh.indices
+        .extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+}
+
+/// Adds the five visible faces of a box centred on `centre` with half extents `size`. Its
+/// bottom is left out, as it stands on the floor.
+fn add_box(mesh: &mut tobj::Mesh, centre: Vec3, size: Vec3) {
+    add_quad(
+        mesh,
+        centre + Vec3::Y * size.y,
+        Vec3::X * size.x,
+        Vec3::NEG_Z * size.z,
+    );
+    add_quad(
+        mesh,
+        centre + Vec3::Z * size.z,
+        Vec3::X * size.x,
+        Vec3::Y * size.y,
+    );
+    add_quad(
+        mesh,
+        centre - Vec3::Z * size.z,
+        Vec3::NEG_X * size.x,
+        Vec3::Y * size.y,
+    );
+    add_quad(
+        mesh,
+        centre + Vec3::X * size.x,
+        Vec3::NEG_Z * size.z,
+        Vec3::Y * size.y,
+    );
+    add_quad(
+        mesh,
+        centre - Vec3::X * size.x,
+        Vec3::Z * size.z,
+        Vec3::Y * size.y,
+    );
+}
@@ -0,0 +1,644 @@
+//! Recordings of everything a frame is rendered from, in a single file: the screen size,
+//! camera and effect uniforms, lights, vertices, indices, materials and textures. A
+//! recording replays headlessly through either backend, or through a CPU reference
+//! rasteriser, without the model files of the scene it was taken from.
+
+use std::{fmt, io, path::Path, str::FromStr, time::Duration};
+
+use crate::{
+    camera::CameraUniform,
+    custom_pipeline::{
+        config::PipelineConfig,
+        present_pass::SURFACE_FORMAT,
+        renderer::CustomRenderer,
+        util::{Index, MaterialInfo},
+    },
+    effect::EffectUniform,
+    model::Model,
+    scene::{Light, Scene, NO_SHADOW_VIEW},
+    vertex::{CustomVertex, WgpuVertex},
+    wgpu_pipeline::renderer::WgpuRenderer,
+};
+
+/// First bytes of a frame recording.
+const MAGIC: [u8; 8] = *b"CRFRAME\0";
+/// Bumped whenever the layout of the recording, or of any struct in it, changes.
+const VERSION: u32 = 3;
+
+/// Follows the magic bytes, giving the size of each array after the uniforms.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrameHeader {
+    version: u32,
+    width: u32,
+    height: u32,
+    light_count: u32,
+    vertex_count: u32,
+    index_count: u32,
+    material_count: u32,
+    texel_count: u32,
+}
+
+/// The inputs of one frame. On disk, the magic bytes and a [`FrameHeader`] are followed by
+/// the camera and effect uniforms and then the lights, vertices, indices, materials and
+/// texels, each laid out as the GPU reads it.
+pub struct FrameRecording {
+    pub width: u32,
+    pub height: u32,
+    /// The camera as both backends see it, without TAA's jitter.
+    pub camera: CameraUniform,
+    pub effect: EffectUniform,
+    /// The lights, with their view space positions for the camera.
+    pub lights: Vec<Light>,
+    /// Every model's vertices one after another, with their materials numbered across them.
+    pub vertices: Vec<CustomVertex>,
+    pub indices: Vec<u32>,
+    pub materials: Vec<MaterialInfo>,
+    /// Every model's texels one after another, with their materials' textures moved to match.
+    pub textures: Vec<u32>,
+}
+
+impl FrameRecording {
+    /// Records the scene as it is rendered at the given size, by either backend.
+    pub fn record(scene: &Scene, width: u32, height: u32) -> Self {
+        let mut camera = CameraUniform::default();
+        let mut lights = scene.lights.clone();
+        if let Some(active_camera) = scene.get_active_camera() {
+            camera.update_view_proj(active_camera);
+            let view_matrix = active_camera.build_view_matrix();
+            for light in &mut lights {
+                let world_pos = glam::Vec3::from(light.world_position);
+                light.view_position = view_matrix.transform_point3(world_pos).to_array();
+            }
+        }
+        let mut effect = EffectUniform::default();
+        if let Some(scene_effect) = &scene.effect {
+            effect.update(scene_effect, scene.time);
+        }
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut materials = Vec::new();
+        let mut textures = Vec::new();
+        for model in &scene.models {
+            let first_vertex = vertices.len() as u32;
+            let first_material = materials.len() as u32;
+            let first_texel = textures.len() as u32;
+            vertices.extend(
+                model
+                    .processed_vertices_custom
+                    .iter()
+                    .copied()
+                    .chain(model.processed_vertices_wgpu.iter().map(CustomVertex::from))
+                    .map(|vertex| CustomVertex {
+                        material: vertex.material + first_material,
+                        ..vertex
+                    }),
+            );
+            indices.extend(
+                model
+                    .processed_indices
+                    .iter()
+                    .map(|index| index.0 + first_vertex),
+            );
+            materials.extend(
+                model
+                    .materials
+                    .iter()
+                    .map(|material| material.with_first_texel(first_texel)),
+            );
+            textures.extend_from_slice(&model.textures);
+        }
+
+        Self {
+            width,
+            height,
+            camera,
+            effect,
+            lights,
+            vertices,
+            indices,
+            materials,
+            textures,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let header = FrameHeader {
+            version: VERSION,
+            width: self.width,
+            height: self.height,
+            light_count: self.lights.len() as u32,
+            vertex_count: self.vertices.len() as u32,
+            index_count: self.indices.len() as u32,
+            material_count: self.materials.len() as u32,
+            texel_count: self.textures.len() as u32,
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(bytemuck::bytes_of(&header));
+        bytes.extend_from_slice(bytemuck::bytes_of(&self.camera));
+        bytes.extend_from_slice(bytemuck::bytes_of(&self.effect));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.lights));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.vertices));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.indices));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.materials));
+        bytes.extend_from_slice(bytemuck::cast_slice(&self.textures));
+        std::fs::write(path, bytes)
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let mut reader = Reader { bytes: &bytes };
+        if reader.take::<u8>(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a frame recording"));
+        }
+        let [header] = reader.take::<FrameHeader>(1)?[..] else {
+            unreachable!()
+        };
+        if header.version != VERSION {
+            return Err(invalid_data(format!(
+                "recorded with version {} of the format, expected {}",
+                header.version, VERSION
+            )));
+        }
+
+        let recording = Self {
+            width: header.width,
+            height: header.height,
+            camera: reader.take(1)?[0],
+            effect: reader.take(1)?[0],
+            lights: reader.take(header.light_count as usize)?,
+            vertices: reader.take(header.vertex_count as usize)?,
+            indices: reader.take(header.index_count as usize)?,
+            materials: reader.take(header.material_count as usize)?,
+            textures: reader.take(header.texel_count as usize)?,
+        };
+        if !reader.bytes.is_empty() {
+            return Err(invalid_data("unexpected data after the textures"));
+        }
+        if recording.width == 0 || recording.height == 0 {
+            return Err(invalid_data("the frame is empty"));
+        }
+        if !recording.indices.len().is_multiple_of(3)
+            || recording
+                .indices
+                .iter()
+                .any(|&index| index as usize >= recording.vertices.len())
+        {
+            return Err(invalid_data(
+                "the indices don't form triangles of the vertices",
+            ));
+        }
+        if recording.materials.iter().any(|material| {
+            let texture = material.texture_info;
+            !texture.is_none()
+                && texture.offset as u64 + texture.width as u64 * texture.height as u64
+                    > recording.textures.len() as u64
+        }) {
+            return Err(invalid_data("a material's texture is past the texels"));
+        }
+        Ok(recording)
+    }
+
+    /// A scene of the recorded geometry and lights, without a camera. Lights cast shadows
+    /// when `pipeline_config` enables them, whether or not they did when recorded.
+    pub fn to_scene(&self, pipeline_config: &PipelineConfig) -> Scene {
+        let mut scene = Scene::new();
+        scene.add_model(Model {
+            processed_vertices_custom: self.vertices.clone(),
+            processed_vertices_wgpu: self.vertices.iter().map(WgpuVertex::from).collect(),
+            processed_indices: self.indices.iter().map(|&index| Index(index)).collect(),
+            materials: self.materials.clone(),
+            meshes: vec![],
+            textures: self.textures.clone(),
+        });
+        scene.lights = self.lights.clone();
+        if pipeline_config.shadows {
+            scene.cast_shadows();
+        } else {
+            for light in &mut scene.lights {
+                light.shadow_view = NO_SHADOW_VIEW;
+            }
+        }
+        scene
+    }
+
+    /// Renders the frame with `backend`, returning its pixels as rows of RGBA8, or why the
+    /// backend can't render it.
+    pub async fn replay(
+        &self,
+        backend: ReplayBackend,
+        pipeline_config: &PipelineConfig,
+    ) -> Result<Vec<u8>, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let mut scene = self.to_scene(pipeline_config);
+        match backend {
+            ReplayBackend::Custom => {
+                let mut renderer = CustomRenderer::new(
+                    &instance,
+                    None,
+                    self.width,
+                    self.height,
+                    &scene,
+                    *pipeline_config,
+                )
+                .await;
+                // Without a camera the scene writes its lights as recorded, and leaves the
+                // camera and effect to these.
+                scene.update_buffers(&mut renderer, Duration::ZERO);
+                let buffers = &renderer.buffers;
+                renderer.queue.write_buffer(
+                    &buffers.camera_buffer,
+                    0,
+                    bytemuck::bytes_of(&self.camera),
+                );
+                renderer.queue.write_buffer(
+                    &buffers.effect_buffer,
+                    0,
+                    bytemuck::bytes_of(&self.effect),
+                );
+
+                let target = create_target(&renderer.device, self.width, self.height);
+                renderer.render_to(&target.create_view(&Default::default()), &scene);
+                Ok(read_target(&renderer.device, &renderer.queue, &target))
+            }
+            ReplayBackend::Wgpu => {
+                let mut renderer = WgpuRenderer::new(
+                    &instance,
+                    None,
+                    self.width,
+                    self.height,
+                    &scene,
+                    pipeline_config,
+                )
+                .await?;
+                renderer.queue.write_buffer(
+                    &renderer.camera_buffer,
+                    0,
+                    bytemuck::bytes_of(&self.camera),
+                );
+                let eye = glam::Vec4::from(self.camera.view_position).truncate();
+                renderer.sort_transparent_triangles(eye);
+
+                let target = create_target(&renderer.device, self.width, self.height);
+                renderer
+                    .render_to(&target.create_view(&Default::default()), &scene)
+                    .await;
+                Ok(read_target(&renderer.device, &renderer.queue, &target))
+            }
+            ReplayBackend::Reference => Ok(self.render_reference()),
+        }
+    }
+
+    /// Rasterises the frame on the CPU with the custom pipeline's coverage rules: vertices
+    /// snapped to 1/256 of a pixel, sampled at pixel centres, with edges owning the samples
+    /// on them by the top-left rule and the lower triangle winning equal depths. Each
+    /// pixel shows the perspective-correct normal of its nearest opaque triangle in the
+    /// colours of the `normals` debug view, to compare with a replay using it. Triangles
+    /// with a vertex behind the camera are left out rather than clipped, and effects are
+    /// ignored.
+    pub fn render_reference(&self) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let view_proj = glam::Mat4::from_cols_array_2d(&self.camera.view_proj);
+        let projected: Vec<Option<ReferenceVertex>> = self
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let clip = view_proj * glam::Vec3::from(vertex.position).extend(1.0);
+                (clip.w > 0.0).then(|| {
+                    let ndc = clip.truncate() / clip.w;
+                    let screen = glam::Vec2::new(
+                        (ndc.x + 1.0) * 0.5 * self.width as f32,
+                        (1.0 - ndc.y) * 0.5 * self.height as f32,
+                    );
+                    ReferenceVertex {
+                        position: (screen * SUBPIXEL_SCALE).round().as_i64vec2(),
+                        depth: ndc.z,
+                        inv_w: 1.0 / clip.w,
+                        normal: glam::Vec3::from(vertex.normal),
+                    }
+                })
+            })
+            .collect();
+
+        let mut depths = vec![f32::INFINITY; width * height];
+        let mut pixels = vec![0; width * height * 4];
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let material = self.vertices[triangle[0] as usize].material;
+            if self
+                .materials
+                .get(material as usize)
+                .is_some_and(MaterialInfo::is_transparent)
+            {
+                continue;
+            }
+            let [Some(v1), Some(v2), Some(v3)] = [0, 1, 2].map(|i| projected[triangle[i] as usize])
+            else {
+                continue;
+            };
+
+            // Edge i is opposite vertex i, so its value weighs that vertex.
+            let starts = [v2.position, v3.position, v1.position];
+            let ends = [v3.position, v1.position, v2.position];
+            let mut normals = [0, 1, 2]
+                .map(|i| glam::I64Vec2::new(starts[i].y - ends[i].y, ends[i].x - starts[i].x));
+            let edges = |normals: &[glam::I64Vec2; 3], p: glam::I64Vec2| {
+                [0, 1, 2].map(|i| normals[i].dot(p - starts[i]))
+            };
+            let area = edges(&normals, v3.position)[2];
+            if area == 0 {
+                continue;
+            }
+            if area < 0 {
+                normals = normals.map(|normal| -normal);
+            }
+            let top_left = normals.map(|normal| normal.x > 0 || (normal.x == 0 && normal.y > 0));
+
+            let positions = [v1.position, v2.position, v3.position];
+            let min = positions.iter().fold(glam::I64Vec2::MAX, |a, &b| a.min(b));
+            let max = positions.iter().fold(glam::I64Vec2::MIN, |a, &b| a.max(b));
+            let scale = SUBPIXEL_SCALE as i64;
+            let (x0, y0) = ((min.x / scale).max(0), (min.y / scale).max(0));
+            let x1 = (max.x / scale).min(width as i64 - 1);
+            let y1 = (max.y / scale).min(height as i64 - 1);
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    let centre = glam::I64Vec2::new(x, y) * scale + scale / 2;
+                    let values = edges(&normals, centre);
+                    let covered = (0..3).all(|i| values[i] > 0 || (values[i] == 0 && top_left[i]));
+                    if !covered {
+                        continue;
+                    }
+
+                    let weights = values.map(|value| value as f32 / area.abs() as f32);
+                    let depth =
+                        weights[0] * v1.depth + weights[1] * v2.depth + weights[2] * v3.depth;
+                    let index = y as usize * width + x as usize;
+                    if !(0.0..=1.0).contains(&depth) || depth >= depths[index] {
+                        continue;
+                    }
+                    depths[index] = depth;
+
+                    let perspective = [
+                        weights[0] * v1.inv_w,
+                        weights[1] * v2.inv_w,
+                        weights[2] * v3.inv_w,
+                    ];
+                    let normal = (perspective[0] * v1.normal
+                        + perspective[1] * v2.normal
+                        + perspective[2] * v3.normal)
+                        / perspective.iter().sum::<f32>();
+                    let color = normal.normalize_or_zero() * 0.5 + 0.5;
+                    let pixel = &mut pixels[index * 4..index * 4 + 3];
+                    pixel.copy_from_slice(&color.to_array().map(unorm8));
+                }
+            }
+        }
+        pixels
+    }
+}
+
+/// The custom pipeline's fixed point precision, in steps per pixel.
+const SUBPIXEL_SCALE: f32 = 256.0;
+
+#[derive(Clone, Copy)]
+struct ReferenceVertex {
+    /// Screen position in sub-pixel steps.
+    position: glam::I64Vec2,
+    depth: f32,
+    inv_w: f32,
+    normal: glam::Vec3,
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid frame recording: {}", message.into()),
+    )
+}
+
+/// Takes arrays of Pod values off the front of a recording, which needn't be aligned for them.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<T: bytemuck::Pod>(&mut self, count: usize) -> io::Result<Vec<T>> {
+        let len = count
+            .checked_mul(size_of::<T>())
+            .filter(|&len| len <= self.bytes.len())
+            .ok_or_else(|| invalid_data("the file is truncated"))?;
+        let (data, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        let mut values = vec![T::zeroed(); count];
+        bytemuck::cast_slice_mut(&mut values).copy_from_slice(data);
+        Ok(values)
+    }
+}
+
+/// What renders a replayed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayBackend {
+    Custom,
+    Wgpu,
+    /// [`FrameRecording::render_reference`].
+    Reference,
+}
+
+impl FromStr for ReplayBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "custom" => Ok(ReplayBackend::Custom),
+            "wgpu" => Ok(ReplayBackend::Wgpu),
+            "cpu" => Ok(ReplayBackend::Reference),
+            _ => Err(format!(
+                "Invalid replay backend '{}'. Use 'custom', 'wgpu' or 'cpu'.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReplayBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayBackend::Custom => write!(f, "custom"),
+            ReplayBackend::Wgpu => write!(f, "WGPU"),
+            ReplayBackend::Reference => write!(f, "CPU reference"),
+        }
+    }
+}
+
+/// An off-screen stand-in for the surface, which the replayed frame is read back from.
+pub(crate) fn create_target(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Replay Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: SURFACE_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+/// Reads the target back as rows of RGBA8, without the padding of the copy.
+fn read_target(device: &wgpu::Device, queue: &wgpu::Queue, target: &wgpu::Texture) -> Vec<u8> {
+    let size = target.size();
+    let row_size = size.width * 4;
+    let padded_row_size = row_size.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Replay Staging Buffer"),
+        size: (padded_row_size * size.height) as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    encoder.copy_texture_to_buffer(
+        target.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &staging,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_size),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+    // The surface format is BGRA.
+    let pixels = slice
+        .get_mapped_range()
+        .chunks(padded_row_size as usize)
+        .flat_map(|row| row[..row_size as usize].to_vec())
+        .collect::<Vec<u8>>()
+        .chunks_exact(4)
+        .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
+        .collect();
+    pixels
+}
+
+/// Writes rows of RGBA8 pixels as a binary PPM image, dropping alpha.
+pub fn write_ppm(path: &Path, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let mut bytes = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    bytes.extend(
+        pixels
+            .chunks_exact(4)
+            .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]]),
+    );
+    std::fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_pipeline::config::DebugView;
+    use crate::custom_pipeline::test_util::{split_quad_scene, test_device};
+
+    const SIZE: u32 = 32;
+
+    /// The split quad scene's triangles and light, with the identity as the camera.
+    fn split_quad_recording() -> FrameRecording {
+        let scene = split_quad_scene(SIZE);
+        let model = &scene.models[0];
+        FrameRecording {
+            width: SIZE,
+            height: SIZE,
+            camera: CameraUniform::default(),
+            effect: EffectUniform::default(),
+            lights: scene.lights.clone(),
+            vertices: model.processed_vertices_custom.clone(),
+            indices: model
+                .processed_indices
+                .iter()
+                .map(|index| index.0)
+                .collect(),
+            materials: model.materials.clone(),
+            textures: vec![],
+        }
+    }
+
+    #[test]
+    fn recordings_survive_a_round_trip_but_not_truncation() {
+        let mut recording = split_quad_recording();
+        recording.textures = vec![0xff20_4080];
+        let path = std::env::temp_dir().join("compute-renderer-round-trip.frame");
+        recording.write(&path).unwrap();
+        let read = FrameRecording::read(&path).unwrap();
+        assert_eq!((read.width, read.height), (SIZE, SIZE));
+        assert_eq!(read.camera.view_proj, recording.camera.view_proj);
+        assert_eq!(read.lights.len(), 1);
+        assert_eq!(read.indices, recording.indices);
+        let positions = |recording: &FrameRecording| {
+            recording
+                .vertices
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&read), positions(&recording));
+        assert_eq!(read.materials.len(), 1);
+        assert_eq!(read.textures, recording.textures);
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let error = FrameRecording::read(&path).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn custom_replay_of_normals_matches_the_reference() {
+        if test_device().is_none() {
+            eprintln!("No adapter supports the custom pipeline, skipping replay test");
+            return;
+        }
+        let recording = split_quad_recording();
+        let reference = recording.render_reference();
+        // The edges pass through pixel centres, so only the top-left rule decides them.
+        let covered = reference
+            .chunks_exact(4)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .count();
+        assert_eq!(covered, 27 * 27);
+
+        let config = PipelineConfig {
+            debug_view: DebugView::Normals,
+            ..Default::default()
+        };
+        let replayed =
+            pollster::block_on(recording.replay(ReplayBackend::Custom, &config)).unwrap();
+        assert_eq!(replayed.len(), reference.len());
+        for (i, (gpu, cpu)) in replayed
+            .chunks_exact(4)
+            .zip(reference.chunks_exact(4))
+            .enumerate()
+        {
+            let close = gpu.iter().zip(cpu).all(|(&a, &b)| a.abs_diff(b) <= 1);
+            assert!(
+                close,
+                "pixel {} is {:?} replayed but {:?} in the reference",
+                i, gpu, cpu
+            );
+        }
+    }
+}
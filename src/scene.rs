@@ -1,33 +1,260 @@
+use crate::camera;
 use crate::camera::{Camera, CameraMode};
+use crate::custom_pipeline::config::{PipelineConfig, ShadingModel, ToneMapping};
+use crate::custom_pipeline::post_chain::ColorLut;
 use crate::custom_pipeline::renderer::CustomRenderer;
 use crate::effect::Effect;
 use crate::model::Model;
 use crate::window::BackendType;
-use crate::camera;
+use std::ops::Range;
 use std::time::Duration;
-use std::u64;
+
+/// Values of [`Light::light_type`], matching the shaders' `LIGHT_*` constants.
+pub const LIGHT_POINT: u32 = 0;
+pub const LIGHT_DIRECTIONAL: u32 = 1;
+pub const LIGHT_SPOT: u32 = 2;
+
+/// [`Light::shadow_view`] of lights without shadow maps.
+pub const NO_SHADOW_VIEW: u32 = u32::MAX;
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Light {
     pub world_position: [f32; 3],
-    _padding1: f32,
+    pub light_type: u32,
     pub view_position: [f32; 3],
-    _padding2: f32,
+    /// Distance at which point and spot lights fade out completely, or 0 for no falloff.
+    pub range: f32,
     pub color: [f32; 3],
     pub intensity: f32,
+    /// Direction the light shines in, for directional and spot lights.
+    pub direction: [f32; 3],
+    /// Cosines of the spot cone's half-angles: full intensity inside the inner cone,
+    /// fading to none at the outer one.
+    pub cos_inner_angle: f32,
+    pub cos_outer_angle: f32,
+    /// Index of the light's first shadow map view, or [`NO_SHADOW_VIEW`].
+    pub shadow_view: u32,
+    _padding: [f32; 2],
+}
+
+impl Light {
+    /// Near and far planes of the shadow map projections, matching the camera's.
+    const SHADOW_NEAR: f32 = 0.1;
+    const SHADOW_FAR: f32 = 10000.0;
+
+    /// Shadow map views rendered for the light: a single one for directional lights, and a
+    /// cube of six for point and spot lights.
+    pub fn shadow_view_count(&self) -> usize {
+        if self.light_type == LIGHT_DIRECTIONAL {
+            1
+        } else {
+            6
+        }
+    }
+
+    /// Views the light's shadow maps are rendered from. Directional lights look along their
+    /// direction with an orthographic projection covering the scene `bounds` (minimum and
+    /// maximum corners). Point and spot lights look along +X, -X, +Y, -Y, +Z and -Z, each
+    /// with a 90 degree square frustum; the shaders pick the face by the major axis of the
+    /// direction from the light, in the same order.
+    pub fn shadow_views(&self, bounds: [glam::Vec3; 2]) -> Vec<camera::CameraUniform> {
+        let view = |eye: glam::Vec3, projection: glam::Mat4, direction: glam::Vec3, up| {
+            let view_proj = projection * glam::Mat4::look_at_rh(eye, eye + direction, up);
+            camera::CameraUniform {
+                view_position: [eye.x, eye.y, eye.z, 1.0],
+                view_proj: view_proj.to_cols_array_2d(),
+                prev_view_proj: view_proj.to_cols_array_2d(),
+                jitter: [0.0; 4],
+                inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+            }
+        };
+
+        if self.light_type == LIGHT_DIRECTIONAL {
+            let direction = glam::Vec3::from(self.direction).normalize();
+            let center = (bounds[0] + bounds[1]) * 0.5;
+            let radius = ((bounds[1] - bounds[0]) * 0.5)
+                .length()
+                .max(Self::SHADOW_NEAR);
+            let eye = center - direction * radius * 2.0;
+            let up = if direction.y.abs() > 0.99 {
+                glam::Vec3::Z
+            } else {
+                glam::Vec3::Y
+            };
+            let projection =
+                glam::Mat4::orthographic_rh(-radius, radius, -radius, radius, radius, radius * 3.0);
+            return vec![view(eye, projection, direction, up)];
+        }
+
+        let eye = glam::Vec3::from(self.world_position);
+        let projection = glam::Mat4::perspective_rh(
+            std::f32::consts::FRAC_PI_2,
+            1.0,
+            Self::SHADOW_NEAR,
+            Self::SHADOW_FAR,
+        );
+        [
+            (glam::Vec3::X, glam::Vec3::Y),
+            (glam::Vec3::NEG_X, glam::Vec3::Y),
+            (glam::Vec3::Y, glam::Vec3::Z),
+            (glam::Vec3::NEG_Y, glam::Vec3::NEG_Z),
+            (glam::Vec3::Z, glam::Vec3::Y),
+            (glam::Vec3::NEG_Z, glam::Vec3::Y),
+        ]
+        .into_iter()
+        .map(|(direction, up)| view(eye, projection, direction, up))
+        .collect()
+    }
+}
+
+impl From<&LightConfig> for Light {
+    fn from(config: &LightConfig) -> Self {
+        let mut light = Self {
+            world_position: [0.0; 3],
+            light_type: LIGHT_POINT,
+            view_position: [0.0; 3],
+            range: 0.0,
+            color: config.color,
+            intensity: config.intensity,
+            direction: [0.0, -1.0, 0.0],
+            cos_inner_angle: 1.0,
+            cos_outer_angle: 1.0,
+            shadow_view: NO_SHADOW_VIEW,
+            _padding: [0.0; 2],
+        };
+        match config.kind {
+            LightKind::Point { position, range } => {
+                light.world_position = position;
+                light.range = range.unwrap_or(0.0);
+            }
+            LightKind::Directional { direction } => {
+                light.light_type = LIGHT_DIRECTIONAL;
+                light.direction = direction;
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                light.light_type = LIGHT_SPOT;
+                light.world_position = position;
+                light.direction = direction;
+                light.range = range.unwrap_or(0.0);
+                light.cos_inner_angle = inner_angle.min(outer_angle).cos();
+                light.cos_outer_angle = outer_angle.cos();
+            }
+        }
+        light
+    }
 }
 
 impl Default for Light {
     fn default() -> Self {
+        Self::from(&LightConfig::point([5.0, 5.0, 5.0], [1.0, 1.0, 1.0], 1.0))
+    }
+}
+
+/// A light of a [`SceneConfig`].
+#[derive(Clone, Copy, Debug)]
+pub struct LightConfig {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    /// Moves the light around a circle every frame, or None to keep it where it is.
+    pub orbit: Option<LightOrbit>,
+}
+
+/// Circles a light around the vertical axis through `centre`, at `speed` radians per
+/// second. Negative speeds go the other way.
+#[derive(Clone, Copy, Debug)]
+pub struct LightOrbit {
+    pub centre: [f32; 3],
+    pub speed: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LightKind {
+    /// Shines in every direction from a position, fading out by `range` if there is one.
+    Point {
+        position: [f32; 3],
+        range: Option<f32>,
+    },
+    /// Shines in a single direction from infinitely far away, like the sun.
+    Directional { direction: [f32; 3] },
+    /// Shines from a position within a cone around `direction`. The angles are the cone's
+    /// half-angles in radians; the light fades between the inner and the outer one.
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        range: Option<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+impl LightConfig {
+    pub fn point(position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
         Self {
-            world_position: [5.0, 5.0, 5.0],
-            _padding1: 0.0,
-            view_position: [0.0, 0.0, 0.0],
-            _padding2: 0.0,
-            color: [1.0, 1.0, 1.0],
-            intensity: 1.0,
+            kind: LightKind::Point {
+                position,
+                range: None,
+            },
+            color,
+            intensity,
+            orbit: None,
+        }
+    }
+
+    pub fn directional(direction: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional { direction },
+            color,
+            intensity,
+            orbit: None,
+        }
+    }
+
+    pub fn spot(
+        position: [f32; 3],
+        direction: [f32; 3],
+        inner_angle: f32,
+        outer_angle: f32,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction,
+                range: None,
+                inner_angle,
+                outer_angle,
+            },
+            color,
+            intensity,
+            orbit: None,
+        }
+    }
+
+    /// Fades a point or spot light out smoothly by `range`. Directional lights don't fade.
+    pub fn with_range(mut self, range: f32) -> Self {
+        match &mut self.kind {
+            LightKind::Point { range: r, .. } | LightKind::Spot { range: r, .. } => {
+                *r = Some(range)
+            }
+            LightKind::Directional { .. } => {}
         }
+        self
+    }
+
+    /// Circles the light around the vertical axis through `centre` at `speed` radians per
+    /// second. Directional lights don't move.
+    pub fn with_orbit(mut self, centre: [f32; 3], speed: f32) -> Self {
+        self.orbit = Some(LightOrbit { centre, speed });
+        self
     }
 }
 
@@ -36,11 +263,18 @@ pub struct Scene {
     cameras: Vec<camera::Camera>,
     active_camera: Option<usize>,
     pub lights: Vec<Light>,
+    /// Lights moved by [`Self::animate_lights`], by index.
+    light_orbits: Vec<(usize, LightOrbit)>,
     pub effect: Option<Effect>,
     pub time: f32,
     pub total_tris: f32,
     pub gx_tris: u32,
     pub gy_tris: u32,
+    /// Minimum and maximum corners of the scene's geometry, which directional light
+    /// shadow maps cover.
+    pub bounds: [glam::Vec3; 2],
+    /// The custom pipeline's colour grading LUT, or None to leave colours as they are.
+    pub color_lut: Option<ColorLut>,
 }
 
 impl Scene {
@@ -50,11 +284,14 @@ impl Scene {
             cameras: vec![],
             active_camera: None,
             lights: vec![],
+            light_orbits: vec![],
             effect: None,
             time: 0.0,
             total_tris: 0.0,
             gx_tris: 0,
             gy_tris: 0,
+            bounds: [glam::Vec3::ZERO; 2],
+            color_lut: None,
         }
     }
 
@@ -66,13 +303,17 @@ impl Scene {
             .add_obj_with_mtl(&scene_config.model_path, scene_config.backend_type)
             .await;
 
-        for (position, color, intensity) in &scene_config.lights {
-            scene.add_light(*position, *color, *intensity);
+        for light in &scene_config.lights {
+            scene.add_light(*light);
+        }
+        if scene_config.pipeline_config.shadows {
+            scene.cast_shadows();
         }
 
         if let Some(effect) = &scene_config.effect {
             scene.effect = Some(effect.clone());
         }
+        scene.color_lut = scene_config.color_lut.clone();
 
         // Add camera and set active
         let camera = match scene_config.camera_config.mode {
@@ -94,11 +335,16 @@ impl Scene {
         scene
     }
 
-    /// Adds an OBJ model *with MTL material(s)*, loads all textures,
-    /// and sets up each sub-mesh's `texture_index` to point to the correct Material in `self.materials`.
+    /// Adds an OBJ model *with MTL material(s)*, or a glTF model with its textures, picking
+    /// the loader by the file's extension.
     pub async fn add_obj_with_mtl(&mut self, obj_path: &str, backend_type: BackendType) -> usize {
         // (A) Load geometry + textures from the .obj + .mtl
         let model = Model::new(obj_path, backend_type).await;
+        self.add_model(model)
+    }
+
+    /// Adds a model, sizing the vertex dispatch and the shadow bounds to it.
+    pub fn add_model(&mut self, model: Model) -> usize {
         let total_indices = model.processed_indices.len();
 
         // do these calculations here so that it does not need to be recalculated every frame
@@ -107,6 +353,7 @@ impl Scene {
         self.gx_tris = self.total_tris.sqrt().ceil() as u32;
         self.gy_tris = (self.total_tris / (self.gx_tris as f32)).ceil() as u32;
 
+        self.bounds = model.bounds();
         self.models.push(model);
 
         self.models.len() - 1
@@ -129,13 +376,11 @@ impl Scene {
         self.active_camera.and_then(|index| self.cameras.get(index))
     }
 
-    pub fn update_buffers(
-        &mut self,
-        renderer: &mut CustomRenderer,
-        delta_time: Duration,
-    ) {
+    pub fn update_buffers(&mut self, renderer: &mut CustomRenderer, delta_time: Duration) {
         self.time += delta_time.as_secs_f32();
 
+        // As it was the frame before, for the motion vectors of animated geometry
+        let previous_effect = self.effect.clone();
         if let Some(effect) = &mut self.effect {
             effect.update(delta_time);
         }
@@ -144,6 +389,13 @@ impl Scene {
         if let Some(camera) = self.get_active_camera() {
             let mut camera_uniform = camera::CameraUniform::default();
             camera_uniform.update_view_proj(camera);
+            // TAA jitters the camera while it is on. Debug views skip the post passes,
+            // so they see the camera as it is.
+            if !renderer.pipeline_config.shows_debug_view() {
+                renderer
+                    .post_chain
+                    .update_view(&renderer.queue, camera, &mut camera_uniform);
+            }
 
             // Transform light positions to view space using only view matrix
             let view_matrix = camera.build_view_matrix();
@@ -167,10 +419,22 @@ impl Scene {
             bytemuck::cast_slice(&self.lights),
         );
 
+        // And the views of the lights that cast shadows
+        if renderer.pipeline_config.shadow_views(self) > 0 {
+            renderer.queue.write_buffer(
+                &renderer.buffers.shadow_view_buffer,
+                0,
+                bytemuck::cast_slice(&self.shadow_views()),
+            );
+        }
+
         // Update effects only if there are any
         if let Some(effect) = &self.effect {
             let mut effect_uniform = crate::effect::EffectUniform::default();
             effect_uniform.update(effect, self.time);
+            if let Some(previous) = &previous_effect {
+                effect_uniform.set_previous(previous);
+            }
             renderer.queue.write_buffer(
                 &renderer.buffers.effect_buffer,
                 0,
@@ -187,39 +451,230 @@ impl Scene {
         }
     }
 
-    pub fn add_light(&mut self, position: [f32; 3], color: [f32; 3], intensity: f32) -> usize {
-        let light = Light {
-            world_position: position,
-            _padding1: 0.0,
-            view_position: [0.0, 0.0, 0.0],
-            _padding2: 0.0,
-            color,
-            intensity,
-        };
-        self.lights.push(light);
-        self.lights.len() - 1
+    pub fn add_light(&mut self, config: LightConfig) -> usize {
+        self.lights.push(Light::from(&config));
+        let index = self.lights.len() - 1;
+        if let Some(orbit) = config.orbit {
+            self.light_orbits.push((index, orbit));
+        }
+        index
+    }
+
+    /// Moves the orbiting lights on by `delta_time`, turning spot lights with them.
+    pub fn animate_lights(&mut self, delta_time: Duration) {
+        for (index, orbit) in &self.light_orbits {
+            let light = &mut self.lights[*index];
+            if light.light_type == LIGHT_DIRECTIONAL {
+                continue;
+            }
+            let rotation = glam::Quat::from_rotation_y(orbit.speed * delta_time.as_secs_f32());
+            let centre = glam::Vec3::from(orbit.centre);
+            let position = glam::Vec3::from(light.world_position);
+            light.world_position = (centre + rotation * (position - centre)).to_array();
+            light.direction = (rotation * glam::Vec3::from(light.direction)).to_array();
+        }
+    }
+
+    /// Gives the first [`PipelineConfig::MAX_SHADOWED_LIGHTS`] lights shadow maps, with
+    /// their views one after another in light order.
+    pub fn cast_shadows(&mut self) {
+        let mut next_view = 0;
+        for (i, light) in self.lights.iter_mut().enumerate() {
+            if i < PipelineConfig::MAX_SHADOWED_LIGHTS {
+                light.shadow_view = next_view as u32;
+                next_view += light.shadow_view_count();
+            } else {
+                light.shadow_view = NO_SHADOW_VIEW;
+            }
+        }
+    }
+
+    /// Shadow map views of all the lights that cast shadows.
+    pub fn shadow_view_count(&self) -> usize {
+        self.lights
+            .iter()
+            .filter(|light| light.shadow_view != NO_SHADOW_VIEW)
+            .map(Light::shadow_view_count)
+            .sum()
+    }
+
+    /// The view of every shadow map, in the order [`Self::cast_shadows`] assigned them.
+    pub fn shadow_views(&self) -> Vec<camera::CameraUniform> {
+        self.lights
+            .iter()
+            .filter(|light| light.shadow_view != NO_SHADOW_VIEW)
+            .flat_map(|light| light.shadow_views(self.bounds))
+            .collect()
     }
+
+    /// Finds `triangle`, numbered across the models as in the object ID image, picked at
+    /// `pixel` of a `width` by `height` image of the active camera's view.
+    pub fn pick(
+        &self,
+        triangle: u32,
+        pixel: [u32; 2],
+        width: u32,
+        height: u32,
+    ) -> Option<PickedTriangle> {
+        let mut first_triangle = 0;
+        for (model_index, model) in self.models.iter().enumerate() {
+            let triangle_count = (model.processed_indices.len() / 3) as u32;
+            if triangle >= first_triangle + triangle_count {
+                first_triangle += triangle_count;
+                continue;
+            }
+
+            let local = triangle - first_triangle;
+            let corners = [0, 1, 2].map(|corner| {
+                let index = model.processed_indices[local as usize * 3 + corner];
+                model.vertex_position(index.0).unwrap_or_default()
+            });
+            let mesh = model.mesh_of(local);
+            let selection = match mesh {
+                Some(mesh) => {
+                    first_triangle + mesh.first_triangle
+                        ..first_triangle + mesh.first_triangle + mesh.triangle_count
+                }
+                None => first_triangle..first_triangle + triangle_count,
+            };
+            let position = self
+                .get_active_camera()
+                .and_then(|camera| pixel_ray_hit(camera, pixel, width, height, corners))
+                .unwrap_or((corners[0] + corners[1] + corners[2]) / 3.0);
+            return Some(PickedTriangle {
+                model: model_index,
+                mesh_name: mesh.map(|mesh| mesh.name.clone()),
+                triangle: local,
+                selection,
+                position,
+            });
+        }
+        None
+    }
+}
+
+/// A triangle picked from the object ID image, found in the scene's models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickedTriangle {
+    pub model: usize,
+    /// Name of the mesh the triangle belongs to, or none for models built in code.
+    pub mesh_name: Option<String>,
+    /// Index of the triangle within its model.
+    pub triangle: u32,
+    /// The triangles of the picked mesh, or of the whole model without meshes, numbered
+    /// across the models like the object ID image.
+    pub selection: Range<u32>,
+    /// Where the ray through the picked pixel meets the triangle's plane, or the triangle's
+    /// centre when there is no camera to cast it from.
+    pub position: glam::Vec3,
+}
+
+/// Where the camera ray through the centre of `pixel` meets the plane of `corners`, if it
+/// isn't parallel to it.
+fn pixel_ray_hit(
+    camera: &Camera,
+    pixel: [u32; 2],
+    width: u32,
+    height: u32,
+    corners: [glam::Vec3; 3],
+) -> Option<glam::Vec3> {
+    let ndc = glam::Vec2::new(
+        (pixel[0] as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+        1.0 - (pixel[1] as f32 + 0.5) / height as f32 * 2.0,
+    );
+    let inverse = camera.build_view_projection_matrix().inverse();
+    let near = inverse.project_point3(ndc.extend(0.0));
+    let far = inverse.project_point3(ndc.extend(1.0));
+    let direction = far - near;
+
+    let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+    let denominator = normal.dot(direction);
+    if denominator.abs() <= f32::EPSILON * normal.length() * direction.length() {
+        return None;
+    }
+    let t = normal.dot(corners[0] - near) / denominator;
+    Some(near + direction * t)
 }
 
 #[derive(Clone)]
 pub struct SceneConfig {
     pub model_path: String,
-    pub lights: Vec<(
-        /* position */ [f32; 3],
-        /* color */ [f32; 3],
-        /* intensity */ f32,
-    )>,
+    pub lights: Vec<LightConfig>,
     pub effect: Option<Effect>,
     // Camera configuration
     pub camera_config: CameraConfig,
     // Benchmark duration in seconds
     pub benchmark_duration_secs: u64,
     pub backend_type: BackendType,
+    // Tile size and thread counts of the custom pipeline. The WGPU backend only uses its
+    // MSAA sample count, shadow, shading and tone mapping settings, so both backends
+    // anti-alias, shadow, shade and present alike.
+    pub pipeline_config: PipelineConfig,
+    // Window size to switch to when the scene is loaded, or None to keep the current size
+    pub resolution: Option<[u32; 2]>,
+    // Colour grading LUT of the custom pipeline's post chain, loaded from a .cube file
+    pub color_lut: Option<ColorLut>,
 }
 
 impl SceneConfig {
+    /// Shadow map views the scene renders each frame, matching [`Scene::cast_shadows`].
+    pub fn shadow_view_count(&self) -> usize {
+        if !self.pipeline_config.shadows {
+            return 0;
+        }
+        self.lights
+            .iter()
+            .take(PipelineConfig::MAX_SHADOWED_LIGHTS)
+            .map(|light| Light::from(light).shadow_view_count())
+            .sum()
+    }
+
     pub fn scene_name(&self) -> String {
-        format!("Scene {} - {} Pipeline", self.model_path, self.backend_type)
+        let config = &self.pipeline_config;
+        let mut options = Vec::new();
+        if matches!(self.backend_type, BackendType::CustomPipeline) {
+            options.push(format!("tile size {}", config.tile_size));
+            if config.visibility_buffer {
+                options.push("visibility buffer".to_string());
+            }
+            if config.deferred {
+                options.push("deferred".to_string());
+            }
+            if !config.post_effects.is_empty() {
+                options.push(format!("{} post", config.post_effects));
+            }
+            if config.shows_debug_view() {
+                options.push(format!("{} view", config.debug_view));
+            }
+        }
+        // The WGPU pipeline culls lights for forward shading when deferred shading is asked for.
+        let custom_deferred =
+            matches!(self.backend_type, BackendType::CustomPipeline) && config.deferred;
+        if config.culls_lights() && !custom_deferred {
+            options.push("light culling".to_string());
+        }
+        if config.msaa_samples > 1 {
+            options.push(format!("{}x MSAA", config.msaa_samples));
+        }
+        if config.shadows {
+            options.push(format!("{}px shadows", config.shadow_map_size));
+        }
+        if config.shading != ShadingModel::Phong {
+            options.push(format!("{} shading", config.shading));
+        }
+        if config.tone_mapping != ToneMapping::default() {
+            options.push(format!("{} tone mapping", config.tone_mapping));
+        }
+        if config.auto_exposure {
+            options.push("auto exposure".to_string());
+        }
+
+        let name = format!("Scene {} - {} Pipeline", self.model_path, self.backend_type);
+        if options.is_empty() {
+            name
+        } else {
+            format!("{} ({})", name, options.join(", "))
+        }
     }
 }
 
@@ -228,14 +683,17 @@ impl Default for SceneConfig {
         Self {
             model_path: "suzanne.obj".to_string(),
             lights: vec![
-                ([0.0, 0.0, 0.0], [1.0, 0.9, 0.8], 1.0),
+                LightConfig::point([0.0, 0.0, 0.0], [1.0, 0.9, 0.8], 1.0),
                 // Fill light
-                ([-5.0, 3.0, 0.0], [0.3, 0.4, 0.5], 0.5),
+                LightConfig::point([-5.0, 3.0, 0.0], [0.3, 0.4, 0.5], 0.5),
             ],
             effect: None,
             camera_config: CameraConfig::default(),
             benchmark_duration_secs: u64::MAX,
             backend_type: BackendType::CustomPipeline,
+            pipeline_config: PipelineConfig::default(),
+            resolution: None,
+            color_lut: None,
         }
     }
 }
@@ -6,6 +6,8 @@ pub struct WgpuVertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coords: [f32; 2],
+    /// Index into the scene's materials.
+    pub material: u32,
 }
 
 impl WgpuVertex {
@@ -33,6 +35,12 @@ impl WgpuVertex {
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                // material
+                wgpu::VertexAttribute {
+                    offset: 32 as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
@@ -47,7 +55,9 @@ pub struct CustomVertex {
     pub normal: [f32; 3],
     pub _padding2: f32,
     pub tex_coords: [f32; 2],
-    pub padding: [f32; 2],
+    /// Index into the scene's materials.
+    pub material: u32,
+    pub _padding3: u32,
 }
 
 impl Default for CustomVertex {
@@ -59,7 +69,31 @@ impl Default for CustomVertex {
             normal: [0.0; 3],
             _padding2: 0.0,
             tex_coords: [0.0; 2],
-            padding: [0.0; 2],
+            material: 0,
+            _padding3: 0,
+        }
+    }
+}
+
+impl From<&WgpuVertex> for CustomVertex {
+    fn from(vertex: &WgpuVertex) -> Self {
+        Self {
+            position: vertex.position,
+            normal: vertex.normal,
+            tex_coords: vertex.tex_coords,
+            material: vertex.material,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&CustomVertex> for WgpuVertex {
+    fn from(vertex: &CustomVertex) -> Self {
+        Self {
+            position: vertex.position,
+            normal: vertex.normal,
+            tex_coords: vertex.tex_coords,
+            material: vertex.material,
         }
     }
 }
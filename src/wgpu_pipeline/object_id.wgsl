@@ -0,0 +1,56 @@
+// Renders the ID of the opaque triangle in each pixel plus one, numbered across the scene's
+// models like in the custom pipeline, for picking and the selection outline. Vertices are
+// pulled from storage, so each triangle's ID follows from the vertex index.
+
+struct CameraUniform {
+    view_position: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// The model's vertices as the nine words of each WgpuVertex (position, normal, UV and
+// material), and all its triangles in their original order, with the transparent ones
+// collapsed to a point so they hide nothing.
+@group(1) @binding(0)
+var<storage, read> vertices: array<f32>;
+
+@group(1) @binding(1)
+var<storage, read> indices: array<u32>;
+
+struct ModelUniform {
+    first_triangle: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+};
+
+@group(1) @binding(2)
+var<uniform> model: ModelUniform;
+
+const VERTEX_WORDS: u32 = 9u;
+
+struct IdOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+@vertex
+fn vs_object_id(@builtin(vertex_index) vertex_index: u32) -> IdOutput {
+    let base = indices[vertex_index] * VERTEX_WORDS;
+    let position = vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+
+    var out: IdOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    out.id = model.first_triangle + vertex_index / 3u + 1u;
+    return out;
+}
+
+@fragment
+fn fs_object_id(in: IdOutput) -> @location(0) u32 {
+    return in.id;
+}
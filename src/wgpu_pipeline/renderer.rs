@@ -1,12 +1,37 @@
+use std::ops::Range;
+
 use wgpu::util::DeviceExt;
 
-use crate::{camera::CameraUniform, scene::Scene, vertex::WgpuVertex};
+use crate::{
+    camera::CameraUniform,
+    custom_pipeline::{
+        config::PipelineConfig,
+        exposure_pass::ExposurePass,
+        light_culling_pass::{DepthSource, LightCullingInputs, LightCullingPass},
+        picking::{Pick, Picker, OBJECT_ID_FORMAT},
+        present_pass::{PresentPass, HDR_FORMAT, SURFACE_FORMAT},
+        util::{Index, MaterialInfo, ScreenUniform},
+    },
+    overlay::Overlay,
+    scene::Scene,
+    vertex::WgpuVertex,
+};
 
 /// Data to hold GPU buffers and bind groups for each Model in the Scene.
 pub struct ModelRenderData {
     pub vertex_buffer: wgpu::Buffer,
+    /// Indices of the opaque triangles.
     pub index_buffer: wgpu::Buffer,
     pub index_count: u32,
+    /// Transparent triangles and their centres, re-sorted from back to front every frame
+    /// into `transparent_index_buffer`.
+    pub transparent_triangles: Vec<[u32; 3]>,
+    pub transparent_centres: Vec<glam::Vec3>,
+    pub transparent_index_buffer: wgpu::Buffer,
+    /// The model's vertices, triangles and first triangle ID for the object ID pass, or none
+    /// for a model without triangles.
+    pub object_id_bind_group: Option<wgpu::BindGroup>,
+    pub triangle_count: u32,
 }
 
 /// The main renderer that uses wgpu's standard raster pipeline.
@@ -15,54 +40,156 @@ pub struct WgpuRenderer {
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
+    pub pipeline_config: PipelineConfig,
 
-    // Pipeline
+    // Pipelines for opaque triangles, and for transparent ones blended over them
     pub render_pipeline: wgpu::RenderPipeline,
+    pub transparent_pipeline: wgpu::RenderPipeline,
 
     // Depth buffer
     pub depth_texture_view: wgpu::TextureView,
 
+    // With light culling, a depth only pipeline filling the depth buffer with the opaque
+    // triangles before the main pass, and the custom pipeline's culling pass listing the
+    // lights of each tile from it
+    pub depth_prepass_pipeline: Option<wgpu::RenderPipeline>,
+    pub light_culling_pass: Option<LightCullingPass>,
+
+    // Bind group for the lists of the lights of each tile, recreated with the screen size
+    pub tile_bind_group_layout: wgpu::BindGroupLayout,
+    pub tile_bind_group: wgpu::BindGroup,
+
+    // Linear HDR colour target, exposed, tone mapped and encoded into the frame by the
+    // present pass
+    pub hdr_texture_view: wgpu::TextureView,
+
+    // Multisampled colour target resolved into the HDR target, when MSAA is enabled
+    pub msaa_samples: u32,
+    pub msaa_texture_view: Option<wgpu::TextureView>,
+
     // Camera and effect buffers
     pub camera_buffer: wgpu::Buffer,
     pub light_buffer: wgpu::Buffer,
 
-    // Bind group for camera & effect data
+    // Bind group for camera, light, material & shadow map data
     pub global_bind_group: wgpu::BindGroup,
 
+    // Depth only pipeline rendering a layer of the shadow map texture per shadow map of the
+    // shadowed lights, from the views in the shadow view buffer
+    pub shadow_pipeline: wgpu::RenderPipeline,
+    pub shadow_bind_group: wgpu::BindGroup,
+    pub shadow_view_buffer: wgpu::Buffer,
+    pub shadow_layer_views: Vec<wgpu::TextureView>,
+
     // Scene geometry (one ModelRenderData per loaded model)
     pub model_data: Vec<ModelRenderData>,
+
+    // The custom pipeline's exposure and present passes, so both backends present alike
+    pub exposure_pass: ExposurePass,
+    pub present_pass: PresentPass,
+
+    // Renders the triangle ID of each pixel, with a depth buffer of its own, while a pick
+    // is waiting for a frame or something is selected
+    pub object_id_pipeline: wgpu::RenderPipeline,
+    pub object_id_bind_group: wgpu::BindGroup,
+    pub object_id_texture: wgpu::Texture,
+    pub object_id_view: wgpu::TextureView,
+    pub object_id_depth_view: wgpu::TextureView,
+    picker: Picker,
+    selection: Option<Range<u32>>,
 }
 
 impl WgpuRenderer {
+    /// Check that `adapter` can render `pipeline_config`'s MSAA sample count into the HDR and
+    /// depth targets. main checks every scene up front, so an unsupported setting is reported
+    /// before a benchmark or sweep starts rather than by [`Self::new`] part way through.
+    pub fn check_support(
+        adapter: &wgpu::Adapter,
+        pipeline_config: &PipelineConfig,
+    ) -> Result<(), String> {
+        let msaa_samples = pipeline_config.msaa_samples;
+        let format_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        let guaranteed = matches!(msaa_samples, 1 | 4);
+        for sample_format in [HDR_FORMAT, create_depth_texture_format()] {
+            if !adapter
+                .get_texture_format_features(sample_format)
+                .flags
+                .sample_count_supported(msaa_samples)
+                || !(guaranteed || adapter.features().contains(format_features))
+            {
+                return Err(format!(
+                    "The GPU does not support {}x MSAA for {:?} in the WGPU pipeline. Use a lower --msaa sample count.",
+                    msaa_samples, sample_format
+                ));
+            }
+        }
+        Ok(())
+    }
+
     /// Create a new raster-based wgpu renderer.
     ///
     /// # Arguments
     /// * `instance` - The wgpu instance to use
-    /// * `surface` - The surface to render to
+    /// * `surface` - The surface to render to, or None to render to off-screen targets with
+    ///   [`Self::render_to`]
     /// * `width` - The initial width of the surface
     /// * `height` - The initial height of the surface
     /// * `scene` - The scene to render
+    /// * `pipeline_config` - Supplies the MSAA sample count, shadow, light culling, shading
+    ///   and tone mapping settings, so both backends render alike
+    ///
+    /// Fails when the GPU can't render the configuration, as [`Self::check_support`] reports.
     pub async fn new(
         instance: &wgpu::Instance,
-        surface: &wgpu::Surface<'_>,
+        surface: Option<&wgpu::Surface<'_>>,
         width: u32,
         height: u32,
         scene: &Scene,
-    ) -> Self {
+        pipeline_config: &PipelineConfig,
+    ) -> Result<Self, String> {
+        let msaa_samples = pipeline_config.msaa_samples;
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
+                compatible_surface: surface,
             })
             .await
             .expect("Failed to find a suitable GPU adapter");
 
+        // WebGPU only guarantees 1 and 4 samples; other counts depend on the adapter.
+        let format = SURFACE_FORMAT;
+        let format_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+        let required_features = if adapter.features().contains(format_features) {
+            format_features
+        } else {
+            wgpu::Features::empty()
+        };
+        Self::check_support(&adapter, pipeline_config)?;
+
+        // Naga can't translate the light culling pass's loads from the depth buffer to GLSL, so
+        // on GL every fragment loops over all the lights instead.
+        let mut pipeline_config = *pipeline_config;
+        if pipeline_config.culls_lights() && adapter.get_info().backend == wgpu::Backend::Gl {
+            let option = if pipeline_config.deferred {
+                "--deferred"
+            } else {
+                "--light-culling"
+            };
+            eprintln!(
+                "{} culls lights, which the WGPU pipeline can't do on OpenGL. Shading every light per fragment instead.",
+                option
+            );
+            pipeline_config.light_culling = false;
+            pipeline_config.deferred = false;
+        }
+        let pipeline_config = &pipeline_config;
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty(),
+                    required_features,
                     required_limits: wgpu::Limits::default(),
                     memory_hints: wgpu::MemoryHints::default(),
                 },
@@ -72,16 +199,13 @@ impl WgpuRenderer {
             .expect("Failed to create device");
 
         // === 2) Create surface configuration
-        let format = wgpu::TextureFormat::Bgra8UnormSrgb;
-
-        let surface_caps = surface.get_capabilities(&adapter);
-        let present_mode = if surface_caps
-            .present_modes
-            .contains(&wgpu::PresentMode::Immediate)
-        {
+        let present_modes = surface.map_or(vec![wgpu::PresentMode::Fifo], |surface| {
+            surface.get_capabilities(&adapter).present_modes
+        });
+        let present_mode = if present_modes.contains(&wgpu::PresentMode::Immediate) {
             &wgpu::PresentMode::Immediate
         } else {
-            surface_caps.present_modes.first().unwrap()
+            present_modes.first().unwrap()
         };
 
         let config = wgpu::SurfaceConfiguration {
@@ -94,11 +218,25 @@ impl WgpuRenderer {
             view_formats: vec![],
             desired_maximum_frame_latency: 1,
         };
-        surface.configure(&device, &config);
+        if let Some(surface) = surface {
+            surface.configure(&device, &config);
+        }
 
-        // === 3) Create depth texture
-        let depth_texture = create_depth_texture(&device, &config, "depth_texture");
+        // === 3) Create depth texture (and the HDR and multisampled colour targets)
+        let depth_texture = create_depth_texture(&device, &config, msaa_samples, "depth_texture");
         let depth_texture_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let hdr_texture_view = create_hdr_texture_view(&device, &config);
+        let msaa_texture_view = create_msaa_texture_view(&device, &config, msaa_samples);
+        let (object_id_texture, object_id_view, object_id_depth_view) =
+            create_object_id_targets(&device, &config);
+        let exposure_pass = ExposurePass::new(&device, &hdr_texture_view, pipeline_config);
+        let present_pass = PresentPass::new(
+            &device,
+            &hdr_texture_view,
+            &object_id_view,
+            &exposure_pass.exposure_buffer,
+            pipeline_config,
+        );
 
         // === 4) Create (camera + lights + effects) buffers & bind group
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -117,6 +255,86 @@ impl WgpuRenderer {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
+        // Materials of all models, numbered across the scene like in the custom pipeline,
+        // and their textures one after another.
+        let mut materials: Vec<MaterialInfo> = Vec::new();
+        let mut textures: Vec<u32> = Vec::new();
+        for model in &scene.models {
+            let first_texel = textures.len() as u32;
+            materials.extend(
+                model
+                    .materials
+                    .iter()
+                    .map(|material| material.with_first_texel(first_texel)),
+            );
+            textures.extend_from_slice(&model.textures);
+        }
+        if materials.is_empty() {
+            materials.push(MaterialInfo::default());
+        }
+        if textures.is_empty() {
+            textures.push(0);
+        }
+        let material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(&materials),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let texture_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Texture Buffer"),
+            contents: bytemuck::cast_slice(&textures),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        // Shadow maps: one depth layer per shadow view, or a placeholder without shadows
+        let shadow_views = pipeline_config.shadow_views(scene) as u32;
+        let shadow_map_size = if shadow_views > 0 {
+            pipeline_config.shadow_map_size
+        } else {
+            1
+        };
+        let shadow_view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow View Buffer"),
+            size: shadow_views.max(1) as u64 * std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shadow_map_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Map Texture"),
+            size: wgpu::Extent3d {
+                width: shadow_map_size,
+                height: shadow_map_size,
+                depth_or_array_layers: shadow_views.max(1),
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_MAP_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let shadow_map_view = shadow_map_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let shadow_layer_views = (0..shadow_views)
+            .map(|layer| {
+                shadow_map_texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        // Nearest filtering, so the 3x3 filter in the shader compares the same texels as
+        // the custom pipeline does.
+        let shadow_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
         // Create bind group layout
         let global_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -148,6 +366,43 @@ impl WgpuRenderer {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    shadow_view_layout_entry(wgpu::ShaderStages::FRAGMENT),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -163,50 +418,315 @@ impl WgpuRenderer {
                     binding: 1,
                     resource: light_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: shadow_view_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Sampler(&shadow_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: texture_buffer.as_entire_binding(),
+                },
             ],
         });
 
-        // === 5) Create the render pipeline
-        let shader_source = include_str!("shaders.wgsl");
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Raster Shader"),
-            source: wgpu::ShaderSource::Wgsl(shader_source.into()),
+        // The shadow pass only needs the views
+        let shadow_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow Bind Group Layout"),
+                entries: &[shadow_view_layout_entry(wgpu::ShaderStages::VERTEX)],
+            });
+        let shadow_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Bind Group"),
+            layout: &shadow_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 3,
+                resource: shadow_view_buffer.as_entire_binding(),
+            }],
         });
 
+        let tile_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Tile Light Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let (tile_bind_group, light_culling_pass) = create_light_culling(
+            &device,
+            &config,
+            pipeline_config,
+            &tile_bind_group_layout,
+            &depth_texture_view,
+            &camera_buffer,
+            &light_buffer,
+        );
+
+        // === 5) Create the render pipeline, with the custom pipeline's constants
+        let shader = pipeline_config.create_shader_module(
+            &device,
+            "Raster Shader",
+            include_str!("shaders.wgsl"),
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[&global_bind_group_layout],
+            bind_group_layouts: &[&global_bind_group_layout, &tile_bind_group_layout],
             push_constant_ranges: &[],
         });
 
-        // Create the pipeline
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&pipeline_layout),
+        // Transparent triangles are blended over the opaque ones without hiding each other,
+        // so their pipeline doesn't write depth. After a depth prepass, the opaque triangles
+        // pass the depth test where they wrote the depth themselves.
+        let light_culling = pipeline_config.culls_lights();
+        let opaque_depth_compare = if light_culling {
+            wgpu::CompareFunction::LessEqual
+        } else {
+            wgpu::CompareFunction::Less
+        };
+        let create_pipeline =
+            |label, fragment_entry_point, blend, depth_write_enabled, depth_compare| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: Some("vs_main"),
+                        buffers: &[WgpuVertex::layout()],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: Some(fragment_entry_point),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: HDR_FORMAT,
+                            blend: Some(blend),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                        compilation_options: wgpu::PipelineCompilationOptions::default(),
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face: wgpu::FrontFace::Ccw, // Counter-clockwise winding
+                        cull_mode: Some(wgpu::Face::Back), // Back-face culling
+                        unclipped_depth: false,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                        conservative: false,
+                    },
+                    depth_stencil: Some(wgpu::DepthStencilState {
+                        format: create_depth_texture_format(),
+                        depth_write_enabled,
+                        depth_compare,
+                        stencil: wgpu::StencilState::default(),
+                        bias: wgpu::DepthBiasState::default(),
+                    }),
+                    multisample: wgpu::MultisampleState {
+                        count: msaa_samples,
+                        mask: !0,
+                        alpha_to_coverage_enabled: false,
+                    },
+                    multiview: None,
+                    cache: None,
+                })
+            };
+        let render_pipeline = create_pipeline(
+            "Render Pipeline",
+            "fs_main",
+            wgpu::BlendState::REPLACE,
+            true,
+            opaque_depth_compare,
+        );
+        let transparent_pipeline = create_pipeline(
+            "Transparent Render Pipeline",
+            "fs_transparent",
+            wgpu::BlendState::ALPHA_BLENDING,
+            false,
+            wgpu::CompareFunction::Less,
+        );
+        let depth_prepass_pipeline = light_culling.then(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Depth Prepass Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[WgpuVertex::layout()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: None,
+                primitive: wgpu::PrimitiveState {
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: create_depth_texture_format(),
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: msaa_samples,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        // Front faces only, like the custom pipeline's depth passes
+        let shadow_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Shadow Pipeline Layout"),
+                bind_group_layouts: &[&shadow_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let shadow_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&shadow_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: Some("vs_main"),
+                entry_point: Some("vs_shadow"),
                 buffers: &[WgpuVertex::layout()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
+            fragment: None,
+            primitive: wgpu::PrimitiveState {
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: SHADOW_MAP_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        // The object ID pass pulls its vertices from the models' buffers
+        let object_id_bind_group_layout_0 =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Object ID Bind Group Layout 0"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let object_id_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Object ID Bind Group"),
+            layout: &object_id_bind_group_layout_0,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+        let vertex_storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let object_id_bind_group_layout_1 =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Object ID Model Bind Group Layout"),
+                entries: &[
+                    vertex_storage_entry(0),
+                    vertex_storage_entry(1),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let object_id_shader = pipeline_config.create_shader_module(
+            &device,
+            "Object ID Shader",
+            include_str!("object_id.wgsl"),
+        );
+        let object_id_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Object ID Pipeline Layout"),
+                bind_group_layouts: &[
+                    &object_id_bind_group_layout_0,
+                    &object_id_bind_group_layout_1,
+                ],
+                push_constant_ranges: &[],
+            });
+        let object_id_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Object ID Pipeline"),
+            layout: Some(&object_id_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &object_id_shader,
+                entry_point: Some("vs_object_id"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: Some("fs_main"),
+                module: &object_id_shader,
+                entry_point: Some("fs_object_id"),
                 targets: &[Some(wgpu::ColorTargetState {
-                    format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    format: OBJECT_ID_FORMAT,
+                    blend: None,
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             }),
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw, // Counter-clockwise winding
-                cull_mode: Some(wgpu::Face::Back), // Back-face culling
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: create_depth_texture_format(),
@@ -215,56 +735,163 @@ impl WgpuRenderer {
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
+            multisample: wgpu::MultisampleState::default(),
             multiview: None,
             cache: None,
         });
 
         // === 6) Create model buffers for each model in the scene
         let mut model_data = Vec::new();
+        let mut first_material = 0;
+        let mut first_triangle = 0;
 
         for model in &scene.models {
             println!("Loading model: {}", model.processed_vertices_wgpu.len());
             // Create vertex buffer
+            let vertices: Vec<WgpuVertex> = model
+                .processed_vertices_wgpu
+                .iter()
+                .map(|vertex| WgpuVertex {
+                    material: vertex.material + first_material,
+                    ..*vertex
+                })
+                .collect();
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&model.processed_vertices_wgpu),
-                usage: wgpu::BufferUsages::VERTEX,
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
             });
 
-            // Create index buffer
+            // Split the triangles by whether their material is transparent. The object ID pass
+            // keeps every triangle in place so IDs match the scene's numbering, but collapses
+            // the transparent ones to a point: like the custom pipeline, which only writes the
+            // IDs of opaque surfaces, picking goes through glass to what is behind it.
+            let mut opaque_indices: Vec<Index> = Vec::new();
+            let mut transparent_triangles = Vec::new();
+            let mut object_id_indices: Vec<Index> = Vec::new();
+            for triangle in model.processed_indices.chunks_exact(3) {
+                let first = &model.processed_vertices_wgpu[triangle[0].0 as usize];
+                let material = model.materials.get(first.material as usize);
+                if material.is_some_and(MaterialInfo::is_transparent) {
+                    transparent_triangles.push([triangle[0].0, triangle[1].0, triangle[2].0]);
+                    object_id_indices.extend_from_slice(&[triangle[0]; 3]);
+                } else {
+                    opaque_indices.extend_from_slice(triangle);
+                    object_id_indices.extend_from_slice(triangle);
+                }
+            }
+            let transparent_centres = transparent_triangles
+                .iter()
+                .map(|triangle| {
+                    triangle
+                        .iter()
+                        .map(|&i| {
+                            glam::Vec3::from(model.processed_vertices_wgpu[i as usize].position)
+                        })
+                        .sum::<glam::Vec3>()
+                        / 3.0
+                })
+                .collect();
+
+            // Create index buffers
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&model.processed_indices),
+                contents: bytemuck::cast_slice(&opaque_indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
+            // Unsorted until the first frame with a camera sorts it
+            let transparent_index_buffer =
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Transparent Index Buffer"),
+                    contents: bytemuck::cast_slice(&transparent_triangles),
+                    usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                });
 
             // The total index count
-            let index_count = model.processed_indices.len() as u32;
+            let index_count = opaque_indices.len() as u32;
+
+            let triangle_count = object_id_indices.len() as u32 / 3;
+            let object_id_bind_group = (triangle_count > 0).then(|| {
+                let object_id_index_buffer =
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Object ID Index Buffer"),
+                        contents: bytemuck::cast_slice(&object_id_indices),
+                        usage: wgpu::BufferUsages::STORAGE,
+                    });
+                let model_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Object ID Model Buffer"),
+                    contents: bytemuck::cast_slice(&[first_triangle, 0, 0, 0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Object ID Model Bind Group"),
+                    layout: &object_id_bind_group_layout_1,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: vertex_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: object_id_index_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: model_buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            });
 
             model_data.push(ModelRenderData {
                 vertex_buffer,
                 index_buffer,
                 index_count,
+                transparent_triangles,
+                transparent_centres,
+                transparent_index_buffer,
+                object_id_bind_group,
+                triangle_count,
             });
+            first_material += model.materials.len() as u32;
+            first_triangle += triangle_count;
         }
+        let picker = Picker::new(&device);
 
         // Return the newly created WgpuRenderer
-        Self {
+        Ok(Self {
             device,
             queue,
             config,
+            pipeline_config: *pipeline_config,
             render_pipeline,
+            transparent_pipeline,
             depth_texture_view,
+            depth_prepass_pipeline,
+            light_culling_pass,
+            tile_bind_group_layout,
+            tile_bind_group,
+            hdr_texture_view,
+            msaa_samples,
+            msaa_texture_view,
             camera_buffer,
             light_buffer,
             global_bind_group,
+            shadow_pipeline,
+            shadow_bind_group,
+            shadow_view_buffer,
+            shadow_layer_views,
             model_data,
-        }
+            exposure_pass,
+            present_pass,
+            object_id_pipeline,
+            object_id_bind_group,
+            object_id_texture,
+            object_id_view,
+            object_id_depth_view,
+            picker,
+            selection: None,
+        })
     }
 
     /// Resize the renderer's resources when the window size changes.
@@ -274,9 +901,35 @@ impl WgpuRenderer {
     pub fn resize(&mut self, config: &wgpu::SurfaceConfiguration) {
         self.config = config.clone();
         // Recreate depth texture with new size
-        let depth_texture = create_depth_texture(&self.device, config, "depth_texture");
+        let depth_texture =
+            create_depth_texture(&self.device, config, self.msaa_samples, "depth_texture");
         self.depth_texture_view =
             depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (self.tile_bind_group, self.light_culling_pass) = create_light_culling(
+            &self.device,
+            config,
+            &self.pipeline_config,
+            &self.tile_bind_group_layout,
+            &self.depth_texture_view,
+            &self.camera_buffer,
+            &self.light_buffer,
+        );
+        self.hdr_texture_view = create_hdr_texture_view(&self.device, config);
+        self.msaa_texture_view = create_msaa_texture_view(&self.device, config, self.msaa_samples);
+        (
+            self.object_id_texture,
+            self.object_id_view,
+            self.object_id_depth_view,
+        ) = create_object_id_targets(&self.device, config);
+        self.exposure_pass =
+            ExposurePass::new(&self.device, &self.hdr_texture_view, &self.pipeline_config);
+        self.present_pass = PresentPass::new(
+            &self.device,
+            &self.hdr_texture_view,
+            &self.object_id_view,
+            &self.exposure_pass.exposure_buffer,
+            &self.pipeline_config,
+        );
     }
 
     /// Render the current scene.
@@ -284,6 +937,7 @@ impl WgpuRenderer {
     /// # Arguments
     /// * `surface` - The surface to render to
     /// * `scene` - The scene to render
+    /// * `overlay` - The debug overlay to paint over the frame, if any
     ///
     /// # Returns
     /// * `Result<(), wgpu::SurfaceError>` - Ok if rendering succeeded, Err if there was a surface error
@@ -291,6 +945,7 @@ impl WgpuRenderer {
         &mut self,
         surface: &wgpu::Surface<'_>,
         scene: &Scene,
+        overlay: Option<&mut Overlay>,
     ) -> Result<(), wgpu::SurfaceError> {
         // Get the next frame
         let frame = match surface.get_current_texture() {
@@ -302,7 +957,53 @@ impl WgpuRenderer {
         let view = frame
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
+        self.render_to(&view, scene).await;
+        if let Some(overlay) = overlay {
+            overlay.paint(
+                &self.device,
+                &self.queue,
+                &view,
+                frame.texture.width(),
+                frame.texture.height(),
+            );
+        }
+        frame.present();
+
+        Ok(())
+    }
+
+    /// Sort the transparent triangles of each model from back to front, by the distance of
+    /// their centres from `eye`. Scenes without a camera leave them as they were last sorted.
+    pub fn sort_transparent_triangles(&self, eye: glam::Vec3) {
+        for model_data in &self.model_data {
+            if model_data.transparent_triangles.is_empty() {
+                continue;
+            }
+            let mut order: Vec<usize> = (0..model_data.transparent_triangles.len()).collect();
+            let distances: Vec<f32> = model_data
+                .transparent_centres
+                .iter()
+                .map(|centre| centre.distance_squared(eye))
+                .collect();
+            order.sort_unstable_by(|&a, &b| distances[b].total_cmp(&distances[a]));
+            let sorted: Vec<[u32; 3]> = order
+                .iter()
+                .map(|&i| model_data.transparent_triangles[i])
+                .collect();
+            self.queue.write_buffer(
+                &model_data.transparent_index_buffer,
+                0,
+                bytemuck::cast_slice(&sorted),
+            );
+        }
+    }
 
+    /// Render the current scene into a texture instead of the surface.
+    ///
+    /// # Arguments
+    /// * `view` - A [`SURFACE_FORMAT`] texture of the surface's size to render to
+    /// * `scene` - The scene to render
+    pub async fn render_to(&mut self, view: &wgpu::TextureView, scene: &Scene) {
         // Create a command encoder for recording commands
         let mut encoder = self
             .device
@@ -327,13 +1028,95 @@ impl WgpuRenderer {
                 .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&scene.lights));
         }
 
+        // Render every shadow map of the shadowed lights
+        if !self.shadow_layer_views.is_empty() {
+            let views = scene.shadow_views();
+            self.queue
+                .write_buffer(&self.shadow_view_buffer, 0, bytemuck::cast_slice(&views));
+        }
+        for (view, layer_view) in self.shadow_layer_views.iter().enumerate() {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: layer_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            shadow_pass.set_pipeline(&self.shadow_pipeline);
+            shadow_pass.set_bind_group(0, &self.shadow_bind_group, &[]);
+
+            // The instance index selects the face's view. Transparent triangles cast no shadows.
+            let view = view as u32;
+            for model_data in &self.model_data {
+                if model_data.index_count == 0 {
+                    continue;
+                }
+                shadow_pass.set_vertex_buffer(0, model_data.vertex_buffer.slice(..));
+                shadow_pass
+                    .set_index_buffer(model_data.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                shadow_pass.draw_indexed(0..model_data.index_count, 0, view..view + 1);
+            }
+        }
+
+        if let Some(camera) = scene.get_active_camera() {
+            self.sort_transparent_triangles(camera.eye);
+        }
+
+        // Fill the depth buffer with the opaque triangles, and cull the lights of each tile
+        // against it
+        if let Some(depth_prepass_pipeline) = &self.depth_prepass_pipeline {
+            let mut depth_prepass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            depth_prepass.set_pipeline(depth_prepass_pipeline);
+            depth_prepass.set_bind_group(0, &self.global_bind_group, &[]);
+            depth_prepass.set_bind_group(1, &self.tile_bind_group, &[]);
+            for model_data in &self.model_data {
+                if model_data.index_count == 0 {
+                    continue;
+                }
+                depth_prepass.set_vertex_buffer(0, model_data.vertex_buffer.slice(..));
+                depth_prepass
+                    .set_index_buffer(model_data.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                depth_prepass.draw_indexed(0..model_data.index_count, 0, 0..1);
+            }
+        }
+        if let Some(light_culling_pass) = &self.light_culling_pass {
+            light_culling_pass.execute(&mut encoder, self.config.width, self.config.height);
+        }
+
         // Begin render pass
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
+                // With MSAA, draw into the multisampled target and resolve it into the HDR one.
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self
+                        .msaa_texture_view
+                        .as_ref()
+                        .unwrap_or(&self.hdr_texture_view),
+                    resolve_target: self
+                        .msaa_texture_view
+                        .as_ref()
+                        .map(|_| &self.hdr_texture_view),
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.0,
@@ -347,7 +1130,11 @@ impl WgpuRenderer {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture_view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: if self.depth_prepass_pipeline.is_some() {
+                            wgpu::LoadOp::Load
+                        } else {
+                            wgpu::LoadOp::Clear(1.0)
+                        },
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -359,24 +1146,112 @@ impl WgpuRenderer {
             // Set the pipeline
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_bind_group(0, &self.global_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.tile_bind_group, &[]);
 
-            // Draw each model
+            // Draw the opaque triangles of each model
             for model_data in &self.model_data {
+                if model_data.index_count == 0 {
+                    continue;
+                }
                 render_pass.set_vertex_buffer(0, model_data.vertex_buffer.slice(..));
                 render_pass
                     .set_index_buffer(model_data.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
                 render_pass.draw_indexed(0..model_data.index_count, 0, 0..1);
             }
+
+            // Then blend the transparent ones over them, sorted within each model
+            render_pass.set_pipeline(&self.transparent_pipeline);
+            for model_data in &self.model_data {
+                if model_data.transparent_triangles.is_empty() {
+                    continue;
+                }
+                render_pass.set_vertex_buffer(0, model_data.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(
+                    model_data.transparent_index_buffer.slice(..),
+                    wgpu::IndexFormat::Uint32,
+                );
+                render_pass.draw_indexed(
+                    0..model_data.transparent_triangles.len() as u32 * 3,
+                    0,
+                    0..1,
+                );
+            }
         }
 
-        // Submit command buffer and present frame
+        // The triangle IDs are only needed to pick or outline something
+        if self.picker.is_requested() || self.selection.is_some() {
+            self.render_object_ids(&mut encoder);
+            self.picker.record(&mut encoder, &self.object_id_texture);
+        }
+
+        // Meter, expose and tone map the HDR image into the frame
+        self.exposure_pass.execute(
+            &self.queue,
+            &mut encoder,
+            self.config.width,
+            self.config.height,
+        );
+        self.present_pass
+            .set_outline(&self.queue, self.selection.clone());
+        self.present_pass.execute(&mut encoder, view);
+
+        // Submit command buffer
         self.queue.submit(std::iter::once(encoder.finish()));
-        frame.present();
+        self.picker.submitted();
 
         // force gpu to wait for rendering to complete to prevent extremely high (fake) frames
         wait_for_gpu(&self.queue, &self.device).await;
+    }
 
-        Ok(())
+    /// Renders the ID of the opaque triangle in each pixel into the object ID texture.
+    fn render_object_ids(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut object_id_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Object ID Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.object_id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.object_id_depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        object_id_pass.set_pipeline(&self.object_id_pipeline);
+        object_id_pass.set_bind_group(0, &self.object_id_bind_group, &[]);
+        for model_data in &self.model_data {
+            let Some(bind_group) = &model_data.object_id_bind_group else {
+                continue;
+            };
+            object_id_pass.set_bind_group(1, bind_group, &[]);
+            object_id_pass.draw(0..model_data.triangle_count * 3, 0..1);
+        }
+    }
+
+    /// Reads back the triangle at pixel (`x`, `y`) of the next frame rendered, returned by
+    /// [`Self::poll_pick`] once the GPU has copied it.
+    pub fn pick(&mut self, x: u32, y: u32) {
+        self.picker.request(x, y);
+    }
+
+    /// The triangle picked with [`Self::pick`], if it has been read back. Doesn't block.
+    pub fn poll_pick(&mut self) -> Option<Pick> {
+        self.picker.poll(&self.device)
+    }
+
+    /// Outlines the surfaces of `triangles`, numbered across the scene's models, from the
+    /// next frame on.
+    pub fn set_selection(&mut self, triangles: Option<Range<u32>>) {
+        self.selection = triangles;
     }
 }
 
@@ -392,6 +1267,87 @@ async fn wait_for_gpu(queue: &wgpu::Queue, device: &wgpu::Device) {
         .expect("GPU work done callback was dropped unexpectedly");
 }
 
+/// 32-bit float depth, the same precision the custom pipeline's shadow maps keep.
+const SHADOW_MAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// The shadow view buffer, at the same binding in the main and shadow bind groups.
+fn shadow_view_layout_entry(visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// The screen size and per-tile light lists fs_main lights opaque fragments with, and the
+/// pass culling the lists from the depth prepass, when lights are culled. Otherwise the lists
+/// are a single unused placeholder.
+fn create_light_culling(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    pipeline_config: &PipelineConfig,
+    layout: &wgpu::BindGroupLayout,
+    depth_texture_view: &wgpu::TextureView,
+    camera_buffer: &wgpu::Buffer,
+    light_buffer: &wgpu::Buffer,
+) -> (wgpu::BindGroup, Option<LightCullingPass>) {
+    let screen_uniform = ScreenUniform::new(
+        config.width as f32,
+        config.height as f32,
+        pipeline_config.tile_size,
+    );
+    let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Screen Buffer"),
+        contents: bytemuck::bytes_of(&screen_uniform),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let tile_light_len = if pipeline_config.culls_lights() {
+        pipeline_config.tile_light_len(config.width, config.height)
+    } else {
+        1
+    };
+    let tile_light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Tile Light Buffer"),
+        size: tile_light_len * std::mem::size_of::<u32>() as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Tile Light Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: tile_light_buffer.as_entire_binding(),
+            },
+        ],
+    });
+    let light_culling_pass = pipeline_config.culls_lights().then(|| {
+        LightCullingPass::with_inputs(
+            device,
+            pipeline_config,
+            LightCullingInputs {
+                screen_buffer: &screen_buffer,
+                camera_buffer,
+                light_buffer,
+                tile_light_buffer: &tile_light_buffer,
+                depth: DepthSource::DepthTexture(depth_texture_view, pipeline_config.msaa_samples),
+            },
+        )
+    });
+    (bind_group, light_culling_pass)
+}
+
 fn create_depth_texture_format() -> wgpu::TextureFormat {
     wgpu::TextureFormat::Depth24Plus
 }
@@ -399,6 +1355,7 @@ fn create_depth_texture_format() -> wgpu::TextureFormat {
 fn create_depth_texture(
     device: &wgpu::Device,
     config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
     label: &str,
 ) -> wgpu::Texture {
     device.create_texture(&wgpu::TextureDescriptor {
@@ -409,10 +1366,84 @@ fn create_depth_texture(
             depth_or_array_layers: 1,
         },
         mip_level_count: 1,
-        sample_count: 1,
+        sample_count,
         dimension: wgpu::TextureDimension::D2,
         format: create_depth_texture_format(),
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        // Read by the light culling pass after a depth prepass
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
         view_formats: &[],
     })
 }
+
+fn create_hdr_texture_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("hdr_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// The object ID texture, its view and the depth buffer the object ID pass tests against.
+fn create_object_id_targets(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> (wgpu::Texture, wgpu::TextureView, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("object_id_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OBJECT_ID_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let depth_view = create_depth_texture(device, config, 1, "object_id_depth_texture")
+        .create_view(&wgpu::TextureViewDescriptor::default());
+    (texture, view, depth_view)
+}
+
+fn create_msaa_texture_view(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count == 1 {
+        return None;
+    }
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("msaa_texture"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
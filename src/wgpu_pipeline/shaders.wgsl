@@ -1,34 +1,120 @@
+// The custom pipeline's constants, among them PBR selecting the shading model and the
+// light culling ones, are prepended by WgpuRenderer::new.
+
 struct CameraUniform {
     view_position: vec4<f32>,
     view_proj: mat4x4<f32>,
+    // Motion vectors and jitter of the custom pipeline's TAA, and the inverse its
+    // deferred mode rebuilds positions with, unused here.
+    prev_view_proj: mat4x4<f32>,
+    jitter: vec4<f32>,
+    inv_view_proj: mat4x4<f32>,
 };
 
 struct Light {
     world_position: vec3<f32>,
-    _pad1: f32,
+    light_type: u32,
     view_position: vec3<f32>,
-    _pad2: f32,
+    range: f32,
     color: vec3<f32>,
     intensity: f32,
+    direction: vec3<f32>,
+    cos_inner_angle: f32,
+    cos_outer_angle: f32,
+    shadow_view: u32,
+    _padding: vec2<f32>,
 };
 
+// Light types and the shadow view of lights without shadow maps, as in scene.rs.
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+const NO_SHADOW_VIEW: u32 = 0xffffffffu;
+
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+struct TextureInfo {
+    offset: u32,
+    width: u32,
+    height: u32,
+    _padding: u32,
+};
+
+struct Material {
+    texture_info: TextureInfo,
+    ambient: vec3<f32>,
+    _padding1: f32,
+    specular: vec3<f32>,
+    _padding2: f32,
+    diffuse: vec3<f32>,
+    shininess: f32,
+    dissolve: f32,
+    optical_density: f32,
+    roughness: f32,
+    metallic: f32,
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+    _padding3: vec2<f32>,
+};
+
+// Values of alpha_mode, as in util.rs.
+const ALPHA_DISSOLVE: u32 = 0u;
+const ALPHA_BLEND: u32 = 1u;
+const ALPHA_MASK: u32 = 2u;
+
 @group(0) @binding(1)
 var<storage, read> lights: array<Light>;
 
+@group(0) @binding(2)
+var<storage, read> materials: array<Material>;
+
+// Texels of the materials' base colour textures, as sRGB RGBA8 (TextureInfo).
+@group(0) @binding(6)
+var<storage, read> textures: array<u32>;
+
+// With shadows on, the camera of each shadow map of the shadowed lights (six cube faces
+// per point or spot light, one per directional light, from each light's shadow_view) and
+// a depth texture layer per map. Otherwise a single unused placeholder each.
+@group(0) @binding(3)
+var<storage, read> shadow_views: array<CameraUniform>;
+
+@group(0) @binding(4)
+var shadow_maps: texture_depth_2d_array;
+
+@group(0) @binding(5)
+var shadow_sampler: sampler_comparison;
+
+struct ScreenUniform {
+    width: f32,
+    height: f32,
+    num_tiles_x: u32,
+    num_tiles_y: u32,
+};
+
+// With LIGHT_CULLING, the lights reaching each tile from the light culling pass: a count,
+// then that many light indices, in slots of MAX_TILE_LIGHTS plus one. Otherwise a single
+// unused placeholder.
+@group(1) @binding(0)
+var<uniform> screen_dims: ScreenUniform;
+
+@group(1) @binding(1)
+var<storage, read> tile_lights: array<u32>;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+    @location(3) material: u32,
 };
 
+// The position is invariant so the depth prepass and the main pass agree on every depth.
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) uv: vec2<f32>,
+    @location(3) @interpolate(flat) material: u32,
 };
 
 @vertex
@@ -39,25 +125,234 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     out.position = world_pos.xyz;
     out.normal = in.normal;
     out.uv = in.uv;
+    out.material = in.material;
     return out;
 }
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let normal = in.normal;
-    
-    var final_color = vec3<f32>(0.1);
+// Renders a shadow map, the one selected by the instance drawn.
+@vertex
+fn vs_shadow(in: VertexInput, @builtin(instance_index) view: u32) -> @builtin(position) vec4<f32> {
+    return shadow_views[view].view_proj * vec4<f32>(in.position, 1.0);
+}
+
+// How far shadow map lookups are moved out along the surface normal, in texels at the
+// fragment's distance from the light, so lit surfaces don't shadow themselves.
+const SHADOW_NORMAL_OFFSET: f32 = 1.5;
+
+// The fraction of a light that reaches a surface, from 3x3 percentage closer filtering
+// of its shadow map, or of the cube face a point or spot light sees it in. Lights without
+// shadow maps always reach it. This matches the custom pipeline's shadow_factor.
+fn shadow_factor(light: Light, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow_view == NO_SHADOW_VIEW {
+        return 1.0;
+    }
+
+    let size = f32(textureDimensions(shadow_maps).x);
+    var view = light.shadow_view;
+    var texel_size: f32;
+    if light.light_type == LIGHT_DIRECTIONAL {
+        // Orthographic texels are the same size everywhere: the first row of the matrix
+        // scales world units to NDC.
+        let view_proj = shadow_views[view].view_proj;
+        let scale = length(vec3<f32>(view_proj[0].x, view_proj[1].x, view_proj[2].x));
+        texel_size = 2.0 / (scale * size);
+    } else {
+        let to_surface = abs(position - light.world_position);
+        texel_size = 2.0 * max(to_surface.x, max(to_surface.y, to_surface.z)) / size;
+    }
+    let lookup = position + normalize(normal) * texel_size * SHADOW_NORMAL_OFFSET;
+
+    // Faces are ordered +X, -X, +Y, -Y, +Z, -Z, like Light::shadow_views.
+    if light.light_type != LIGHT_DIRECTIONAL {
+        let direction = lookup - light.world_position;
+        let distance = abs(direction);
+        var face = select(5u, 4u, direction.z > 0.0);
+        if distance.x >= distance.y && distance.x >= distance.z {
+            face = select(1u, 0u, direction.x > 0.0);
+        } else if distance.y >= distance.z {
+            face = select(3u, 2u, direction.y > 0.0);
+        }
+        view += face;
+    }
+
+    let clip = shadow_views[view].view_proj * vec4<f32>(lookup, 1.0);
+    if clip.w <= 0.0 {
+        return 1.0;
+    }
+    let ndc = clip.xyz / clip.w;
+    let texel = floor(vec2<f32>(ndc.x + 1.0, 1.0 - ndc.y) * 0.5 * size);
+
+    // Nearest filtering at texel centres picks the same texels as the custom pipeline.
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let uv = (texel + vec2<f32>(f32(x), f32(y)) + 0.5) / size;
+            lit += textureSampleCompareLevel(shadow_maps, shadow_sampler, uv, view, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
+
+// The direction from a surface to a light, and the fraction of the light that reaches it
+// before shadowing, like the custom pipeline's light_incidence.
+fn light_incidence(light: Light, position: vec3<f32>) -> vec4<f32> {
+    if light.light_type == LIGHT_DIRECTIONAL {
+        return vec4<f32>(-normalize(light.direction), 1.0);
+    }
+
+    let to_light = light.world_position - position;
+    let distance = length(to_light);
+    let direction = to_light / distance;
+    var attenuation = 1.0;
+    if light.range > 0.0 {
+        let falloff = saturate(1.0 - pow(distance / light.range, 4.0));
+        attenuation = falloff * falloff;
+    }
+    if light.light_type == LIGHT_SPOT {
+        let cos_angle = dot(-direction, normalize(light.direction));
+        let cone = saturate((cos_angle - light.cos_outer_angle)
+            / max(light.cos_inner_angle - light.cos_outer_angle, 1e-4));
+        attenuation *= cone * cone;
+    }
+    return vec4<f32>(direction, attenuation);
+}
+
+const PI: f32 = 3.14159265;
 
+// Roughness is clamped so the highlights of smooth materials stay wider than a pixel.
+const MIN_ROUGHNESS: f32 = 0.045;
+
+// The reflectance of the two shading models, like the custom pipeline's.
+fn phong_reflectance(material: Material, normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>) -> vec3<f32> {
+    let diff = max(dot(normal, light_dir), 0.0);
+    let reflect_dir = reflect(-light_dir, normal);
+    let spec = pow(max(dot(view_dir, reflect_dir), 0.0), max(material.shininess, 1.0));
+    return diff + spec * material.specular;
+}
+
+// As in the custom pipeline's fragment.wgsl.
+fn texture_texel(material: Material, uv: vec2<f32>) -> vec4<f32> {
+    let texture = material.texture_info;
+    if texture.offset == 0xffffffffu {
+        return vec4<f32>(1.0);
+    }
+    let size = vec2<u32>(texture.width, texture.height);
+    let texel = min(vec2<u32>(fract(uv) * vec2<f32>(size)), size - 1u);
+    return unpack4x8unorm(textures[texture.offset + texel.y * texture.width + texel.x]);
+}
+
+fn texture_color(material: Material, uv: vec2<f32>) -> vec3<f32> {
+    let srgb = texture_texel(material, uv).rgb;
+    return select(pow((srgb + 0.055) / 1.055, vec3<f32>(2.4)), srgb / 12.92, srgb <= vec3<f32>(0.04045));
+}
+
+// The opacity transparent surfaces are blended with: the dissolve, times the texture's
+// alpha for glTF materials, which masked ones then cut out at their alpha cutoff.
+fn material_alpha(material: Material, uv: vec2<f32>) -> f32 {
+    if material.alpha_mode == ALPHA_DISSOLVE {
+        return material.dissolve;
+    }
+    let alpha = material.dissolve * texture_texel(material, uv).a;
+    if material.alpha_mode == ALPHA_MASK {
+        return select(0.0, 1.0, alpha >= material.alpha_cutoff);
+    }
+    return alpha;
+}
+
+fn base_color(material: Material, uv: vec2<f32>) -> vec3<f32> {
+    return mix(material.diffuse, material.specular, material.metallic) * texture_color(material, uv);
+}
+
+fn pbr_reflectance(material: Material, uv: vec2<f32>, normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = dot(normal, light_dir);
+    if n_dot_l <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let n_dot_v = max(dot(normal, view_dir), 1e-4);
+    let half_dir = normalize(view_dir + light_dir);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+    let v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    let color = base_color(material, uv);
+    let f0 = mix(vec3<f32>(0.04), color, material.metallic);
+    let alpha = pow(max(material.roughness, MIN_ROUGHNESS), 2.0);
+    let alpha2 = alpha * alpha;
+
+    let d_denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    let distribution = alpha2 / (PI * d_denom * d_denom);
+    let k = alpha * 0.5;
+    let geometry = n_dot_l / (n_dot_l * (1.0 - k) + k) * n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+
+    let specular = distribution * geometry * fresnel / (4.0 * n_dot_l * n_dot_v);
+    let diffuse = (1.0 - fresnel) * (1.0 - material.metallic) * color / PI;
+    return (diffuse + specular) * n_dot_l * PI;
+}
+
+// Light arriving at a fragment from a single light and reflected towards `view_dir`.
+fn light_contribution(light: Light, material: Material, in: VertexOutput, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let incidence = light_incidence(light, in.position);
+    var reflectance: vec3<f32>;
+    if PBR {
+        reflectance = pbr_reflectance(material, in.uv, normal, view_dir, incidence.xyz);
+    } else {
+        reflectance = phong_reflectance(material, normal, view_dir, incidence.xyz);
+    }
+    let shadow = shadow_factor(light, in.position, in.normal);
+    return reflectance * incidence.w * shadow * light.color * light.intensity;
+}
+
+// The ambient term every fragment starts from, and the normal it is lit with.
+fn ambient(material: Material, uv: vec2<f32>) -> vec3<f32> {
+    if PBR {
+        return vec3<f32>(0.1) * base_color(material, uv);
+    }
+    return vec3<f32>(0.1);
+}
+
+fn shading_normal(in: VertexOutput) -> vec3<f32> {
+    if PBR {
+        return normalize(in.normal);
+    }
+    return in.normal;
+}
+
+// Lit colour of a fragment from every light.
+fn shade_linear(in: VertexOutput, material: Material) -> vec3<f32> {
+    let normal = shading_normal(in);
+    let view_dir = normalize(camera.view_position.xyz - in.position);
+    var final_color = ambient(material, in.uv);
     let num_lights = arrayLength(&lights);
     for (var i = 0u; i < num_lights; i++) {
-        let light = lights[i];
-        let light_dir = normalize(light.world_position - in.position);
-        let diff = max(dot(normal, light_dir), 0.0);
-        let view_dir = normalize(camera.view_position.xyz - in.position);
-        let reflect_dir = reflect(-light_dir, normal);
-        let spec = pow(max(dot(view_dir, reflect_dir), 0.0), 32.0);
-        final_color += (diff + spec * 0.5) * light.color * light.intensity;
+        final_color += light_contribution(lights[i], material, in, normal, view_dir);
     }
+    return final_color;
+}
 
+// Opaque fragments are lit with the lights listed for their tile when lights are culled.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let material = materials[in.material];
+    if !LIGHT_CULLING {
+        return vec4<f32>(shade_linear(in, material), 1.0);
+    }
+
+    let normal = shading_normal(in);
+    let view_dir = normalize(camera.view_position.xyz - in.position);
+    var final_color = ambient(material, in.uv);
+    let tile = vec2<u32>(in.clip_position.xy) / TILE_SIZE;
+    let slot = (tile.y * screen_dims.num_tiles_x + tile.x) * (MAX_TILE_LIGHTS + 1u);
+    let count = tile_lights[slot];
+    for (var i = 0u; i < count; i++) {
+        final_color += light_contribution(lights[tile_lights[slot + 1u + i]], material, in, normal, view_dir);
+    }
     return vec4<f32>(final_color, 1.0);
-}
\ No newline at end of file
+}
+
+// Transparent fragments can be in front of the surfaces the lights were culled against, so
+// they are always lit with every light, and blended by their material's alpha.
+@fragment
+fn fs_transparent(in: VertexOutput) -> @location(0) vec4<f32> {
+    let material = materials[in.material];
+    return vec4<f32>(shade_linear(in, material), material_alpha(material, in.uv));
+}
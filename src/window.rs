@@ -1,18 +1,58 @@
 use core::fmt;
-use std::{collections::HashSet, time::Duration};
+use std::{collections::HashSet, path::PathBuf, time::Duration};
 use winit::application::ApplicationHandler;
-use winit::dpi::LogicalSize;
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
 use winit::event::{DeviceEvent, ElementState, MouseButton, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window as WinitWindow, WindowAttributes, WindowId};
 
+use crate::benchmark::SweepState;
+use crate::custom_pipeline::capture::CaptureBuffer;
+use crate::custom_pipeline::config::PostEffect;
 use crate::custom_pipeline::renderer::CustomRenderer;
+use crate::overlay::Overlay;
+use crate::performance::PerformanceData;
+use crate::replay::FrameRecording;
+use crate::scene::PickedTriangle;
 use crate::{performance::PerformanceCollector, scene, wgpu_pipeline::renderer::WgpuRenderer};
 
+/// The post pass toggled by a function key, in the order of [`PostEffect::ALL`].
+fn post_effect_key(keycode: KeyCode) -> Option<PostEffect> {
+    let keys = [
+        KeyCode::F1,
+        KeyCode::F2,
+        KeyCode::F3,
+        KeyCode::F4,
+        KeyCode::F5,
+        KeyCode::F6,
+    ];
+    let index = keys.iter().position(|&key| key == keycode)?;
+    PostEffect::ALL.get(index).copied()
+}
+
+/// How far the cursor may move between pressing and releasing the left button, in pixels,
+/// for the click to pick rather than drag the camera.
+const CLICK_DISTANCE: f64 = 3.0;
+
+/// The picked triangle's model, mesh, size and position, for the title and the console.
+fn describe_pick(picked: &PickedTriangle) -> String {
+    let [x, y, z] = picked.position.to_array();
+    format!(
+        "model {}, mesh {} ({} triangles), triangle {} at ({:.3}, {:.3}, {:.3})",
+        picked.model,
+        picked.mesh_name.as_deref().unwrap_or("<unnamed>"),
+        picked.selection.len(),
+        picked.triangle,
+        x,
+        y,
+        z
+    )
+}
+
 pub enum RenderBackend {
-    WgpuPipeline { renderer: WgpuRenderer },
-    CustomPipeline { renderer: CustomRenderer },
+    WgpuPipeline { renderer: Box<WgpuRenderer> },
+    CustomPipeline { renderer: Box<CustomRenderer> },
 }
 
 pub struct Window {
@@ -30,6 +70,23 @@ pub struct Window {
     scene_configs: Vec<scene::SceneConfig>,
     current_scene_index: usize,
 
+    // Set when running a benchmark sweep, so completed scenes are recorded in its report
+    sweep: Option<SweepState>,
+
+    // Where to dump the intermediate buffers of the next frame the custom pipeline renders
+    dump_frame: Option<PathBuf>,
+    // Where to record the inputs of the next frame rendered, by either backend
+    record_frame: Option<PathBuf>,
+
+    // The cursor, where the left button was last pressed, and the triangle picked by
+    // clicking without dragging, outlined until something else is picked
+    cursor_position: PhysicalPosition<f64>,
+    press_position: Option<PhysicalPosition<f64>>,
+    picked: Option<PickedTriangle>,
+
+    // The debug UI, recreated with each backend since it paints with the backend's device
+    overlay: Option<Overlay>,
+
     backend_type: BackendType,
 }
 
@@ -45,14 +102,15 @@ impl ApplicationHandler for Window {
         ));
 
         // Create the OS window with specified dimensions as the rendering target.
-        self.winit_window = Some(
-            event_loop
-                .create_window(
-                    WindowAttributes::default()
-                        .with_inner_size(LogicalSize::new(self.width as f64, self.height as f64)),
-                )
-                .unwrap(),
-        );
+        // Scenes with an explicit resolution (benchmark sweeps) are sized in physical pixels.
+        let attributes = match self.scene_configs[self.current_scene_index].resolution {
+            Some([width, height]) => {
+                WindowAttributes::default().with_inner_size(PhysicalSize::new(width, height))
+            }
+            None => WindowAttributes::default()
+                .with_inner_size(LogicalSize::new(self.width as f64, self.height as f64)),
+        };
+        self.winit_window = Some(event_loop.create_window(attributes).unwrap());
 
         let window = self.winit_window.as_ref().unwrap();
         // Set window title based on the current scene to identify the active scene.
@@ -74,35 +132,64 @@ impl ApplicationHandler for Window {
         match self.backend_type {
             // Depending on the backend type, initialise the corresponding renderer to configure the rendering pipeline.
             BackendType::WgpuPipeline => {
-                let renderer = pollster::block_on(WgpuRenderer::new(
+                let scene_config = &self.scene_configs[self.current_scene_index];
+                let renderer = match pollster::block_on(WgpuRenderer::new(
                     &instance,
-                    self.surface.as_ref().unwrap(),
+                    self.surface.as_ref(),
                     self.width as u32,
                     self.height as u32,
                     &self.scene,
-                ));
+                    &scene_config.pipeline_config,
+                )) {
+                    Ok(renderer) => renderer,
+                    Err(e) => {
+                        eprintln!("{}: {}", scene_config.scene_name(), e);
+                        event_loop.exit();
+                        return;
+                    }
+                };
 
-                self.backend = Some(RenderBackend::WgpuPipeline { renderer });
+                self.backend = Some(RenderBackend::WgpuPipeline {
+                    renderer: Box::new(renderer),
+                });
             }
             BackendType::CustomPipeline => {
                 let renderer = pollster::block_on(CustomRenderer::new(
                     &instance,
-                    self.surface.as_ref().unwrap(),
+                    self.surface.as_ref(),
                     self.width as u32,
                     self.height as u32,
                     &self.scene,
+                    self.scene_configs[self.current_scene_index].pipeline_config,
                 ));
 
-                self.backend = Some(RenderBackend::CustomPipeline { renderer });
+                self.backend = Some(RenderBackend::CustomPipeline {
+                    renderer: Box::new(renderer),
+                });
             }
         }
+        self.create_overlay();
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        // Presses the debug UI takes for itself don't reach the camera or picking. Releases
+        // always do, so no key is left held.
+        if let (Some(overlay), Some(window)) = (&mut self.overlay, &self.winit_window) {
+            let consumed = overlay.on_window_event(window, &event);
+            let pressed = match &event {
+                WindowEvent::KeyboardInput { event, .. } => event.state == ElementState::Pressed,
+                WindowEvent::MouseInput { state, .. } => *state == ElementState::Pressed,
+                _ => false,
+            };
+            if consumed && pressed {
+                return;
+            }
+        }
+
         match event {
             WindowEvent::CloseRequested => {
                 // On close request, finalize performance metrics and exit the event loop.
-                self.collector.as_mut().unwrap().finalise();
+                self.finalise_scene();
                 event_loop.exit();
             }
             WindowEvent::KeyboardInput { event, .. } => {
@@ -112,12 +199,34 @@ impl ApplicationHandler for Window {
                             // Record key press to track user input for camera and scene control.
                             self.keys_down.insert(keycode);
                             // Escape key pressed triggers scene switching; finalise current metrics and load the next scene.
-                            match keycode {
-                                KeyCode::Escape => {
-                                    self.collector.as_mut().unwrap().finalise();
-                                    pollster::block_on(self.load_next_scene(event_loop));
+                            if keycode == KeyCode::Escape {
+                                self.finalise_scene();
+                                pollster::block_on(self.load_next_scene(event_loop));
+                            }
+                            // F7 cycles through the custom pipeline's debug views.
+                            if keycode == KeyCode::F7 {
+                                if let Some(RenderBackend::CustomPipeline { renderer }) =
+                                    &mut self.backend
+                                {
+                                    let debug_view = renderer.pipeline_config.debug_view.next();
+                                    renderer.set_debug_view(debug_view, &self.scene);
+                                    println!("Debug view: {}", debug_view);
+                                }
+                            }
+                            // F8 shows and hides the debug UI.
+                            if keycode == KeyCode::F8 {
+                                if let Some(overlay) = &mut self.overlay {
+                                    overlay.visible = !overlay.visible;
+                                }
+                            }
+                            // F1 to F6 toggle the custom pipeline's post passes.
+                            if let Some(effect) = post_effect_key(keycode) {
+                                if let Some(RenderBackend::CustomPipeline { renderer }) =
+                                    &mut self.backend
+                                {
+                                    let enabled = renderer.post_chain.toggle(effect);
+                                    println!("{}: {}", effect, if enabled { "on" } else { "off" });
                                 }
-                                _ => {}
                             }
                         }
                         ElementState::Released => {
@@ -133,12 +242,43 @@ impl ApplicationHandler for Window {
             } => {
                 // Update mouse pressed state to enable camera panning based on input.
                 self.mouse_pressed = state == ElementState::Pressed;
+                // A click that didn't drag the camera picks what is under the cursor.
+                match state {
+                    ElementState::Pressed => self.press_position = Some(self.cursor_position),
+                    ElementState::Released => {
+                        if let Some(press) = self.press_position.take() {
+                            let (dx, dy) = (
+                                self.cursor_position.x - press.x,
+                                self.cursor_position.y - press.y,
+                            );
+                            if dx.hypot(dy) <= CLICK_DISTANCE {
+                                self.pick_at_cursor();
+                            }
+                        }
+                    }
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = position;
             }
             WindowEvent::Resized(size) => {
                 // Handle window resize: update dimensions, adjust camera aspect ratio, and reconfigure the rendering backend accordingly.
+                let resized =
+                    (size.width as usize, size.height as usize) != (self.width, self.height);
                 self.width = size.width as usize;
                 self.height = size.height as usize;
 
+                // Scenes with a fixed resolution only measure the frames rendered at the window's
+                // final size.
+                let scene_config = &self.scene_configs[self.current_scene_index];
+                if resized && scene_config.resolution.is_some() {
+                    self.collector = Some(PerformanceCollector::new(
+                        scene_config.scene_name(),
+                        self.current_scene_index,
+                        Duration::from_secs(scene_config.benchmark_duration_secs),
+                    ));
+                }
+
                 if let Some(camera) = self.scene.get_active_camera_mut() {
                     camera.set_aspect_ratio(size.width as f32 / size.height as f32);
                 }
@@ -178,16 +318,13 @@ impl ApplicationHandler for Window {
         _device_id: winit::event::DeviceId,
         event: DeviceEvent,
     ) {
-        match event {
-            DeviceEvent::MouseMotion { delta } => {
-                // Use mouse motion delta to pan the active camera when the left mouse button is pressed.
-                if self.mouse_pressed {
-                    if let Some(camera) = self.scene.get_active_camera_mut() {
-                        camera.process_mouse(delta.0 as f32, -delta.1 as f32);
-                    }
+        if let DeviceEvent::MouseMotion { delta } = event {
+            // Use mouse motion delta to pan the active camera when the left mouse button is pressed.
+            if self.mouse_pressed {
+                if let Some(camera) = self.scene.get_active_camera_mut() {
+                    camera.process_mouse(delta.0 as f32, -delta.1 as f32);
                 }
             }
-            _ => (),
         }
     }
 
@@ -200,8 +337,24 @@ impl ApplicationHandler for Window {
         if pollster::block_on(async {
             // Asynchronously update the scene; if update fails or the scene completes, finalize metrics and attempt to load the next scene.
             if !self.update(delta_time).await {
-                // Scene is done, try to load next scene
-                self.collector.as_mut().unwrap().finalise();
+                // Scene is done, record its results if this is a sweep and try to load next scene
+                let data = self.finalise_scene();
+                if let Some(sweep) = &mut self.sweep {
+                    // The platform may clamp the window, so only record runs that rendered at
+                    // the resolution they are reported under.
+                    let actual = [self.width as u32, self.height as u32];
+                    match self.scene_configs[self.current_scene_index].resolution {
+                        Some(requested) if requested != actual => eprintln!(
+                            "Not recording {}: the window was {}x{} instead of {}x{}",
+                            self.scene_configs[self.current_scene_index].scene_name(),
+                            actual[0],
+                            actual[1],
+                            requested[0],
+                            requested[1]
+                        ),
+                        _ => sweep.record(self.current_scene_index, &data),
+                    }
+                }
                 if !self.load_next_scene(event_loop).await {
                     event_loop.exit();
                     return Err(());
@@ -222,7 +375,11 @@ impl ApplicationHandler for Window {
 
     fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
         // Finalise performance metrics as the application exits.
-        self.collector.as_mut().unwrap().finalise();
+        self.finalise_scene();
+
+        if let Some(sweep) = &self.sweep {
+            sweep.report.print_table();
+        }
     }
 }
 
@@ -232,6 +389,21 @@ pub enum BackendType {
     CustomPipeline,
 }
 
+impl std::str::FromStr for BackendType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wgpu" => Ok(BackendType::WgpuPipeline),
+            "custom" => Ok(BackendType::CustomPipeline),
+            other => Err(format!(
+                "Invalid backend type '{}'. Use 'wgpu' or 'custom'.",
+                other
+            )),
+        }
+    }
+}
+
 impl fmt::Display for BackendType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -263,12 +435,111 @@ impl Window {
             collector: None,
             scene_configs: Vec::new(),
             current_scene_index: 0,
+            sweep: None,
+            dump_frame: None,
+            record_frame: None,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            press_position: None,
+            picked: None,
+            overlay: None,
         })
     }
 
-    pub fn set_scene_configs(&mut self, configs: Vec<scene::SceneConfig>) {
-        // Store the provided scene configurations for future scene cycling.
+    pub fn set_scene_configs(&mut self, configs: Vec<scene::SceneConfig>, start_index: usize) {
+        // Store the provided scene configurations for future scene cycling, starting from the scene already loaded.
         self.scene_configs = configs;
+        self.current_scene_index = start_index;
+    }
+
+    pub fn set_sweep(&mut self, sweep: SweepState) {
+        self.sweep = Some(sweep);
+    }
+
+    pub fn set_dump_frame(&mut self, dir: PathBuf) {
+        self.dump_frame = Some(dir);
+    }
+
+    pub fn set_record_frame(&mut self, path: PathBuf) {
+        self.record_frame = Some(path);
+    }
+
+    /// Creates the debug UI for the active backend, shown if it was before.
+    fn create_overlay(&mut self) {
+        let (Some(window), Some(backend)) = (&self.winit_window, &self.backend) else {
+            return;
+        };
+        let device = match backend {
+            RenderBackend::WgpuPipeline { renderer } => &renderer.device,
+            RenderBackend::CustomPipeline { renderer } => &renderer.device,
+        };
+        let visible = self.overlay.as_ref().is_some_and(|overlay| overlay.visible);
+        self.overlay = Some(Overlay::new(window, device, visible));
+    }
+
+    /// Asks the active backend for the triangle under the cursor.
+    fn pick_at_cursor(&mut self) {
+        let (x, y) = (
+            self.cursor_position.x.max(0.0) as u32,
+            self.cursor_position.y.max(0.0) as u32,
+        );
+        match &mut self.backend {
+            Some(RenderBackend::WgpuPipeline { renderer }) => renderer.pick(x, y),
+            Some(RenderBackend::CustomPipeline { renderer }) => renderer.pick(x, y),
+            None => {}
+        }
+    }
+
+    /// Once the backend has read back a pick, outlines the picked mesh and shows it in the
+    /// title, or clears both when nothing was under the cursor.
+    fn update_pick(&mut self) {
+        let (pick, width, height) = match &mut self.backend {
+            Some(RenderBackend::WgpuPipeline { renderer }) => (
+                renderer.poll_pick(),
+                renderer.config.width,
+                renderer.config.height,
+            ),
+            Some(RenderBackend::CustomPipeline { renderer }) => (
+                renderer.poll_pick(),
+                renderer.surface_config.width,
+                renderer.surface_config.height,
+            ),
+            None => return,
+        };
+        let Some(pick) = pick else {
+            return;
+        };
+        self.picked = pick
+            .triangle
+            .and_then(|triangle| self.scene.pick(triangle, [pick.x, pick.y], width, height));
+
+        let selection = self.picked.as_ref().map(|picked| picked.selection.clone());
+        match &mut self.backend {
+            Some(RenderBackend::WgpuPipeline { renderer }) => renderer.set_selection(selection),
+            Some(RenderBackend::CustomPipeline { renderer }) => renderer.set_selection(selection),
+            None => {}
+        }
+
+        let scene_name = self.scene_configs[self.current_scene_index].scene_name();
+        let title = match &self.picked {
+            Some(picked) => {
+                println!("Picked {}", describe_pick(picked));
+                format!("{} | {}", scene_name, describe_pick(picked))
+            }
+            None => scene_name,
+        };
+        if let Some(window) = &self.winit_window {
+            window.set_title(&title);
+        }
+    }
+
+    /// Finalises the current scene's metrics, including the custom pipeline's counters when it is active.
+    fn finalise_scene(&mut self) -> PerformanceData {
+        let collector = self.collector.as_mut().unwrap();
+        if let Some(RenderBackend::CustomPipeline { renderer }) = &self.backend {
+            collector.set_pipeline_stats(renderer.read_stats());
+            collector.set_pass_timings(renderer.read_timings());
+        }
+        collector.finalise()
     }
 
     async fn load_next_scene(&mut self, event_loop: &ActiveEventLoop) -> bool {
@@ -284,6 +555,18 @@ impl Window {
         // Get the next scene config
         let scene_config = &self.scene_configs[self.current_scene_index];
 
+        // Switch the window to the resolution requested by the scene, if any. Platforms that
+        // resize asynchronously report the new size in a Resized event, which restarts the
+        // collector, so until then the scene renders at the window's current size.
+        if let (Some([width, height]), Some(window)) = (scene_config.resolution, &self.winit_window)
+        {
+            let size = window
+                .request_inner_size(PhysicalSize::new(width, height))
+                .unwrap_or_else(|| window.inner_size());
+            self.width = size.width as usize;
+            self.height = size.height as usize;
+        }
+
         // Reinitialize performance collector for the new scene.
         self.collector = Some(PerformanceCollector::new(
             scene_config.scene_name(),
@@ -292,15 +575,11 @@ impl Window {
         ));
 
         // Asynchronously create the new scene based on the updated configuration.
-        self.scene = crate::scene::Scene::from_config(
-            scene_config,
-            self.width as usize,
-            self.height as usize,
-        )
-        .await;
+        self.scene = crate::scene::Scene::from_config(scene_config, self.width, self.height).await;
 
         // Update backend type to match the new scene configuration.
         self.backend_type = scene_config.backend_type;
+        self.picked = None;
 
         // Recreate rendering backend to initialize the new scene.
         if let Some(window) = &self.winit_window {
@@ -314,28 +593,45 @@ impl Window {
 
             match self.backend_type {
                 BackendType::WgpuPipeline => {
-                    let renderer = WgpuRenderer::new(
+                    let renderer = match WgpuRenderer::new(
                         &instance,
-                        self.surface.as_ref().unwrap(),
+                        self.surface.as_ref(),
                         self.width as u32,
                         self.height as u32,
                         &self.scene,
-                    ).await;
+                        &scene_config.pipeline_config,
+                    )
+                    .await
+                    {
+                        Ok(renderer) => renderer,
+                        Err(e) => {
+                            eprintln!("{}: {}", scene_config.scene_name(), e);
+                            event_loop.exit();
+                            return false;
+                        }
+                    };
 
-                    self.backend = Some(RenderBackend::WgpuPipeline { renderer });
+                    self.backend = Some(RenderBackend::WgpuPipeline {
+                        renderer: Box::new(renderer),
+                    });
                 }
                 BackendType::CustomPipeline => {
                     let renderer = CustomRenderer::new(
                         &instance,
-                        self.surface.as_ref().unwrap(),
+                        self.surface.as_ref(),
                         self.width as u32,
                         self.height as u32,
                         &self.scene,
-                    ).await;
+                        scene_config.pipeline_config,
+                    )
+                    .await;
 
-                    self.backend = Some(RenderBackend::CustomPipeline { renderer });
+                    self.backend = Some(RenderBackend::CustomPipeline {
+                        renderer: Box::new(renderer),
+                    });
                 }
             }
+            self.create_overlay();
         }
 
         true
@@ -348,13 +644,33 @@ impl Window {
             camera.update_over_time(delta_time.as_secs_f32());
             camera.process_keyboard(&self.keys_down, delta_time.as_secs_f32());
         }
+        // Move the animated lights, whichever backend draws them.
+        self.scene.animate_lights(delta_time);
+
+        // Lay out the debug UI, applying its edits before the frame is drawn.
+        if let (Some(overlay), Some(window), Some(backend)) =
+            (&mut self.overlay, &self.winit_window, &mut self.backend)
+        {
+            if overlay.visible {
+                overlay.run(
+                    window,
+                    &mut self.scene,
+                    backend,
+                    self.collector.as_ref().unwrap(),
+                );
+            }
+        }
 
         if let Some(backend) = &mut self.backend {
             match backend {
                 RenderBackend::WgpuPipeline { renderer } => {
                     // Render scene using the WGPU pipeline; reconfigure if the rendering surface is lost.
                     match renderer
-                        .render(self.surface.as_ref().unwrap(), &self.scene)
+                        .render(
+                            self.surface.as_ref().unwrap(),
+                            &self.scene,
+                            self.overlay.as_mut(),
+                        )
                         .await
                     {
                         Ok(_) => {}
@@ -379,12 +695,31 @@ impl Window {
                 } => {
                     // Update scene buffers and render using the custom pipeline; reconfigure on loss of rendering surface.
                     self.scene.update_buffers(custom_renderer, delta_time);
+                    if self.dump_frame.is_some() {
+                        custom_renderer.capture_next_frame(&CaptureBuffer::ALL);
+                    }
                     // run the pipeline here
                     match custom_renderer
-                        .render(self.surface.as_ref().unwrap(), &self.scene)
+                        .render(
+                            self.surface.as_ref().unwrap(),
+                            &self.scene,
+                            self.overlay.as_mut(),
+                        )
                         .await
                     {
-                        Ok(_) => {}
+                        Ok(_) => {
+                            if let Some(capture) = custom_renderer.read_capture() {
+                                let dir = self.dump_frame.take().unwrap();
+                                match capture.write_to(&dir) {
+                                    Ok(()) => println!("Dumped frame buffers to {}", dir.display()),
+                                    Err(e) => eprintln!(
+                                        "Failed to dump frame buffers to {}: {}",
+                                        dir.display(),
+                                        e
+                                    ),
+                                }
+                            }
+                        }
                         Err(wgpu::SurfaceError::Lost) => {
                             if let Some(window) = &self.winit_window {
                                 let size = window.inner_size();
@@ -404,6 +739,27 @@ impl Window {
             }
         }
 
+        self.update_pick();
+
+        // Record the frame just rendered, with the scene as it was drawn.
+        let frame_size = match &self.backend {
+            Some(RenderBackend::WgpuPipeline { renderer }) => {
+                Some((renderer.config.width, renderer.config.height))
+            }
+            Some(RenderBackend::CustomPipeline { renderer }) => Some((
+                renderer.surface_config.width,
+                renderer.surface_config.height,
+            )),
+            None => None,
+        };
+        if let (Some(path), Some((width, height))) = (&self.record_frame, frame_size) {
+            match FrameRecording::record(&self.scene, width, height).write(path) {
+                Ok(()) => println!("Recorded the frame to {}", path.display()),
+                Err(e) => eprintln!("Failed to record the frame to {}: {}", path.display(), e),
+            }
+            self.record_frame = None;
+        }
+
         // Return whether the scene should continue running based on the performance collector update.
         // Performance collector contains logic for scene completion and benchmark duration.
         !self.collector.as_mut().unwrap().update()